@@ -0,0 +1,85 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use bucket_sdk::pipeline::buffer_pool::BufferPool;
+use bucket_sdk::pipeline::stages::compression::Lz4CompressStage;
+use bucket_sdk::pipeline::stages::encryption::Aes256GcmEncryptStage;
+use bucket_sdk::pipeline::{Pipeline, PipelineDirection};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+const CHUNKS: usize = 16;
+
+fn cipher() -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&[7u8; 32]))
+}
+
+/// What the upload path did before the buffer pool, a fresh ciphertext vector and record per chunk.
+fn allocating_encrypt(cipher: &Aes256Gcm, nonce: &Nonce<aes_gcm::aead::consts::U12>, chunks: &[Vec<u8>]) -> usize {
+    let mut written = 0;
+    for (index, chunk) in chunks.iter().enumerate() {
+        let aad = (index as u64).to_be_bytes();
+        let ciphertext = cipher.encrypt(nonce, Payload { msg: chunk, aad: &aad }).unwrap();
+        let mut record = Vec::with_capacity(4 + ciphertext.len());
+        record.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+        record.extend_from_slice(&ciphertext);
+        written += record.len();
+    }
+    written
+}
+
+fn pipeline_benchmark(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let chunks: Vec<Vec<u8>> = (0..CHUNKS)
+        .map(|i| (0..CHUNK_SIZE).map(|b| (b * 31 + i) as u8).collect())
+        .collect();
+    let shared_chunks: Vec<bytes::Bytes> = chunks.iter().map(|chunk| chunk.clone().into()).collect();
+    let nonce = *Nonce::from_slice(&[1u8; 12]);
+
+    let mut group = c.benchmark_group("upload_pipeline");
+    group.throughput(Throughput::Bytes((CHUNK_SIZE * CHUNKS) as u64));
+
+    group.bench_function(BenchmarkId::new("encrypt", "allocating"), |b| {
+        let cipher = cipher();
+        b.iter(|| allocating_encrypt(&cipher, &nonce, &chunks))
+    });
+
+    group.bench_function(BenchmarkId::new("encrypt", "pooled"), |b| {
+        let pool = BufferPool::default();
+        b.iter(|| {
+            runtime.block_on(async {
+                let mut pipeline = Pipeline::builder(PipelineDirection::Upload)
+                    .stage(Aes256GcmEncryptStage::from_cipher(cipher(), nonce).with_pool(pool.clone()))
+                    .build()
+                    .unwrap();
+                let mut written = 0;
+                for chunk in &shared_chunks {
+                    written += pipeline.process(chunk.clone()).await.unwrap().len();
+                }
+                written + pipeline.finish().await.unwrap().len()
+            })
+        })
+    });
+
+    group.bench_function(BenchmarkId::new("compress_encrypt", "pooled"), |b| {
+        let pool = BufferPool::default();
+        b.iter(|| {
+            runtime.block_on(async {
+                let mut pipeline = Pipeline::builder(PipelineDirection::Upload)
+                    .stage(Lz4CompressStage::with_pool(pool.clone()))
+                    .stage(Aes256GcmEncryptStage::from_cipher(cipher(), nonce).with_pool(pool.clone()))
+                    .build()
+                    .unwrap();
+                let mut written = 0;
+                for chunk in &shared_chunks {
+                    written += pipeline.process(chunk.clone()).await.unwrap().len();
+                }
+                written + pipeline.finish().await.unwrap().len()
+            })
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, pipeline_benchmark);
+criterion_main!(benches);
@@ -1,4 +1,6 @@
 use std::io::{Read, Write};
+use crate::io::bucket_path::LocalPathResolver;
+use crate::wrapper::bucket::download::conflict::DownloadReport;
 use std::rc::Rc;
 use bucket_api::backend_api;
 use bucket_api::backend_api::{CreateBucketRequest, CreateBucketResponse, DeleteBucketRequest, DeleteBucketResponse, DeleteFilesInBucketRequest, DeleteFilesInBucketResponse, DownloadBucketRequest, DownloadFilesRequest, File, GetBucketDetailsRequest, GetBucketDetailsResponse, GetBucketFilestructureRequest, GetBucketFilestructureResponse, MoveFilesInBucketRequest, MoveFilesInBucketResponse, UpdateBucketRequest, UpdateBucketResponse, UploadFilesToBucketRequest};
@@ -11,11 +13,23 @@ use crate::client::grpc::request_ext::RequestAuthorizationMetadataExt;
 use crate::compression::CompressionChooserHandling;
 use crate::encryption::EncryptionChooserHandler;
 use crate::io::FileWrapper;
+use crate::io::loading::dataloader::DataLoader;
 use crate::token::ContinuationToken;
 use crate::wrapper::bucket::bucket::DownloadFilesFromBucketError;
 use crate::wrapper::bucket::upload::FileUploadHandler;
 use crate::wrapper::bucket::ClientUploadExt;
+use crate::wrapper::bucket::errors::UploadError;
+use crate::wrapper::bucket::bucket::UploadFileDescriptionState;
+use crate::wrapper::bucket::upload::upload_handler::{BucketDownloadHandlerFileErrors, BucketFileReader};
+use crate::pipeline::Pipeline;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use crate::bundle::{bundle_object_path, plan_bundles, BundleError};
 use crate::wrapper::bucket::download::FileDownloadHandlerBuilder;
+use crate::archive::download::{finish_archive, ArchiveDownloadHandlerBuilder};
+use crate::archive::{ArchiveError, ArchiveFormat};
+use crate::io::file::VirtualFileDetails;
+use futures::AsyncWrite;
 
 impl<R: std::io::Read, W: std::io::Write> crate::api::ClientBucketExt<R, W> for BucketClient {
     async fn create_bucket(
@@ -64,59 +78,189 @@ impl<R: std::io::Read, W: std::io::Write> crate::api::ClientBucketExt<R, W> for
             .into_inner())
     }
 
-    async fn upload_files_to_bucket<File : FileWrapper, HTTP: HttpUploadClientExt>(
+    async fn upload_files_to_bucket<File : FileWrapper, HTTP: HttpUploadClientExt + Clone>(
         &mut self,
-        param: UploadFilesParams<File>,
-        upload_file_handler: impl FileUploadHandler<R, W>,
+        mut param: UploadFilesParams<File>,
+        make_pipeline: impl Fn(&UploadFileDescriptionState) -> Pipeline,
         http_client: HTTP,
     ) -> Result<(), BucketApiError> {
-        let uftbr: UploadFilesToBucketRequest = param.try_into().unwrap();
+        let transfer_context = param.transfer_context.take().unwrap_or_default();
+        if let Some(policy) = param.bundling {
+            // The small files go into bundle objects, the rest is uploaded below as usual.
+            let key = param.bundle_key.as_ref();
+            if param.encryption.is_some() && key.is_none() {
+                return Err(UploadError::from(BundleError::MissingBundleKey).into());
+            }
+            let sizes: Vec<u64> = param.source_files.iter().map(|file| file.source_file.get_size()).collect();
+            let plan = plan_bundles(&sizes, &policy);
+            let mut files: Vec<_> = param.source_files.drain(..).map(Some).collect();
+            for bundle in plan.bundles {
+                let bundled = bundle
+                    .iter()
+                    .filter_map(|index| files[*index].take())
+                    .map(|file| (file.target_directory, file.source_file))
+                    .collect();
+                let req = UploadFilesToBucketRequest {
+                    target_bucket_id: param.target_bucket_id.to_string(),
+                    target_bucket_owner_id: param.target_user_id.to_string(),
+                    target_directory: param.target_directory.clone(),
+                    source_files: Vec::new(),
+                    hashed_password: param.hashed_password.clone(),
+                };
+                self.client
+                    .upload_bundle_to_bucket_raw(
+                        req,
+                        bundle_object_path(&param.target_directory),
+                        bundled,
+                        key,
+                        &self.api_token,
+                        http_client.clone(),
+                        &transfer_context,
+                    )
+                    .await?;
+            }
+            param.source_files = files.into_iter().flatten().collect();
+            param.total_size_in_bytes = param.source_files.iter().map(|file| file.source_file.get_size()).sum();
+            if param.source_files.is_empty() {
+                return Ok(());
+            }
+        }
+        let uftbr: UploadFilesToBucketRequest = (&param).try_into()?;
         let mut req = Request::new(uftbr);
         req.set_authorization_metadata(&self.api_token);
+        let sources: RefCell<HashMap<String, File>> = RefCell::new(
+            param
+                .source_files
+                .into_iter()
+                .map(|file| (file.target_directory, file.source_file))
+                .collect(),
+        );
         self.client
-            .upload_files_to_bucket_raw(req, upload_file_handler,&self.api_token, http_client)
+            .upload_files_to_bucket_raw::<R, W, BucketFileReader<File>, _>(
+                req,
+                |state| {
+                    let file = sources
+                        .borrow_mut()
+                        .remove(&state.file_path)
+                        .ok_or_else(|| BucketDownloadHandlerFileErrors::UnknownFile(state.file_path.clone()))?;
+                    Ok(<BucketFileReader<File> as FileUploadHandler<R, W>>::new(file, make_pipeline(state))?
+                        .with_buffer_pool(transfer_context.buffers.clone()))
+                },
+                &self.api_token,
+                http_client,
+                &transfer_context,
+            )
             .await?;
         Ok(())
     }
 
+    async fn upload_from_data_loader<L: DataLoader, HTTP: HttpUploadClientExt + Clone>(
+        &mut self,
+        loader: &L,
+        target_user_id: uuid::Uuid,
+        target_bucket_id: uuid::Uuid,
+        target_directory: String,
+        make_pipeline: impl Fn(&UploadFileDescriptionState) -> Pipeline,
+        http_client: HTTP,
+    ) -> Result<(), BucketApiError> {
+        let param = UploadFilesParams::from_data_loader(loader, target_user_id, target_bucket_id, target_directory)
+            .await
+            .map_err(|error| BucketApiError::DataLoaderError(Box::new(error)))?;
+        <Self as crate::api::ClientBucketExt<R, W>>::upload_files_to_bucket(self, param, make_pipeline, http_client).await
+    }
+
     async fn download_files_from_bucket<N: ArrayLength,HTTP: HttpDownloadClientExt,CCH: CompressionChooserHandling<R, W>, ECH: EncryptionChooserHandler<R, W, N>,FDHB: FileDownloadHandlerBuilder<R, W, N,HTTP, CCH, ECH, >>(
         &mut self,
-        param: DownloadFilesParams,
-        file_download_handler_builder: FDHB
-    )  -> Result<(), DownloadFilesFromBucketError> {
+        mut param: DownloadFilesParams,
+        mut file_download_handler_builder: FDHB,
+        http_client: HTTP,
+    )  -> Result<DownloadReport, DownloadFilesFromBucketError> {
+        if let Some(options) = param.download_options.take() {
+            file_download_handler_builder.set_download_options(options);
+        }
         let keep_file_structure = param.keep_file_structure;
-        let dfr: DownloadFilesRequest = param.try_into().unwrap();
+        let conflict_policy = param.conflict_policy;
+        let filter = param.filter.take();
+        let transfer_context = param.transfer_context.take().unwrap_or_default();
+        let local = param
+            .local_directory
+            .clone()
+            .map(|directory| LocalPathResolver::new(directory, keep_file_structure));
+        let dfr: DownloadFilesRequest = param.try_into()?;
         let mut req = Request::new(dfr);
         req.set_authorization_metadata(&self.api_token);
-        self.client
+        let report = self
+            .client
             .download_files_from_bucket_raw::<R, W, N, HTTP, CCH, ECH, FDHB>(
                 req,
                 file_download_handler_builder,
                 &self.api_token,
+                &http_client,
                 keep_file_structure,
+                local.as_ref(),
+                conflict_policy,
+                filter.as_ref(),
+                &transfer_context,
             )
             .await?;
-        Ok(())
+        Ok(report)
     }
 
     async fn download_bucket<N:ArrayLength,HTTP: HttpDownloadClientExt,CCH: CompressionChooserHandling<R, W>, ECH: EncryptionChooserHandler<R, W, N>, FDHB: FileDownloadHandlerBuilder<R, W, N,HTTP, CCH, ECH>>(
         &mut self,
-        param: DownloadBucketParams,
-        file_download_handler_builder: FDHB,
+        mut param: DownloadBucketParams,
+        mut file_download_handler_builder: FDHB,
         http_client: HTTP,
-    ) -> Result<Vec<String>, BucketApiError> {
+    ) -> Result<DownloadReport, BucketApiError> {
+        if let Some(options) = param.download_options.take() {
+            file_download_handler_builder.set_download_options(options);
+        }
+        // Handlers that archive on the client get the plain files, the server can't archive encrypted objects.
+        if let Some(archive_format) = file_download_handler_builder.archive_format() {
+            if let Some(format) = param.format.take() {
+                if ArchiveFormat::from_download_format(&format) != Some(archive_format) {
+                    return Err(BucketApiError::ArchiveFormatMismatch(format.to_string()));
+                }
+            }
+        }
         let keep_file_structure = param.keep_file_structure;
-        let dbr: DownloadBucketRequest = param.try_into().unwrap();
+        let conflict_policy = param.conflict_policy;
+        let filter = param.filter.take();
+        let transfer_context = param.transfer_context.take().unwrap_or_default();
+        let local = param
+            .local_directory
+            .clone()
+            .map(|directory| LocalPathResolver::new(directory, keep_file_structure));
+        let dbr: DownloadBucketRequest = param.try_into()?;
         let mut req = dbr.into_request();
         req.set_authorization_metadata(&self.api_token);
         let resp = self
             .client
-            .download_bucket_raw::<R, W, N, HTTP, CCH, ECH, FDHB>(req, keep_file_structure, file_download_handler_builder, &self.api_token ,http_client)
-            .await
-            .unwrap();
+            .download_bucket_raw::<R, W, N, HTTP, CCH, ECH, FDHB>(req, keep_file_structure, file_download_handler_builder, &self.api_token ,http_client, local.as_ref(), conflict_policy, filter.as_ref(), &transfer_context)
+            .await?;
         Ok(resp)
     }
 
+    async fn download_bucket_archive<N: ArrayLength, HTTP: HttpDownloadClientExt, CCH: CompressionChooserHandling<R, W>, ECH: EncryptionChooserHandler<R, W, N>, AW: AsyncWrite + Unpin>(
+        &mut self,
+        param: DownloadBucketParams,
+        writer: AW,
+        make_pipeline: impl Fn(&VirtualFileDetails) -> Pipeline + 'static,
+        http_client: HTTP,
+    ) -> Result<(DownloadReport, AW), BucketApiError> {
+        let format = param
+            .format
+            .as_ref()
+            .and_then(ArchiveFormat::from_download_format)
+            .ok_or_else(|| BucketApiError::UnsupportedArchiveFormat(param.format.as_ref().map(ToString::to_string)))?;
+        let builder = ArchiveDownloadHandlerBuilder::new(writer, format).with_pipeline(make_pipeline);
+        let archive = builder.archive().ok_or(ArchiveError::NoWriter)?;
+        let report = <Self as crate::api::ClientBucketExt<R, W>>::download_bucket::<N, HTTP, CCH, ECH, _>(self, param, builder, http_client).await?;
+        // The handlers are dropped with the download, the archive is only held here now.
+        let writer = finish_archive(archive).await?;
+        Ok((report, writer))
+    }
+
     async fn move_files_in_bucket(
         &mut self,
         param: MoveFilesInBucketParams,
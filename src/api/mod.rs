@@ -1,4 +1,5 @@
 use std::rc::Rc;
+use crate::wrapper::bucket::download::conflict::DownloadReport;
 use bucket_api::backend_api;
 use bucket_api::backend_api::{CreateBucketResponse, DeleteAccountResponse, DeleteBucketResponse, DeleteFilesInBucketResponse, GetAccountDetailsResponse, GetBucketDetailsResponse, GetBucketFilestructureResponse, MoveFilesInBucketResponse, UpdateAccountResponse, UpdateBucketResponse};
 use generic_array::ArrayLength;
@@ -10,7 +11,12 @@ use crate::compression::CompressionChooserHandling;
 use crate::wrapper::bucket::bucket::{ DownloadFilesFromBucketError};
 use crate::wrapper::bucket::errors::{DownloadError, UploadError};
 use crate::io::FileWrapper;
-use crate::wrapper::bucket::upload::FileUploadHandler;
+use crate::io::loading::dataloader::DataLoader;
+use crate::wrapper::bucket::bucket::UploadFileDescriptionState;
+use crate::pipeline::Pipeline;
+use crate::archive::ArchiveError;
+use crate::io::file::VirtualFileDetails;
+use futures::AsyncWrite;
 use crate::dto::account::{DeleteAccountParams, DeleteAccountParamsParsingError, GetAccountDetailsParams, GetAccountDetailsParamsParsingError, UpdateAccountParams, UpdateAccountParamsParsingError};
 use crate::dto::authentication::{LoginParams, RegistrationParams};
 use crate::dto::bucket::{CreateBucketParams, CreateBucketParamsParsingError, DeleteBucketParams, DeleteFilesInBucketParams, DeleteFilesInBucketParamsParsingError, DownloadBucketParams, DownloadBucketParamsParsingError, DownloadFilesParams, DownloadFilesParamsParsingError, GetBucketDetailsParams, GetBucketDetailsRequestParsingError, GetFilesystemDetailsParams, GetFilesystemDetailsParamsParsingError, MoveFilesInBucketParams, MoveFilesInBucketRequestParsingError, ParseDeleteBucketRequestError, UpdateBucketParams, UpdateBucketParamsParsingError, UploadFilesParams, UploadFilesRequestParsingError};
@@ -47,10 +53,24 @@ pub trait ClientBucketExt<R: std::io::Read, W: std::io::Write> {
         param: GetBucketDetailsParams,
     ) -> Result<GetBucketDetailsResponse, BucketApiError>;
 
-    async fn upload_files_to_bucket<File: FileWrapper,HTTP: HttpUploadClientExt>(
+    /// Files are packed into bundle objects when `param.bundling` is set, see `BundlePolicy`.
+    /// `make_pipeline` returns the pipeline of every other file, the limits of `param.transfer_context` apply.
+    async fn upload_files_to_bucket<File: FileWrapper,HTTP: HttpUploadClientExt + Clone>(
         &mut self,
         param: UploadFilesParams<File>,
-        upload_file_handler: impl FileUploadHandler<R, W>,
+        make_pipeline: impl Fn(&UploadFileDescriptionState) -> Pipeline,
+        http_client: HTTP,
+    ) -> Result<(), BucketApiError>;
+
+    /// Uploads everything `loader` produces under `target_directory`, see `UploadFilesParams::from_data_loader`
+    /// to set encryption, bundling or a transfer context.
+    async fn upload_from_data_loader<L: DataLoader, HTTP: HttpUploadClientExt + Clone>(
+        &mut self,
+        loader: &L,
+        target_user_id: uuid::Uuid,
+        target_bucket_id: uuid::Uuid,
+        target_directory: String,
+        make_pipeline: impl Fn(&UploadFileDescriptionState) -> Pipeline,
         http_client: HTTP,
     ) -> Result<(), BucketApiError>;
     ///https://repost.aws/questions/QUxynkZDbASDaqrUcpx_sILQ/s3-support-multiple-byte-ranges-download
@@ -60,7 +80,8 @@ pub trait ClientBucketExt<R: std::io::Read, W: std::io::Write> {
         //file_handle: BucketFileTrait<Error = BucketFileError, FileHandle = FileHandle>,
         // Hook function will take in the details for the file and either return a WebBucketFile or NativeBucketFile depending on enviorment implementation, diffrent between WASM and NATIVE.
         create_file_download_handler: FDHB,
-    ) -> Result<(), BucketApiError>;
+        http_client: HTTP,
+    ) -> Result<DownloadReport, BucketApiError>;
 
     ///
     /// Downloads the entire bucket.
@@ -82,7 +103,17 @@ pub trait ClientBucketExt<R: std::io::Read, W: std::io::Write> {
         param: DownloadBucketParams,
         file_download_handler_builder: FDHB,
         http_client: HTTP,
-    ) -> Result<Vec<String>, BucketApiError>;
+    ) -> Result<DownloadReport, BucketApiError>;
+
+    /// Downloads the bucket into a client-side archive of `param.format` written to `writer`, for buckets the
+    /// server can't archive because their objects are encrypted. Returns the writer after the archive was ended.
+    async fn download_bucket_archive<N: ArrayLength, HTTP: HttpDownloadClientExt, CCH: CompressionChooserHandling<R, W>, ECH: EncryptionChooserHandler<R, W, N>, AW: AsyncWrite + Unpin>(
+        &mut self,
+        param: DownloadBucketParams,
+        writer: AW,
+        make_pipeline: impl Fn(&VirtualFileDetails) -> Pipeline + 'static,
+        http_client: HTTP,
+    ) -> Result<(DownloadReport, AW), BucketApiError>;
 
     async fn move_files_in_bucket(
         &mut self,
@@ -198,6 +229,14 @@ pub enum BucketApiError {
     DownloadError(#[from] DownloadError),
     #[error(transparent)]
     UploadError(#[from] UploadError),
+    #[error("Loading the upload sources failed: {0}")]
+    DataLoaderError(Box<dyn std::error::Error>),
+    #[error(transparent)]
+    ArchiveError(#[from] ArchiveError),
+    #[error("Download format {0:?} can't be archived on the client")]
+    UnsupportedArchiveFormat(Option<String>),
+    #[error("Download format {0} isn't the archive the download handlers build")]
+    ArchiveFormatMismatch(String),
 
     // Response parsing error
     #[error("GetBucketDetailsRequestFullyResponseParsingError")]
@@ -0,0 +1,244 @@
+use super::{ArchiveEntry, ArchiveError, ArchiveFormat, ArchiveWriter};
+use crate::client::http::HttpDownloadClientExt;
+use crate::compression::CompressionChooserHandling;
+use crate::encryption::EncryptionChooserHandler;
+use crate::io::bucket_path::BucketPath;
+use crate::io::file::VirtualFileDetails;
+use crate::io::metadata::PreservedMetadata;
+use crate::pipeline::{Pipeline, PipelineDirection};
+use crate::token::ApiToken;
+use crate::wrapper::bucket::download::download_handler::BucketDownloadHandlerErrors;
+use crate::wrapper::bucket::download::{FileDownloadHandler, FileDownloadHandlerBuilder};
+use async_trait::async_trait;
+use bucket_common_types::BucketGuid;
+use bytes::Bytes;
+use futures::lock::Mutex;
+use futures::AsyncWrite;
+use generic_array::ArrayLength;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use url::Url;
+
+/// Download handler that writes the decrypted file into a shared archive instead of the filesystem.
+/// Files of one archive have to be downloaded one after another, a handler whose file fails or is cancelled
+/// halfway leaves the archive unusable.
+pub struct ArchiveFileWriter<W> {
+    archive: Rc<Mutex<ArchiveWriter<W>>>,
+    /// Taken when the entry is started with the first chunk, after the metadata header was seen.
+    entry: Option<ArchiveEntry>,
+    pipeline: Pipeline,
+    metadata_key: Option<Option<aes_gcm::Aes256Gcm>>,
+}
+
+impl<W: AsyncWrite + Unpin> ArchiveFileWriter<W> {
+    /// `entry.size_in_bytes` is the size after decryption and decompression, without it a tar entry is spooled.
+    pub fn new(archive: Rc<Mutex<ArchiveWriter<W>>>, entry: ArchiveEntry, pipeline: Pipeline) -> Self {
+        Self {
+            archive,
+            entry: Some(entry),
+            pipeline,
+            metadata_key: None,
+        }
+    }
+
+    /// Takes the modification time and mode of the entry from the preserved metadata of the object.
+    pub fn with_metadata(mut self, key: Option<aes_gcm::Aes256Gcm>) -> Self {
+        self.metadata_key = Some(key);
+        self
+    }
+
+    async fn write_plaintext(&mut self, plaintext: &[u8]) -> Result<(), BucketDownloadHandlerErrors> {
+        let mut archive = self.archive.lock().await;
+        if let Some(entry) = self.entry.take() {
+            archive.start_entry(entry).await?;
+        }
+        if !plaintext.is_empty() {
+            archive.write(plaintext).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(? Send)]
+impl<R, W, AW: AsyncWrite + Unpin> FileDownloadHandler<R, W> for ArchiveFileWriter<AW> {
+    type Error = BucketDownloadHandlerErrors;
+
+    async fn on_download_chunk(&mut self, chunk: Bytes) -> Result<(), Self::Error> {
+        let plaintext = self.pipeline.process(chunk).await?;
+        self.write_plaintext(&plaintext).await
+    }
+
+    async fn on_file_metadata(&mut self, file_metadata: &str) -> Result<(), Self::Error> {
+        if let (Some(key), Some(entry)) = (self.metadata_key.as_ref(), self.entry.as_mut()) {
+            let metadata = PreservedMetadata::decode(file_metadata, key.as_ref())?;
+            entry.modified = metadata.modified().or(entry.modified);
+            entry.mode = metadata.mode.or(entry.mode);
+        }
+        Ok(())
+    }
+
+    async fn on_download_finish(mut self) -> Result<(), Self::Error> {
+        let tail = self.pipeline.finish().await?;
+        self.write_plaintext(&tail).await?;
+        self.archive.lock().await.finish_entry().await?;
+        Ok(())
+    }
+}
+
+/// Creates an [`ArchiveFileWriter`] for every file of a download, all of them write into one archive.
+/// Keep the handle of [`Self::archive`] before the builder is moved into the download and end it with
+/// [`finish_archive`] once the download returned.
+pub struct ArchiveDownloadHandlerBuilder<AW> {
+    /// `None` for a builder made by the trait constructor, which has no writer to archive into.
+    archive: Option<Rc<Mutex<ArchiveWriter<AW>>>>,
+    format: ArchiveFormat,
+    keep_file_structure: bool,
+    make_pipeline: Box<dyn Fn(&VirtualFileDetails) -> Pipeline>,
+    metadata_key: Option<Option<aes_gcm::Aes256Gcm>>,
+}
+
+impl<AW: AsyncWrite + Unpin> ArchiveDownloadHandlerBuilder<AW> {
+    pub fn new(writer: AW, format: ArchiveFormat) -> Self {
+        Self {
+            archive: Some(Rc::new(Mutex::new(ArchiveWriter::new(writer, format)))),
+            format,
+            keep_file_structure: true,
+            make_pipeline: Box::new(|_| Pipeline::passthrough(PipelineDirection::Download)),
+            metadata_key: None,
+        }
+    }
+
+    /// `make_pipeline` is called for every file, like `FileDownloadHandlerBuilder::set_pipeline`.
+    pub fn with_pipeline(mut self, make_pipeline: impl Fn(&VirtualFileDetails) -> Pipeline + 'static) -> Self {
+        self.make_pipeline = Box::new(make_pipeline);
+        self
+    }
+
+    /// Takes the modification time and mode of every entry from the preserved metadata of its object.
+    pub fn with_metadata(mut self, key: Option<aes_gcm::Aes256Gcm>) -> Self {
+        self.metadata_key = Some(key);
+        self
+    }
+
+    pub fn archive(&self) -> Option<Rc<Mutex<ArchiveWriter<AW>>>> {
+        self.archive.clone()
+    }
+
+    /// The handler of `file`. Its entry has a size only when the pipeline has no stages, the stored size is
+    /// the plaintext size then, otherwise tar spools the entry to learn it.
+    pub fn handler_for(
+        &self,
+        file: VirtualFileDetails,
+        keep_file_structure: bool,
+    ) -> Result<ArchiveFileWriter<AW>, BucketDownloadHandlerErrors> {
+        let archive = self.archive.clone().ok_or(ArchiveError::NoWriter)?;
+        let bucket_path = BucketPath::parse(&file.path)?;
+        let path = match keep_file_structure && self.keep_file_structure {
+            true => bucket_path.to_string(),
+            false => format!("/{}", bucket_path.file_name()),
+        };
+        let pipeline = (self.make_pipeline)(&file);
+        let mut entry = ArchiveEntry::new(path);
+        entry.size_in_bytes = pipeline.stage_kinds().is_empty().then_some(file.size_in_bytes);
+        entry.modified = file.date;
+        let handler = ArchiveFileWriter::new(archive, entry, pipeline);
+        Ok(match self.metadata_key.clone() {
+            Some(key) => handler.with_metadata(key),
+            None => handler,
+        })
+    }
+}
+
+/// Ends the archive of an [`ArchiveDownloadHandlerBuilder`] after its download and returns the writer.
+pub async fn finish_archive<AW: AsyncWrite + Unpin>(archive: Rc<Mutex<ArchiveWriter<AW>>>) -> Result<AW, ArchiveError> {
+    let archive = Rc::try_unwrap(archive).map_err(|_| ArchiveError::InUse)?;
+    archive.into_inner().finish().await
+}
+
+impl<R, W, N, HTTP, CCH, ECH, AW> FileDownloadHandlerBuilder<R, W, N, HTTP, CCH, ECH> for ArchiveDownloadHandlerBuilder<AW>
+where
+    R: Read,
+    W: Write,
+    N: ArrayLength,
+    HTTP: HttpDownloadClientExt,
+    CCH: CompressionChooserHandling<R, W>,
+    ECH: EncryptionChooserHandler<R, W, N>,
+    AW: AsyncWrite + Unpin,
+{
+    type OutputType = Self;
+    type Handler = ArchiveFileWriter<AW>;
+
+    /// There is no writer to archive into, every file fails until the builder is made with `ArchiveDownloadHandlerBuilder::new`.
+    fn new(_target_bucket: &BucketGuid, _api_token: &ApiToken, _target_path: &String, _upload_url: &Url, _client: &HTTP) -> Self {
+        Self {
+            archive: None,
+            format: ArchiveFormat::Zip,
+            keep_file_structure: true,
+            make_pipeline: Box::new(|_| Pipeline::passthrough(PipelineDirection::Download)),
+            metadata_key: None,
+        }
+    }
+
+    fn set_total_download_size(&mut self, _total_download_size: u64) {}
+
+    fn set_keep_structure(&mut self, keep_file_structure: bool) {
+        self.keep_file_structure = keep_file_structure;
+    }
+
+    fn set_pipeline(&mut self, make_pipeline: Box<dyn Fn(&VirtualFileDetails) -> Pipeline>) {
+        self.make_pipeline = make_pipeline;
+    }
+
+    fn build(self) -> Self::OutputType {
+        self
+    }
+
+    fn archive_format(&self) -> Option<ArchiveFormat> {
+        Some(self.format)
+    }
+
+    fn handle(&self, file: VirtualFileDetails, keep_file_structure: bool) -> Result<Self::Handler, BucketDownloadHandlerErrors> {
+        self.handler_for(file, keep_file_structure)
+    }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use super::*;
+    use std::io::Read as _;
+
+    #[tokio::test]
+    async fn test_builder_archives_downloaded_files() {
+        let builder = ArchiveDownloadHandlerBuilder::new(futures::io::Cursor::new(Vec::new()), ArchiveFormat::TarGz);
+        let archive = builder.archive().unwrap();
+        for (path, content) in [("/docs/a.txt", &b"hello"[..]), ("/docs/deep/b.txt", &b"abc"[..])] {
+            let file = VirtualFileDetails {
+                path: path.to_string(),
+                date: None,
+                size_in_bytes: content.len() as u64,
+                local_target: None,
+            };
+            let mut handler = builder.handler_for(file, path.ends_with("a.txt")).unwrap();
+            FileDownloadHandler::<std::io::Empty, std::io::Sink>::on_download_chunk(&mut handler, Bytes::from_static(content))
+                .await
+                .unwrap();
+            FileDownloadHandler::<std::io::Empty, std::io::Sink>::on_download_finish(handler).await.unwrap();
+        }
+        assert!(matches!(finish_archive(archive.clone()).await, Err(ArchiveError::InUse)));
+        drop(builder);
+        let archive = finish_archive(archive).await.unwrap().into_inner();
+
+        let mut tar = ::tar::Archive::new(flate2::read::GzDecoder::new(archive.as_slice()));
+        let mut files = Vec::new();
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            files.push((entry.path().unwrap().to_string_lossy().into_owned(), content));
+        }
+        assert_eq!(
+            files,
+            vec![("docs/a.txt".to_string(), "hello".to_string()), ("b.txt".to_string(), "abc".to_string())]
+        );
+    }
+}
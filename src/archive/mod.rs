@@ -0,0 +1,182 @@
+//! Builds zip and tar.gz archives on the client from downloaded and decrypted files, for buckets the server
+//! can't archive because it only sees ciphertext. Archives are written to an `AsyncWrite` as entries come in,
+//! only the current chunk and the zip central directory are held in memory. A tar entry whose size isn't known
+//! up front is the exception, it is spooled like a streamed upload until it is finished.
+
+pub mod download;
+pub mod tar;
+pub mod zip;
+
+use crate::io::bucket_path::{BucketPath, BucketPathError};
+use crate::pipeline::buffer_pool::MemoryBudget;
+use crate::wrapper::bucket::upload::stream::StreamUploadError;
+use bucket_common_types::DownloadFormat;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite};
+use time::OffsetDateTime;
+
+/// Size of the reads of `ArchiveWriter::add_entry`.
+const ARCHIVE_COPY_CHUNK_SIZE: usize = 256 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("Entry {path} was declared with {expected} bytes but {actual} bytes were written")]
+    SizeMismatch { path: String, expected: u64, actual: u64 },
+    #[error("Entry {0} was started before the previous entry was finished")]
+    EntryInProgress(String),
+    #[error("No entry was started")]
+    NoEntry,
+    #[error("The archive is still used by a download handler")]
+    InUse,
+    #[error("The download handler builder has no writer to archive into")]
+    NoWriter,
+    #[error(transparent)]
+    InvalidPath(#[from] BucketPathError),
+    #[error(transparent)]
+    SpoolError(#[from] StreamUploadError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// Deflate compressed entries, sizes don't have to be known up front.
+    Zip,
+    /// The size of every entry is written before its data, entries without a size are spooled to find it.
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// The format the client builds for a `DownloadFormat`, `None` for formats only the server can build.
+    pub fn from_download_format(format: &DownloadFormat) -> Option<Self> {
+        match format.to_string().to_ascii_lowercase().as_str() {
+            "zip" => Some(ArchiveFormat::Zip),
+            "tar.gz" | "targz" | "tgz" => Some(ArchiveFormat::TarGz),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn mime(&self) -> mime::Mime {
+        match self {
+            ArchiveFormat::Zip => "application/zip".parse().expect("valid mime"),
+            ArchiveFormat::TarGz => "application/gzip".parse().expect("valid mime"),
+        }
+    }
+}
+
+/// A file of the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// Path inside the archive, validated like a bucket path and stored without the leading `/`.
+    pub path: String,
+    /// Plaintext size, checked for both formats when given. It is the stored size when the object was uploaded
+    /// without pipeline stages, otherwise it is only known once the file is decrypted and decompressed.
+    pub size_in_bytes: Option<u64>,
+    pub modified: Option<OffsetDateTime>,
+    pub mode: Option<u32>,
+}
+
+impl ArchiveEntry {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            size_in_bytes: None,
+            modified: None,
+            mode: None,
+        }
+    }
+
+    pub fn with_size(mut self, size_in_bytes: u64) -> Self {
+        self.size_in_bytes = Some(size_in_bytes);
+        self
+    }
+
+    /// The path relative to the archive root, `a/b.txt` for `/a/b.txt`.
+    fn archive_path(&self) -> Result<String, BucketPathError> {
+        let path = BucketPath::parse(&self.path)?;
+        Ok(path.components().collect::<Vec<_>>().join("/"))
+    }
+}
+
+enum ArchiveInner<W> {
+    Zip(zip::ZipStreamWriter<W>),
+    TarGz(tar::TarGzStreamWriter<W>),
+}
+
+/// Writes entries one after another, each is started, written in chunks and finished before the next one.
+pub struct ArchiveWriter<W> {
+    inner: ArchiveInner<W>,
+}
+
+impl<W: AsyncWrite + Unpin> ArchiveWriter<W> {
+    pub fn new(writer: W, format: ArchiveFormat) -> Self {
+        let inner = match format {
+            ArchiveFormat::Zip => ArchiveInner::Zip(zip::ZipStreamWriter::new(writer)),
+            ArchiveFormat::TarGz => ArchiveInner::TarGz(tar::TarGzStreamWriter::new(writer)),
+        };
+        Self { inner }
+    }
+
+    /// Limits the memory a spooled tar entry takes before the rest of it is spilled to disk.
+    pub fn with_memory_budget(mut self, budget: MemoryBudget) -> Self {
+        if let ArchiveInner::TarGz(writer) = &mut self.inner {
+            writer.set_memory_budget(budget);
+        }
+        self
+    }
+
+    pub fn format(&self) -> ArchiveFormat {
+        match self.inner {
+            ArchiveInner::Zip(_) => ArchiveFormat::Zip,
+            ArchiveInner::TarGz(_) => ArchiveFormat::TarGz,
+        }
+    }
+
+    pub async fn start_entry(&mut self, entry: ArchiveEntry) -> Result<(), ArchiveError> {
+        match &mut self.inner {
+            ArchiveInner::Zip(writer) => writer.start_entry(entry).await,
+            ArchiveInner::TarGz(writer) => writer.start_entry(entry).await,
+        }
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), ArchiveError> {
+        match &mut self.inner {
+            ArchiveInner::Zip(writer) => writer.write(data).await,
+            ArchiveInner::TarGz(writer) => writer.write(data).await,
+        }
+    }
+
+    pub async fn finish_entry(&mut self) -> Result<(), ArchiveError> {
+        match &mut self.inner {
+            ArchiveInner::Zip(writer) => writer.finish_entry().await,
+            ArchiveInner::TarGz(writer) => writer.finish_entry().await,
+        }
+    }
+
+    /// Adds the whole of `reader` as `entry`.
+    pub async fn add_entry<R: AsyncRead + Unpin>(&mut self, entry: ArchiveEntry, mut reader: R) -> Result<(), ArchiveError> {
+        self.start_entry(entry).await?;
+        let mut buf = vec![0u8; ARCHIVE_COPY_CHUNK_SIZE];
+        loop {
+            match reader.read(&mut buf).await? {
+                0 => break,
+                n => self.write(&buf[..n]).await?,
+            }
+        }
+        self.finish_entry().await
+    }
+
+    /// Writes the end of the archive and returns the writer, flushed.
+    pub async fn finish(self) -> Result<W, ArchiveError> {
+        match self.inner {
+            ArchiveInner::Zip(writer) => writer.finish().await,
+            ArchiveInner::TarGz(writer) => writer.finish().await,
+        }
+    }
+}
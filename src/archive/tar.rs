@@ -0,0 +1,295 @@
+use super::{ArchiveEntry, ArchiveError};
+use crate::io::FileWrapper;
+use crate::pipeline::buffer_pool::{MemoryBudget, MemoryReservation, DEFAULT_TRANSFER_MEMORY_BUDGET};
+use crate::wrapper::bucket::upload::stream::{SpooledStream, STREAM_SPOOL_CHUNK_SIZE};
+use bytes::BytesMut;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::{AsyncWrite, AsyncWriteExt};
+use std::io::Write;
+
+const BLOCK_SIZE: usize = 512;
+/// Largest size the 11 octal digits of the ustar size field hold, larger entries get a PAX `size` record.
+const USTAR_MAX_SIZE: u64 = 0o77777777777;
+const USTAR_NAME_LEN: usize = 100;
+const USTAR_PREFIX_LEN: usize = 155;
+const DEFAULT_MODE: u32 = 0o644;
+
+struct TarEntryState {
+    path: String,
+    expected: Option<u64>,
+    written: u64,
+    /// Set for an entry without a size, its header is only written once all of its data was spooled.
+    spool: Option<TarEntrySpool>,
+}
+
+struct TarEntrySpool {
+    entry: ArchiveEntry,
+    data: SpooledStream,
+    /// The tail that doesn't fill a spool chunk yet.
+    pending: BytesMut,
+    reservations: Vec<MemoryReservation>,
+}
+
+/// ustar entries in a gzip stream. The gzip output is drained to the writer after every call, so only
+/// the compressor state and the last chunk are buffered. Entries without a size are spooled, in memory
+/// within the budget and spilled to an unlinked temp file beyond it on native targets.
+pub struct TarGzStreamWriter<W> {
+    writer: W,
+    encoder: GzEncoder<Vec<u8>>,
+    current: Option<TarEntryState>,
+    budget: MemoryBudget,
+}
+
+impl<W: AsyncWrite + Unpin> TarGzStreamWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            encoder: GzEncoder::new(Vec::new(), Compression::default()),
+            current: None,
+            budget: MemoryBudget::new(DEFAULT_TRANSFER_MEMORY_BUDGET),
+        }
+    }
+
+    pub fn set_memory_budget(&mut self, budget: MemoryBudget) {
+        self.budget = budget;
+    }
+
+    pub async fn start_entry(&mut self, entry: ArchiveEntry) -> Result<(), ArchiveError> {
+        if self.current.is_some() {
+            return Err(ArchiveError::EntryInProgress(entry.path));
+        }
+        let path = entry.archive_path()?;
+        let spool = match entry.size_in_bytes {
+            Some(size) => {
+                self.write_headers(&path, &entry, size).await?;
+                None
+            }
+            None => Some(TarEntrySpool {
+                data: SpooledStream::empty(path.clone(), mime::APPLICATION_OCTET_STREAM),
+                entry: entry.clone(),
+                pending: BytesMut::new(),
+                reservations: Vec::new(),
+            }),
+        };
+        self.current = Some(TarEntryState {
+            path,
+            expected: entry.size_in_bytes,
+            written: 0,
+            spool,
+        });
+        Ok(())
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), ArchiveError> {
+        let current = self.current.as_mut().ok_or(ArchiveError::NoEntry)?;
+        let written = current.written + data.len() as u64;
+        if let Some(expected) = current.expected.filter(|expected| written > *expected) {
+            return Err(ArchiveError::SizeMismatch {
+                path: current.path.clone(),
+                expected,
+                actual: written,
+            });
+        }
+        current.written = written;
+        if let Some(spool) = current.spool.as_mut() {
+            spool.pending.extend_from_slice(data);
+            while spool.pending.len() >= STREAM_SPOOL_CHUNK_SIZE {
+                let chunk = spool.pending.split_to(STREAM_SPOOL_CHUNK_SIZE).freeze();
+                spool.data.push(chunk, &self.budget, &mut spool.reservations).await?;
+            }
+            return Ok(());
+        }
+        self.encoder.write_all(data)?;
+        self.drain().await
+    }
+
+    pub async fn finish_entry(&mut self) -> Result<(), ArchiveError> {
+        let current = self.current.take().ok_or(ArchiveError::NoEntry)?;
+        if let Some(expected) = current.expected.filter(|expected| current.written != *expected) {
+            return Err(ArchiveError::SizeMismatch {
+                path: current.path,
+                expected,
+                actual: current.written,
+            });
+        }
+        if let Some(mut spool) = current.spool {
+            let tail = std::mem::take(&mut spool.pending).freeze();
+            if !tail.is_empty() {
+                spool.data.push(tail, &self.budget, &mut spool.reservations).await?;
+            }
+            self.write_headers(&current.path, &spool.entry, spool.data.size()).await?;
+            let mut offset = 0;
+            while offset < spool.data.size() {
+                let chunk = spool.data.read_at(offset, STREAM_SPOOL_CHUNK_SIZE).await?;
+                self.encoder.write_all(&chunk)?;
+                self.drain().await?;
+                offset += chunk.len() as u64;
+            }
+        }
+        self.encoder.write_all(&[0u8; BLOCK_SIZE][..padding(current.written)])?;
+        self.drain().await
+    }
+
+    /// Ends the archive with two zero blocks and the gzip trailer.
+    pub async fn finish(mut self) -> Result<W, ArchiveError> {
+        if let Some(current) = self.current {
+            return Err(ArchiveError::EntryInProgress(current.path));
+        }
+        self.encoder.write_all(&[0u8; 2 * BLOCK_SIZE])?;
+        let rest = self.encoder.finish()?;
+        self.writer.write_all(&rest).await?;
+        self.writer.flush().await?;
+        Ok(self.writer)
+    }
+
+    async fn write_headers(&mut self, path: &str, entry: &ArchiveEntry, size: u64) -> Result<(), ArchiveError> {
+        for block in entry_headers(path, entry, size) {
+            self.encoder.write_all(&block)?;
+        }
+        self.drain().await
+    }
+
+    async fn drain(&mut self) -> Result<(), ArchiveError> {
+        let buffer = self.encoder.get_mut();
+        if !buffer.is_empty() {
+            self.writer.write_all(buffer).await?;
+            buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+fn padding(size: u64) -> usize {
+    (BLOCK_SIZE - (size % BLOCK_SIZE as u64) as usize) % BLOCK_SIZE
+}
+
+/// The ustar header of `entry`, preceded by a PAX header when the path or size don't fit into it.
+fn entry_headers(path: &str, entry: &ArchiveEntry, size: u64) -> Vec<Vec<u8>> {
+    let mtime = entry.modified.map_or(0, |modified| modified.unix_timestamp().max(0) as u64);
+    let mode = entry.mode.unwrap_or(DEFAULT_MODE) & 0o7777;
+    let split = split_ustar_path(path);
+
+    let mut pax = Vec::new();
+    if split.is_none() {
+        pax.extend(pax_record("path", path));
+    }
+    if size > USTAR_MAX_SIZE {
+        pax.extend(pax_record("size", &size.to_string()));
+    }
+
+    let mut blocks = Vec::new();
+    if !pax.is_empty() {
+        let pax_name = format!("PaxHeaders/{}", truncate(path, USTAR_NAME_LEN - "PaxHeaders/".len()));
+        blocks.push(ustar_header(&pax_name, "", pax.len() as u64, mode, mtime, b'x').to_vec());
+        let padding = padding(pax.len() as u64);
+        pax.resize(pax.len() + padding, 0);
+        blocks.push(pax);
+    }
+    let (prefix, name) = split.unwrap_or(("", truncate(path, USTAR_NAME_LEN)));
+    blocks.push(ustar_header(name, prefix, size.min(USTAR_MAX_SIZE), mode, mtime, b'0').to_vec());
+    blocks
+}
+
+/// Splits `path` into the ustar prefix and name fields at a `/`, `None` when it doesn't fit.
+fn split_ustar_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= USTAR_NAME_LEN {
+        return Some(("", path));
+    }
+    path.match_indices('/')
+        .map(|(index, _)| (&path[..index], &path[index + 1..]))
+        .find(|(prefix, name)| prefix.len() <= USTAR_PREFIX_LEN && name.len() <= USTAR_NAME_LEN && !name.is_empty())
+}
+
+/// The longest prefix of `value` of at most `len` bytes that ends on a char boundary.
+fn truncate(value: &str, len: usize) -> &str {
+    let mut end = value.len().min(len);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// A `<length> <key>=<value>\n` record, the length counts its own digits.
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    format!("{} {}={}\n", len, key, value).into_bytes()
+}
+
+fn ustar_header(name: &str, prefix: &str, size: u64, mode: u32, mtime: u64, kind: u8) -> [u8; BLOCK_SIZE] {
+    let mut header = [0u8; BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], mode as u64);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], size);
+    write_octal(&mut header[136..148], mtime.min(USTAR_MAX_SIZE));
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // The checksum is computed with its own field set to spaces.
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+    header[148..154].copy_from_slice(format!("{:06o}", checksum).as_bytes());
+    header[154] = 0;
+    header
+}
+
+/// Zero padded octal digits followed by a NUL.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    field[..digits].copy_from_slice(format!("{:0width$o}", value, width = digits).as_bytes());
+    field[digits] = 0;
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use super::*;
+    use crate::archive::{ArchiveFormat, ArchiveWriter};
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn test_tar_gz_roundtrip() {
+        let long_path = format!("/{}/report.txt", "nested".repeat(30));
+        // The entry without a size doesn't fit into the budget and is spilled before it is written.
+        let mut writer = ArchiveWriter::new(futures::io::Cursor::new(Vec::new()), ArchiveFormat::TarGz)
+            .with_memory_budget(MemoryBudget::new(0));
+        writer
+            .add_entry(ArchiveEntry::new("/docs/a.txt").with_size(5), &b"hello"[..])
+            .await
+            .unwrap();
+        writer
+            .add_entry(ArchiveEntry::new(long_path.clone()), &b"abc"[..])
+            .await
+            .unwrap();
+        let archive = writer.finish().await.unwrap().into_inner();
+
+        let mut tar = ::tar::Archive::new(flate2::read::GzDecoder::new(archive.as_slice()));
+        let mut files = Vec::new();
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            files.push((entry.path().unwrap().to_string_lossy().into_owned(), content));
+        }
+        assert_eq!(
+            files,
+            vec![
+                ("docs/a.txt".to_string(), "hello".to_string()),
+                (long_path.trim_start_matches('/').to_string(), "abc".to_string()),
+            ]
+        );
+
+        let mut writer = ArchiveWriter::new(futures::io::Cursor::new(Vec::new()), ArchiveFormat::TarGz);
+        assert!(matches!(
+            writer.add_entry(ArchiveEntry::new("/short.txt").with_size(10), &b"abc"[..]).await,
+            Err(ArchiveError::SizeMismatch { expected: 10, actual: 3, .. })
+        ));
+    }
+}
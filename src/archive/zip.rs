@@ -0,0 +1,312 @@
+use super::{ArchiveEntry, ArchiveError};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use futures::{AsyncWrite, AsyncWriteExt};
+use std::io::Write;
+use time::{OffsetDateTime, UtcOffset};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const ZIP64_END_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const END_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+/// 4.5, the first version with zip64.
+const VERSION_NEEDED: u16 = 45;
+/// Unix attributes in the upper half of the external attributes.
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION_NEEDED;
+/// Sizes and CRC follow the data in a descriptor, names are UTF-8.
+const FLAGS: u16 = (1 << 3) | (1 << 11);
+const METHOD_DEFLATE: u16 = 8;
+const DEFAULT_MODE: u32 = 0o644;
+const S_IFREG: u32 = 0o100000;
+
+/// What the central directory needs of an entry that was written.
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    offset: u64,
+    dos_time: u16,
+    dos_date: u16,
+    mode: u32,
+}
+
+struct ZipEntryState {
+    central: CentralEntry,
+    /// The sizes go into the data descriptor, the declared size is only checked when there is one.
+    expected: Option<u64>,
+    data_start: u64,
+    encoder: DeflateEncoder<Vec<u8>>,
+    hasher: crc32fast::Hasher,
+}
+
+/// A zip written front to back: every entry is followed by a data descriptor and all sizes and offsets are
+/// zip64, so neither the sizes nor the total length have to be known up front. Only the central directory,
+/// a few dozen bytes per entry, is kept until `finish`.
+pub struct ZipStreamWriter<W> {
+    writer: W,
+    offset: u64,
+    entries: Vec<CentralEntry>,
+    current: Option<ZipEntryState>,
+}
+
+impl<W: AsyncWrite + Unpin> ZipStreamWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            offset: 0,
+            entries: Vec::new(),
+            current: None,
+        }
+    }
+
+    pub async fn start_entry(&mut self, entry: ArchiveEntry) -> Result<(), ArchiveError> {
+        if self.current.is_some() {
+            return Err(ArchiveError::EntryInProgress(entry.path));
+        }
+        let name = entry.archive_path()?;
+        let (dos_time, dos_date) = dos_date_time(entry.modified.unwrap_or_else(OffsetDateTime::now_utc));
+
+        let mut header = Vec::with_capacity(50 + name.len());
+        put_u32(&mut header, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut header, VERSION_NEEDED);
+        put_u16(&mut header, FLAGS);
+        put_u16(&mut header, METHOD_DEFLATE);
+        put_u16(&mut header, dos_time);
+        put_u16(&mut header, dos_date);
+        put_u32(&mut header, 0);
+        put_u32(&mut header, u32::MAX);
+        put_u32(&mut header, u32::MAX);
+        put_u16(&mut header, name.len() as u16);
+        put_u16(&mut header, 20);
+        header.extend_from_slice(name.as_bytes());
+        // The real sizes are in the data descriptor.
+        put_u16(&mut header, ZIP64_EXTRA_ID);
+        put_u16(&mut header, 16);
+        put_u64(&mut header, 0);
+        put_u64(&mut header, 0);
+
+        let offset = self.offset;
+        self.emit(&header).await?;
+        self.current = Some(ZipEntryState {
+            central: CentralEntry {
+                name,
+                crc: 0,
+                compressed_size: 0,
+                uncompressed_size: 0,
+                offset,
+                dos_time,
+                dos_date,
+                mode: entry.mode.unwrap_or(DEFAULT_MODE) & 0o7777,
+            },
+            expected: entry.size_in_bytes,
+            data_start: self.offset,
+            encoder: DeflateEncoder::new(Vec::new(), Compression::default()),
+            hasher: crc32fast::Hasher::new(),
+        });
+        Ok(())
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), ArchiveError> {
+        let current = self.current.as_mut().ok_or(ArchiveError::NoEntry)?;
+        let written = current.central.uncompressed_size + data.len() as u64;
+        if let Some(expected) = current.expected.filter(|expected| written > *expected) {
+            return Err(ArchiveError::SizeMismatch {
+                path: current.central.name.clone(),
+                expected,
+                actual: written,
+            });
+        }
+        current.central.uncompressed_size = written;
+        current.hasher.update(data);
+        current.encoder.write_all(data)?;
+        let compressed = std::mem::take(current.encoder.get_mut());
+        self.emit(&compressed).await
+    }
+
+    pub async fn finish_entry(&mut self) -> Result<(), ArchiveError> {
+        let current = self.current.take().ok_or(ArchiveError::NoEntry)?;
+        let ZipEntryState {
+            mut central,
+            expected,
+            data_start,
+            encoder,
+            hasher,
+        } = current;
+        if let Some(expected) = expected.filter(|expected| central.uncompressed_size != *expected) {
+            return Err(ArchiveError::SizeMismatch {
+                path: central.name,
+                expected,
+                actual: central.uncompressed_size,
+            });
+        }
+        let compressed = encoder.finish()?;
+        self.emit(&compressed).await?;
+        central.crc = hasher.finalize();
+        central.compressed_size = self.offset - data_start;
+
+        let mut descriptor = Vec::with_capacity(24);
+        put_u32(&mut descriptor, DATA_DESCRIPTOR_SIGNATURE);
+        put_u32(&mut descriptor, central.crc);
+        put_u64(&mut descriptor, central.compressed_size);
+        put_u64(&mut descriptor, central.uncompressed_size);
+        self.emit(&descriptor).await?;
+        self.entries.push(central);
+        Ok(())
+    }
+
+    /// Writes the central directory and the zip64 and classic end records.
+    pub async fn finish(mut self) -> Result<W, ArchiveError> {
+        if let Some(current) = self.current {
+            return Err(ArchiveError::EntryInProgress(current.central.name));
+        }
+        let directory_offset = self.offset;
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            put_u32(&mut directory, CENTRAL_HEADER_SIGNATURE);
+            put_u16(&mut directory, VERSION_MADE_BY);
+            put_u16(&mut directory, VERSION_NEEDED);
+            put_u16(&mut directory, FLAGS);
+            put_u16(&mut directory, METHOD_DEFLATE);
+            put_u16(&mut directory, entry.dos_time);
+            put_u16(&mut directory, entry.dos_date);
+            put_u32(&mut directory, entry.crc);
+            put_u32(&mut directory, u32::MAX);
+            put_u32(&mut directory, u32::MAX);
+            put_u16(&mut directory, entry.name.len() as u16);
+            put_u16(&mut directory, 28);
+            put_u16(&mut directory, 0);
+            put_u16(&mut directory, 0);
+            put_u16(&mut directory, 0);
+            put_u32(&mut directory, (S_IFREG | entry.mode) << 16);
+            put_u32(&mut directory, u32::MAX);
+            directory.extend_from_slice(entry.name.as_bytes());
+            put_u16(&mut directory, ZIP64_EXTRA_ID);
+            put_u16(&mut directory, 24);
+            put_u64(&mut directory, entry.uncompressed_size);
+            put_u64(&mut directory, entry.compressed_size);
+            put_u64(&mut directory, entry.offset);
+        }
+        let directory_size = directory.len() as u64;
+        let count = self.entries.len() as u64;
+        let zip64_end_offset = directory_offset + directory_size;
+
+        put_u32(&mut directory, ZIP64_END_SIGNATURE);
+        put_u64(&mut directory, 44);
+        put_u16(&mut directory, VERSION_MADE_BY);
+        put_u16(&mut directory, VERSION_NEEDED);
+        put_u32(&mut directory, 0);
+        put_u32(&mut directory, 0);
+        put_u64(&mut directory, count);
+        put_u64(&mut directory, count);
+        put_u64(&mut directory, directory_size);
+        put_u64(&mut directory, directory_offset);
+
+        put_u32(&mut directory, ZIP64_LOCATOR_SIGNATURE);
+        put_u32(&mut directory, 0);
+        put_u64(&mut directory, zip64_end_offset);
+        put_u32(&mut directory, 1);
+
+        put_u32(&mut directory, END_SIGNATURE);
+        put_u16(&mut directory, 0);
+        put_u16(&mut directory, 0);
+        put_u16(&mut directory, count.min(u16::MAX as u64) as u16);
+        put_u16(&mut directory, count.min(u16::MAX as u64) as u16);
+        put_u32(&mut directory, directory_size.min(u32::MAX as u64) as u32);
+        put_u32(&mut directory, directory_offset.min(u32::MAX as u64) as u32);
+        put_u16(&mut directory, 0);
+
+        self.emit(&directory).await?;
+        self.writer.flush().await?;
+        Ok(self.writer)
+    }
+
+    async fn emit(&mut self, bytes: &[u8]) -> Result<(), ArchiveError> {
+        if !bytes.is_empty() {
+            self.writer.write_all(bytes).await?;
+            self.offset += bytes.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+/// MS-DOS time and date in UTC, clamped to the 1980 to 2107 range they can hold.
+fn dos_date_time(modified: OffsetDateTime) -> (u16, u16) {
+    let modified = modified.to_offset(UtcOffset::UTC);
+    let (year, month, day, hour, minute, second) = match modified.year() {
+        year if year < 1980 => (1980, 1, 1, 0, 0, 0),
+        year if year > 2107 => (2107, 12, 31, 23, 59, 58),
+        year => (
+            year,
+            u8::from(modified.month()),
+            modified.day(),
+            modified.hour(),
+            modified.minute(),
+            modified.second(),
+        ),
+    };
+    let time = ((hour as u16) << 11) | ((minute as u16) << 5) | (second as u16 / 2);
+    let date = (((year - 1980) as u16) << 9) | ((month as u16) << 5) | day as u16;
+    (time, date)
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use super::*;
+    use crate::archive::{ArchiveFormat, ArchiveWriter};
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn test_zip_layout() {
+        let content = b"client side archive ".repeat(100);
+        let mut entry = ArchiveEntry::new("/docs/notes.txt");
+        entry.modified = Some(OffsetDateTime::from_unix_timestamp(1_790_812_800).unwrap());
+        let mut writer = ArchiveWriter::new(futures::io::Cursor::new(Vec::new()), ArchiveFormat::Zip);
+        writer.add_entry(entry, content.as_slice()).await.unwrap();
+        let archive = writer.finish().await.unwrap().into_inner();
+
+        let u16_at = |at: usize| u16::from_le_bytes(archive[at..at + 2].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_le_bytes(archive[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(archive[at..at + 8].try_into().unwrap());
+        assert_eq!(u32_at(0), LOCAL_HEADER_SIGNATURE);
+        assert_eq!(&archive[30..44], b"docs/notes.txt");
+
+        let data_start = 30 + 14 + 20;
+        let end = archive.len() - 22;
+        assert_eq!(u32_at(end), END_SIGNATURE);
+        assert_eq!(u16_at(end + 10), 1);
+        let directory_offset = u32_at(end + 16) as usize;
+        assert_eq!(u32_at(directory_offset), CENTRAL_HEADER_SIGNATURE);
+        let compressed_size = u64_at(directory_offset + 46 + 14 + 4 + 8) as usize;
+        assert_eq!(u64_at(directory_offset + 46 + 14 + 4), content.len() as u64);
+        assert_eq!(u32_at(data_start + compressed_size), DATA_DESCRIPTOR_SIGNATURE);
+
+        let mut inflated = Vec::new();
+        flate2::read::DeflateDecoder::new(&archive[data_start..data_start + compressed_size])
+            .read_to_end(&mut inflated)
+            .unwrap();
+        assert_eq!(inflated, content);
+        assert_eq!(u32_at(directory_offset + 16), crc32fast::hash(&content));
+
+        let mut writer = ArchiveWriter::new(futures::io::Cursor::new(Vec::new()), ArchiveFormat::Zip);
+        assert!(matches!(
+            writer.add_entry(ArchiveEntry::new("/short.txt").with_size(2), &b"abc"[..]).await,
+            Err(ArchiveError::SizeMismatch { expected: 2, actual: 3, .. })
+        ));
+    }
+}
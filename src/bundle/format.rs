@@ -0,0 +1,273 @@
+use crate::bundle::BundleError;
+use crate::io::file::VirtualFileDetails;
+use aes_gcm::aead::{Aead, AeadCore, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use std::io::{Read, Write};
+
+/// Layout: `[file data][index][index length: u64 LE][flags: u8][magic]`.
+/// In an encrypted bundle every file is stored as `[nonce][ciphertext][tag]`, so it can still be read on its own.
+pub const BUNDLE_MAGIC: &[u8; 7] = b"BKTBNDL";
+pub const TRAILER_SIZE: usize = 16;
+pub const INDEX_VERSION: u32 = 1;
+const FLAG_ENCRYPTED_INDEX: u8 = 1;
+const FLAG_ENCRYPTED_DATA: u8 = 2;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+/// Bytes an encrypted file takes in the bundle on top of its plaintext.
+pub const ENCRYPTED_ENTRY_OVERHEAD: u64 = (NONCE_SIZE + TAG_SIZE) as u64;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BundleEntry {
+    pub path: String,
+    /// Where the stored file starts in the bundle.
+    pub offset: u64,
+    /// Size of the plaintext.
+    pub length: u64,
+}
+
+impl BundleEntry {
+    /// Bytes the file takes in the bundle.
+    pub fn stored_length(&self, encrypted: bool) -> u64 {
+        match encrypted {
+            true => self.length + ENCRYPTED_ENTRY_OVERHEAD,
+            false => self.length,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BundleIndex {
+    pub version: u32,
+    pub entries: Vec<BundleEntry>,
+}
+
+impl BundleIndex {
+    pub fn find(&self, path: &str) -> Option<&BundleEntry> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    /// The files in the bundle as they are shown in listings.
+    pub fn virtual_files(&self) -> Vec<VirtualFileDetails> {
+        self.entries
+            .iter()
+            .map(|entry| VirtualFileDetails {
+                path: entry.path.clone(),
+                date: None,
+                size_in_bytes: entry.length,
+                local_target: None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trailer {
+    pub index_len: u64,
+    pub encrypted_index: bool,
+    pub encrypted_data: bool,
+}
+
+impl Trailer {
+    pub fn encode(&self) -> [u8; TRAILER_SIZE] {
+        let mut trailer = [0u8; TRAILER_SIZE];
+        trailer[..8].copy_from_slice(&self.index_len.to_le_bytes());
+        if self.encrypted_index {
+            trailer[8] |= FLAG_ENCRYPTED_INDEX;
+        }
+        if self.encrypted_data {
+            trailer[8] |= FLAG_ENCRYPTED_DATA;
+        }
+        trailer[9..].copy_from_slice(BUNDLE_MAGIC);
+        trailer
+    }
+
+    pub fn decode(trailer: &[u8]) -> Result<Self, BundleError> {
+        if trailer.len() != TRAILER_SIZE || &trailer[9..] != BUNDLE_MAGIC {
+            return Err(BundleError::InvalidTrailer);
+        }
+        Ok(Self {
+            index_len: u64::from_le_bytes(trailer[..8].try_into().unwrap()),
+            encrypted_index: trailer[8] & FLAG_ENCRYPTED_INDEX != 0,
+            encrypted_data: trailer[8] & FLAG_ENCRYPTED_DATA != 0,
+        })
+    }
+}
+
+/// The index is lz4 compressed, and encrypted when the bucket is, as the file names are not public.
+pub fn encode_index(index: &BundleIndex, key: Option<&Aes256Gcm>) -> Result<Vec<u8>, BundleError> {
+    let compressed = lz4_flex::block::compress_prepend_size(&serde_json::to_vec(index)?);
+    let Some(cipher) = key else {
+        return Ok(compressed);
+    };
+    // Every bundle gets a fresh random nonce, so the same key can be used for all bundles of a bucket.
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: &compressed, aad: BUNDLE_MAGIC })
+        .map_err(|_| BundleError::IndexEncryptionError)?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+/// The path is authenticated with the data, a file can't be swapped for another one of the same bundle.
+fn encrypt_entry(cipher: &Aes256Gcm, path: &str, data: &[u8]) -> Result<Vec<u8>, BundleError> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: data, aad: path.as_bytes() })
+        .map_err(|_| BundleError::DataEncryptionError)?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+/// Decrypts a file read from an encrypted bundle, `stored` holds the `stored_length` bytes at its offset.
+pub fn decrypt_entry(cipher: &Aes256Gcm, entry: &BundleEntry, stored: &[u8]) -> Result<Vec<u8>, BundleError> {
+    if stored.len() as u64 != entry.stored_length(true) {
+        return Err(BundleError::DataDecryptionError);
+    }
+    let (nonce, ciphertext) = stored.split_at(NONCE_SIZE);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: entry.path.as_bytes() })
+        .map_err(|_| BundleError::DataDecryptionError)
+}
+
+pub fn decode_index(blob: &[u8], encrypted: bool, key: Option<&Aes256Gcm>) -> Result<BundleIndex, BundleError> {
+    let compressed = if encrypted {
+        let cipher = key.ok_or(BundleError::MissingIndexKey)?;
+        if blob.len() < NONCE_SIZE {
+            return Err(BundleError::IndexDecryptionError);
+        }
+        let (nonce, ciphertext) = blob.split_at(NONCE_SIZE);
+        cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: BUNDLE_MAGIC })
+            .map_err(|_| BundleError::IndexDecryptionError)?
+    } else {
+        blob.to_vec()
+    };
+    Ok(serde_json::from_slice(&lz4_flex::block::decompress_size_prepended(&compressed)?)?)
+}
+
+/// Writes files one after another, and the index once the bundle is finished.
+pub struct BundleWriter<W: Write> {
+    writer: W,
+    offset: u64,
+    index: BundleIndex,
+    key: Option<Aes256Gcm>,
+}
+
+impl<W: Write> BundleWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            offset: 0,
+            index: BundleIndex {
+                version: INDEX_VERSION,
+                entries: Vec::new(),
+            },
+            key: None,
+        }
+    }
+
+    /// Encrypts the files and the index, must be set for encrypted buckets as file names and content are private.
+    pub fn with_key(mut self, key: Aes256Gcm) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// The underlying writer, e.g. to take what has been written so far.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Bytes of file data written so far.
+    pub fn data_size(&self) -> u64 {
+        self.offset
+    }
+
+    /// An encrypted file is read into memory to be sealed at once, bundles are meant for small files.
+    pub fn add_file<R: Read>(&mut self, path: impl Into<String>, mut reader: R) -> Result<&BundleEntry, BundleError> {
+        let path = path.into();
+        let (length, stored) = match &self.key {
+            Some(cipher) => {
+                let mut data = Vec::new();
+                reader.read_to_end(&mut data)?;
+                let sealed = encrypt_entry(cipher, &path, &data)?;
+                self.writer.write_all(&sealed)?;
+                (data.len() as u64, sealed.len() as u64)
+            }
+            None => {
+                let length = std::io::copy(&mut reader, &mut self.writer)?;
+                (length, length)
+            }
+        };
+        self.index.entries.push(BundleEntry {
+            path,
+            offset: self.offset,
+            length,
+        });
+        self.offset += stored;
+        Ok(self.index.entries.last().unwrap())
+    }
+
+    /// Appends the index and trailer, returns the writer, the index and the total size of the bundle.
+    pub fn finish(mut self) -> Result<(W, BundleIndex, u64), BundleError> {
+        let index = encode_index(&self.index, self.key.as_ref())?;
+        let trailer = Trailer {
+            index_len: index.len() as u64,
+            encrypted_index: self.key.is_some(),
+            encrypted_data: self.key.is_some(),
+        };
+        self.writer.write_all(&index)?;
+        self.writer.write_all(&trailer.encode())?;
+        self.writer.flush()?;
+        let total = self.offset + index.len() as u64 + TRAILER_SIZE as u64;
+        Ok((self.writer, self.index, total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::KeyInit;
+
+    fn write_bundle(key: Option<&Aes256Gcm>) -> Vec<u8> {
+        let mut writer = BundleWriter::new(Vec::new());
+        if let Some(key) = key {
+            writer = writer.with_key(key.clone());
+        }
+        writer.add_file("a.txt", &b"hello"[..]).unwrap();
+        writer.add_file("dir/b.txt", &b""[..]).unwrap();
+        writer.add_file("dir/c.txt", &b"world!"[..]).unwrap();
+        let (bundle, _, total) = writer.finish().unwrap();
+        assert_eq!(bundle.len() as u64, total);
+        bundle
+    }
+
+    fn read_index(bundle: &[u8], key: Option<&Aes256Gcm>) -> Result<BundleIndex, BundleError> {
+        let trailer = Trailer::decode(&bundle[bundle.len() - TRAILER_SIZE..])?;
+        let index_end = bundle.len() - TRAILER_SIZE;
+        let index_start = index_end - trailer.index_len as usize;
+        decode_index(&bundle[index_start..index_end], trailer.encrypted_index, key)
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let bundle = write_bundle(None);
+        let index = read_index(&bundle, None).unwrap();
+        let entry = index.find("dir/c.txt").unwrap();
+        assert_eq!(&bundle[entry.offset as usize..(entry.offset + entry.length) as usize], b"world!");
+        assert_eq!(index.find("dir/b.txt").unwrap().length, 0);
+    }
+
+    #[test]
+    fn test_encrypted_index() {
+        let key = Aes256Gcm::new_from_slice(&[5u8; 32]).unwrap();
+        let bundle = write_bundle(Some(&key));
+        assert!(matches!(read_index(&bundle, None), Err(BundleError::MissingIndexKey)));
+        let index = read_index(&bundle, Some(&key)).unwrap();
+        assert_eq!(index.entries.len(), 3);
+        assert!(!bundle.windows(6).any(|window| window == b"world!"));
+        let entry = index.find("dir/c.txt").unwrap();
+        let stored = &bundle[entry.offset as usize..(entry.offset + entry.stored_length(true)) as usize];
+        assert_eq!(decrypt_entry(&key, entry, stored).unwrap(), b"world!");
+
+        let other = Aes256Gcm::new_from_slice(&[6u8; 32]).unwrap();
+        assert!(matches!(read_index(&bundle, Some(&other)), Err(BundleError::IndexDecryptionError)));
+    }
+}
@@ -0,0 +1,122 @@
+//! Packs many small files into a few large bundle objects, so they can be uploaded with a handful of
+//! presigned URLs instead of one per file. The bundle ends with an index describing where every file is,
+//! which lets single files be downloaded with a range GET.
+
+pub mod format;
+pub mod remote;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BundleError {
+    #[error("Not a bundle object")]
+    InvalidTrailer,
+    #[error("Bundle index is encrypted but no key was given")]
+    MissingIndexKey,
+    #[error("Failed to encrypt bundle index")]
+    IndexEncryptionError,
+    #[error("Failed to decrypt bundle index")]
+    IndexDecryptionError,
+    #[error("Failed to encrypt a bundled file")]
+    DataEncryptionError,
+    #[error("Failed to decrypt a bundled file")]
+    DataDecryptionError,
+    #[error("Bundles of an encrypted bucket need a key")]
+    MissingBundleKey,
+    #[error("Failed to decompress bundle index")]
+    IndexDecompressionError(#[from] lz4_flex::block::DecompressError),
+    #[error("File {0} is not in the bundle")]
+    FileNotFound(String),
+    #[error("Range request returned {actual} bytes, expected {expected} bytes")]
+    ShortRead { expected: u64, actual: u64 },
+    #[error("Http request failed: {0}")]
+    HttpError(String),
+    #[error(transparent)]
+    SerializationError(#[from] serde_json::Error),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// Bundle objects are uploaded next to the files, under a name starting with this prefix.
+pub const BUNDLE_OBJECT_PREFIX: &str = ".bundle-";
+
+/// Path of a new bundle object in `directory`.
+pub fn bundle_object_path(directory: &str) -> String {
+    format!("{}/{}{}", directory.trim_end_matches('/'), BUNDLE_OBJECT_PREFIX, uuid::Uuid::new_v4())
+}
+
+/// Decides which files are packed and how large bundles get.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BundlePolicy {
+    /// Files larger than this are uploaded as their own object.
+    pub max_file_size: u64,
+    /// A bundle is closed once it reaches this size.
+    pub target_bundle_size: u64,
+    pub max_files_per_bundle: usize,
+}
+
+impl Default for BundlePolicy {
+    fn default() -> Self {
+        Self {
+            max_file_size: 1024 * 1024,
+            target_bundle_size: 64 * 1024 * 1024,
+            max_files_per_bundle: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BundlePlan {
+    /// Indices into the planned files, one list per bundle object.
+    pub bundles: Vec<Vec<usize>>,
+    /// Files uploaded on their own.
+    pub standalone: Vec<usize>,
+}
+
+/// Groups the small files of an upload into bundles, `sizes` holds the size of every file to upload.
+pub fn plan_bundles(sizes: &[u64], policy: &BundlePolicy) -> BundlePlan {
+    let mut plan = BundlePlan::default();
+    let mut current = Vec::new();
+    let mut current_size = 0u64;
+    for (index, size) in sizes.iter().copied().enumerate() {
+        if size > policy.max_file_size {
+            plan.standalone.push(index);
+            continue;
+        }
+        if !current.is_empty()
+            && (current_size + size > policy.target_bundle_size || current.len() >= policy.max_files_per_bundle)
+        {
+            plan.bundles.push(std::mem::take(&mut current));
+            current_size = 0;
+        }
+        current.push(index);
+        current_size += size;
+    }
+    if !current.is_empty() {
+        plan.bundles.push(current);
+    }
+    // A bundle of one file is only overhead.
+    let (singles, bundles): (Vec<_>, Vec<_>) = plan.bundles.into_iter().partition(|bundle| bundle.len() == 1);
+    plan.bundles = bundles;
+    plan.standalone.extend(singles.into_iter().flatten());
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_bundles() {
+        let policy = BundlePolicy {
+            max_file_size: 100,
+            target_bundle_size: 250,
+            max_files_per_bundle: 3,
+        };
+        let plan = plan_bundles(&[10, 500, 100, 100, 100, 20, 30, 40, 50], &policy);
+        assert_eq!(plan.bundles, vec![vec![0, 2, 3], vec![4, 5, 6], vec![7, 8]]);
+        assert_eq!(plan.standalone, vec![1]);
+
+        let plan = plan_bundles(&[10, 500], &policy);
+        assert!(plan.bundles.is_empty());
+        assert_eq!(plan.standalone, vec![1, 0]);
+    }
+}
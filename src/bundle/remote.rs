@@ -0,0 +1,87 @@
+use crate::bundle::format::{decode_index, decrypt_entry, BundleEntry, BundleIndex, Trailer, TRAILER_SIZE};
+use crate::bundle::BundleError;
+use crate::client::http::{ByteRange, HttpDownloadClientExt};
+use crate::token::ApiToken;
+use aes_gcm::Aes256Gcm;
+use bytes::Bytes;
+use url::Url;
+
+/// A bundle object in the bucket, only the index is fetched when opening it.
+pub struct RemoteBundle {
+    url: Url,
+    index: BundleIndex,
+    /// Set when the files of the bundle are encrypted.
+    cipher: Option<Aes256Gcm>,
+}
+
+async fn fetch<HTTP: HttpDownloadClientExt>(
+    http_client: &HTTP,
+    url: &Url,
+    api_token: &ApiToken,
+    range: ByteRange,
+    expected: u64,
+) -> Result<Bytes, BundleError> {
+    let resp = http_client
+        .get_range(url.clone(), api_token, range)
+        .await
+        .map_err(|e| BundleError::HttpError(format!("{:?}", e)))?;
+    if resp.bytes.len() as u64 != expected {
+        return Err(BundleError::ShortRead {
+            expected,
+            actual: resp.bytes.len() as u64,
+        });
+    }
+    Ok(resp.bytes)
+}
+
+impl RemoteBundle {
+    /// Reads the trailer and then the index with two range requests.
+    pub async fn open<HTTP: HttpDownloadClientExt>(
+        http_client: &HTTP,
+        url: Url,
+        api_token: &ApiToken,
+        key: Option<&Aes256Gcm>,
+    ) -> Result<Self, BundleError> {
+        let trailer = fetch(http_client, &url, api_token, ByteRange::Suffix(TRAILER_SIZE as u64), TRAILER_SIZE as u64).await?;
+        let trailer = Trailer::decode(&trailer)?;
+        let index_range = ByteRange::Suffix(trailer.index_len + TRAILER_SIZE as u64);
+        let index = fetch(http_client, &url, api_token, index_range, trailer.index_len + TRAILER_SIZE as u64).await?;
+        let index = decode_index(&index[..trailer.index_len as usize], trailer.encrypted_index, key)?;
+        let cipher = match trailer.encrypted_data {
+            true => Some(key.ok_or(BundleError::MissingIndexKey)?.clone()),
+            false => None,
+        };
+        Ok(Self { url, index, cipher })
+    }
+
+    pub fn index(&self) -> &BundleIndex {
+        &self.index
+    }
+
+    pub fn entry(&self, path: &str) -> Result<&BundleEntry, BundleError> {
+        self.index.find(path).ok_or_else(|| BundleError::FileNotFound(path.to_string()))
+    }
+
+    /// Downloads a single file out of the bundle.
+    pub async fn read_file<HTTP: HttpDownloadClientExt>(
+        &self,
+        http_client: &HTTP,
+        api_token: &ApiToken,
+        path: &str,
+    ) -> Result<Bytes, BundleError> {
+        let entry = self.entry(path)?;
+        if entry.length == 0 {
+            return Ok(Bytes::new());
+        }
+        let stored_length = entry.stored_length(self.cipher.is_some());
+        let range = ByteRange::Bounded {
+            start: entry.offset,
+            end: entry.offset + stored_length,
+        };
+        let stored = fetch(http_client, &self.url, api_token, range, stored_length).await?;
+        match &self.cipher {
+            Some(cipher) => Ok(Bytes::from(decrypt_entry(cipher, entry, &stored)?)),
+            None => Ok(stored),
+        }
+    }
+}
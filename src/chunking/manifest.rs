@@ -0,0 +1,55 @@
+use crate::chunking::{ChunkId, ChunkingConfig, ChunkingError};
+use std::collections::BTreeSet;
+
+pub const CHUNK_MANIFEST_VERSION: u32 = 1;
+
+/// Position of a chunk within the file it was split from.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ChunkRef {
+    pub id: ChunkId,
+    pub offset: u64,
+    pub length: u32,
+}
+
+/// Describes how a file is reassembled from the chunks stored in the bucket.
+/// Uploaded in place of the file content when the file is chunked.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ChunkManifest {
+    pub version: u32,
+    pub file_size: u64,
+    pub config: ChunkingConfig,
+    pub chunks: Vec<ChunkRef>,
+}
+
+impl ChunkManifest {
+    pub fn new(config: ChunkingConfig) -> Self {
+        Self {
+            version: CHUNK_MANIFEST_VERSION,
+            file_size: 0,
+            config,
+            chunks: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, id: ChunkId, length: u32) {
+        self.chunks.push(ChunkRef {
+            id,
+            offset: self.file_size,
+            length,
+        });
+        self.file_size += length as u64;
+    }
+
+    /// Chunk ids without duplicates, a file repeating content references the same chunk more than once.
+    pub fn unique_chunks(&self) -> BTreeSet<ChunkId> {
+        self.chunks.iter().map(|chunk| chunk.id).collect()
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>, ChunkingError> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, ChunkingError> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
@@ -0,0 +1,304 @@
+use crate::encryption::key::derived_key::EncryptionDerivedKey;
+use crate::encryption::key::master_key::MasterKey;
+use core::slice::SlicePattern;
+use fastcdc::v2020::{FastCDC, StreamCDC};
+use highway::{HighwayHash, HighwayHasher};
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+use uuid::Uuid;
+
+pub mod manifest;
+pub mod store;
+
+/// Domain separation for the chunk id key, so it never equals a key used for encryption.
+const CHUNK_ID_KEY_CONTEXT: &[u8] = b"bucket-sdk chunk id";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChunkingError {
+    #[error("Invalid chunk sizes, expected min <= avg <= max within the FastCDC limits")]
+    InvalidChunkSizes,
+    #[error("Chunk {0} is not in the store")]
+    ChunkNotFound(ChunkId),
+    #[error("Chunk {0} does not match its id")]
+    ChunkHashMismatch(ChunkId),
+    #[error("Reassembled {actual} bytes but the manifest describes {expected} bytes")]
+    SizeMismatch { expected: u64, actual: u64 },
+    #[error("Invalid chunk id")]
+    InvalidChunkId,
+    #[error("Reading the source failed: {0}")]
+    SourceError(#[from] crate::io::FileError),
+    #[error("Chunk store error: {0}")]
+    StoreError(Box<dyn std::error::Error + Send + Sync>),
+    #[error(transparent)]
+    ManifestError(#[from] serde_json::Error),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+impl From<fastcdc::v2020::Error> for ChunkingError {
+    fn from(error: fastcdc::v2020::Error) -> Self {
+        match error {
+            fastcdc::v2020::Error::IoError(error) => ChunkingError::IoError(error),
+            other => ChunkingError::IoError(std::io::Error::other(other)),
+        }
+    }
+}
+
+/// FastCDC chunk size bounds in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ChunkingConfig {
+    pub min_size: u32,
+    pub avg_size: u32,
+    pub max_size: u32,
+}
+
+impl ChunkingConfig {
+    pub fn new(min_size: u32, avg_size: u32, max_size: u32) -> Result<Self, ChunkingError> {
+        use fastcdc::v2020::{AVERAGE_MAX, AVERAGE_MIN, MAXIMUM_MAX, MAXIMUM_MIN, MINIMUM_MAX, MINIMUM_MIN};
+        let in_limits = (MINIMUM_MIN..=MINIMUM_MAX).contains(&min_size)
+            && (AVERAGE_MIN..=AVERAGE_MAX).contains(&avg_size)
+            && (MAXIMUM_MIN..=MAXIMUM_MAX).contains(&max_size);
+        if !in_limits || min_size > avg_size || avg_size > max_size {
+            return Err(ChunkingError::InvalidChunkSizes);
+        }
+        Ok(Self {
+            min_size,
+            avg_size,
+            max_size,
+        })
+    }
+}
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        Self {
+            min_size: 256 * 1024,
+            avg_size: 1024 * 1024,
+            max_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+/// Content address of a chunk, a keyed 256-bit HighwayHash of its plaintext.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChunkId(pub [u8; 32]);
+
+impl fmt::Display for ChunkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ChunkId {
+    type Err = ChunkingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 64 || !s.is_ascii() {
+            return Err(ChunkingError::InvalidChunkId);
+        }
+        let mut id = [0u8; 32];
+        for (i, byte) in id.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).map_err(|_| ChunkingError::InvalidChunkId)?;
+        }
+        Ok(Self(id))
+    }
+}
+
+impl serde::Serialize for ChunkId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ChunkId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Computes chunk ids with a key that is unique per bucket.
+/// The key is derived from the user's master key, so the server cannot confirm whether a bucket holds a known
+/// piece of content, and identical content in buckets of different users gets unrelated ids.
+#[derive(Clone)]
+pub struct ChunkHasher {
+    key: highway::Key,
+}
+
+impl ChunkHasher {
+    pub fn new(key: highway::Key) -> Self {
+        Self { key }
+    }
+
+    pub fn for_bucket(master_key: &MasterKey, bucket_id: Uuid) -> Self {
+        let derived = EncryptionDerivedKey::new(master_key, &[CHUNK_ID_KEY_CONTEXT, bucket_id.as_bytes()].concat());
+        Self::new(highway::Key(bytemuck::pod_read_unaligned::<[u64; 4]>(derived.as_slice())))
+    }
+
+    pub fn chunk_id(&self, data: &[u8]) -> ChunkId {
+        let mut hasher = HighwayHasher::new(self.key);
+        hasher.append(data);
+        let mut id = [0u8; 32];
+        id.copy_from_slice(bytemuck::bytes_of(&hasher.finalize256()));
+        ChunkId(id)
+    }
+}
+
+/// A content defined chunk of a file.
+pub struct Chunk {
+    pub id: ChunkId,
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+/// Splits `reader` into content defined chunks, so an insert or removal only changes the chunks around the edit.
+pub fn split<R: Read>(
+    reader: R,
+    config: ChunkingConfig,
+    hasher: &ChunkHasher,
+) -> impl Iterator<Item = Result<Chunk, ChunkingError>> + '_ {
+    StreamCDC::new(reader, config.min_size, config.avg_size, config.max_size).map(move |chunk| {
+        let chunk = chunk?;
+        Ok(Chunk {
+            id: hasher.chunk_id(&chunk.data),
+            offset: chunk.offset,
+            data: chunk.data,
+        })
+    })
+}
+
+/// Splits data into content defined chunks as it arrives, at most `max_size` bytes are buffered.
+/// Produces the same chunks as [`split`] over the concatenated input.
+pub struct ChunkSplitter {
+    config: ChunkingConfig,
+    hasher: ChunkHasher,
+    buffer: Vec<u8>,
+    offset: u64,
+}
+
+impl ChunkSplitter {
+    pub fn new(config: ChunkingConfig, hasher: ChunkHasher) -> Self {
+        Self {
+            config,
+            hasher,
+            buffer: Vec::with_capacity(config.max_size as usize),
+            offset: 0,
+        }
+    }
+
+    /// Appends `data` and returns the chunks whose end is known. A boundary is only certain once `max_size`
+    /// bytes follow the start of the chunk, the rest stays buffered.
+    pub fn push(&mut self, mut data: &[u8]) -> Vec<Chunk> {
+        let max_size = self.config.max_size as usize;
+        let mut chunks = Vec::new();
+        while !data.is_empty() {
+            let take = (max_size - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() == max_size {
+                chunks.push(self.cut());
+            }
+        }
+        chunks
+    }
+
+    /// Returns the chunks still buffered, at the end of the input.
+    pub fn finish(&mut self) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        while !self.buffer.is_empty() {
+            chunks.push(self.cut());
+        }
+        chunks
+    }
+
+    fn cut(&mut self) -> Chunk {
+        let length = FastCDC::new(&self.buffer, self.config.min_size, self.config.avg_size, self.config.max_size)
+            .next()
+            .map_or(self.buffer.len(), |chunk| chunk.length);
+        let data: Vec<u8> = self.buffer.drain(..length).collect();
+        let chunk = Chunk {
+            id: self.hasher.chunk_id(&data),
+            offset: self.offset,
+            data,
+        };
+        self.offset += length as u64;
+        chunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_data(len: usize, seed: u64) -> Vec<u8> {
+        // xorshift, enough entropy for the rolling hash to find boundaries.
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn small_config() -> ChunkingConfig {
+        ChunkingConfig::new(1024, 4096, 16384).unwrap()
+    }
+
+    #[test]
+    fn test_insert_keeps_most_chunks() {
+        let hasher = ChunkHasher::new(highway::Key([1, 2, 3, 4]));
+        let original = test_data(512 * 1024, 42);
+        let mut edited = original.clone();
+        edited.splice(100_000..100_000, b"inserted".iter().copied());
+
+        let ids = |data: &[u8]| {
+            split(data, small_config(), &hasher)
+                .map(|chunk| chunk.unwrap().id)
+                .collect::<Vec<_>>()
+        };
+        let before = ids(&original);
+        let after = ids(&edited);
+        let shared = after.iter().filter(|id| before.contains(id)).count();
+        assert!(shared + 3 >= before.len(), "only {shared} of {} chunks shared", before.len());
+    }
+
+    #[test]
+    fn test_chunk_id_is_keyed() {
+        let data = test_data(4096, 7);
+        let a = ChunkHasher::new(highway::Key([1, 2, 3, 4])).chunk_id(&data);
+        let b = ChunkHasher::new(highway::Key([5, 6, 7, 8])).chunk_id(&data);
+        assert_ne!(a, b);
+        assert_eq!(a.to_string().parse::<ChunkId>().unwrap(), a);
+    }
+
+    #[test]
+    fn test_splitter_matches_split() {
+        let hasher = ChunkHasher::new(highway::Key([1, 2, 3, 4]));
+        let data = test_data(256 * 1024, 11);
+        let expected: Vec<_> = split(data.as_slice(), small_config(), &hasher)
+            .map(|chunk| chunk.map(|chunk| (chunk.id, chunk.offset)).unwrap())
+            .collect();
+
+        let mut splitter = ChunkSplitter::new(small_config(), hasher.clone());
+        let mut chunks = Vec::new();
+        for part in data.chunks(1000) {
+            chunks.extend(splitter.push(part));
+        }
+        chunks.extend(splitter.finish());
+        let actual: Vec<_> = chunks.iter().map(|chunk| (chunk.id, chunk.offset)).collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(ChunkingConfig::new(8192, 4096, 16384).is_err());
+        assert!(ChunkingConfig::new(1, 4096, 16384).is_err());
+    }
+}
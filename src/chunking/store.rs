@@ -0,0 +1,213 @@
+use crate::chunking::manifest::ChunkManifest;
+use crate::chunking::{Chunk, ChunkHasher, ChunkId, ChunkSplitter, ChunkingConfig, ChunkingError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Read, Write};
+
+/// Where chunks are kept, addressed by their id.
+#[async_trait(?Send)]
+pub trait ChunkStore {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Returns the subset of `ids` the store does not have yet.
+    async fn missing_chunks(&self, ids: &BTreeSet<ChunkId>) -> Result<BTreeSet<ChunkId>, Self::Error>;
+    async fn put_chunk(&self, id: ChunkId, data: Bytes) -> Result<(), Self::Error>;
+    async fn get_chunk(&self, id: ChunkId) -> Result<Option<Bytes>, Self::Error>;
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeduplicationStats {
+    pub chunks: usize,
+    pub uploaded_chunks: usize,
+    pub uploaded_bytes: u64,
+    pub skipped_bytes: u64,
+}
+
+fn store_error<E: std::error::Error + Send + Sync + 'static>(error: E) -> ChunkingError {
+    ChunkingError::StoreError(Box::new(error))
+}
+
+/// Size of the reads `store_deduplicated` feeds the splitter with.
+const READ_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Chunks data as it is written and uploads each chunk the store is missing as soon as its boundary is known.
+/// Only the chunk being cut is held in memory, at most `max_size` bytes.
+pub struct Deduplicator<'a, S: ChunkStore> {
+    splitter: ChunkSplitter,
+    store: &'a S,
+    manifest: ChunkManifest,
+    /// Chunks already handled for this file, a repeated chunk is neither queried nor uploaded again.
+    seen: BTreeSet<ChunkId>,
+    stats: DeduplicationStats,
+}
+
+impl<'a, S: ChunkStore> Deduplicator<'a, S> {
+    pub fn new(config: ChunkingConfig, hasher: ChunkHasher, store: &'a S) -> Self {
+        Self {
+            splitter: ChunkSplitter::new(config, hasher),
+            store,
+            manifest: ChunkManifest::new(config),
+            seen: BTreeSet::new(),
+            stats: DeduplicationStats::default(),
+        }
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), ChunkingError> {
+        for chunk in self.splitter.push(data) {
+            self.store_chunk(chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Stores the last chunks and returns the manifest of everything written.
+    pub async fn finish(mut self) -> Result<(ChunkManifest, DeduplicationStats), ChunkingError> {
+        for chunk in self.splitter.finish() {
+            self.store_chunk(chunk).await?;
+        }
+        self.stats.skipped_bytes = self.manifest.file_size - self.stats.uploaded_bytes;
+        Ok((self.manifest, self.stats))
+    }
+
+    async fn store_chunk(&mut self, chunk: Chunk) -> Result<(), ChunkingError> {
+        self.manifest.push(chunk.id, chunk.data.len() as u32);
+        self.stats.chunks += 1;
+        if !self.seen.insert(chunk.id) {
+            return Ok(());
+        }
+        let missing = self
+            .store
+            .missing_chunks(&BTreeSet::from([chunk.id]))
+            .await
+            .map_err(store_error)?;
+        if missing.contains(&chunk.id) {
+            self.stats.uploaded_chunks += 1;
+            self.stats.uploaded_bytes += chunk.data.len() as u64;
+            self.store.put_chunk(chunk.id, Bytes::from(chunk.data)).await.map_err(store_error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Chunks `reader` and uploads only the chunks the store is missing, see [`Deduplicator`].
+pub async fn store_deduplicated<R: Read, S: ChunkStore>(
+    mut reader: R,
+    config: ChunkingConfig,
+    hasher: &ChunkHasher,
+    store: &S,
+) -> Result<(ChunkManifest, DeduplicationStats), ChunkingError> {
+    let mut deduplicator = Deduplicator::new(config, hasher.clone(), store);
+    let mut block = vec![0u8; READ_BLOCK_SIZE];
+    loop {
+        let read = match reader.read(&mut block) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        deduplicator.write(&block[..read]).await?;
+    }
+    deduplicator.finish().await
+}
+
+/// Writes the file described by `manifest` to `writer`, every chunk is checked against its id.
+pub async fn reassemble<W: Write, S: ChunkStore>(
+    manifest: &ChunkManifest,
+    hasher: &ChunkHasher,
+    store: &S,
+    mut writer: W,
+) -> Result<u64, ChunkingError> {
+    let mut written = 0u64;
+    for chunk in &manifest.chunks {
+        let data = store
+            .get_chunk(chunk.id)
+            .await
+            .map_err(store_error)?
+            .ok_or(ChunkingError::ChunkNotFound(chunk.id))?;
+        if data.len() != chunk.length as usize || hasher.chunk_id(&data) != chunk.id {
+            return Err(ChunkingError::ChunkHashMismatch(chunk.id));
+        }
+        writer.write_all(&data)?;
+        written += data.len() as u64;
+    }
+    writer.flush()?;
+    if written != manifest.file_size {
+        return Err(ChunkingError::SizeMismatch {
+            expected: manifest.file_size,
+            actual: written,
+        });
+    }
+    Ok(written)
+}
+
+/// Keeps chunks in memory, used as a local cache and in tests.
+#[derive(Default)]
+pub struct MemoryChunkStore {
+    chunks: RefCell<HashMap<ChunkId, Bytes>>,
+}
+
+impl MemoryChunkStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.borrow().is_empty()
+    }
+}
+
+#[async_trait(?Send)]
+impl ChunkStore for MemoryChunkStore {
+    type Error = std::convert::Infallible;
+
+    async fn missing_chunks(&self, ids: &BTreeSet<ChunkId>) -> Result<BTreeSet<ChunkId>, Self::Error> {
+        let chunks = self.chunks.borrow();
+        Ok(ids.iter().filter(|id| !chunks.contains_key(id)).copied().collect())
+    }
+
+    async fn put_chunk(&self, id: ChunkId, data: Bytes) -> Result<(), Self::Error> {
+        self.chunks.borrow_mut().insert(id, data);
+        Ok(())
+    }
+
+    async fn get_chunk(&self, id: ChunkId) -> Result<Option<Bytes>, Self::Error> {
+        Ok(self.chunks.borrow().get(&id).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_second_upload_is_deduplicated() {
+        let hasher = ChunkHasher::new(highway::Key([9, 8, 7, 6]));
+        let config = ChunkingConfig::new(1024, 4096, 16384).unwrap();
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let data: Vec<u8> = (0..256 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let store = MemoryChunkStore::new();
+
+        let (manifest, first) = store_deduplicated(data.as_slice(), config, &hasher, &store).await.unwrap();
+        assert_eq!(first.uploaded_bytes, data.len() as u64);
+        let (_, second) = store_deduplicated(data.as_slice(), config, &hasher, &store).await.unwrap();
+        assert_eq!(second.uploaded_chunks, 0);
+        assert_eq!(second.skipped_bytes, data.len() as u64);
+
+        let manifest = ChunkManifest::from_json(&manifest.to_json().unwrap()).unwrap();
+        let mut output = Vec::new();
+        reassemble(&manifest, &hasher, &store, &mut output).await.unwrap();
+        assert_eq!(output, data);
+    }
+}
@@ -3,14 +3,28 @@ use futures::SinkExt;
 use mime::Mime;
 use url::Url;
 use wasm_bindgen::JsValue;
-use crate::client::http::{HttpDownloadClientExt, HttpUploadClientExt};
-use crate::client::http::http_request_ext::{HttpRequestContentEncodingHeaderExt, HttpRequestContentTypeHeaderExt};
+use crate::client::http::{parse_content_range_total, ByteRange, HttpDownloadClientExt, HttpStatusCode, HttpUploadClientExt, ObjectResponse, RangeResponse};
+use crate::client::http::http_request_ext::{HttpRequestAuthorizationMetadataExt, HttpRequestContentEncodingHeaderExt, HttpRequestContentTypeHeaderExt};
+use crate::integrity::CONTENT_HASH_HEADER;
+use crate::io::metadata::FILE_METADATA_HEADER;
+use crate::token::ApiToken;
 
 pub enum HttpError {
     #[error("HttpPutFailed")]
     HttpPutError(#[from] gloo::net::Error),
     #[error("HttpGetFailed")]
     HttpGetError(#[source] gloo::net::Error),
+    #[error("Http response error code: {0}")]
+    HttpResponseStatusError(u16),
+}
+
+impl HttpStatusCode for HttpError {
+    fn status_code(&self) -> Option<u16> {
+        match self {
+            HttpError::HttpResponseStatusError(status) => Some(*status),
+            _ => None,
+        }
+    }
 }
 
 pub struct HttpClient {
@@ -19,12 +33,15 @@ pub struct HttpClient {
 
 impl HttpUploadClientExt for HttpClient{
     type Error = HttpError;
-    async fn put(&self, url: Url,body: &[u8], content_type: Mime, content_encoding: Option<Encoding>) -> Result<(), HttpError> {
+    async fn put(&self, url: Url,body: &[u8], content_type: Mime, content_encoding: Option<Encoding>) -> Result<u16, HttpError> {
         let val = JsValue::from_str(std::str::from_utf8(
             body,
         ).unwrap());
         let resp = gloo::net::http::Request::put(url.as_str()).set_content_type(content_type).set_content_encoding(&content_encoding).body(val).send().await.map_err(|e| Self::Error::HttpPutError(e))?;
-        Ok(())
+        if !resp.ok() {
+            return Err(Self::Error::HttpResponseStatusError(resp.status()));
+        }
+        Ok(resp.status())
     }
 }
 
@@ -37,4 +54,26 @@ impl HttpDownloadClientExt for HttpClient {
         let resp = gloo::net::http::Request::get(url.as_str()).send().await.map_err(|e| Self::Error::HttpGetError(e))?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    async fn get_object(&self, url: Url, api_token: &ApiToken, content_encoding: Option<Encoding>) -> Result<ObjectResponse, Self::Error> {
+        let resp = gloo::net::http::Request::get(url.as_str()).set_authorization_metadata(api_token).set_content_encoding(&content_encoding).send().await.map_err(|e| Self::Error::HttpGetError(e))?;
+        let status = resp.status();
+        if !resp.ok() {
+            return Err(Self::Error::HttpResponseStatusError(status));
+        }
+        let content_hash = resp.headers().get(CONTENT_HASH_HEADER).and_then(|value| value.parse().ok());
+        let file_metadata = resp.headers().get(FILE_METADATA_HEADER);
+        let bytes = resp.binary().await.map_err(|e| Self::Error::HttpGetError(e))?.into();
+        Ok(ObjectResponse { bytes, status, content_hash, file_metadata })
+    }
+
+    async fn get_range(&self, url: Url, api_token: &ApiToken, range: ByteRange) -> Result<RangeResponse, Self::Error> {
+        let resp = gloo::net::http::Request::get(url.as_str()).set_authorization_metadata(api_token).header("range", range.to_header_value().as_str()).send().await.map_err(|e| Self::Error::HttpGetError(e))?;
+        let etag = resp.headers().get("etag");
+        let total_size = resp.headers().get("content-range").as_deref().and_then(parse_content_range_total);
+        let content_hash = resp.headers().get(CONTENT_HASH_HEADER).and_then(|value| value.parse().ok());
+        let file_metadata = resp.headers().get(FILE_METADATA_HEADER);
+        let bytes = resp.binary().await.map_err(|e| Self::Error::HttpGetError(e))?.into();
+        Ok(RangeResponse { bytes, etag, total_size, content_hash, file_metadata })
+    }
+}
@@ -1,6 +1,9 @@
 use gloo::net::http::RequestBuilder;
 use mime::Mime;
 use crate::client::http::http_request_ext::{HttpRequestAuthorizationMetadataExt, HttpRequestContentEncodingHeaderExt, HttpRequestContentTypeHeaderExt};
+use crate::client::http::http_request_ext::{HttpRequestContentHashHeaderExt, HttpRequestFileMetadataHeaderExt};
+use crate::integrity::{ContentHash, CONTENT_HASH_HEADER};
+use crate::io::metadata::FILE_METADATA_HEADER;
 use crate::token::ApiToken;
 
 impl HttpRequestAuthorizationMetadataExt for RequestBuilder {
@@ -27,4 +30,21 @@ impl HttpRequestContentEncodingHeaderExt for RequestBuilder {
             .join(", ");
         self.header("content-encoding", encoding_str.as_str())
     }
-}
\ No newline at end of file
+}
+impl HttpRequestContentHashHeaderExt for RequestBuilder {
+    fn set_content_hash(self, content_hash: Option<&ContentHash>) -> Self {
+        match content_hash {
+            None => self,
+            Some(content_hash) => self.header(CONTENT_HASH_HEADER, content_hash.to_string().as_str()),
+        }
+    }
+}
+
+impl HttpRequestFileMetadataHeaderExt for RequestBuilder {
+    fn set_file_metadata(self, file_metadata: Option<&str>) -> Self {
+        match file_metadata {
+            None => self,
+            Some(file_metadata) => self.header(FILE_METADATA_HEADER, file_metadata),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use mime::Mime;
+use crate::integrity::ContentHash;
 use crate::token::ApiToken;
 
 /// Note this is for HTTP request
@@ -20,6 +21,16 @@ pub trait HttpRequestContentEncodingHeaderExt {
 pub trait HttpRequestInTransitEncryptionHeaderExt {
 }
 
+/// Sets `CONTENT_HASH_HEADER` so the body can be checked by the storage and by later downloads.
+pub trait HttpRequestContentHashHeaderExt {
+    fn set_content_hash(self, content_hash: Option<&ContentHash>) -> Self;
+}
+
+/// Sets `FILE_METADATA_HEADER` to the encoded `PreservedMetadata` of the uploaded file.
+pub trait HttpRequestFileMetadataHeaderExt {
+    fn set_file_metadata(self, file_metadata: Option<&str>) -> Self;
+}
+
 
 // pub trait RequestBuilderCompressionExt<R: std::io::Read,W: std::io::Write>  {
 //     fn set_compression(self, compression: &impl CompressorModule<R, W>) -> Self;
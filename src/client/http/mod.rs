@@ -1,8 +1,9 @@
 use std::fmt::Debug;
-use std::io::Bytes;
 use bucket_common_types::Encoding;
+use bytes::Bytes;
 use mime::Mime;
 use url::Url;
+use crate::integrity::ContentHash;
 use crate::token::ApiToken;
 
 #[cfg(target_arch = "wasm32")]
@@ -11,15 +12,85 @@ pub mod gloo;
 pub mod native;
 pub mod http_request_ext;
 
+/// Exposes the HTTP status of a failed request, e.g. to tell an expired presigned URL (403) apart from other failures.
+pub trait HttpStatusCode {
+    fn status_code(&self) -> Option<u16>;
+}
+
 pub trait HttpUploadClientExt: Sized{
-    type Error: Debug;
-    async fn put(&self, url: Url,body: &[u8], api_token: &ApiToken, content_type: Mime, content_encoding: Option<Encoding>) -> Result<(), Self::Error>;
+    type Error: Debug + HttpStatusCode;
+    /// Returns the status of the response, any status other than a success is an error.
+    async fn put(&self, url: Url,body: &[u8], api_token: &ApiToken, content_type: Mime, content_encoding: Option<Encoding>) -> Result<u16, Self::Error>;
+    /// Same as `put` with the object headers set. Clients that can't set the headers fall back to `put`.
+    async fn put_with_object_headers(&self, url: Url, body: &[u8], api_token: &ApiToken, content_type: Mime, content_encoding: Option<Encoding>, headers: &ObjectHeaders<'_>) -> Result<u16, Self::Error> {
+        let _ = headers;
+        self.put(url, body, api_token, content_type, content_encoding).await
+    }
+}
+
+/// Headers stored as user metadata of the object.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ObjectHeaders<'a> {
+    /// Sent in `CONTENT_HASH_HEADER`.
+    pub content_hash: Option<&'a ContentHash>,
+    /// Encoded `PreservedMetadata`, sent in `FILE_METADATA_HEADER`.
+    pub file_metadata: Option<&'a str>,
+}
+
+/// Byte range of an object, sent as the HTTP `Range` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `start..end`, end exclusive.
+    Bounded { start: u64, end: u64 },
+    /// Everything from `start` to the end of the object.
+    From(u64),
+    /// The last `n` bytes of the object.
+    Suffix(u64),
+}
+
+impl ByteRange {
+    pub fn to_header_value(&self) -> String {
+        match *self {
+            ByteRange::Bounded { start, end } => format!("bytes={}-{}", start, end.saturating_sub(1)),
+            ByteRange::From(start) => format!("bytes={}-", start),
+            ByteRange::Suffix(len) => format!("bytes=-{}", len),
+        }
+    }
+}
+
+pub struct RangeResponse {
+    pub bytes: Bytes,
+    pub etag: Option<String>,
+    /// Size of the whole object, taken from `Content-Range`.
+    pub total_size: Option<u64>,
+    /// Hash of the whole object from `CONTENT_HASH_HEADER`, when it was stored with one.
+    pub content_hash: Option<ContentHash>,
+    /// Encoded `PreservedMetadata` from `FILE_METADATA_HEADER`.
+    pub file_metadata: Option<String>,
+}
+
+/// A whole object, with the headers it was stored with.
+pub struct ObjectResponse {
+    pub bytes: Bytes,
+    pub status: u16,
+    /// Hash of the whole object from `CONTENT_HASH_HEADER`, when it was stored with one.
+    pub content_hash: Option<ContentHash>,
+    /// Encoded `PreservedMetadata` from `FILE_METADATA_HEADER`.
+    pub file_metadata: Option<String>,
+}
+
+/// Parses the total size out of a `Content-Range: bytes 0-99/1234` header.
+pub fn parse_content_range_total(value: &str) -> Option<u64> {
+    value.rsplit_once('/')?.1.trim().parse().ok()
 }
 
 /// Trait implemented for an HTTP client
 /// to be able to download a file from the URL supplied from the backend
 pub trait HttpDownloadClientExt : Sized{
     type Error: Debug;
-    async fn get(&self, url: Url, api_token: &ApiToken ,content_encoding: Option<Encoding>) -> Result<Bytes<u8>, Self::Error>;
+    async fn get(&self, url: Url, api_token: &ApiToken ,content_encoding: Option<Encoding>) -> Result<Bytes, Self::Error>;
+    /// Same as `get`, keeping the status and the object headers. Fails on a non-success status.
+    async fn get_object(&self, url: Url, api_token: &ApiToken, content_encoding: Option<Encoding>) -> Result<ObjectResponse, Self::Error>;
+    async fn get_range(&self, url: Url, api_token: &ApiToken, range: ByteRange) -> Result<RangeResponse, Self::Error>;
 }
 
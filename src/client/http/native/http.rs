@@ -1,39 +1,101 @@
 use crate::client::http::http_request_ext::{HttpRequestAuthorizationMetadataExt, HttpRequestContentEncodingHeaderExt, HttpRequestContentTypeHeaderExt};
-use crate::client::http::{HttpDownloadClientExt, HttpUploadClientExt};
+use crate::client::http::{parse_content_range_total, ByteRange, HttpDownloadClientExt, HttpStatusCode, HttpUploadClientExt, ObjectHeaders, ObjectResponse, RangeResponse};
+use crate::client::http::http_request_ext::{HttpRequestContentHashHeaderExt, HttpRequestFileMetadataHeaderExt};
+use crate::integrity::CONTENT_HASH_HEADER;
+use crate::io::metadata::FILE_METADATA_HEADER;
 use crate::token::ApiToken;
 use bucket_common_types::Encoding;
 use futures::SinkExt;
 use mime::Mime;
 use reqwest::{Client, Error};
-use std::io::{Bytes, Read};
+use bytes::Bytes;
 use url::Url;
 
 pub struct HttpClient {
     client: Client,
 }
 
+impl HttpClient {
+    pub fn new() -> Self {
+        Self { client: Client::new() }
+    }
+
+    pub fn from_client(client: Client) -> Self {
+        Self { client }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum HttpError {
     #[error("HttpUploadError")]
     HttpUploadError(#[source] Error),
     #[error("HttpDownloadError")]
     HttpDownloadError(#[source] Error),
+    #[error("Http response error code: {0}")]
+    HttpResponseStatusError(u16),
+}
+
+impl HttpStatusCode for HttpError {
+    fn status_code(&self) -> Option<u16> {
+        match self {
+            HttpError::HttpUploadError(e) | HttpError::HttpDownloadError(e) => e.status().map(|status| status.as_u16()),
+            HttpError::HttpResponseStatusError(status) => Some(*status),
+        }
+    }
 }
 
 impl HttpUploadClientExt for  HttpClient{
 type Error = HttpError;
-    async fn put(&self, url: Url, body: &[u8], api_token: &ApiToken, content_type: Mime, content_encoding: Option<Encoding>) -> Result<(), Self::Error> {
-        let resp = self.client.put(url).body(body).set_authorization_metadata(api_token).set_content_type(content_type).set_content_encoding(content_encoding).send().await.map_err(|e| Self::Error::HttpDownloadError(e))?;
-        Ok(())
+    async fn put(&self, url: Url, body: &[u8], api_token: &ApiToken, content_type: Mime, content_encoding: Option<Encoding>) -> Result<u16, Self::Error> {
+        let resp = self.client.put(url).body(body.to_vec()).set_authorization_metadata(api_token).set_content_type(content_type).set_content_encoding(content_encoding).send().await.map_err(|e| Self::Error::HttpUploadError(e))?;
+        if !resp.status().is_success() {
+            return Err(Self::Error::HttpResponseStatusError(resp.status().as_u16()));
+        }
+        Ok(resp.status().as_u16())
+    }
+
+    async fn put_with_object_headers(&self, url: Url, body: &[u8], api_token: &ApiToken, content_type: Mime, content_encoding: Option<Encoding>, headers: &ObjectHeaders<'_>) -> Result<u16, Self::Error> {
+        let resp = self.client.put(url).body(body.to_vec()).set_authorization_metadata(api_token).set_content_type(content_type).set_content_encoding(content_encoding).set_content_hash(headers.content_hash).set_file_metadata(headers.file_metadata).send().await.map_err(|e| Self::Error::HttpUploadError(e))?;
+        if !resp.status().is_success() {
+            return Err(Self::Error::HttpResponseStatusError(resp.status().as_u16()));
+        }
+        Ok(resp.status().as_u16())
     }
 }
 
 impl HttpDownloadClientExt for HttpClient {
     type Error = HttpError;
-    async fn get(&self, url: Url, api_token: &ApiToken, content_encoding: Option<Encoding>) -> Result<Bytes<u8>, Self::Error> {
+    async fn get(&self, url: Url, api_token: &ApiToken, content_encoding: Option<Encoding>) -> Result<Bytes, Self::Error> {
         use HttpRequestContentEncodingHeaderExt;
         let resp = self.client.get(url.as_str()).set_authorization_metadata(api_token).set_content_encoding(content_encoding).send().await.map_err(|e| Self::Error::HttpDownloadError(e))?;
         let binary = resp.bytes().await.unwrap();
         Ok(binary)
     }
-}
\ No newline at end of file
+
+    async fn get_object(&self, url: Url, api_token: &ApiToken, content_encoding: Option<Encoding>) -> Result<ObjectResponse, Self::Error> {
+        let resp = self.client.get(url.as_str()).set_authorization_metadata(api_token).set_content_encoding(content_encoding).send().await.map_err(|e| Self::Error::HttpDownloadError(e))?;
+        let status = resp.status().as_u16();
+        if !resp.status().is_success() {
+            return Err(Self::Error::HttpResponseStatusError(status));
+        }
+        let header = |name: &'static str| resp.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+        let content_hash = header(CONTENT_HASH_HEADER).and_then(|value| value.parse().ok());
+        let file_metadata = header(FILE_METADATA_HEADER);
+        let bytes = resp.bytes().await.map_err(|e| Self::Error::HttpDownloadError(e))?;
+        Ok(ObjectResponse { bytes, status, content_hash, file_metadata })
+    }
+
+    async fn get_range(&self, url: Url, api_token: &ApiToken, range: ByteRange) -> Result<RangeResponse, Self::Error> {
+        let resp = self.client.get(url.as_str()).set_authorization_metadata(api_token).header(reqwest::header::RANGE, range.to_header_value()).send().await.map_err(|e| Self::Error::HttpDownloadError(e))?;
+        if !resp.status().is_success() {
+            return Err(Self::Error::HttpResponseStatusError(resp.status().as_u16()));
+        }
+        let header = |name: reqwest::header::HeaderName| resp.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+        let etag = header(reqwest::header::ETAG);
+        let total_size = header(reqwest::header::CONTENT_RANGE).as_deref().and_then(parse_content_range_total);
+        let content_hash = header(reqwest::header::HeaderName::from_static(CONTENT_HASH_HEADER)).and_then(|value| value.parse().ok());
+        let file_metadata = header(reqwest::header::HeaderName::from_static(FILE_METADATA_HEADER));
+        let bytes = resp.bytes().await.map_err(|e| Self::Error::HttpDownloadError(e))?;
+        Ok(RangeResponse { bytes, etag, total_size, content_hash, file_metadata })
+    }
+}
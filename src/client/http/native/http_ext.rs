@@ -2,6 +2,9 @@ use bucket_common_types::Encoding;
 use mime::Mime;
 use reqwest::RequestBuilder;
 use crate::client::http::http_request_ext::{HttpRequestAuthorizationMetadataExt, HttpRequestContentEncodingHeaderExt, HttpRequestContentTypeHeaderExt, HttpRequestInTransitEncryptionHeaderExt};
+use crate::client::http::http_request_ext::{HttpRequestContentHashHeaderExt, HttpRequestFileMetadataHeaderExt};
+use crate::integrity::{ContentHash, CONTENT_HASH_HEADER};
+use crate::io::metadata::FILE_METADATA_HEADER;
 use crate::token::ApiToken;
 
 impl HttpRequestContentEncodingHeaderExt for RequestBuilder {
@@ -17,13 +20,53 @@ impl HttpRequestContentEncodingHeaderExt for RequestBuilder {
 
 impl HttpRequestAuthorizationMetadataExt for RequestBuilder {
     fn set_authorization_metadata(self, api_token: &ApiToken) -> Self {
-        todo!()
+        self.bearer_auth(api_token)
     }
 }
 
 impl HttpRequestContentTypeHeaderExt for RequestBuilder {
     fn set_content_type(self, content_type: Mime) -> Self {
-        todo!()
+        self.header(reqwest::header::CONTENT_TYPE, content_type.as_ref())
     }
 }
 
+
+impl HttpRequestContentHashHeaderExt for RequestBuilder {
+    fn set_content_hash(self, content_hash: Option<&ContentHash>) -> Self {
+        match content_hash {
+            None => self,
+            Some(content_hash) => self.header(CONTENT_HASH_HEADER, content_hash.to_string()),
+        }
+    }
+}
+
+impl HttpRequestFileMetadataHeaderExt for RequestBuilder {
+    fn set_file_metadata(self, file_metadata: Option<&str>) -> Self {
+        match file_metadata {
+            None => self,
+            Some(file_metadata) => self.header(FILE_METADATA_HEADER, file_metadata),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_headers_are_set() {
+        let api_token = ApiToken::try_from("token").unwrap();
+        let request = reqwest::Client::new()
+            .put("https://storage.test/file.txt")
+            .set_authorization_metadata(&api_token)
+            .set_content_type(mime::TEXT_PLAIN)
+            .set_content_encoding(None)
+            .build()
+            .unwrap();
+
+        let headers = request.headers();
+        assert_eq!(headers[reqwest::header::AUTHORIZATION], format!("Bearer {}", api_token).as_str());
+        assert_eq!(headers[reqwest::header::CONTENT_TYPE], "text/plain");
+        assert!(!headers.contains_key(reqwest::header::CONTENT_ENCODING));
+    }
+}
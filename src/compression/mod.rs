@@ -1,5 +1,5 @@
 pub mod lz4;
-mod default_compression_chooser_handler;
+pub(crate) mod default_compression_chooser_handler;
 mod brotli;
 
 use std::fmt::Debug;
@@ -0,0 +1,22 @@
+//! Rsync style delta transfer, a modified file is uploaded as the blocks that changed plus a patch manifest
+//! describing how to rebuild it from the version already in the bucket.
+
+pub mod patch;
+pub mod rolling;
+pub mod signature;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeltaError {
+    #[error("Block size must be between {min} and {max} bytes", min = signature::MIN_BLOCK_SIZE, max = signature::MAX_BLOCK_SIZE)]
+    InvalidBlockSize,
+    #[error("Unsupported signature or patch version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Patch does not apply to a basis of {actual} bytes, expected {expected} bytes")]
+    BasisSizeMismatch { expected: u64, actual: u64 },
+    #[error("Patch references bytes outside of the basis file")]
+    CopyOutOfRange,
+    #[error(transparent)]
+    SerializationError(#[from] serde_json::Error),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
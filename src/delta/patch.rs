@@ -0,0 +1,298 @@
+use crate::delta::rolling::RollingChecksum;
+use crate::delta::signature::{strong_hash, Signature};
+use crate::delta::DeltaError;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+pub const PATCH_VERSION: u32 = 1;
+/// Patch manifests are uploaded next to the literal data, under the target path with this suffix.
+pub const PATCH_OBJECT_SUFFIX: &str = ".bucketpatch";
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PatchOp {
+    /// Bytes that are unchanged, copied from the basis file.
+    Copy { source_offset: u64, length: u64 },
+    /// Bytes taken from the next `length` bytes of the uploaded literal data.
+    Literal { length: u64 },
+}
+
+/// How to rebuild the new version of a file from the version in the bucket and the uploaded literal data.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PatchManifest {
+    pub version: u32,
+    pub base_file_size: u64,
+    pub target_size: u64,
+    pub literal_size: u64,
+    pub ops: Vec<PatchOp>,
+}
+
+impl PatchManifest {
+    fn push_copy(&mut self, source_offset: u64, length: u64) {
+        if let Some(PatchOp::Copy { source_offset: previous, length: previous_length }) = self.ops.last_mut() {
+            if *previous + *previous_length == source_offset {
+                *previous_length += length;
+                return;
+            }
+        }
+        self.ops.push(PatchOp::Copy { source_offset, length });
+    }
+
+    fn push_literal(&mut self, length: u64) {
+        if length == 0 {
+            return;
+        }
+        self.literal_size += length;
+        // Literals are flushed in pieces while streaming, consecutive pieces are one op.
+        if let Some(PatchOp::Literal { length: previous }) = self.ops.last_mut() {
+            *previous += length;
+            return;
+        }
+        self.ops.push(PatchOp::Literal { length });
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>, DeltaError> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, DeltaError> {
+        let manifest: Self = serde_json::from_slice(data)?;
+        if manifest.version != PATCH_VERSION {
+            return Err(DeltaError::UnsupportedVersion(manifest.version));
+        }
+        Ok(manifest)
+    }
+}
+
+/// The changed blocks of a file, the only data that has to be uploaded.
+pub struct Delta {
+    pub manifest: PatchManifest,
+    pub literals: Vec<u8>,
+}
+
+/// Finds the blocks of `target` that already exist in the file described by `signature`.
+pub fn compute_delta(target: &[u8], signature: &Signature, key: highway::Key) -> Delta {
+    let mut encoder = DeltaEncoder::new(signature, key);
+    let mut literals = encoder.write(target);
+    let (manifest, tail) = encoder.finish();
+    literals.extend_from_slice(&tail);
+    Delta { manifest, literals }
+}
+
+/// Literal bytes before the window are only flushed once this many blocks have built up, so the buffer is not
+/// shifted on every byte the window rolls.
+const FLUSH_BLOCKS: usize = 4;
+
+/// Computes a delta as the target is read, a rolling hash pass that keeps a few blocks in memory.
+/// Literal bytes are returned as soon as they can no longer be part of a match, to be uploaded while reading.
+pub struct DeltaEncoder<'a> {
+    signature: &'a Signature,
+    key: highway::Key,
+    block_size: usize,
+    full_blocks: usize,
+    index: HashMap<u32, Vec<usize>>,
+    manifest: PatchManifest,
+    /// Target bytes after the last copied block, literals followed by the window starting at `pos`.
+    buffer: Vec<u8>,
+    pos: usize,
+    rolling: Option<RollingChecksum>,
+}
+
+impl<'a> DeltaEncoder<'a> {
+    pub fn new(signature: &'a Signature, key: highway::Key) -> Self {
+        let block_size = signature.block_size as usize;
+        // Only full blocks are matched while rolling, a short last block can only match the end of the target.
+        let full_blocks = (signature.file_size / block_size as u64) as usize;
+        let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (i, block) in signature.blocks.iter().take(full_blocks).enumerate() {
+            index.entry(block.weak).or_default().push(i);
+        }
+        Self {
+            signature,
+            key,
+            block_size,
+            full_blocks,
+            index,
+            manifest: PatchManifest {
+                version: PATCH_VERSION,
+                base_file_size: signature.file_size,
+                target_size: 0,
+                literal_size: 0,
+                ops: Vec::new(),
+            },
+            buffer: Vec::with_capacity((FLUSH_BLOCKS + 2) * block_size),
+            pos: 0,
+            rolling: None,
+        }
+    }
+
+    /// Feeds the next bytes of the target, returns the literal bytes that are final.
+    pub fn write(&mut self, data: &[u8]) -> Vec<u8> {
+        self.manifest.target_size += data.len() as u64;
+        self.buffer.extend_from_slice(data);
+        let block_size = self.block_size;
+        let mut literals = Vec::new();
+        while self.buffer.len() >= self.pos + block_size {
+            let window = &self.buffer[self.pos..self.pos + block_size];
+            let checksum = self.rolling.get_or_insert_with(|| RollingChecksum::new(window));
+            if let Some(block) = find_block(&self.index, self.signature, self.key, window, checksum.digest()) {
+                literals.extend_from_slice(&self.buffer[..self.pos]);
+                self.manifest.push_literal(self.pos as u64);
+                self.manifest.push_copy((block * block_size) as u64, block_size as u64);
+                self.buffer.drain(..self.pos + block_size);
+                self.pos = 0;
+                self.rolling = None;
+            } else if self.pos + block_size < self.buffer.len() {
+                checksum.roll(self.buffer[self.pos], self.buffer[self.pos + block_size]);
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        // A block before the window is kept, the end of the target may still match the short last block.
+        if self.pos >= FLUSH_BLOCKS * block_size {
+            let flushed = self.pos - block_size;
+            literals.extend(self.buffer.drain(..flushed));
+            self.manifest.push_literal(flushed as u64);
+            self.pos -= flushed;
+        }
+        literals
+    }
+
+    /// Ends the target, returns the manifest and the last literal bytes.
+    pub fn finish(mut self) -> (PatchManifest, Vec<u8>) {
+        let mut tail_end = self.buffer.len();
+        if let Some(last) = self.signature.blocks.get(self.full_blocks) {
+            let tail_len = (self.signature.file_size % self.block_size as u64) as usize;
+            if let Some(start) = self.buffer.len().checked_sub(tail_len) {
+                if strong_hash(self.key, &self.buffer[start..]) == last.strong {
+                    tail_end = start;
+                }
+            }
+        }
+        let tail_len = (self.buffer.len() - tail_end) as u64;
+        self.buffer.truncate(tail_end);
+        self.manifest.push_literal(tail_end as u64);
+        if tail_len > 0 {
+            self.manifest.push_copy((self.full_blocks * self.block_size) as u64, tail_len);
+        }
+        (self.manifest, self.buffer)
+    }
+}
+
+fn find_block(
+    index: &HashMap<u32, Vec<usize>>,
+    signature: &Signature,
+    key: highway::Key,
+    window: &[u8],
+    weak: u32,
+) -> Option<usize> {
+    let candidates = index.get(&weak)?;
+    let strong = strong_hash(key, window);
+    candidates.iter().copied().find(|i| signature.blocks[*i].strong == strong)
+}
+
+/// Rebuilds the new version into `output`, returns the number of bytes written.
+pub fn apply_delta<B: Read + Seek, L: Read, W: Write>(
+    manifest: &PatchManifest,
+    mut basis: B,
+    mut literals: L,
+    mut output: W,
+) -> Result<u64, DeltaError> {
+    let basis_size = basis.seek(SeekFrom::End(0))?;
+    if basis_size != manifest.base_file_size {
+        return Err(DeltaError::BasisSizeMismatch {
+            expected: manifest.base_file_size,
+            actual: basis_size,
+        });
+    }
+    let mut written = 0;
+    for op in &manifest.ops {
+        let copied = match *op {
+            PatchOp::Copy { source_offset, length } => {
+                if source_offset.checked_add(length).map_or(true, |end| end > basis_size) {
+                    return Err(DeltaError::CopyOutOfRange);
+                }
+                basis.seek(SeekFrom::Start(source_offset))?;
+                std::io::copy(&mut (&mut basis).take(length), &mut output)?
+            }
+            PatchOp::Literal { length } => std::io::copy(&mut (&mut literals).take(length), &mut output)?,
+        };
+        let expected = match op {
+            PatchOp::Copy { length, .. } | PatchOp::Literal { length } => *length,
+        };
+        if copied != expected {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        written += copied;
+    }
+    output.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const KEY: highway::Key = highway::Key([3, 1, 4, 1]);
+
+    fn test_data(len: usize) -> Vec<u8> {
+        let mut state = 88172645463325252u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn round_trip(basis: &[u8], target: &[u8]) -> Delta {
+        let signature = Signature::from_json(&Signature::compute(basis, 1024, KEY).unwrap().to_json().unwrap()).unwrap();
+        let delta = compute_delta(target, &signature, KEY);
+        let mut output = Vec::new();
+        apply_delta(&delta.manifest, Cursor::new(basis), delta.literals.as_slice(), &mut output).unwrap();
+        assert_eq!(output, target);
+        delta
+    }
+
+    #[test]
+    fn test_small_edit_uploads_little() {
+        let basis = test_data(100 * 1024 + 300);
+        let mut target = basis.clone();
+        target[50_000..50_010].copy_from_slice(b"0123456789");
+        target.splice(80_000..80_000, b"inserted".iter().copied());
+        let delta = round_trip(&basis, &target);
+        assert!(delta.manifest.literal_size <= 3 * 1024, "{} literal bytes", delta.manifest.literal_size);
+    }
+
+    #[test]
+    fn test_streamed_delta_matches_whole() {
+        let basis = test_data(100 * 1024 + 300);
+        let mut target = basis.clone();
+        target.splice(20_000..20_000, test_data(9000));
+        target.truncate(90_000);
+        target.extend_from_slice(&basis[100 * 1024..]);
+        let signature = Signature::compute(basis.as_slice(), 1024, KEY).unwrap();
+        let whole = compute_delta(&target, &signature, KEY);
+
+        let mut encoder = DeltaEncoder::new(&signature, KEY);
+        let mut literals = Vec::new();
+        for part in target.chunks(777) {
+            literals.extend(encoder.write(part));
+        }
+        let (manifest, tail) = encoder.finish();
+        literals.extend(tail);
+        assert_eq!(manifest, whole.manifest);
+        assert_eq!(literals, whole.literals);
+    }
+
+    #[test]
+    fn test_unrelated_and_empty_files() {
+        round_trip(&test_data(4096), &[]);
+        round_trip(&[], &test_data(4096));
+        let delta = round_trip(&test_data(5000), &test_data(5000));
+        assert_eq!(delta.manifest.literal_size, 0);
+    }
+}
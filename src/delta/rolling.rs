@@ -0,0 +1,47 @@
+/// The weak checksum used by rsync, cheap to slide over the data one byte at a time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RollingChecksum {
+    a: u16,
+    b: u16,
+    len: u16,
+}
+
+impl RollingChecksum {
+    pub fn new(block: &[u8]) -> Self {
+        let mut checksum = Self {
+            len: block.len() as u16,
+            ..Default::default()
+        };
+        for (i, byte) in block.iter().enumerate() {
+            checksum.a = checksum.a.wrapping_add(*byte as u16);
+            checksum.b = checksum.b.wrapping_add(((block.len() - i) as u16).wrapping_mul(*byte as u16));
+        }
+        checksum
+    }
+
+    /// Slides the window by one byte, `outgoing` leaves at the front and `incoming` enters at the back.
+    pub fn roll(&mut self, outgoing: u8, incoming: u8) {
+        self.a = self.a.wrapping_sub(outgoing as u16).wrapping_add(incoming as u16);
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(outgoing as u16)).wrapping_add(self.a);
+    }
+
+    pub fn digest(&self) -> u32 {
+        (self.b as u32) << 16 | self.a as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roll_matches_recompute() {
+        let data: Vec<u8> = (0..2048u32).map(|i| (i * 7919 % 251) as u8).collect();
+        let window = 512;
+        let mut rolling = RollingChecksum::new(&data[..window]);
+        for start in 1..data.len() - window {
+            rolling.roll(data[start - 1], data[start + window - 1]);
+            assert_eq!(rolling.digest(), RollingChecksum::new(&data[start..start + window]).digest());
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::delta::rolling::RollingChecksum;
+use crate::delta::DeltaError;
+use highway::{HighwayHash, HighwayHasher};
+use std::io::Read;
+
+pub const SIGNATURE_VERSION: u32 = 1;
+/// Signatures are cached in the bucket next to the file they describe, under the file path with this suffix.
+pub const SIGNATURE_OBJECT_SUFFIX: &str = ".bucketsig";
+pub const MIN_BLOCK_SIZE: u32 = 512;
+/// The rolling checksum window length is stored in 16 bits.
+pub const MAX_BLOCK_SIZE: u32 = u16::MAX as u32;
+pub const DEFAULT_BLOCK_SIZE: u32 = 32 * 1024;
+
+pub fn signature_object_path(file_path: &str) -> String {
+    format!("{file_path}{SIGNATURE_OBJECT_SUFFIX}")
+}
+
+/// Keyed so the signature object does not reveal the content of an encrypted file.
+pub fn strong_hash(key: highway::Key, block: &[u8]) -> [u64; 2] {
+    let mut hasher = HighwayHasher::new(key);
+    hasher.append(block);
+    hasher.finalize128()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BlockSignature {
+    pub weak: u32,
+    pub strong: [u64; 2],
+}
+
+/// Checksums of every block of a file version, enough to compute a delta against it without having its content.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Signature {
+    pub version: u32,
+    pub block_size: u32,
+    pub file_size: u64,
+    pub blocks: Vec<BlockSignature>,
+}
+
+impl Signature {
+    pub fn compute<R: Read>(mut reader: R, block_size: u32, key: highway::Key) -> Result<Self, DeltaError> {
+        if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+            return Err(DeltaError::InvalidBlockSize);
+        }
+        let mut signature = Self {
+            version: SIGNATURE_VERSION,
+            block_size,
+            file_size: 0,
+            blocks: Vec::new(),
+        };
+        let mut block = vec![0u8; block_size as usize];
+        loop {
+            let mut filled = 0;
+            while filled < block.len() {
+                match reader.read(&mut block[filled..])? {
+                    0 => break,
+                    n => filled += n,
+                }
+            }
+            if filled == 0 {
+                break;
+            }
+            signature.file_size += filled as u64;
+            signature.blocks.push(BlockSignature {
+                weak: RollingChecksum::new(&block[..filled]).digest(),
+                strong: strong_hash(key, &block[..filled]),
+            });
+            if filled < block.len() {
+                break;
+            }
+        }
+        Ok(signature)
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>, DeltaError> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, DeltaError> {
+        let signature: Self = serde_json::from_slice(data)?;
+        if signature.version != SIGNATURE_VERSION {
+            return Err(DeltaError::UnsupportedVersion(signature.version));
+        }
+        Ok(signature)
+    }
+}
@@ -3,6 +3,12 @@ use bucket_api::backend_api::{download_files_request, CreateBucketRequest, Delet
 use bucket_common_types::{BucketCompression, BucketEncryption, BucketGuid, BucketRedundancy, BucketStorageClass, BucketVisibility, RegionCluster};
 use crate::io::file::{VirtualFileDetails};
 use crate::io::FileWrapper;
+use crate::io::loading::dataloader::DataLoader;
+use crate::bundle::BundlePolicy;
+use crate::wrapper::bucket::download::conflict::ConflictPolicy;
+use crate::wrapper::bucket::download::filter::DownloadFilter;
+use crate::wrapper::bucket::download::DownloadHandlerOptions;
+use crate::transfer::TransferContext;
 
 pub struct CreateBucketParams {
     pub target_user_id: uuid::Uuid,
@@ -153,7 +159,8 @@ impl TryInto<GetBucketDetailsRequest> for GetBucketDetailsParams {
 pub struct UploadFile<File: FileWrapper> {
     pub target_directory: String,
     pub source_file: File,
-    //pub content_type: mime::Mime,
+    /// Overrides the content type resolved from the file.
+    pub content_type: Option<mime::Mime>,
 }
 
 pub struct UploadFilesParams<File: FileWrapper> {
@@ -164,19 +171,57 @@ pub struct UploadFilesParams<File: FileWrapper> {
     pub encryption: Option<BucketEncryption>,
     pub total_size_in_bytes: u64, // Can not go over this value. Going over will result in overwriting previous writes. pretty much unexpected behavior.
     pub hashed_password: Option<String>,
+    /// Opt-in, packs small files into bundle objects instead of uploading each on its own.
+    pub bundling: Option<BundlePolicy>,
+    /// Encrypts the bundle objects, required when bundling into an encrypted bucket.
+    pub bundle_key: Option<aes_gcm::Aes256Gcm>,
+    /// Shares request and memory limits with other transfers, a default context is used when none is given.
+    pub transfer_context: Option<TransferContext>,
+}
+
+impl<File: FileWrapper> UploadFilesParams<File> {
+    /// Uploads everything `loader` produces under `target_directory`.
+    pub async fn from_data_loader<L: DataLoader<File = File>>(
+        loader: &L,
+        target_user_id: uuid::Uuid,
+        target_bucket_id: uuid::Uuid,
+        target_directory: String,
+    ) -> Result<Self, L::Error> {
+        let files = loader.load().await?;
+        let directory = target_directory.trim_end_matches('/').to_string();
+        Ok(Self {
+            target_user_id,
+            target_bucket_id,
+            total_size_in_bytes: files.iter().map(|file| file.size_in_bytes).sum(),
+            source_files: files
+                .into_iter()
+                .map(|file| UploadFile {
+                    target_directory: format!("{}/{}", directory, file.name),
+                    source_file: file.file,
+                    content_type: Some(file.content_type),
+                })
+                .collect(),
+            target_directory,
+            encryption: None,
+            hashed_password: None,
+            bundling: None,
+            bundle_key: None,
+            transfer_context: None,
+        })
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum UploadFilesRequestParsingError {}
 
-impl TryInto<UploadFilesToBucketRequest> for UploadFilesParams<dyn FileWrapper<Error=(), FileHandle=()>> {
+impl<File: FileWrapper> TryInto<UploadFilesToBucketRequest> for &UploadFilesParams<File> {
     type Error = UploadFilesRequestParsingError;
 
     fn try_into(self) -> Result<UploadFilesToBucketRequest, Self::Error> {
         Ok(UploadFilesToBucketRequest {
             target_bucket_id: self.target_bucket_id.to_string(),
             target_bucket_owner_id: self.target_user_id.to_string(),
-            target_directory: self.target_directory,
+            target_directory: self.target_directory.clone(),
             source_files: self.source_files.iter().fold(
                 Vec::<backend_api::upload_files_to_bucket_request::File>::with_capacity(
                     self.source_files.len(),
@@ -185,12 +230,17 @@ impl TryInto<UploadFilesToBucketRequest> for UploadFilesParams<dyn FileWrapper<E
                     acc.push(backend_api::upload_files_to_bucket_request::File {
                         file_path: num.target_directory.clone(),
                         size_in_bytes: num.source_file.get_size(),
-                        content_type: num.source_file.get_mime_type().unwrap().to_string(),
+                        content_type: num
+                            .content_type
+                            .clone()
+                            .or_else(|| num.source_file.get_mime_type().ok())
+                            .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+                            .to_string(),
                     });
                     acc
                 },
             ),
-            hashed_password: self.hashed_password,
+            hashed_password: self.hashed_password.clone(),
         })
     }
 }
@@ -204,6 +254,15 @@ pub struct DownloadFilesParams {
     pub bucket_encryption: Option<BucketEncryption>,
 
     pub keep_file_structure: bool,
+    /// Local directory the files are written to, existing files are only detected when it is set.
+    pub local_directory: Option<std::path::PathBuf>,
+    pub conflict_policy: ConflictPolicy,
+    /// Files of the listing left out of the download, everything is downloaded when `None`.
+    pub filter: Option<DownloadFilter>,
+    /// Ranged downloads, resume and metadata restore, the handler builder keeps its own options when `None`.
+    pub download_options: Option<DownloadHandlerOptions>,
+    /// Shares request and memory limits with other transfers, a default context is used when none is given.
+    pub transfer_context: Option<TransferContext>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -234,8 +293,19 @@ impl TryInto<DownloadFilesRequest> for DownloadFilesParams {
 pub struct DownloadBucketParams {
     pub bucket_guid: BucketGuid,
     pub hashed_password: Option<String>,
+    /// Archived by the server, which can't read client-side encrypted buckets. For those the archive is built by
+    /// the download handlers, see `ClientBucketExt::download_bucket_archive` and `crate::archive`.
     pub format: Option<bucket_common_types::DownloadFormat>,
     pub keep_file_structure: bool,
+    /// Local directory the files are written to, existing files are only detected when it is set.
+    pub local_directory: Option<std::path::PathBuf>,
+    pub conflict_policy: ConflictPolicy,
+    /// Files of the bucket left out of the download, e.g. everything but `logs/2026-10/*.json`.
+    pub filter: Option<DownloadFilter>,
+    /// Ranged downloads, resume and metadata restore, the handler builder keeps its own options when `None`.
+    pub download_options: Option<DownloadHandlerOptions>,
+    /// Shares request and memory limits with other transfers, a default context is used when none is given.
+    pub transfer_context: Option<TransferContext>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -1,2 +1,2 @@
-//mod hash_based_signature;
-//mod hash_based_signature_verifier;
+pub mod hash_based_signature;
+pub mod hash_based_signature_verifier;
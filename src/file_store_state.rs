@@ -1,3 +1,5 @@
+use crate::delta::signature::Signature;
+
 /// Will contain the local file store state.
 /// Will update, according to webhooks and other such functionality.
 /// Could be though of as a virtual filesystem representing the cloud storage system, and used to map cloud to local.
@@ -6,6 +8,9 @@ pub struct FileSystemState {}
 pub struct FileState {
     pub name: String,
     pub path: String,
+    /// Signature of the version last uploaded from this machine, lets the next upload compute a delta
+    /// without fetching the `.bucketsig` object from the bucket.
+    pub signature: Option<Signature>,
 }
 
 pub trait FileStoreStates {
@@ -0,0 +1,235 @@
+use highway::{HighwayHash, HighwayHasher, Key};
+use sha3::{Digest, Sha3_256};
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Carries the content hash of a request or response body, formatted as `<algorithm>:<hex digest>`.
+/// Stored as user metadata by S3 compatible storage, so it is returned again on download.
+pub const CONTENT_HASH_HEADER: &str = "x-amz-meta-bucket-content-hash";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ContentHashParseError {
+    #[error("Unknown content hash algorithm {0}")]
+    UnknownAlgorithm(String),
+    #[error("Malformed content hash {0}")]
+    Malformed(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Integrity check failed for {file}, expected {expected} but got {actual}")]
+pub struct IntegrityError {
+    pub file: String,
+    pub expected: ContentHash,
+    pub actual: ContentHash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum ContentHashAlgorithm {
+    Sha3_256,
+    /// Keyed, several times faster than SHA3 but only comparable between parties sharing the key.
+    HighwayHash256,
+}
+
+impl ContentHashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentHashAlgorithm::Sha3_256 => "sha3-256",
+            ContentHashAlgorithm::HighwayHash256 => "highway-256",
+        }
+    }
+}
+
+impl FromStr for ContentHashAlgorithm {
+    type Err = ContentHashParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha3-256" => Ok(ContentHashAlgorithm::Sha3_256),
+            "highway-256" => Ok(ContentHashAlgorithm::HighwayHash256),
+            _ => Err(ContentHashParseError::UnknownAlgorithm(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct ContentHash {
+    pub algorithm: ContentHashAlgorithm,
+    pub digest: [u8; 32],
+}
+
+impl ContentHash {
+    /// Hashes `data` in one call, see `ContentHasher` for streaming.
+    pub fn sha3_256(data: &[u8]) -> Self {
+        let mut hasher = ContentHasher::sha3_256();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Compares against the hash of the received content, `file` names the file in the error.
+    pub fn verify(&self, file: &str, actual: &ContentHash) -> Result<(), IntegrityError> {
+        if self == actual {
+            return Ok(());
+        }
+        Err(IntegrityError {
+            file: file.to_string(),
+            expected: *self,
+            actual: *actual,
+        })
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.algorithm.name())?;
+        for byte in self.digest {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ContentHash {
+    type Err = ContentHashParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, hex) = s
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| ContentHashParseError::Malformed(s.to_string()))?;
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(ContentHashParseError::Malformed(s.to_string()));
+        }
+        let mut digest = [0u8; 32];
+        for (i, byte) in digest.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| ContentHashParseError::Malformed(s.to_string()))?;
+        }
+        Ok(Self {
+            algorithm: algorithm.parse()?,
+            digest,
+        })
+    }
+}
+
+/// Computes a `ContentHash` incrementally while the content is streamed.
+#[derive(Clone)]
+pub enum ContentHasher {
+    Sha3_256(Sha3_256),
+    HighwayHash256(HighwayHasher),
+}
+
+impl ContentHasher {
+    pub fn sha3_256() -> Self {
+        ContentHasher::Sha3_256(Sha3_256::new())
+    }
+
+    pub fn highway(key: Key) -> Self {
+        ContentHasher::HighwayHash256(HighwayHasher::new(key))
+    }
+
+    pub fn algorithm(&self) -> ContentHashAlgorithm {
+        match self {
+            ContentHasher::Sha3_256(_) => ContentHashAlgorithm::Sha3_256,
+            ContentHasher::HighwayHash256(_) => ContentHashAlgorithm::HighwayHash256,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            ContentHasher::Sha3_256(hasher) => hasher.update(data),
+            ContentHasher::HighwayHash256(hasher) => hasher.append(data),
+        }
+    }
+
+    pub fn finalize(self) -> ContentHash {
+        let algorithm = self.algorithm();
+        let mut digest = [0u8; 32];
+        match self {
+            ContentHasher::Sha3_256(hasher) => digest.copy_from_slice(&hasher.finalize()),
+            ContentHasher::HighwayHash256(hasher) => {
+                for (out, word) in digest.chunks_exact_mut(8).zip(hasher.finalize256()) {
+                    out.copy_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+        ContentHash { algorithm, digest }
+    }
+}
+
+/// Hashes everything read through it.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: ContentHasher,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R, hasher: ContentHasher) -> Self {
+        Self { inner, hasher }
+    }
+
+    pub fn finalize(self) -> (R, ContentHash) {
+        (self.inner, self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Hashes everything written through it.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: ContentHasher,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, hasher: ContentHasher) -> Self {
+        Self { inner, hasher }
+    }
+
+    pub fn finalize(self) -> (W, ContentHash) {
+        (self.inner, self.hasher.finalize())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_streaming_matches_header_round_trip() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 253) as u8).collect();
+        let mut reader = HashingReader::new(&data[..], ContentHasher::sha3_256());
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        let (_, streamed) = reader.finalize();
+        assert_eq!(streamed, ContentHash::sha3_256(&data));
+
+        let header = streamed.to_string();
+        assert!(header.starts_with("sha3-256:"));
+        assert_eq!(header.parse::<ContentHash>().unwrap(), streamed);
+
+        let mut writer = HashingWriter::new(Vec::new(), ContentHasher::highway(Key([1, 2, 3, 4])));
+        writer.write_all(&data).unwrap();
+        let (_, keyed) = writer.finalize();
+        let error = streamed.verify("/docs/a.bin", &keyed).unwrap_err();
+        assert_eq!(error.file, "/docs/a.bin");
+        assert!(error.to_string().contains("/docs/a.bin"));
+    }
+}
@@ -0,0 +1,204 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Device names Windows resolves in every directory, with or without an extension.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1",
+    "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+/// Characters Windows does not allow in file names, `:` also rules out drive letters and alternate data streams.
+const WINDOWS_RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*', '\\'];
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum BucketPathError {
+    #[error("Bucket path is empty")]
+    Empty,
+    #[error("Bucket path {0} leaves its directory")]
+    ParentComponent(String),
+    #[error("Bucket path {0} contains a NUL byte")]
+    NulByte(String),
+    #[error("Bucket path {path} contains the reserved name {component}")]
+    ReservedName { path: String, component: String },
+    #[error("Bucket path {path} contains {character:?}, which is not allowed in file names")]
+    InvalidCharacter { path: String, character: char },
+    #[error("{0} is a symlink, downloads don't write through links")]
+    SymlinkInPath(PathBuf),
+    #[error("{0} is outside of the download directory")]
+    OutsideRoot(PathBuf),
+}
+
+/// A normalized path of a file in a bucket, such as `/photos/2024/img.jpg`.
+/// Paths come from the server and share links, so anything that could resolve outside of the download
+/// directory on any platform is rejected instead of being cleaned up.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BucketPath {
+    components: Vec<String>,
+}
+
+impl BucketPath {
+    /// Empty and `.` components are dropped, the leading `/` is optional.
+    pub fn parse(path: &str) -> Result<Self, BucketPathError> {
+        if path.contains('\0') {
+            return Err(BucketPathError::NulByte(path.replace('\0', "\\0")));
+        }
+        let mut components = Vec::new();
+        for component in path.split('/') {
+            match component {
+                "" | "." => continue,
+                ".." => return Err(BucketPathError::ParentComponent(path.to_string())),
+                component => {
+                    validate_component(path, component)?;
+                    components.push(component.to_string());
+                }
+            }
+        }
+        if components.is_empty() {
+            return Err(BucketPathError::Empty);
+        }
+        Ok(Self { components })
+    }
+
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.components.iter().map(String::as_str)
+    }
+
+    pub fn file_name(&self) -> &str {
+        self.components.last().expect("a bucket path has at least one component")
+    }
+
+    /// The path below the bucket root, to be joined onto a local directory.
+    pub fn to_relative_path(&self) -> PathBuf {
+        self.components.iter().collect()
+    }
+}
+
+fn validate_component(path: &str, component: &str) -> Result<(), BucketPathError> {
+    if let Some(character) = component
+        .chars()
+        .find(|character| character.is_control() || WINDOWS_RESERVED_CHARS.contains(character))
+    {
+        return Err(BucketPathError::InvalidCharacter {
+            path: path.to_string(),
+            character,
+        });
+    }
+    let stem = component.split('.').next().unwrap_or(component).trim_end();
+    // Windows strips trailing dots and spaces, `foo.` and `foo` would be the same file.
+    let reserved = WINDOWS_RESERVED_NAMES.iter().any(|name| stem.eq_ignore_ascii_case(name))
+        || component.ends_with(['.', ' ']);
+    if reserved {
+        return Err(BucketPathError::ReservedName {
+            path: path.to_string(),
+            component: component.to_string(),
+        });
+    }
+    Ok(())
+}
+
+impl fmt::Display for BucketPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for component in &self.components {
+            write!(f, "/{}", component)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BucketPath {
+    type Err = BucketPathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Maps bucket paths to files below a local download directory, the only way download targets are built.
+#[derive(Debug, Clone)]
+pub struct LocalPathResolver {
+    root: PathBuf,
+    keep_file_structure: bool,
+}
+
+impl LocalPathResolver {
+    /// Without `keep_file_structure` every file is placed directly in `root` under its file name.
+    pub fn new(root: impl Into<PathBuf>, keep_file_structure: bool) -> Self {
+        Self {
+            root: root.into(),
+            keep_file_structure,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The local path of `path`. On native targets the directories below the root and the target itself must not
+    /// be symlinks, a link placed there earlier could redirect the write outside of the root.
+    pub fn resolve(&self, path: &BucketPath) -> Result<PathBuf, BucketPathError> {
+        let relative = if self.keep_file_structure {
+            path.to_relative_path()
+        } else {
+            PathBuf::from(path.file_name())
+        };
+        #[cfg(not(target_family = "wasm"))]
+        {
+            let mut current = self.root.clone();
+            for component in relative.components() {
+                current.push(component);
+                match std::fs::symlink_metadata(&current) {
+                    Ok(metadata) if metadata.file_type().is_symlink() => {
+                        return Err(BucketPathError::SymlinkInPath(current));
+                    }
+                    Ok(_) => {}
+                    // Nothing below a missing directory can exist yet.
+                    Err(_) => break,
+                }
+            }
+        }
+        Ok(self.root.join(relative))
+    }
+
+    pub fn resolve_str(&self, path: &str) -> Result<PathBuf, BucketPathError> {
+        self.resolve(&BucketPath::parse(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rejects_escapes() {
+        let path = BucketPath::parse("photos//./2024/img.jpg").unwrap();
+        assert_eq!(path.to_string(), "/photos/2024/img.jpg");
+        assert_eq!(path.file_name(), "img.jpg");
+
+        assert!(matches!(BucketPath::parse("../../.ssh/authorized_keys"), Err(BucketPathError::ParentComponent(_))));
+        assert!(matches!(BucketPath::parse("/a/../../b"), Err(BucketPathError::ParentComponent(_))));
+        assert!(matches!(BucketPath::parse("C:/Windows/system.ini"), Err(BucketPathError::InvalidCharacter { character: ':', .. })));
+        assert!(matches!(BucketPath::parse("a\\..\\b"), Err(BucketPathError::InvalidCharacter { character: '\\', .. })));
+        assert!(matches!(BucketPath::parse("docs/file\0.txt"), Err(BucketPathError::NulByte(_))));
+        assert!(matches!(BucketPath::parse("logs/con.txt"), Err(BucketPathError::ReservedName { .. })));
+        assert!(matches!(BucketPath::parse("notes. "), Err(BucketPathError::ReservedName { .. })));
+        assert_eq!(BucketPath::parse("/./"), Err(BucketPathError::Empty));
+        assert!(BucketPath::parse("console/contacts.vcf").is_ok());
+    }
+
+    #[test]
+    fn test_resolver_stays_in_root() {
+        let root = std::env::temp_dir().join(format!("bucket-resolve-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let path = BucketPath::parse("/docs/a.txt").unwrap();
+        assert_eq!(LocalPathResolver::new(&root, true).resolve(&path).unwrap(), root.join("docs/a.txt"));
+        assert_eq!(LocalPathResolver::new(&root, false).resolve(&path).unwrap(), root.join("a.txt"));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(std::env::temp_dir(), root.join("docs")).unwrap();
+            let resolved = LocalPathResolver::new(&root, true).resolve(&path);
+            assert_eq!(resolved, Err(BucketPathError::SymlinkInPath(root.join("docs"))));
+        }
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -18,6 +18,8 @@ pub struct VirtualFileDetails {
     pub date: Option<time::OffsetDateTime>,
     pub size_in_bytes: u64,
     //pub file_format: mime::Mime,
+    /// Where the file is written, chosen by the conflict policy. `None` leaves it to the download handler.
+    pub local_target: Option<std::path::PathBuf>,
 }
 
 pub struct VirtualBucketFileMetadata {
@@ -1,6 +1,22 @@
+use crate::io::FileWrapper;
+use async_trait::async_trait;
+use mime::Mime;
 
+/// An upload source produced by a `DataLoader`.
+pub struct LoadedFile<File: FileWrapper> {
+    /// Path relative to the upload's target directory, `/` separated.
+    pub name: String,
+    pub size_in_bytes: u64,
+    pub content_type: Mime,
+    pub file: File,
+}
 
 // Call data loader when loading in a file to be uploaded.
+#[async_trait(?Send)]
 pub trait DataLoader {
-    fn load_from_path(&self, );
-}
\ No newline at end of file
+    type File: FileWrapper;
+    type Error: std::error::Error + 'static;
+
+    /// Opens every source of the loader, nothing is read yet unless the source has to be buffered.
+    async fn load(&self) -> Result<Vec<LoadedFile<Self::File>>, Self::Error>;
+}
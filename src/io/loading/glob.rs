@@ -0,0 +1,79 @@
+use crate::io::loading::dataloader::{DataLoader, LoadedFile};
+use crate::io::loading::path::{open_path, PathLoaderError};
+use crate::io::mime_resolver::MimeResolver;
+use crate::io::NativeFile;
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum GlobLoaderError {
+    #[error(transparent)]
+    PatternError(#[from] glob::PatternError),
+    #[error(transparent)]
+    GlobError(#[from] glob::GlobError),
+    #[error(transparent)]
+    PathLoaderError(#[from] PathLoaderError),
+}
+
+/// Opens every file matching a glob such as `logs/**/*.json`. Files are named by their path relative to
+/// `base`, the directory part of the pattern by default.
+pub struct GlobLoader {
+    pattern: String,
+    base: PathBuf,
+    mime_resolver: MimeResolver,
+}
+
+impl GlobLoader {
+    pub fn new(pattern: impl Into<String>) -> Self {
+        let pattern = pattern.into();
+        let literal: Vec<&str> = pattern
+            .split('/')
+            .take_while(|component| !component.contains(['*', '?', '[']))
+            .collect();
+        // Without any wildcard the last component is the file name itself.
+        let base = if literal.len() == pattern.split('/').count() {
+            Path::new(&pattern).parent().map(Path::to_path_buf).unwrap_or_default()
+        } else {
+            PathBuf::from(literal.join("/"))
+        };
+        Self {
+            pattern,
+            base,
+            mime_resolver: MimeResolver::default(),
+        }
+    }
+
+    pub fn with_base(mut self, base: impl Into<PathBuf>) -> Self {
+        self.base = base.into();
+        self
+    }
+
+    pub fn with_mime_resolver(mut self, mime_resolver: MimeResolver) -> Self {
+        self.mime_resolver = mime_resolver;
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl DataLoader for GlobLoader {
+    type File = NativeFile;
+    type Error = GlobLoaderError;
+
+    async fn load(&self) -> Result<Vec<LoadedFile<Self::File>>, Self::Error> {
+        let mut files = Vec::new();
+        for path in glob::glob(&self.pattern)? {
+            let path = path?;
+            if !path.is_file() {
+                continue;
+            }
+            let relative = path.strip_prefix(&self.base).unwrap_or(&path);
+            let name = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push(open_path(&path, name, &self.mime_resolver)?);
+        }
+        Ok(files)
+    }
+}
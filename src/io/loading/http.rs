@@ -0,0 +1,116 @@
+use crate::io::loading::dataloader::{DataLoader, LoadedFile};
+use crate::pipeline::buffer_pool::{MemoryBudget, DEFAULT_TRANSFER_MEMORY_BUDGET};
+use crate::wrapper::bucket::upload::stream::{SpooledStream, StreamUploadError};
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use mime::Mime;
+use url::Url;
+
+#[derive(Debug, thiserror::Error)]
+pub enum HttpLoaderError {
+    #[error("Http response error code: {0}")]
+    HttpResponseStatusError(u16),
+    #[error(transparent)]
+    HttpError(#[from] reqwest::Error),
+    #[error(transparent)]
+    StreamUploadError(#[from] StreamUploadError),
+}
+
+/// Downloads a URL, e.g. to copy a public file into a bucket. The name is the last path
+/// segment of the URL and the content type is taken from the response.
+/// The body is spooled like a streamed upload, what doesn't fit into the memory budget is spilled to disk.
+pub struct HttpLoader {
+    url: Url,
+    client: reqwest::Client,
+    budget: MemoryBudget,
+}
+
+impl HttpLoader {
+    pub fn new(url: Url) -> Self {
+        Self::with_client(url, reqwest::Client::new())
+    }
+
+    pub fn with_client(url: Url, client: reqwest::Client) -> Self {
+        Self {
+            url,
+            client,
+            budget: MemoryBudget::new(DEFAULT_TRANSFER_MEMORY_BUDGET),
+        }
+    }
+
+    /// Use the budget of the upload's `TransferContext` to keep the body and the upload buffers under one limit.
+    pub fn with_memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    fn name(&self) -> String {
+        self.url
+            .path_segments()
+            .and_then(|segments| segments.filter(|segment| !segment.is_empty()).last())
+            .unwrap_or("index")
+            .to_string()
+    }
+}
+
+#[async_trait(?Send)]
+impl DataLoader for HttpLoader {
+    type File = SpooledStream;
+    type Error = HttpLoaderError;
+
+    async fn load(&self) -> Result<Vec<LoadedFile<Self::File>>, Self::Error> {
+        let resp = self.client.get(self.url.clone()).send().await?;
+        if !resp.status().is_success() {
+            return Err(HttpLoaderError::HttpResponseStatusError(resp.status().as_u16()));
+        }
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<Mime>().ok())
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        let body = futures::stream::try_unfold(resp, |mut resp| async move {
+            Ok::<_, std::io::Error>(resp.chunk().await.map_err(std::io::Error::other)?.map(|chunk| (chunk, resp)))
+        });
+        let name = self.name();
+        let file = SpooledStream::spool(Box::pin(body).into_async_read(), name.clone(), content_type.clone(), &self.budget).await?;
+        Ok(vec![LoadedFile {
+            size_in_bytes: file.size(),
+            file,
+            name,
+            content_type,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::FileWrapper;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_load_from_local_server() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await.unwrap();
+            let body = "{\"ok\":true}";
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let url = Url::parse(&format!("http://{}/exports/report.json", address)).unwrap();
+        let files = HttpLoader::new(url).load().await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "report.json");
+        assert_eq!(files[0].content_type, mime::APPLICATION_JSON);
+        assert_eq!(files[0].file.get_size(), 11);
+    }
+}
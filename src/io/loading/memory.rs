@@ -0,0 +1,47 @@
+use crate::io::loading::dataloader::{DataLoader, LoadedFile};
+use crate::io::memory::MemoryFile;
+use async_trait::async_trait;
+use bytes::Bytes;
+use mime::Mime;
+use std::convert::Infallible;
+
+/// Uploads bytes that are already in memory.
+pub struct MemoryLoader {
+    files: Vec<(String, Bytes, Mime)>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    pub fn with_file(mut self, name: impl Into<String>, data: impl Into<Bytes>, content_type: Mime) -> Self {
+        self.files.push((name.into(), data.into(), content_type));
+        self
+    }
+}
+
+impl Default for MemoryLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait(?Send)]
+impl DataLoader for MemoryLoader {
+    type File = MemoryFile;
+    type Error = Infallible;
+
+    async fn load(&self) -> Result<Vec<LoadedFile<Self::File>>, Self::Error> {
+        Ok(self
+            .files
+            .iter()
+            .map(|(name, data, content_type)| LoadedFile {
+                name: name.clone(),
+                size_in_bytes: data.len() as u64,
+                content_type: content_type.clone(),
+                file: MemoryFile::from_bytes(name.clone(), data.clone(), content_type),
+            })
+            .collect())
+    }
+}
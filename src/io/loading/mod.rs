@@ -1 +1,10 @@
-pub mod dataloader;
\ No newline at end of file
+pub mod dataloader;
+pub mod memory;
+#[cfg(not(target_family = "wasm"))]
+pub mod path;
+#[cfg(not(target_family = "wasm"))]
+pub mod glob;
+#[cfg(not(target_family = "wasm"))]
+pub mod http;
+#[cfg(not(target_family = "wasm"))]
+pub mod tar;
@@ -0,0 +1,72 @@
+use crate::io::loading::dataloader::{DataLoader, LoadedFile};
+use crate::io::mime_resolver::{MimeResolver, SNIFF_LEN};
+use crate::io::{positional, FileWrapper, NativeFile};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum PathLoaderError {
+    #[error("{0} is not a file")]
+    NotAFile(PathBuf),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// Opens a local file, it is uploaded under its file name.
+pub struct PathLoader {
+    path: PathBuf,
+    mime_resolver: MimeResolver,
+}
+
+impl PathLoader {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mime_resolver: MimeResolver::default(),
+        }
+    }
+
+    pub fn with_mime_resolver(mut self, mime_resolver: MimeResolver) -> Self {
+        self.mime_resolver = mime_resolver;
+        self
+    }
+}
+
+/// Opens `path` as an upload source named `name`.
+pub(crate) fn open_path(path: &Path, name: String, mime_resolver: &MimeResolver) -> Result<LoadedFile<NativeFile>, PathLoaderError> {
+    let file = std::fs::File::open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Err(PathLoaderError::NotAFile(path.to_path_buf()));
+    }
+    let content_type = match mime_resolver.from_filename(&name) {
+        Some(content_type) => content_type,
+        None => {
+            let mut head = [0u8; SNIFF_LEN];
+            let read = positional::read_at(&file, &mut head, 0)?;
+            mime_resolver.resolve(&name, &head[..read])
+        }
+    };
+    Ok(LoadedFile {
+        file: NativeFile::from_file_handle(file, path.to_string_lossy().into_owned(), &content_type),
+        name,
+        size_in_bytes: metadata.len(),
+        content_type,
+    })
+}
+
+#[async_trait(?Send)]
+impl DataLoader for PathLoader {
+    type File = NativeFile;
+    type Error = PathLoaderError;
+
+    async fn load(&self) -> Result<Vec<LoadedFile<Self::File>>, Self::Error> {
+        let name = self
+            .path
+            .file_name()
+            .ok_or_else(|| PathLoaderError::NotAFile(self.path.clone()))?
+            .to_string_lossy()
+            .into_owned();
+        Ok(vec![open_path(&self.path, name, &self.mime_resolver)?])
+    }
+}
@@ -0,0 +1,173 @@
+use crate::io::loading::dataloader::{DataLoader, LoadedFile};
+use crate::io::mime_resolver::{MimeResolver, SNIFF_LEN};
+use crate::io::{positional, FileMetadata, FileWrapper};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use mime::Mime;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Uploads the regular files of a tar archive, named by their path inside the archive.
+/// Only the headers are read when loading, every entry reads its data from the archive when it's uploaded.
+pub struct TarLoader {
+    path: PathBuf,
+    mime_resolver: MimeResolver,
+}
+
+impl TarLoader {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mime_resolver: MimeResolver::default(),
+        }
+    }
+
+    pub fn with_mime_resolver(mut self, mime_resolver: MimeResolver) -> Self {
+        self.mime_resolver = mime_resolver;
+        self
+    }
+
+    fn scan(path: PathBuf, mime_resolver: MimeResolver) -> std::io::Result<Vec<LoadedFile<TarEntryFile>>> {
+        let archive_file = Arc::new(std::fs::File::open(path)?);
+        let mut archive = tar::Archive::new(&*archive_file);
+        let mut files = Vec::new();
+        // Seeks over the entry data instead of reading through it.
+        for entry in archive.entries_with_seek()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry.path()?.to_string_lossy().trim_start_matches("./").to_string();
+            let (offset, size) = (entry.raw_file_position(), entry.size());
+            let mut head = Vec::with_capacity(SNIFF_LEN);
+            entry.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+            let content_type = mime_resolver.resolve(&name, &head);
+            files.push(LoadedFile {
+                size_in_bytes: size,
+                file: TarEntryFile {
+                    archive: archive_file.clone(),
+                    offset,
+                    size,
+                    filename: name.clone(),
+                    content_type: content_type.clone(),
+                },
+                name,
+                content_type,
+            });
+        }
+        Ok(files)
+    }
+}
+
+#[async_trait(?Send)]
+impl DataLoader for TarLoader {
+    type File = TarEntryFile;
+    type Error = std::io::Error;
+
+    async fn load(&self) -> Result<Vec<LoadedFile<Self::File>>, Self::Error> {
+        let (path, mime_resolver) = (self.path.clone(), self.mime_resolver.clone());
+        tokio::task::spawn_blocking(move || Self::scan(path, mime_resolver))
+            .await
+            .map_err(std::io::Error::other)?
+    }
+}
+
+/// A regular file inside a tar archive, read from the archive on the blocking pool.
+#[derive(Clone)]
+pub struct TarEntryFile {
+    archive: Arc<std::fs::File>,
+    /// Position of the entry data in the archive.
+    offset: u64,
+    size: u64,
+    filename: String,
+    content_type: Mime,
+}
+
+#[async_trait(?Send)]
+impl FileWrapper for TarEntryFile {
+    type Error = std::io::Error;
+    type FileHandle = TarEntryFile;
+
+    fn create_file(_filename: &str, _mime: &Mime) -> Result<Self, Self::Error> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn from_file_handle(mut file_handle: Self::FileHandle, filename: String, mime: &Mime) -> Self {
+        file_handle.filename = filename;
+        file_handle.content_type = mime.clone();
+        file_handle
+    }
+
+    fn get_extension(&self) -> Option<String> {
+        self.filename.rsplit_once('.').map(|(_, extension)| extension.to_string())
+    }
+
+    fn get_mime_type(&self) -> Result<Mime, Self::Error> {
+        Ok(self.content_type.clone())
+    }
+
+    async fn infer_mime_type(&self) -> Result<infer::Type, Self::Error> {
+        let head = self.read_at(0, 16).await?;
+        infer::get(&head).ok_or_else(|| std::io::ErrorKind::InvalidData.into())
+    }
+
+    fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    async fn read_at(&self, offset: u64, len: usize) -> Result<Bytes, Self::Error> {
+        let len = u64::min(len as u64, self.size.saturating_sub(offset)) as usize;
+        let (archive, position) = (self.archive.clone(), self.offset + offset);
+        let read = tokio::task::spawn_blocking(move || -> std::io::Result<Bytes> {
+            let mut buf = BytesMut::zeroed(len);
+            positional::read_exact_at(&archive, &mut buf, position)?;
+            Ok(buf.freeze())
+        });
+        read.await.map_err(std::io::Error::other)?
+    }
+
+    async fn write_at(&self, _offset: u64, _data: &[u8]) -> Result<(), Self::Error> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    async fn metadata(&self) -> Result<FileMetadata, Self::Error> {
+        Ok(FileMetadata {
+            size_in_bytes: self.size,
+            modified: None,
+            mode: None,
+        })
+    }
+
+    async fn truncate(&self, _size: u64) -> Result<(), Self::Error> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_tar_entries() {
+        let path = std::env::temp_dir().join(format!("bucket-loader-{}.tar", uuid::Uuid::new_v4()));
+        let mut builder = tar::Builder::new(std::fs::File::create(&path).unwrap());
+        for (name, data) in [("a.txt", &b"first"[..]), ("nested/b.bin", &b"second file"[..])] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data).unwrap();
+        }
+        builder.finish().unwrap();
+        drop(builder);
+
+        let files = TarLoader::new(&path).load().await.unwrap();
+        let names: Vec<_> = files.iter().map(|file| (file.name.as_str(), file.size_in_bytes)).collect();
+        assert_eq!(names, vec![("a.txt", 5), ("nested/b.bin", 11)]);
+        assert_eq!(files[0].content_type, mime::TEXT_PLAIN);
+        assert_eq!(&files[1].file.read_at(0, 64).await.unwrap()[..], b"second file");
+        assert_eq!(&files[1].file.read_at(7, 2).await.unwrap()[..], b"fi");
+        std::fs::remove_file(path).unwrap();
+    }
+}
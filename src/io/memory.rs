@@ -0,0 +1,234 @@
+use crate::io::{FileMetadata, FileWrapper};
+use async_trait::async_trait;
+use bytes::Bytes;
+use mime::Mime;
+use std::cell::RefCell;
+use std::io::{Read, Write};
+
+#[derive(thiserror::Error, Debug)]
+pub enum MemoryFileError {
+    #[error("UnknownInferredFileType")]
+    UnknownInferredFileType,
+    #[error("Injected read failure at offset {0}")]
+    InjectedReadError(u64),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// Simulated faults for testing transfer handlers against unreliable sources.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FailureInjection {
+    /// Reads touching this offset or anything after it fail.
+    pub fail_reads_from: Option<u64>,
+    /// Reads return at most this many bytes, like a pipe or a slow network filesystem would.
+    pub max_read_len: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+enum MemoryData {
+    Owned(Vec<u8>),
+    /// Read without copying, converted to `Owned` by the first write.
+    Shared(Bytes),
+}
+
+impl MemoryData {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            MemoryData::Owned(data) => data,
+            MemoryData::Shared(data) => data,
+        }
+    }
+
+    fn to_mut(&mut self) -> &mut Vec<u8> {
+        if let MemoryData::Shared(data) = self {
+            *self = MemoryData::Owned(data.to_vec());
+        }
+        match self {
+            MemoryData::Owned(data) => data,
+            MemoryData::Shared(_) => unreachable!(),
+        }
+    }
+}
+
+/// A file held in memory, used for sources that are not on a filesystem such as HTTP bodies, archive entries
+/// and generated reports, and as the file of handler tests.
+#[derive(Debug, Clone)]
+pub struct MemoryFile {
+    data: RefCell<MemoryData>,
+    failures: FailureInjection,
+    /// Cursor of the `Read` and `Write` impls, the positional methods don't move it.
+    position: u64,
+    filename: String,
+    file_type: Mime,
+}
+
+impl MemoryFile {
+    pub fn new(filename: impl Into<String>, data: impl Into<Vec<u8>>, mime: &Mime) -> Self {
+        Self::with_data(filename, MemoryData::Owned(data.into()), mime)
+    }
+
+    pub fn from_bytes(filename: impl Into<String>, data: Bytes, mime: &Mime) -> Self {
+        Self::with_data(filename, MemoryData::Shared(data), mime)
+    }
+
+    fn with_data(filename: impl Into<String>, data: MemoryData, mime: &Mime) -> Self {
+        Self {
+            data: RefCell::new(data),
+            failures: FailureInjection::default(),
+            position: 0,
+            filename: filename.into(),
+            file_type: mime.clone(),
+        }
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn with_failure_injection(mut self, failures: FailureInjection) -> Self {
+        self.failures = failures;
+        self
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.data.borrow().as_slice().to_vec()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        match self.data.into_inner() {
+            MemoryData::Owned(data) => data,
+            MemoryData::Shared(data) => data.to_vec(),
+        }
+    }
+
+    fn read_slice(&self, offset: u64, len: usize) -> Result<Bytes, MemoryFileError> {
+        let len = self.failures.max_read_len.map_or(len, |max| usize::min(len, max));
+        let data = self.data.borrow();
+        let size = data.as_slice().len();
+        let start = usize::min(offset as usize, size);
+        let end = usize::min(start.saturating_add(len), size);
+        if let Some(fail_from) = self.failures.fail_reads_from.filter(|fail_from| (end as u64) > *fail_from) {
+            return Err(MemoryFileError::InjectedReadError(fail_from));
+        }
+        Ok(match &*data {
+            MemoryData::Owned(data) => Bytes::copy_from_slice(&data[start..end]),
+            MemoryData::Shared(data) => data.slice(start..end),
+        })
+    }
+
+    fn write_slice(&self, offset: u64, buf: &[u8]) {
+        let mut data = self.data.borrow_mut();
+        let data = data.to_mut();
+        let end = offset as usize + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset as usize..end].copy_from_slice(buf);
+    }
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let chunk = self
+            .read_slice(self.position, buf.len())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        buf[..chunk.len()].copy_from_slice(&chunk);
+        self.position += chunk.len() as u64;
+        Ok(chunk.len())
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_slice(self.position, buf);
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl FileWrapper for MemoryFile {
+    type Error = MemoryFileError;
+    type FileHandle = Vec<u8>;
+
+    fn create_file(filename: &str, mime: &Mime) -> Result<Self, Self::Error> {
+        Ok(Self::new(filename, Vec::new(), mime))
+    }
+
+    fn from_file_handle(file_handle: Self::FileHandle, filename: String, mime: &Mime) -> Self {
+        Self::new(filename, file_handle, mime)
+    }
+
+    fn get_extension(&self) -> Option<String> {
+        self.filename.rsplit_once('.').map(|(_, extension)| extension.to_string())
+    }
+
+    fn get_mime_type(&self) -> Result<Mime, Self::Error> {
+        Ok(self.file_type.clone())
+    }
+
+    async fn infer_mime_type(&self) -> Result<infer::Type, Self::Error> {
+        infer::get(self.data.borrow().as_slice()).ok_or(MemoryFileError::UnknownInferredFileType)
+    }
+
+    fn get_size(&self) -> u64 {
+        self.data.borrow().as_slice().len() as u64
+    }
+
+    async fn read_at(&self, offset: u64, len: usize) -> Result<Bytes, Self::Error> {
+        self.read_slice(offset, len)
+    }
+
+    async fn write_at(&self, offset: u64, data: &[u8]) -> Result<(), Self::Error> {
+        self.write_slice(offset, data);
+        Ok(())
+    }
+
+    async fn metadata(&self) -> Result<FileMetadata, Self::Error> {
+        Ok(FileMetadata {
+            size_in_bytes: self.get_size(),
+            modified: None,
+            mode: None,
+        })
+    }
+
+    async fn truncate(&self, size: u64) -> Result<(), Self::Error> {
+        self.data.borrow_mut().to_mut().resize(size as usize, 0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_positional_io() {
+        let file = MemoryFile::create_file("report.csv", &mime::TEXT_CSV).unwrap();
+        file.write_at(4, b"world").await.unwrap();
+        file.write_at(0, b"hey ").await.unwrap();
+        assert_eq!(file.read_at(4, 100).await.unwrap(), Bytes::from_static(b"world"));
+        assert_eq!(file.read_at(20, 4).await.unwrap(), Bytes::new());
+        file.truncate(3).await.unwrap();
+        assert_eq!(file.metadata().await.unwrap().size_in_bytes, 3);
+        assert_eq!(file.into_inner(), b"hey");
+    }
+
+    #[tokio::test]
+    async fn test_failure_injection() {
+        let data = Bytes::from_static(b"0123456789");
+        let file = MemoryFile::from_bytes("data.bin", data, &mime::APPLICATION_OCTET_STREAM).with_failure_injection(
+            FailureInjection {
+                fail_reads_from: Some(8),
+                max_read_len: Some(3),
+            },
+        );
+        assert_eq!(file.read_at(0, 10).await.unwrap(), Bytes::from_static(b"012"));
+        assert_eq!(file.read_at(4, 10).await.unwrap(), Bytes::from_static(b"456"));
+        assert!(matches!(file.read_at(6, 10).await, Err(MemoryFileError::InjectedReadError(8))));
+    }
+}
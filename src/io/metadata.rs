@@ -0,0 +1,208 @@
+use crate::io::FileMetadata;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::Engine;
+use std::collections::BTreeMap;
+use time::OffsetDateTime;
+
+/// Carries the encoded `PreservedMetadata` of an object, stored as user metadata and returned again on download.
+pub const FILE_METADATA_HEADER: &str = "x-amz-meta-bucket-file-metadata";
+/// S3 limits all user metadata of an object to 2 KB, larger values are rejected before the upload.
+pub const MAX_ENCODED_METADATA_LEN: usize = 1536;
+
+const PLAINTEXT_PREFIX: &str = "p:";
+const ENCRYPTED_PREFIX: &str = "e:";
+const METADATA_AAD: &[u8] = b"bucket-file-metadata";
+const NONCE_SIZE: usize = 12;
+/// Only user attributes are restored, the other namespaces are either privileged or owned by the system.
+#[cfg(unix)]
+const RESTORED_XATTR_PREFIX: &str = "user.";
+
+#[derive(Debug, thiserror::Error)]
+pub enum FileMetadataError {
+    #[error("Encoded file metadata is {0} bytes, at most {MAX_ENCODED_METADATA_LEN} fit in the object metadata")]
+    TooLarge(usize),
+    #[error("Malformed file metadata {0}")]
+    Malformed(String),
+    #[error("File metadata is encrypted but no key was given")]
+    MissingKey,
+    #[error("EncryptionError")]
+    EncryptionError,
+    #[error("DecryptionError")]
+    DecryptionError,
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+    #[error(transparent)]
+    Base64Error(#[from] base64::DecodeError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// File attributes recorded on upload and restored on native downloads.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PreservedMetadata {
+    /// Nanoseconds since the Unix epoch, kept at full precision so sync tools can compare it.
+    #[serde(rename = "m", default, skip_serializing_if = "Option::is_none")]
+    modified_unix_nanos: Option<i128>,
+    /// Unix mode bits, only the permission bits are restored, not setuid, setgid or sticky.
+    #[serde(rename = "p", default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Extended attributes, only captured when asked for and only the `user.` namespace is restored.
+    #[serde(rename = "x", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+impl PreservedMetadata {
+    pub fn modified(&self) -> Option<OffsetDateTime> {
+        self.modified_unix_nanos
+            .and_then(|nanos| OffsetDateTime::from_unix_timestamp_nanos(nanos).ok())
+    }
+
+    pub fn set_modified(&mut self, modified: Option<OffsetDateTime>) {
+        self.modified_unix_nanos = modified.map(OffsetDateTime::unix_timestamp_nanos);
+    }
+
+    /// Encodes for `FILE_METADATA_HEADER`. With a key the attributes are encrypted, the storage only sees
+    /// that the object has metadata.
+    pub fn encode(&self, key: Option<&Aes256Gcm>) -> Result<String, FileMetadataError> {
+        let json = serde_json::to_vec(self)?;
+        let encoded = match key {
+            None => format!("{}{}", PLAINTEXT_PREFIX, base64::engine::general_purpose::STANDARD.encode(json)),
+            Some(cipher) => {
+                let nonce_source = uuid::Uuid::new_v4();
+                let nonce = Nonce::from_slice(&nonce_source.as_bytes()[..NONCE_SIZE]);
+                let ciphertext = cipher
+                    .encrypt(nonce, Payload { msg: &json, aad: METADATA_AAD })
+                    .map_err(|_| FileMetadataError::EncryptionError)?;
+                let blob = [nonce.as_slice(), &ciphertext].concat();
+                format!("{}{}", ENCRYPTED_PREFIX, base64::engine::general_purpose::STANDARD.encode(blob))
+            }
+        };
+        if encoded.len() > MAX_ENCODED_METADATA_LEN {
+            return Err(FileMetadataError::TooLarge(encoded.len()));
+        }
+        Ok(encoded)
+    }
+
+    pub fn decode(value: &str, key: Option<&Aes256Gcm>) -> Result<Self, FileMetadataError> {
+        let value = value.trim();
+        let json = if let Some(plaintext) = value.strip_prefix(PLAINTEXT_PREFIX) {
+            base64::engine::general_purpose::STANDARD.decode(plaintext)?
+        } else if let Some(encrypted) = value.strip_prefix(ENCRYPTED_PREFIX) {
+            let cipher = key.ok_or(FileMetadataError::MissingKey)?;
+            let blob = base64::engine::general_purpose::STANDARD.decode(encrypted)?;
+            if blob.len() < NONCE_SIZE {
+                return Err(FileMetadataError::DecryptionError);
+            }
+            let (nonce, ciphertext) = blob.split_at(NONCE_SIZE);
+            cipher
+                .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: METADATA_AAD })
+                .map_err(|_| FileMetadataError::DecryptionError)?
+        } else {
+            return Err(FileMetadataError::Malformed(value.to_string()));
+        };
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+impl From<&FileMetadata> for PreservedMetadata {
+    fn from(metadata: &FileMetadata) -> Self {
+        let mut preserved = PreservedMetadata {
+            mode: metadata.mode,
+            ..Default::default()
+        };
+        preserved.set_modified(metadata.modified);
+        preserved
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl PreservedMetadata {
+    /// Reads the attributes of the file at `path`, extended attributes only when `include_xattrs` is set.
+    /// Mode bits and extended attributes are only read on Unix.
+    pub fn read_from_path(path: &std::path::Path, include_xattrs: bool) -> Result<Self, FileMetadataError> {
+        let metadata = std::fs::metadata(path)?;
+        let mut preserved = PreservedMetadata::default();
+        preserved.set_modified(metadata.modified().ok().map(OffsetDateTime::from));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            preserved.mode = Some(metadata.permissions().mode());
+        }
+        #[cfg(unix)]
+        if include_xattrs {
+            for name in xattr::list(path)? {
+                let Some(name) = name.to_str().map(str::to_string) else { continue };
+                if let Some(value) = xattr::get(path, &name)? {
+                    preserved.xattrs.insert(name, value);
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = include_xattrs;
+        Ok(preserved)
+    }
+
+    /// Applies the attributes to the file at `path`. The mode is set last, it may remove the write permission
+    /// the other steps need.
+    pub fn restore(&self, path: &std::path::Path) -> Result<(), FileMetadataError> {
+        #[cfg(unix)]
+        for (name, value) in self.xattrs.iter().filter(|(name, _)| name.starts_with(RESTORED_XATTR_PREFIX)) {
+            xattr::set(path, name, value)?;
+        }
+        if let Some(modified) = self.modified() {
+            std::fs::File::open(path)?.set_modified(modified.into())?;
+        }
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::KeyInit;
+
+    #[test]
+    fn test_encode_round_trip() {
+        let mut metadata = PreservedMetadata {
+            mode: Some(0o100640),
+            ..Default::default()
+        };
+        metadata.set_modified(Some(OffsetDateTime::from_unix_timestamp_nanos(1_700_000_000_123_456_789).unwrap()));
+        metadata.xattrs.insert("user.origin".to_string(), b"camera".to_vec());
+
+        let plaintext = metadata.encode(None).unwrap();
+        assert_eq!(PreservedMetadata::decode(&plaintext, None).unwrap(), metadata);
+
+        let cipher = Aes256Gcm::new(&[7u8; 32].into());
+        let encrypted = metadata.encode(Some(&cipher)).unwrap();
+        assert!(!encrypted.contains(&plaintext[PLAINTEXT_PREFIX.len()..]));
+        assert!(matches!(PreservedMetadata::decode(&encrypted, None), Err(FileMetadataError::MissingKey)));
+        assert_eq!(PreservedMetadata::decode(&encrypted, Some(&cipher)).unwrap(), metadata);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("bucket-metadata-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"content").unwrap();
+        let mut metadata = PreservedMetadata {
+            mode: Some(0o4600),
+            ..Default::default()
+        };
+        metadata.set_modified(Some(OffsetDateTime::from_unix_timestamp(1_600_000_000).unwrap()));
+        metadata.xattrs.insert("trusted.origin".to_string(), b"camera".to_vec());
+        metadata.restore(&path).unwrap();
+
+        let restored = PreservedMetadata::read_from_path(&path, false).unwrap();
+        assert_eq!(restored.modified(), metadata.modified());
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o7777, 0o600);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use crate::io::FileWrapper;
+use mime::Mime;
+use std::collections::HashMap;
+
+/// Number of leading bytes handed to `infer`, enough for every format it knows.
+pub const SNIFF_LEN: usize = 512;
+
+/// Extensions of common formats, looked up case-insensitively.
+const EXTENSION_TABLE: &[(&str, &str)] = &[
+    ("txt", "text/plain"),
+    ("log", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("tsv", "text/tab-separated-values"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("7z", "application/x-7z-compressed"),
+    ("zst", "application/zstd"),
+    ("doc", "application/msword"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("xls", "application/vnd.ms-excel"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("heic", "image/heic"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("m4a", "audio/mp4"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("mov", "video/quicktime"),
+    ("webm", "video/webm"),
+    ("mkv", "video/x-matroska"),
+    ("avi", "video/x-msvideo"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+];
+
+/// Determines the content type of an upload from its file name, then its first bytes, and falls back to
+/// `application/octet-stream`. Extensions registered with `with_extension` take precedence over the table.
+#[derive(Debug, Clone, Default)]
+pub struct MimeResolver {
+    overrides: HashMap<String, Mime>,
+}
+
+impl MimeResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_extension(mut self, extension: &str, mime: Mime) -> Self {
+        self.overrides.insert(extension.to_ascii_lowercase(), mime);
+        self
+    }
+
+    /// Looks up the extension of `filename`, `None` for unknown or missing extensions.
+    pub fn from_filename(&self, filename: &str) -> Option<Mime> {
+        let name = filename.rsplit(['/', '\\']).next().unwrap_or(filename);
+        let (stem, extension) = name.rsplit_once('.')?;
+        if stem.is_empty() {
+            // A dotfile such as `.env` has no extension.
+            return None;
+        }
+        let extension = extension.to_ascii_lowercase();
+        if let Some(mime) = self.overrides.get(&extension) {
+            return Some(mime.clone());
+        }
+        EXTENSION_TABLE
+            .iter()
+            .find(|(known, _)| *known == extension)
+            .and_then(|(_, mime)| mime.parse().ok())
+    }
+
+    /// Detects the format from the leading bytes of the content.
+    pub fn sniff(head: &[u8]) -> Option<Mime> {
+        infer::get(head).and_then(|kind| kind.mime_type().parse().ok())
+    }
+
+    pub fn resolve(&self, filename: &str, head: &[u8]) -> Mime {
+        self.from_filename(filename)
+            .or_else(|| Self::sniff(head))
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+    }
+
+    /// Like `resolve`, the first bytes are only read when the extension is unknown.
+    pub async fn resolve_file<F: FileWrapper>(&self, filename: &str, file: &F) -> Mime {
+        if let Some(mime) = self.from_filename(filename) {
+            return mime;
+        }
+        match file.read_at(0, SNIFF_LEN).await {
+            Ok(head) => Self::sniff(&head).unwrap_or(mime::APPLICATION_OCTET_STREAM),
+            Err(_) => mime::APPLICATION_OCTET_STREAM,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_order() {
+        let resolver = MimeResolver::new().with_extension("LOG", mime::TEXT_CSV);
+        let png_head = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0x0D, b'I', b'H', b'D', b'R'];
+
+        assert_eq!(resolver.resolve("photos/IMG_01.JPG", &png_head), mime::IMAGE_JPEG);
+        assert_eq!(resolver.resolve("export", &png_head), mime::IMAGE_PNG);
+        assert_eq!(resolver.resolve("server.log", b""), mime::TEXT_CSV);
+        assert_eq!(resolver.resolve(".env", b"KEY=value"), mime::APPLICATION_OCTET_STREAM);
+        assert_eq!(resolver.resolve("data.unknown", b"plain"), mime::APPLICATION_OCTET_STREAM);
+    }
+}
@@ -1,17 +1,56 @@
 use std::fmt::Debug;
-use std::io::{Read, Write};
+use async_trait::async_trait;
+use bytes::Bytes;
 use mime::Mime;
+use time::OffsetDateTime;
 
+pub mod bucket_path;
 pub mod file;
 mod native;
 mod web;
-mod loading;
+pub mod loading;
+pub mod memory;
+pub mod metadata;
+pub mod mime_resolver;
+
+#[cfg(not(target_family = "wasm"))]
+pub use native::native_file::NativeFile;
+#[cfg(not(target_family = "wasm"))]
+pub(crate) use native::positional;
+
+/// Size, modification time and permissions of a file, as far as the platform knows them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub size_in_bytes: u64,
+    pub modified: Option<OffsetDateTime>,
+    /// Unix mode bits, `None` where the platform has none.
+    pub mode: Option<u32>,
+}
+
+/// The errors of the `FileWrapper` implementations, handlers generic over the file keep them typed through this.
+/// Other implementations implement `From` for their error.
+#[derive(Debug, thiserror::Error)]
+pub enum FileError {
+    #[cfg(not(target_family = "wasm"))]
+    #[error(transparent)]
+    NativeFileError(#[from] native::native_file::NativeBucketFileError),
+    #[cfg(target_family = "wasm")]
+    #[error(transparent)]
+    WebFileError(#[from] web::web_file::WebBucketFileError),
+    #[error(transparent)]
+    MemoryFileError(#[from] memory::MemoryFileError),
+    #[error(transparent)]
+    StreamUploadError(#[from] crate::wrapper::bucket::upload::stream::StreamUploadError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
 
 /// Wrapper for file io. Supports underlying filesystem through std::fs and wasm using gloo.
-#[tonic::async_trait(?Send)]
-pub trait FileWrapper: Read + Write + Sized {
-    type Error: Debug;
-    type FileHandle: Read + Write;
+/// Reads and writes are positional and take `&self`, so the parts of one file can be transferred concurrently.
+#[async_trait(?Send)]
+pub trait FileWrapper: Sized {
+    type Error: Debug + Into<FileError>;
+    type FileHandle;
     fn create_file(filename: &str, mime: &Mime) -> Result<Self, Self::Error>
     where
         Self: Sized;
@@ -23,13 +62,20 @@ pub trait FileWrapper: Read + Write + Sized {
     /// Get the mime-type from the extension.
     fn get_mime_type(&self) -> Result<Mime, Self::Error>;
     /// Uses the first couple of bytes in the file to determine the mime-type
-    async fn infer_mime_type(&mut self) -> Result<infer::Type, Self::Error>;
+    async fn infer_mime_type(&self) -> Result<infer::Type, Self::Error>;
     fn get_size(&self) -> u64;
+    /// Reads up to `len` bytes at `offset`. Fewer bytes may be returned, an empty result is the end of the file.
+    async fn read_at(&self, offset: u64, len: usize) -> Result<Bytes, Self::Error>;
+    /// Writes all of `data` at `offset`, growing the file when needed.
+    async fn write_at(&self, offset: u64, data: &[u8]) -> Result<(), Self::Error>;
+    async fn metadata(&self) -> Result<FileMetadata, Self::Error>;
+    async fn truncate(&self, size: u64) -> Result<(), Self::Error>;
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::io::native::native_file::NativeFile;
+    use crate::io::memory::MemoryFile;
+    use crate::io::FileWrapper;
 
     #[test]
     fn test_delete() {}
@@ -37,11 +83,13 @@ mod tests {
     #[test]
     fn test_file_creation() {}
 
-    #[test]
-    fn test_write() {
-        let file = std::fs::File::open("hello").unwrap();
-
-
+    #[tokio::test]
+    async fn test_write() {
+        let file = MemoryFile::create_file("hello", &mime::TEXT_PLAIN).unwrap();
+        file.write_at(0, b"hello").await.unwrap();
+        file.write_at(5, b" world").await.unwrap();
+        assert_eq!(file.get_size(), 11);
+        assert_eq!(file.read_at(0, 11).await.unwrap().as_ref(), b"hello world");
     }
 
     #[test]
@@ -1,2 +1,4 @@
 #[cfg(not(target_family = "wasm"))]
-pub mod native_file;
\ No newline at end of file
+pub mod native_file;
+#[cfg(not(target_family = "wasm"))]
+pub mod positional;
\ No newline at end of file
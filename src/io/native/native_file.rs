@@ -2,10 +2,15 @@ use mime::{FromStrError, Mime};
 use std::fs::File;
 use std::path::Path;
 use std::sync::Arc;
-use std::{io::Write, os::unix::prelude::FileExt, str::FromStr};
+use std::io::Write;
+use crate::io::positional;
 use std::io::Read;
 use tonic::async_trait;
-use crate::io::FileWrapper;
+use crate::io::mime_resolver::MimeResolver;
+use crate::io::{FileMetadata, FileWrapper};
+use bytes::{Bytes, BytesMut};
+use time::OffsetDateTime;
+use crate::wrapper::bucket::download::ranged::PositionalWrite;
 
 #[derive(Clone)]
 pub struct NativeFile {
@@ -29,19 +34,40 @@ pub enum NativeBucketFileError {
     FromStrError(#[from] FromStrError),
 }
 
+impl NativeFile {
+    /// Writes at `offset` without moving the cursor, clones of the file can write different ranges at the same time.
+    pub fn write_all_at(&self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+        positional::write_all_at(&self.file_handle, buf, offset)
+    }
+
+    pub fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        positional::read_exact_at(&self.file_handle, buf, offset)
+    }
+}
+
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> std::io::Result<T> + Send + 'static) -> std::io::Result<T> {
+    tokio::task::spawn_blocking(f).await.map_err(std::io::Error::other)?
+}
+
+impl PositionalWrite for NativeFile {
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+        NativeFile::write_all_at(self, buf, offset)
+    }
+}
+
 impl Read for NativeFile {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.file_handle.read(buf)
+        (&*self.file_handle).read(buf)
     }
 }
 
 impl Write for NativeFile {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.file_handle.write(buf)
+        (&*self.file_handle).write(buf)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        self.file_handle.flush()
+        (&*self.file_handle).flush()
     }
 }
 
@@ -53,7 +79,7 @@ impl FileWrapper for NativeFile {
     where
         Self: Sized,
     {
-        let file = File::create(Path::new(filename))?;
+        let file = File::options().read(true).write(true).create(true).truncate(true).open(Path::new(filename))?;
         Ok(Self {
             file_handle: Arc::from(file),
             filename: filename.to_string(),
@@ -80,14 +106,13 @@ impl FileWrapper for NativeFile {
     }
 
     fn get_mime_type(&self) -> Result<Mime, Self::Error> {
-        let extension = self.get_extension().unwrap();
-        let mime = mime::Mime::from_str(extension.as_str())?;
-        Ok(mime)
+        Ok(MimeResolver::default()
+            .from_filename(&self.filename)
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM))
     }
 
-    async fn infer_mime_type(&mut self) -> Result<infer::Type, Self::Error> {
-        let mut buf = [1u8;16];
-        self.file_handle.read_exact(&mut buf);
+    async fn infer_mime_type(&self) -> Result<infer::Type, Self::Error> {
+        let buf = self.read_at(0, 16).await?;
         let kind = infer::get(&buf);
         match kind {
             None => Err(NativeBucketFileError::UnknownInferredFileType),
@@ -98,4 +123,48 @@ impl FileWrapper for NativeFile {
     fn get_size(&self) -> u64 {
         self.file_handle.metadata().unwrap().len()
     }
+
+    async fn read_at(&self, offset: u64, len: usize) -> Result<Bytes, Self::Error> {
+        // Disk access runs on the blocking pool, a slow disk must not stall the other transfers on the executor.
+        let file_handle = self.file_handle.clone();
+        blocking(move || -> std::io::Result<Bytes> {
+            let mut buf = BytesMut::zeroed(len);
+            let mut filled = 0;
+            while filled < len {
+                match positional::read_at(&file_handle, &mut buf[filled..], offset + filled as u64)? {
+                    0 => break,
+                    n => filled += n,
+                }
+            }
+            buf.truncate(filled);
+            Ok(buf.freeze())
+        })
+        .await
+        .map_err(NativeBucketFileError::from)
+    }
+
+    async fn write_at(&self, offset: u64, data: &[u8]) -> Result<(), Self::Error> {
+        let file_handle = self.file_handle.clone();
+        let data = Bytes::copy_from_slice(data);
+        Ok(blocking(move || positional::write_all_at(&file_handle, &data, offset)).await?)
+    }
+
+    async fn metadata(&self) -> Result<FileMetadata, Self::Error> {
+        let file_handle = self.file_handle.clone();
+        let metadata = blocking(move || file_handle.metadata()).await?;
+        #[cfg(unix)]
+        let mode = Some(std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()));
+        #[cfg(not(unix))]
+        let mode = None;
+        Ok(FileMetadata {
+            size_in_bytes: metadata.len(),
+            modified: metadata.modified().ok().map(OffsetDateTime::from),
+            mode,
+        })
+    }
+
+    async fn truncate(&self, size: u64) -> Result<(), Self::Error> {
+        let file_handle = self.file_handle.clone();
+        Ok(blocking(move || file_handle.set_len(size)).await?)
+    }
 }
@@ -0,0 +1,83 @@
+//! Reads and writes at an offset of a file, so concurrent transfers don't share a cursor.
+//! On Windows the cursor of the handle moves as well, files accessed here are only accessed by offset.
+use std::fs::File;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+
+/// Reads up to `buf.len()` bytes at `offset`, returns the number of bytes read, zero at the end of the file.
+#[cfg(unix)]
+pub fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    file.read_at(buf, offset)
+}
+
+/// Reads up to `buf.len()` bytes at `offset`, returns the number of bytes read, zero at the end of the file.
+#[cfg(windows)]
+pub fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    file.seek_read(buf, offset)
+}
+
+#[cfg(unix)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    file.write_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    file.seek_write(buf, offset)
+}
+
+/// Fills `buf` from `offset`, fails with `UnexpectedEof` when the file ends before.
+pub fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match read_at(file, buf, offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Writes all of `buf` at `offset`, the file grows when it is shorter.
+pub fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match write_at(file, buf, offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer")),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positional_access_leaves_other_offsets() {
+        let path = std::env::temp_dir().join(format!("bucket-positional-{}", uuid::Uuid::new_v4()));
+        let file = File::options().read(true).write(true).create_new(true).open(&path).unwrap();
+        write_all_at(&file, b"world", 6).unwrap();
+        write_all_at(&file, b"hello ", 0).unwrap();
+
+        let mut buf = [0u8; 11];
+        read_exact_at(&file, &mut buf, 0).unwrap();
+        assert_eq!(&buf, b"hello world");
+        let mut past_end = [0u8; 4];
+        assert_eq!(read_exact_at(&file, &mut past_end, 9).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        drop(file);
+        std::fs::remove_file(path).unwrap();
+    }
+}
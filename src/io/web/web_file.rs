@@ -9,7 +9,13 @@ use std::vec;
 use async_trait::async_trait;
 use wasm_bindgen::JsCast;
 use wasm_streams::ReadableStream;
-use crate::io::file::FileWrapper;
+use crate::io::mime_resolver::MimeResolver;
+use crate::io::{FileMetadata, FileWrapper};
+use bytes::Bytes;
+use std::cell::{Cell, RefCell};
+use wasm_bindgen::JsValue;
+use std::str::FromStr;
+use time::OffsetDateTime;
 
 #[derive(thiserror::Error, Debug)]
 pub enum WebBucketFileError {
@@ -21,6 +27,10 @@ pub enum WebBucketFileError {
     Empty,
     #[error("No extension")]
     NoExtension,
+    #[error("Reading the file failed")]
+    ReadError,
+    #[error("Building the file from its parts failed")]
+    BuildError,
     #[error(transparent)]
     TryFromIntError(#[from] TryFromIntError),
     #[error(transparent)]
@@ -53,11 +63,52 @@ fn convert_html_input_element_to_file_list(
 pub struct VirtualWebBucketFile {
     //pub file_handle: Option<web_sys::HtmlInputElement>,
     //pub virtual_file_details: Arc<VirtualFileDetails>,
-    file_handle: gloo::file::File,
+    /// Browser files are immutable, writes collect blob parts that are joined into a new file when it's read.
+    file_handle: RefCell<gloo::file::File>,
+    /// The whole content while writes are pending, empty when the handle is up to date.
+    parts: RefCell<Vec<JsValue>>,
+    size: Cell<u64>,
     filename: String,
     file_type: Mime,
 }
 
+impl VirtualWebBucketFile {
+    fn with_handle(file_handle: gloo::file::File, filename: String, file_type: Mime) -> Self {
+        Self {
+            size: Cell::new(file_handle.size()),
+            file_handle: RefCell::new(file_handle),
+            parts: RefCell::new(Vec::new()),
+            filename,
+            file_type,
+        }
+    }
+
+    async fn read_blob(blob: &gloo::file::Blob) -> Result<Vec<u8>, WebBucketFileError> {
+        gloo::file::futures::read_as_bytes(blob)
+            .await
+            .map_err(|_| WebBucketFileError::ReadError)
+    }
+
+    /// Joins the pending parts into a new file, only done once per batch of writes.
+    fn handle(&self) -> Result<gloo::file::File, WebBucketFileError> {
+        let mut parts = self.parts.borrow_mut();
+        if !parts.is_empty() {
+            let options = web_sys::FilePropertyBag::new();
+            options.set_type(&self.file_type.to_string());
+            let sequence: web_sys::js_sys::Array = parts.drain(..).collect();
+            let file = web_sys::File::new_with_blob_sequence_and_options(&sequence, &self.filename, &options)
+                .map_err(|_| WebBucketFileError::BuildError)?;
+            self.file_handle.replace(gloo::file::File::from(file));
+        }
+        Ok(self.file_handle.borrow().clone())
+    }
+
+    /// The parts of the current content from `start` to `end`.
+    fn slice(&self, start: u64, end: u64) -> Result<JsValue, WebBucketFileError> {
+        let handle = self.handle()?;
+        Ok(JsValue::from(handle.slice(start, end)))
+    }
+}
 
 #[async_trait(?Send)]
 impl FileWrapper for VirtualWebBucketFile {
@@ -74,54 +125,31 @@ impl FileWrapper for VirtualWebBucketFile {
             Some(mime.to_string().as_str()),
             None,
         );
-        Ok(Self {
-            file_handle,
-            filename: filename.to_string(),
-            file_type: mime.clone(),
-        })
+        Ok(Self::with_handle(file_handle, filename.to_string(), mime.clone()))
     }
     fn from_file_handle(file_handle: Self::FileHandle, filename: String, mime: &Mime) -> Self {
-        Self {
-            file_handle,
-            filename,
-            file_type: mime,
-        }
-    }
-
-    async fn read_chunk(&self, size: u64, offset: u64) -> Result<Vec<u8>, Self::Error> {
-        // https://github.com/rustwasm/gloo/blob/master/examples/file-hash/src/lib.rs#L53
-        let web_file: &web_sys::File = self.file_handle.as_ref();
-        let mut s = ReadableStream::from_raw(web_file.stream().unchecked_into()).into_stream();
-        let mut res_chunk = Vec::with_capacity(size as usize);
-        while let Some(chunk) = s.try_next().await.unwrap() {
-            let mut temp = chunk.unchecked_into::<Uint8Array>();
-            res_chunk.append(&mut temp.to_vec());
-        }
-        Ok(res_chunk)
+        Self::with_handle(file_handle, filename, mime.clone())
     }
 
-    fn read_stream(&self) -> Result<Box<dyn Read>, Self::Error> {
-        let web_file: &web_sys::File = self.file_handle.as_ref();
-        let mut s = ReadableStream::from_raw(web_file.stream().unchecked_into()).into_stream();
-        todo!()
-    }
-
-    fn get_extension(&self) -> Result<String, Self::Error> {
-        let extension = self
-            .filename
+    fn get_extension(&self) -> Option<String> {
+        self.filename
             .rsplit_once('.')
-            .ok_or(WebBucketFileError::NoExtension)?;
-        let (_, extension) = extension; // Unwrap the result
-        Ok(extension.to_string())
+            .map(|(_, extension)| extension.to_string())
     }
 
+    // The browser reports an empty type for extensions it doesn't know.
     fn get_mime_type(&self) -> Result<Mime, Self::Error> {
-        let mime = mime::Mime::try_from(self.file_handle.raw_mime_type().as_str()).unwrap();
-        Ok(mime)
+        let file = self.file_handle.borrow();
+        match mime::Mime::from_str(file.raw_mime_type().as_str()) {
+            Ok(mime) => Ok(mime),
+            Err(_) => Ok(MimeResolver::default()
+                .from_filename(&file.name())
+                .unwrap_or(mime::APPLICATION_OCTET_STREAM)),
+        }
     }
     //Checks the first couple of bytes of the file to get mime type.
     async fn infer_mime_type(&self) -> Result<infer::Type, Self::Error> {
-        let buf = self.read_chunk(16, 0).await?;
+        let buf = self.read_at(0, 16).await?;
         let kind = infer::get(&buf);
         match kind {
             None => Err(WebBucketFileError::UnknownFileType),
@@ -129,31 +157,65 @@ impl FileWrapper for VirtualWebBucketFile {
         }
     }
 
-    fn write_chunk(&self, chunk: &vec::Vec<u8>, offset: u64) -> Result<(), Self::Error> {
-        let web_file: &web_sys::File = self.file_handle.as_ref();
-        let mut writable_stream =
-            WritableStream::from_raw(web_file.stream().unchecked_into()).into_stream();
-        let mut writer = writable_stream.get_writer();
+    fn get_size(&self) -> u64 {
+        self.size.get()
+    }
 
-        let array = Uint8Array::new_with_length(chunk.len() as u32);
-        array.copy_from(chunk.as_slice());
+    async fn read_at(&self, offset: u64, len: usize) -> Result<Bytes, Self::Error> {
+        let size = self.get_size();
+        let start = u64::min(offset, size);
+        let end = u64::min(start.saturating_add(len as u64), size);
+        // Slicing the blob only reads the requested range.
+        let blob = self.handle()?.slice(start, end);
+        Ok(Bytes::from(Self::read_blob(&blob).await?))
+    }
 
-        // Writing the chunk to the stream
-        writer.write(&array.to_vec());
+    async fn write_at(&self, offset: u64, data: &[u8]) -> Result<(), Self::Error> {
+        let size = self.size.get();
+        let end = offset + data.len() as u64;
+        let data = JsValue::from(gloo::file::Blob::new(data));
+        if offset < size {
+            // An overwrite joins what is pending, the new content is cut around the written range.
+            let mut parts = vec![self.slice(0, offset)?, data];
+            if end < size {
+                parts.push(self.slice(end, size)?);
+            }
+            self.parts.replace(parts);
+        } else {
+            let mut parts = self.parts.borrow_mut();
+            if parts.is_empty() {
+                parts.push(JsValue::from(self.file_handle.borrow().slice(0, size)));
+            }
+            if offset > size {
+                parts.push(JsValue::from(gloo::file::Blob::new(&vec![0u8; (offset - size) as usize][..])));
+            }
+            parts.push(data);
+        }
+        self.size.set(u64::max(size, end));
         Ok(())
     }
 
-    fn write_stream(&self, stream: &dyn Write) -> Result<(), Self::Error> {
-        let web_file: &web_sys::File = self.file_handle.as_ref();
-        let mut writable_stream =
-            WritableStream::from_raw(web_file.stream().unchecked_into()).into_stream();
-        let mut writer = writable_stream.get_writer();
-
-        todo!()
+    async fn metadata(&self) -> Result<FileMetadata, Self::Error> {
+        let handle = self.handle()?;
+        Ok(FileMetadata {
+            size_in_bytes: handle.size(),
+            modified: OffsetDateTime::from_unix_timestamp_nanos(
+                handle.last_modified_time().duration_since(std::time::UNIX_EPOCH).map(|since| since.as_nanos() as i128).unwrap_or_default(),
+            )
+            .ok(),
+            mode: None,
+        })
     }
 
-    fn get_size(&self) -> u64 {
-        self.file_handle.size()
+    async fn truncate(&self, size: u64) -> Result<(), Self::Error> {
+        let current = self.size.get();
+        let mut parts = vec![self.slice(0, u64::min(size, current))?];
+        if size > current {
+            parts.push(JsValue::from(gloo::file::Blob::new(&vec![0u8; (size - current) as usize][..])));
+        }
+        self.parts.replace(parts);
+        self.size.set(size);
+        Ok(())
     }
 }
 
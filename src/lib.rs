@@ -17,4 +17,11 @@ pub mod token;
 pub mod io;
 pub mod encryption;
 pub mod captcha;
+pub mod pipeline;
+pub mod chunking;
+pub mod delta;
+pub mod bundle;
+pub mod transfer;
+pub mod integrity;
+pub mod archive;
 
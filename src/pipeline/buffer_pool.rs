@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use bytes::{Bytes, BytesMut};
+
+/// Memory a single transfer is allowed to keep in buffers unless configured otherwise.
+pub const DEFAULT_TRANSFER_MEMORY_BUDGET: u64 = 256 * 1024 * 1024;
+
+/// Buffers kept around for reuse per pool, anything above is released back to the allocator.
+const MAX_POOLED_BUFFERS: usize = 64;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MemoryBudgetError {
+    #[error("Requested {requested} bytes but the budget only allows {limit} bytes")]
+    ExceedsLimit { requested: u64, limit: u64 },
+}
+
+struct BudgetState {
+    available: u64,
+    /// One entry per waiting [`Reserve`], keyed by its id so polling again replaces the waker.
+    waiters: VecDeque<(u64, Waker)>,
+    next_waiter: u64,
+}
+
+/// Upper bound on the bytes held in buffers, reserving more than is available waits until memory is released.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    limit: u64,
+    state: Arc<Mutex<BudgetState>>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            state: Arc::new(Mutex::new(BudgetState {
+                available: limit,
+                waiters: VecDeque::new(),
+                next_waiter: 0,
+            })),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(u64::MAX)
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn available(&self) -> u64 {
+        self.state.lock().unwrap().available
+    }
+
+    pub fn try_reserve(&self, bytes: u64) -> Option<MemoryReservation> {
+        let mut state = self.state.lock().unwrap();
+        if state.available < bytes {
+            return None;
+        }
+        state.available -= bytes;
+        Some(MemoryReservation {
+            budget: self.clone(),
+            bytes,
+        })
+    }
+
+    pub fn reserve(&self, bytes: u64) -> Result<Reserve, MemoryBudgetError> {
+        if bytes > self.limit {
+            return Err(MemoryBudgetError::ExceedsLimit {
+                requested: bytes,
+                limit: self.limit,
+            });
+        }
+        Ok(Reserve {
+            budget: self.clone(),
+            bytes,
+            waiter: None,
+        })
+    }
+
+    fn release(&self, bytes: u64) {
+        let waiters = {
+            let mut state = self.state.lock().unwrap();
+            state.available += bytes;
+            std::mem::take(&mut state.waiters)
+        };
+        for (_, waker) in waiters {
+            waker.wake();
+        }
+    }
+}
+
+/// Future returned by [`MemoryBudget::reserve`].
+pub struct Reserve {
+    budget: MemoryBudget,
+    bytes: u64,
+    /// Id of the waker registered with the budget while the reservation waits.
+    waiter: Option<u64>,
+}
+
+impl Future for Reserve {
+    type Output = MemoryReservation;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut state = this.budget.state.lock().unwrap();
+        if state.available >= this.bytes {
+            state.available -= this.bytes;
+            if let Some(id) = this.waiter.take() {
+                state.waiters.retain(|(waiter, _)| *waiter != id);
+            }
+            return Poll::Ready(MemoryReservation {
+                budget: this.budget.clone(),
+                bytes: this.bytes,
+            });
+        }
+        // Releasing memory wakes and removes every waiter, a future that is still registered only swaps its waker.
+        let registered = match this.waiter {
+            Some(id) => state.waiters.iter_mut().find(|(waiter, _)| *waiter == id),
+            None => None,
+        };
+        match registered {
+            Some((_, waker)) => waker.clone_from(cx.waker()),
+            None => {
+                let id = state.next_waiter;
+                state.next_waiter += 1;
+                state.waiters.push_back((id, cx.waker().clone()));
+                this.waiter = Some(id);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Reserve {
+    fn drop(&mut self) {
+        if let Some(id) = self.waiter {
+            self.budget.state.lock().unwrap().waiters.retain(|(waiter, _)| *waiter != id);
+        }
+    }
+}
+
+/// Memory taken from a [`MemoryBudget`], given back when dropped.
+pub struct MemoryReservation {
+    budget: MemoryBudget,
+    bytes: u64,
+}
+
+impl MemoryReservation {
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes);
+    }
+}
+
+struct PoolState {
+    free: Vec<(BytesMut, MemoryReservation)>,
+}
+
+/// Reusable buffers for the transfer pipeline.
+/// Every buffer handed out is accounted against the pool's [`MemoryBudget`], and returns to the pool once the last
+/// reference to it is dropped, including references held by frozen [`Bytes`].
+#[derive(Clone)]
+pub struct BufferPool {
+    budget: MemoryBudget,
+    state: Arc<Mutex<PoolState>>,
+}
+
+impl BufferPool {
+    pub fn new(budget: MemoryBudget) -> Self {
+        Self {
+            budget,
+            state: Arc::new(Mutex::new(PoolState { free: Vec::new() })),
+        }
+    }
+
+    pub fn budget(&self) -> &MemoryBudget {
+        &self.budget
+    }
+
+    /// Returns an empty buffer with at least `capacity` bytes of room, waits if the budget is exhausted.
+    pub async fn acquire(&self, capacity: usize) -> Result<PooledBuffer, MemoryBudgetError> {
+        if let Some(buffer) = self.take_free(capacity) {
+            return Ok(buffer);
+        }
+        let reserve = self.budget.reserve(capacity as u64)?;
+        // Idle buffers too small for this request still hold budget, they are released instead of waited on.
+        if self.budget.available() < capacity as u64 {
+            self.release_idle();
+        }
+        let reservation = reserve.await;
+        Ok(PooledBuffer {
+            buf: BytesMut::with_capacity(capacity),
+            reservation: Some(reservation),
+            pool: self.clone(),
+        })
+    }
+
+    fn take_free(&self, capacity: usize) -> Option<PooledBuffer> {
+        let mut state = self.state.lock().unwrap();
+        let position = state.free.iter().position(|(buf, _)| buf.capacity() >= capacity)?;
+        let (buf, reservation) = state.free.swap_remove(position);
+        Some(PooledBuffer {
+            buf,
+            reservation: Some(reservation),
+            pool: self.clone(),
+        })
+    }
+
+    /// Drops every idle buffer, giving its memory back to the budget.
+    pub fn release_idle(&self) {
+        let idle = std::mem::take(&mut self.state.lock().unwrap().free);
+        drop(idle);
+    }
+
+    fn give_back(&self, mut buf: BytesMut, reservation: MemoryReservation) {
+        let mut state = self.state.lock().unwrap();
+        if state.free.len() < MAX_POOLED_BUFFERS {
+            buf.clear();
+            state.free.push((buf, reservation));
+        }
+    }
+}
+
+/// Buffer borrowed from a [`BufferPool`].
+pub struct PooledBuffer {
+    buf: BytesMut,
+    reservation: Option<MemoryReservation>,
+    pool: BufferPool,
+}
+
+impl PooledBuffer {
+    /// Freezes the buffer without copying, the memory returns to the pool when the last clone of the bytes is dropped.
+    pub fn freeze(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(reservation) = self.reservation.take() {
+            // A buffer that grew past its reservation is not reused, the allocation no longer matches the budget.
+            if self.buf.capacity() as u64 <= reservation.bytes() {
+                self.pool.give_back(std::mem::take(&mut self.buf), reservation);
+            }
+        }
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(MemoryBudget::new(DEFAULT_TRANSFER_MEMORY_BUDGET))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn test_reserve_keeps_one_waker() {
+        let budget = MemoryBudget::new(10);
+        let held = budget.try_reserve(10).unwrap();
+        let mut reserve = budget.reserve(5).unwrap();
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        for _ in 0..3 {
+            assert!(Pin::new(&mut reserve).poll(&mut cx).is_pending());
+        }
+        assert_eq!(budget.state.lock().unwrap().waiters.len(), 1);
+        drop(held);
+        assert!(Pin::new(&mut reserve).poll(&mut cx).is_ready());
+        assert!(budget.state.lock().unwrap().waiters.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_releases_idle_buffers() {
+        let pool = BufferPool::new(MemoryBudget::new(100));
+        drop(pool.acquire(60).await.unwrap());
+        // The idle buffer is too small for the request and would otherwise hold the budget it waits for.
+        let buffer = pool.acquire(80).now_or_never().expect("idle buffers are released").unwrap();
+        assert!(buffer.capacity() >= 80);
+    }
+}
@@ -0,0 +1,56 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use crate::pipeline::PipelineError;
+
+/// Every record is prefixed by its length as a big endian u32.
+pub const RECORD_HEADER_SIZE: usize = 4;
+
+/// Upper bound for a single record, protects against allocating on a corrupted length prefix.
+pub const MAX_RECORD_SIZE: usize = 64 * 1024 * 1024;
+
+pub fn encode_record(payload: &[u8], output: &mut BytesMut) {
+    output.reserve(RECORD_HEADER_SIZE + payload.len());
+    output.put_u32(payload.len() as u32);
+    output.put_slice(payload);
+}
+
+/// Reassembles length prefixed records from chunks that do not line up with record boundaries,
+/// as is the case with HTTP bodies.
+#[derive(Default)]
+pub struct RecordDecoder {
+    buf: BytesMut,
+}
+
+impl RecordDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// Returns the next complete record, or none if more bytes are needed.
+    pub fn next_record(&mut self) -> Result<Option<Bytes>, PipelineError> {
+        if self.buf.len() < RECORD_HEADER_SIZE {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes(self.buf[..RECORD_HEADER_SIZE].try_into().unwrap()) as usize;
+        if len > MAX_RECORD_SIZE {
+            return Err(PipelineError::MalformedRecord);
+        }
+        if self.buf.len() < RECORD_HEADER_SIZE + len {
+            return Ok(None);
+        }
+        self.buf.advance(RECORD_HEADER_SIZE);
+        if self.buf.len() == len {
+            // Taking the whole buffer keeps the record uniquely owned, so stages can reuse it in place.
+            return Ok(Some(std::mem::take(&mut self.buf).freeze()));
+        }
+        Ok(Some(self.buf.split_to(len).freeze()))
+    }
+
+    /// Bytes that have been pushed but not returned as a record.
+    pub fn pending(&self) -> usize {
+        self.buf.len()
+    }
+}
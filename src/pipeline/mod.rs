@@ -0,0 +1,336 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::{AsyncRead, AsyncReadExt, Stream, StreamExt};
+use crate::pipeline::buffer_pool::{BufferPool, MemoryBudgetError, PooledBuffer};
+
+pub mod buffer_pool;
+pub mod framing;
+pub mod stages;
+
+/// The kind of transformation a stage performs.
+/// The declaration order is the order stages run in on upload, downloads run them in reverse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StageKind {
+    Compression,
+    Encryption,
+    Signature,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineDirection {
+    /// read -> compress -> encrypt -> sign -> HTTP body
+    Upload,
+    /// HTTP body -> verify -> decrypt -> decompress -> write
+    Download,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PipelineError {
+    #[error("Stage {0:?} added more than once")]
+    DuplicateStage(StageKind),
+    #[error("Malformed record in chunked stream")]
+    MalformedRecord,
+    #[error("Stream ended before the final record")]
+    Truncated,
+    #[error("Failed to compress chunk")]
+    CompressionError(#[source] lz4_flex::block::CompressError),
+    #[error("Failed to decompress chunk")]
+    DecompressionError(#[source] lz4_flex::block::DecompressError),
+    #[error("Failed to encrypt chunk: {0}")]
+    EncryptionError(aes_gcm::Error),
+    #[error("Failed to decrypt record {0}")]
+    DecryptionError(u64),
+    #[error("Signature stage failed")]
+    SignatureError(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error(transparent)]
+    MemoryBudgetError(#[from] MemoryBudgetError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// Free bytes a stage wants in front of and behind a chunk to transform it in place.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Room {
+    pub front: usize,
+    pub back: usize,
+}
+
+/// A chunk on its way through the pipeline.
+pub enum Chunk {
+    Shared(Bytes),
+    /// A pooled buffer only this chunk uses. The data starts at `front`, the bytes before it and the spare
+    /// capacity behind it are free for the next stage.
+    Owned { buffer: PooledBuffer, front: usize },
+}
+
+impl Chunk {
+    pub fn len(&self) -> usize {
+        match self {
+            Chunk::Shared(bytes) => bytes.len(),
+            Chunk::Owned { buffer, front } => buffer.len() - front,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The data as `Bytes`, without copying.
+    pub fn freeze(self) -> Bytes {
+        match self {
+            Chunk::Shared(bytes) => bytes,
+            Chunk::Owned { mut buffer, front } => {
+                bytes::Buf::advance(&mut *buffer, front);
+                buffer.freeze()
+            }
+        }
+    }
+}
+
+/// A single step of the transfer pipeline.
+/// Stages receive chunks in order and may buffer, a stage returning an empty chunk simply has nothing to emit yet.
+#[async_trait(?Send)]
+pub trait TransformStage {
+    fn kind(&self) -> StageKind;
+    async fn transform(&mut self, chunk: Bytes) -> Result<Bytes, PipelineError>;
+    /// Called once after the last chunk, returns whatever the stage still has buffered.
+    async fn finish(&mut self) -> Result<Bytes, PipelineError>;
+    /// Same as `transform`, stages that work in place override it and take over `Chunk::Owned` buffers.
+    async fn transform_chunk(&mut self, chunk: Chunk) -> Result<Chunk, PipelineError> {
+        Ok(Chunk::Shared(self.transform(chunk.freeze()).await?))
+    }
+    /// Room this stage needs to work in place on a `Chunk::Owned`.
+    fn room(&self) -> Room {
+        Room::default()
+    }
+    /// Set by the pipeline to the room of the next stage, stages allocating their output leave it free.
+    fn set_output_room(&mut self, room: Room) {
+        let _ = room;
+    }
+    /// Stages allocating from a pool switch to `pool`, so they share the memory budget of the transfer.
+    fn set_buffer_pool(&mut self, pool: &BufferPool) {
+        let _ = pool;
+    }
+}
+
+/// Ordered chain of [`TransformStage`]s shared by the upload and download handlers.
+pub struct Pipeline {
+    direction: PipelineDirection,
+    stages: Vec<Box<dyn TransformStage>>,
+}
+
+pub struct PipelineBuilder {
+    direction: PipelineDirection,
+    stages: Vec<Box<dyn TransformStage>>,
+}
+
+impl PipelineBuilder {
+    pub fn stage(mut self, stage: impl TransformStage + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    pub fn optional_stage(self, stage: Option<impl TransformStage + 'static>) -> Self {
+        match stage {
+            None => self,
+            Some(stage) => self.stage(stage),
+        }
+    }
+
+    /// Sorts the stages into their declared order, the order they were added in does not matter.
+    pub fn build(mut self) -> Result<Pipeline, PipelineError> {
+        self.stages.sort_by_key(|stage| stage.kind());
+        for pair in self.stages.windows(2) {
+            if pair[0].kind() == pair[1].kind() {
+                return Err(PipelineError::DuplicateStage(pair[0].kind()));
+            }
+        }
+        if self.direction == PipelineDirection::Download {
+            self.stages.reverse();
+        }
+        for i in 1..self.stages.len() {
+            let room = self.stages[i].room();
+            self.stages[i - 1].set_output_room(room);
+        }
+        Ok(Pipeline {
+            direction: self.direction,
+            stages: self.stages,
+        })
+    }
+}
+
+impl Pipeline {
+    pub fn builder(direction: PipelineDirection) -> PipelineBuilder {
+        PipelineBuilder {
+            direction,
+            stages: Vec::new(),
+        }
+    }
+
+    /// Pipeline without any stages, chunks are passed through untouched.
+    pub fn passthrough(direction: PipelineDirection) -> Self {
+        Self {
+            direction,
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn direction(&self) -> PipelineDirection {
+        self.direction
+    }
+
+    pub fn stage_kinds(&self) -> Vec<StageKind> {
+        self.stages.iter().map(|stage| stage.kind()).collect()
+    }
+
+    pub async fn process(&mut self, chunk: Bytes) -> Result<Bytes, PipelineError> {
+        let mut chunk = chunk;
+        for stage in self.stages.iter_mut() {
+            if chunk.is_empty() {
+                break;
+            }
+            chunk = stage.transform(chunk).await?;
+        }
+        Ok(chunk)
+    }
+
+    /// Room the first stage needs, a chunk read into a buffer with this room is processed without copying it.
+    pub fn input_room(&self) -> Room {
+        self.stages.first().map(|stage| stage.room()).unwrap_or_default()
+    }
+
+    /// Same as `process`, owned buffers are handed from stage to stage for as long as the stages work in place.
+    pub async fn process_chunk(&mut self, chunk: Chunk) -> Result<Bytes, PipelineError> {
+        let mut chunk = chunk;
+        for stage in self.stages.iter_mut() {
+            if chunk.is_empty() {
+                break;
+            }
+            chunk = stage.transform_chunk(chunk).await?;
+        }
+        Ok(chunk.freeze())
+    }
+
+    /// Moves every stage to `pool`, see [`TransformStage::set_buffer_pool`].
+    pub fn set_buffer_pool(&mut self, pool: &BufferPool) {
+        for stage in self.stages.iter_mut() {
+            stage.set_buffer_pool(pool);
+        }
+    }
+
+    /// Flushes every stage in order, the tail of each stage is fed through the stages after it before they are flushed.
+    pub async fn finish(&mut self) -> Result<Bytes, PipelineError> {
+        let mut output = BytesMut::new();
+        for i in 0..self.stages.len() {
+            let mut tail = self.stages[i].finish().await?;
+            for stage in self.stages[i + 1..].iter_mut() {
+                if tail.is_empty() {
+                    break;
+                }
+                tail = stage.transform(tail).await?;
+            }
+            output.extend_from_slice(&tail);
+        }
+        Ok(output.freeze())
+    }
+
+    /// Drives the pipeline over a stream of chunks, the flushed tail is emitted as the last item.
+    pub fn into_stream<S>(self, input: S) -> impl Stream<Item = Result<Bytes, PipelineError>>
+    where
+        S: Stream<Item = Result<Bytes, std::io::Error>> + Unpin,
+    {
+        futures::stream::unfold(
+            (self, input, false),
+            |(mut pipeline, mut input, finished)| async move {
+                if finished {
+                    return None;
+                }
+                loop {
+                    match input.next().await {
+                        Some(Ok(chunk)) => match pipeline.process(chunk).await {
+                            Ok(out) if out.is_empty() => continue,
+                            Ok(out) => return Some((Ok(out), (pipeline, input, false))),
+                            Err(e) => return Some((Err(e), (pipeline, input, true))),
+                        },
+                        Some(Err(e)) => return Some((Err(e.into()), (pipeline, input, true))),
+                        None => {
+                            let tail = pipeline.finish().await;
+                            return Some((tail, (pipeline, input, true)));
+                        }
+                    }
+                }
+            },
+        )
+        .filter(|item| futures::future::ready(!matches!(item, Ok(chunk) if chunk.is_empty())))
+    }
+}
+
+/// Splits an [`AsyncRead`] into chunks of `chunk_size`, only the last chunk may be shorter.
+pub fn read_chunks<R>(reader: R, chunk_size: usize) -> impl Stream<Item = Result<Bytes, std::io::Error>>
+where
+    R: AsyncRead + Unpin,
+{
+    futures::stream::unfold((reader, false), move |(mut reader, eof)| async move {
+        if eof {
+            return None;
+        }
+        let mut buf = vec![0u8; chunk_size];
+        let mut filled = 0;
+        while filled < chunk_size {
+            match reader.read(&mut buf[filled..]).await {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) => return Some((Err(e), (reader, true))),
+            }
+        }
+        if filled == 0 {
+            return None;
+        }
+        buf.truncate(filled);
+        let eof = filled < chunk_size;
+        Some((Ok(Bytes::from(buf)), (reader, eof)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use crate::pipeline::stages::compression::{Lz4CompressStage, Lz4DecompressStage};
+    use crate::pipeline::{Pipeline, PipelineDirection, PipelineError, StageKind};
+
+    #[test]
+    fn test_duplicate_stage() {
+        let res = Pipeline::builder(PipelineDirection::Upload)
+            .stage(Lz4CompressStage::new())
+            .stage(Lz4CompressStage::new())
+            .build();
+        assert!(matches!(res, Err(PipelineError::DuplicateStage(StageKind::Compression))));
+    }
+
+    #[tokio::test]
+    async fn test_compression_round_trip() {
+        let mut upload = Pipeline::builder(PipelineDirection::Upload)
+            .stage(Lz4CompressStage::new())
+            .build()
+            .unwrap();
+        let mut download = Pipeline::builder(PipelineDirection::Download)
+            .stage(Lz4DecompressStage::new())
+            .build()
+            .unwrap();
+        let plaintext: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut body = BytesMut::new();
+        for chunk in plaintext.chunks(4096) {
+            body.extend_from_slice(&upload.process(Bytes::copy_from_slice(chunk)).await.unwrap());
+        }
+        body.extend_from_slice(&upload.finish().await.unwrap());
+
+        // The HTTP body does not line up with the records.
+        let mut output = BytesMut::new();
+        for chunk in body.chunks(1000) {
+            output.extend_from_slice(&download.process(Bytes::copy_from_slice(chunk)).await.unwrap());
+        }
+        output.extend_from_slice(&download.finish().await.unwrap());
+        assert_eq!(output.as_ref(), plaintext.as_slice());
+    }
+}
@@ -0,0 +1,105 @@
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use crate::pipeline::buffer_pool::BufferPool;
+use crate::pipeline::framing::{RecordDecoder, RECORD_HEADER_SIZE};
+use crate::pipeline::{Chunk, PipelineError, Room, StageKind, TransformStage};
+
+/// The uncompressed size is stored in front of every block, little endian like `lz4_flex::block::compress_prepend_size`.
+const SIZE_PREFIX: usize = 4;
+
+/// Compresses every chunk into its own lz4 block, so chunks can be decompressed independently.
+#[derive(Default)]
+pub struct Lz4CompressStage {
+    pool: BufferPool,
+    /// Left free around each block for the next stage, so it can work on the block in place.
+    output_room: Room,
+}
+
+impl Lz4CompressStage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_pool(pool: BufferPool) -> Self {
+        Self {
+            pool,
+            output_room: Room::default(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl TransformStage for Lz4CompressStage {
+    fn kind(&self) -> StageKind {
+        StageKind::Compression
+    }
+
+    async fn transform(&mut self, chunk: Bytes) -> Result<Bytes, PipelineError> {
+        Ok(self.transform_chunk(Chunk::Shared(chunk)).await?.freeze())
+    }
+
+    async fn finish(&mut self) -> Result<Bytes, PipelineError> {
+        Ok(Bytes::new())
+    }
+
+    async fn transform_chunk(&mut self, chunk: Chunk) -> Result<Chunk, PipelineError> {
+        let chunk = chunk.freeze();
+        let Room { front, back } = self.output_room;
+        let max_output = lz4_flex::block::get_maximum_output_size(chunk.len());
+        let header = front + RECORD_HEADER_SIZE + SIZE_PREFIX;
+        let mut output = self.pool.acquire(header + max_output + back).await?;
+        output.resize(front, 0);
+        output.put_u32(0); // Record length, filled in once the compressed size is known.
+        output.put_u32_le(chunk.len() as u32);
+        output.resize(header + max_output, 0);
+        let compressed = lz4_flex::block::compress_into(&chunk, &mut output[header..])
+            .map_err(PipelineError::CompressionError)?;
+        output.truncate(header + compressed);
+        output[front..front + RECORD_HEADER_SIZE].copy_from_slice(&((SIZE_PREFIX + compressed) as u32).to_be_bytes());
+        Ok(Chunk::Owned { buffer: output, front })
+    }
+
+    fn set_output_room(&mut self, room: Room) {
+        self.output_room = room;
+    }
+
+    fn set_buffer_pool(&mut self, pool: &BufferPool) {
+        self.pool = pool.clone();
+    }
+}
+
+#[derive(Default)]
+pub struct Lz4DecompressStage {
+    decoder: RecordDecoder,
+}
+
+impl Lz4DecompressStage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait(?Send)]
+impl TransformStage for Lz4DecompressStage {
+    fn kind(&self) -> StageKind {
+        StageKind::Compression
+    }
+
+    async fn transform(&mut self, chunk: Bytes) -> Result<Bytes, PipelineError> {
+        self.decoder.push(&chunk);
+        let mut output = BytesMut::new();
+        while let Some(record) = self.decoder.next_record()? {
+            let decompressed = lz4_flex::block::decompress_size_prepended(&record)
+                .map_err(PipelineError::DecompressionError)?;
+            output.extend_from_slice(&decompressed);
+        }
+        Ok(output.freeze())
+    }
+
+    async fn finish(&mut self) -> Result<Bytes, PipelineError> {
+        if self.decoder.pending() != 0 {
+            return Err(PipelineError::Truncated);
+        }
+        Ok(Bytes::new())
+    }
+}
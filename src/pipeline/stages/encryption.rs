@@ -0,0 +1,203 @@
+use aes_gcm::aead::generic_array::typenum;
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::{Aes256Gcm, Nonce, Tag};
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use crate::encryption::key::derived_key::EncryptionDerivedKey;
+use crate::pipeline::buffer_pool::BufferPool;
+use crate::pipeline::framing::{RecordDecoder, RECORD_HEADER_SIZE};
+use crate::pipeline::{Chunk, PipelineError, Room, StageKind, TransformStage};
+
+pub const TAG_SIZE: usize = 16;
+
+/// Nonce for the record at `index`, the counter is xor'ed into the last 8 bytes of the base nonce.
+pub fn record_nonce(base: &Nonce<typenum::U12>, index: u64) -> Nonce<typenum::U12> {
+    let mut nonce = *base;
+    for (byte, counter) in nonce[4..].iter_mut().zip(index.to_be_bytes()) {
+        *byte ^= counter;
+    }
+    nonce
+}
+
+/// The associated data binds each record to its position, and marks the empty terminating record
+/// so a truncated stream is detected.
+fn record_aad(index: u64, is_final: bool) -> [u8; 9] {
+    let mut aad = [0u8; 9];
+    aad[..8].copy_from_slice(&index.to_be_bytes());
+    aad[8] = is_final as u8;
+    aad
+}
+
+/// Chunked AES-256-GCM, each chunk becomes an independently authenticated record.
+pub struct Aes256GcmEncryptStage {
+    cipher: Aes256Gcm,
+    base_nonce: Nonce<typenum::U12>,
+    index: u64,
+    pool: BufferPool,
+}
+
+impl Aes256GcmEncryptStage {
+    pub fn new(secret: &EncryptionDerivedKey, base_nonce: Nonce<typenum::U12>) -> Self {
+        Self::from_cipher(secret.get_aead_encryption_key().unwrap(), base_nonce) // Infallible
+    }
+
+    pub fn from_cipher(cipher: Aes256Gcm, base_nonce: Nonce<typenum::U12>) -> Self {
+        Self {
+            cipher,
+            base_nonce,
+            index: 0,
+            pool: BufferPool::default(),
+        }
+    }
+
+    pub fn with_pool(mut self, pool: BufferPool) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Encrypts a plaintext that is shared with someone else, it is copied into a pooled record buffer first.
+    async fn seal(&mut self, plaintext: &[u8], is_final: bool) -> Result<Bytes, PipelineError> {
+        let mut record = self.pool.acquire(RECORD_HEADER_SIZE + plaintext.len() + TAG_SIZE).await?;
+        record.put_slice(&[0u8; RECORD_HEADER_SIZE]);
+        record.put_slice(plaintext);
+        self.seal_in_place(&mut record, RECORD_HEADER_SIZE, is_final)?;
+        Ok(record.freeze())
+    }
+
+    /// Encrypts `record[front..]` where it is, writes the header into the room in front of it and appends the tag.
+    /// Returns where the record starts.
+    fn seal_in_place(&mut self, record: &mut BytesMut, front: usize, is_final: bool) -> Result<usize, PipelineError> {
+        let aad = record_aad(self.index, is_final);
+        let start = front - RECORD_HEADER_SIZE;
+        let record_len = (record.len() - front + TAG_SIZE) as u32;
+        record[start..front].copy_from_slice(&record_len.to_be_bytes());
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&record_nonce(&self.base_nonce, self.index), &aad, &mut record[front..])
+            .map_err(PipelineError::EncryptionError)?;
+        record.put_slice(&tag);
+        self.index += 1;
+        Ok(start)
+    }
+}
+
+#[async_trait(?Send)]
+impl TransformStage for Aes256GcmEncryptStage {
+    fn kind(&self) -> StageKind {
+        StageKind::Encryption
+    }
+
+    async fn transform(&mut self, chunk: Bytes) -> Result<Bytes, PipelineError> {
+        self.seal(&chunk, false).await
+    }
+
+    async fn finish(&mut self) -> Result<Bytes, PipelineError> {
+        self.seal(&[], true).await
+    }
+
+    // The reader leaves room for the header and the tag, the record is then sealed in the buffer it was read into.
+    async fn transform_chunk(&mut self, chunk: Chunk) -> Result<Chunk, PipelineError> {
+        match chunk {
+            Chunk::Owned { mut buffer, front } if front >= RECORD_HEADER_SIZE && buffer.capacity() - buffer.len() >= TAG_SIZE => {
+                let start = self.seal_in_place(&mut buffer, front, false)?;
+                Ok(Chunk::Owned { buffer, front: start })
+            }
+            chunk => Ok(Chunk::Shared(self.seal(&chunk.freeze(), false).await?)),
+        }
+    }
+
+    fn room(&self) -> Room {
+        Room {
+            front: RECORD_HEADER_SIZE,
+            back: TAG_SIZE,
+        }
+    }
+
+    fn set_buffer_pool(&mut self, pool: &BufferPool) {
+        self.pool = pool.clone();
+    }
+}
+
+pub struct Aes256GcmDecryptStage {
+    cipher: Aes256Gcm,
+    base_nonce: Nonce<typenum::U12>,
+    index: u64,
+    decoder: RecordDecoder,
+    seen_final: bool,
+}
+
+impl Aes256GcmDecryptStage {
+    pub fn new(secret: &EncryptionDerivedKey, base_nonce: Nonce<typenum::U12>) -> Self {
+        Self::from_cipher(secret.get_aead_encryption_key().unwrap(), base_nonce) // Infallible
+    }
+
+    pub fn from_cipher(cipher: Aes256Gcm, base_nonce: Nonce<typenum::U12>) -> Self {
+        Self {
+            cipher,
+            base_nonce,
+            index: 0,
+            decoder: RecordDecoder::new(),
+            seen_final: false,
+        }
+    }
+
+    /// Starts at the record with the given index, used when a range of the ciphertext is downloaded on its own.
+    /// The range must start at a record boundary.
+    pub fn with_first_record_index(mut self, index: u64) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Decrypts in place, the record is only copied when it still shares its allocation with the decoder.
+    fn open(&mut self, record: Bytes) -> Result<Bytes, PipelineError> {
+        if self.seen_final || record.len() < TAG_SIZE {
+            return Err(PipelineError::MalformedRecord);
+        }
+        // Only the terminating record has an empty plaintext.
+        let is_final = record.len() == TAG_SIZE;
+        let aad = record_aad(self.index, is_final);
+        let mut buf = record.try_into_mut().unwrap_or_else(|shared| BytesMut::from(shared.as_ref()));
+        let plaintext_len = buf.len() - TAG_SIZE;
+        let tag = Tag::clone_from_slice(&buf[plaintext_len..]);
+        self.cipher
+            .decrypt_in_place_detached(
+                &record_nonce(&self.base_nonce, self.index),
+                &aad,
+                &mut buf[..plaintext_len],
+                &tag,
+            )
+            .map_err(|_| PipelineError::DecryptionError(self.index))?;
+        buf.truncate(plaintext_len);
+        self.index += 1;
+        self.seen_final = is_final;
+        Ok(buf.freeze())
+    }
+}
+
+#[async_trait(?Send)]
+impl TransformStage for Aes256GcmDecryptStage {
+    fn kind(&self) -> StageKind {
+        StageKind::Encryption
+    }
+
+    async fn transform(&mut self, chunk: Bytes) -> Result<Bytes, PipelineError> {
+        self.decoder.push(&chunk);
+        let mut records = Vec::new();
+        while let Some(record) = self.decoder.next_record()? {
+            records.push(self.open(record)?);
+        }
+        // Usually a chunk completes a single record, which is returned as is.
+        match records.len() {
+            0 => Ok(Bytes::new()),
+            1 => Ok(records.pop().unwrap()),
+            _ => Ok(records.concat().into()),
+        }
+    }
+
+    async fn finish(&mut self) -> Result<Bytes, PipelineError> {
+        if !self.seen_final || self.decoder.pending() != 0 {
+            return Err(PipelineError::Truncated);
+        }
+        Ok(Bytes::new())
+    }
+}
@@ -0,0 +1,3 @@
+pub mod compression;
+pub mod encryption;
+pub mod signature;
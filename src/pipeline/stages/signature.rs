@@ -0,0 +1,109 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use async_trait::async_trait;
+use bytes::Bytes;
+use ed25519_compact::Signature;
+use highway::{HighwayHash, HighwayHasher};
+use crate::encryption::mte::hash_based_signature::HashBasedSignature;
+use crate::encryption::mte::hash_based_signature_verifier::HashBasedSignatureVerifier;
+use crate::pipeline::{PipelineError, StageKind, TransformStage};
+
+/// Gives access to the signature once the pipeline has been finished.
+#[derive(Clone, Default)]
+pub struct SignatureHandle(Rc<RefCell<Option<Signature>>>);
+
+impl SignatureHandle {
+    pub fn get(&self) -> Option<Signature> {
+        self.0.borrow().clone()
+    }
+}
+
+/// Signs everything that passes through it, the data itself is left untouched.
+pub struct SignStage<S: HashBasedSignature> {
+    signer: Option<S>,
+    handle: SignatureHandle,
+}
+
+impl<S: HashBasedSignature> SignStage<S> {
+    pub fn new(signer: S) -> (Self, SignatureHandle) {
+        let handle = SignatureHandle::default();
+        (
+            Self {
+                signer: Some(signer),
+                handle: handle.clone(),
+            },
+            handle,
+        )
+    }
+}
+
+#[async_trait(?Send)]
+impl<S> TransformStage for SignStage<S>
+where
+    S: HashBasedSignature,
+    S::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn kind(&self) -> StageKind {
+        StageKind::Signature
+    }
+
+    async fn transform(&mut self, chunk: Bytes) -> Result<Bytes, PipelineError> {
+        if let Some(signer) = &mut self.signer {
+            signer.update(&chunk);
+        }
+        Ok(chunk)
+    }
+
+    async fn finish(&mut self) -> Result<Bytes, PipelineError> {
+        if let Some(signer) = self.signer.take() {
+            let signature = signer
+                .finalize()
+                .map_err(|e| PipelineError::SignatureError(Box::new(e)))?;
+            *self.handle.0.borrow_mut() = Some(signature);
+        }
+        Ok(Bytes::new())
+    }
+}
+
+/// Hashes everything that passes through it and checks the hash against the signature when finished.
+pub struct VerifySignatureStage<V: HashBasedSignatureVerifier> {
+    hasher: Option<HighwayHasher>,
+    verifier: Option<V>,
+}
+
+impl<V: HashBasedSignatureVerifier> VerifySignatureStage<V> {
+    pub fn new(key: highway::Key, verifier: V) -> Self {
+        Self {
+            hasher: Some(HighwayHasher::new(key)),
+            verifier: Some(verifier),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<V> TransformStage for VerifySignatureStage<V>
+where
+    V: HashBasedSignatureVerifier,
+    V::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn kind(&self) -> StageKind {
+        StageKind::Signature
+    }
+
+    async fn transform(&mut self, chunk: Bytes) -> Result<Bytes, PipelineError> {
+        if let Some(hasher) = &mut self.hasher {
+            hasher.append(&chunk);
+        }
+        Ok(chunk)
+    }
+
+    async fn finish(&mut self) -> Result<Bytes, PipelineError> {
+        if let (Some(hasher), Some(verifier)) = (self.hasher.take(), self.verifier.take()) {
+            let hash = hasher.finalize256();
+            verifier
+                .verify_hash(bytemuck::bytes_of(&hash))
+                .map_err(|e| PipelineError::SignatureError(Box::new(e)))?;
+        }
+        Ok(Bytes::new())
+    }
+}
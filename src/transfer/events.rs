@@ -0,0 +1,245 @@
+use pharos::{Channel, Events, ObserveConfig, PharErr, SharedPharos};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub transferred_bytes: u64,
+    pub total_bytes: u64,
+    /// Average since the transfer started.
+    pub bytes_per_second: f64,
+}
+
+/// Progress of every file of the transfer together, sent after each file event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateProgress {
+    pub files_total: usize,
+    pub files_finished: usize,
+    pub files_failed: usize,
+    pub progress: Progress,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransferEvent {
+    Started { file: String, direction: TransferDirection, total_bytes: u64 },
+    PartUploaded { file: String, part: usize, bytes: u64 },
+    BytesTransferred { file: String, progress: Progress },
+    Retried { file: String, attempt: u32, reason: String },
+    Verified { file: String },
+    Finished { file: String, progress: Progress },
+    Failed { file: String, error: String },
+    Aggregate(AggregateProgress),
+}
+
+struct FileProgress {
+    transferred_bytes: u64,
+    total_bytes: u64,
+    started_at: OffsetDateTime,
+    done: bool,
+}
+
+#[derive(Default)]
+struct AggregateState {
+    started_at: Option<OffsetDateTime>,
+    files: HashMap<String, FileProgress>,
+    files_finished: usize,
+    files_failed: usize,
+}
+
+fn throughput(bytes: u64, since: OffsetDateTime) -> f64 {
+    let seconds = (OffsetDateTime::now_utc() - since).as_seconds_f64();
+    if seconds > 0.0 {
+        bytes as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+impl AggregateState {
+    fn file_progress(&self, file: &str) -> Progress {
+        match self.files.get(file) {
+            Some(state) => Progress {
+                transferred_bytes: state.transferred_bytes,
+                total_bytes: state.total_bytes,
+                bytes_per_second: throughput(state.transferred_bytes, state.started_at),
+            },
+            None => Progress {
+                transferred_bytes: 0,
+                total_bytes: 0,
+                bytes_per_second: 0.0,
+            },
+        }
+    }
+
+    fn aggregate(&self) -> AggregateProgress {
+        let transferred_bytes = self.files.values().map(|file| file.transferred_bytes).sum();
+        AggregateProgress {
+            files_total: self.files.len(),
+            files_finished: self.files_finished,
+            files_failed: self.files_failed,
+            progress: Progress {
+                transferred_bytes,
+                total_bytes: self.files.values().map(|file| file.total_bytes).sum(),
+                bytes_per_second: self
+                    .started_at
+                    .map_or(0.0, |started_at| throughput(transferred_bytes, started_at)),
+            },
+        }
+    }
+}
+
+/// Observable stream of [`TransferEvent`]s, cloned into every transfer that should report to the same observers.
+#[derive(Clone, Default)]
+pub struct TransferEvents {
+    pharos: SharedPharos<TransferEvent>,
+    state: Arc<Mutex<AggregateState>>,
+}
+
+impl TransferEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribes to all events, the channel is unbounded so a slow observer never stalls a transfer.
+    pub async fn observe(&self) -> Result<Events<TransferEvent>, PharErr> {
+        self.pharos.observe_shared(ObserveConfig::from(Channel::Unbounded)).await
+    }
+
+    async fn notify(&self, event: TransferEvent, with_aggregate: bool) {
+        let aggregate = with_aggregate.then(|| self.state.lock().unwrap().aggregate());
+        // Failing to notify only means nobody is listening anymore, which must not fail the transfer.
+        let _ = self.pharos.notify(event).await;
+        if let Some(aggregate) = aggregate {
+            let _ = self.pharos.notify(TransferEvent::Aggregate(aggregate)).await;
+        }
+    }
+
+    pub async fn started(&self, file: &str, direction: TransferDirection, total_bytes: u64) {
+        {
+            let mut state = self.state.lock().unwrap();
+            let now = OffsetDateTime::now_utc();
+            state.started_at.get_or_insert(now);
+            state.files.insert(
+                file.to_string(),
+                FileProgress {
+                    transferred_bytes: 0,
+                    total_bytes,
+                    started_at: now,
+                    done: false,
+                },
+            );
+        }
+        let event = TransferEvent::Started {
+            file: file.to_string(),
+            direction,
+            total_bytes,
+        };
+        self.notify(event, true).await;
+    }
+
+    pub async fn bytes_transferred(&self, file: &str, bytes: u64) {
+        let progress = {
+            let mut state = self.state.lock().unwrap();
+            if let Some(file) = state.files.get_mut(file) {
+                file.transferred_bytes += bytes;
+            }
+            state.file_progress(file)
+        };
+        let event = TransferEvent::BytesTransferred {
+            file: file.to_string(),
+            progress,
+        };
+        self.notify(event, true).await;
+    }
+
+    pub async fn part_uploaded(&self, file: &str, part: usize, bytes: u64) {
+        let event = TransferEvent::PartUploaded {
+            file: file.to_string(),
+            part,
+            bytes,
+        };
+        self.notify(event, false).await;
+    }
+
+    pub async fn retried(&self, file: &str, attempt: u32, reason: impl ToString) {
+        let event = TransferEvent::Retried {
+            file: file.to_string(),
+            attempt,
+            reason: reason.to_string(),
+        };
+        self.notify(event, false).await;
+    }
+
+    pub async fn verified(&self, file: &str) {
+        self.notify(TransferEvent::Verified { file: file.to_string() }, false).await;
+    }
+
+    pub async fn finished(&self, file: &str) {
+        let progress = {
+            let mut state = self.state.lock().unwrap();
+            let newly_done = state.files.get_mut(file).is_some_and(|file| !std::mem::replace(&mut file.done, true));
+            if newly_done {
+                state.files_finished += 1;
+            }
+            state.file_progress(file)
+        };
+        let event = TransferEvent::Finished {
+            file: file.to_string(),
+            progress,
+        };
+        self.notify(event, true).await;
+    }
+
+    pub async fn failed(&self, file: &str, error: impl ToString) {
+        {
+            let mut state = self.state.lock().unwrap();
+            let newly_done = state.files.get_mut(file).is_some_and(|file| !std::mem::replace(&mut file.done, true));
+            if newly_done {
+                state.files_failed += 1;
+            }
+        }
+        let event = TransferEvent::Failed {
+            file: file.to_string(),
+            error: error.to_string(),
+        };
+        self.notify(event, true).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_aggregates_files() {
+        let events = TransferEvents::new();
+        let mut observer = events.observe().await.unwrap();
+
+        events.started("a", TransferDirection::Upload, 100).await;
+        events.started("b", TransferDirection::Upload, 50).await;
+        events.bytes_transferred("a", 100).await;
+        events.finished("a").await;
+        events.failed("b", "connection reset").await;
+        drop(events);
+
+        let received: Vec<_> = observer.by_ref().take(10).collect().await;
+        let Some(TransferEvent::Aggregate(last)) = received.last() else {
+            panic!("expected an aggregate event last, got {:?}", received.last());
+        };
+        assert_eq!((last.files_total, last.files_finished, last.files_failed), (2, 1, 1));
+        assert_eq!(last.progress.transferred_bytes, 100);
+        assert_eq!(last.progress.total_bytes, 150);
+        assert!(received.iter().any(|event| matches!(
+            event,
+            TransferEvent::Finished { file, progress } if file == "a" && progress.transferred_bytes == 100
+        )));
+    }
+}
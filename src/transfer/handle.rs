@@ -0,0 +1,203 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferState {
+    Running,
+    Paused,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Transfer was cancelled")]
+pub struct TransferCancelled;
+
+struct HandleState {
+    state: TransferState,
+    /// One entry per waiting [`Checkpoint`], keyed so a future polled again replaces its waker.
+    waiters: Vec<(u64, Waker)>,
+    next_waiter: u64,
+}
+
+/// Controls running transfers from outside, e.g. from a cancel button.
+/// Transfers check the handle between requests, a request that already started runs to completion when paused.
+#[derive(Clone)]
+pub struct TransferHandle {
+    inner: Arc<Mutex<HandleState>>,
+}
+
+impl TransferHandle {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(HandleState {
+                state: TransferState::Running,
+                waiters: Vec::new(),
+                next_waiter: 0,
+            })),
+        }
+    }
+
+    pub fn state(&self) -> TransferState {
+        self.inner.lock().unwrap().state
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state() == TransferState::Cancelled
+    }
+
+    pub fn pause(&self) {
+        self.set_state(TransferState::Paused);
+    }
+
+    pub fn resume(&self) {
+        self.set_state(TransferState::Running);
+    }
+
+    /// Stops the transfer, the handlers get to clean up. The multipart uploads of unfinished files have to be
+    /// aborted on the server, the API has no request for that yet, so such an upload fails with
+    /// `UploadError::MultipartAbortUnsupported` and their upload IDs instead of `UploadError::Cancelled`.
+    /// A cancelled handle stays cancelled until it is [`reset`](Self::reset).
+    pub fn cancel(&self) {
+        self.set_state(TransferState::Cancelled);
+    }
+
+    /// Makes a cancelled or paused handle run again, so a context can be reused for the next transfer.
+    pub fn reset(&self) {
+        self.inner.lock().unwrap().state = TransferState::Running;
+        self.wake_all();
+    }
+
+    fn set_state(&self, new_state: TransferState) {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.state == TransferState::Cancelled {
+                return;
+            }
+            inner.state = new_state;
+        }
+        self.wake_all();
+    }
+
+    fn wake_all(&self) {
+        let waiters = std::mem::take(&mut self.inner.lock().unwrap().waiters);
+        for (_, waker) in waiters {
+            waker.wake();
+        }
+    }
+
+    /// Completes right away while running, waits while paused, and fails once cancelled.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            handle: self.clone(),
+            until_cancelled: false,
+            waiter: None,
+        }
+    }
+
+    /// Completes once the transfer is cancelled.
+    pub fn cancelled(&self) -> Checkpoint {
+        Checkpoint {
+            handle: self.clone(),
+            until_cancelled: true,
+            waiter: None,
+        }
+    }
+}
+
+impl Default for TransferHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by [`TransferHandle::checkpoint`] and [`TransferHandle::cancelled`].
+pub struct Checkpoint {
+    handle: TransferHandle,
+    until_cancelled: bool,
+    waiter: Option<u64>,
+}
+
+impl Future for Checkpoint {
+    type Output = Result<(), TransferCancelled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut inner = this.handle.inner.lock().unwrap();
+        let ready = match inner.state {
+            TransferState::Cancelled => Some(Err(TransferCancelled)),
+            TransferState::Running if !this.until_cancelled => Some(Ok(())),
+            _ => None,
+        };
+        if let Some(result) = ready {
+            if let Some(id) = this.waiter.take() {
+                inner.waiters.retain(|(waiter, _)| *waiter != id);
+            }
+            return Poll::Ready(result);
+        }
+        let registered = match this.waiter {
+            Some(id) => inner.waiters.iter_mut().find(|(waiter, _)| *waiter == id),
+            None => None,
+        };
+        match registered {
+            Some((_, waker)) => waker.clone_from(cx.waker()),
+            None => {
+                let id = inner.next_waiter;
+                inner.next_waiter += 1;
+                inner.waiters.push((id, cx.waker().clone()));
+                this.waiter = Some(id);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Checkpoint {
+    fn drop(&mut self) {
+        if let Some(id) = self.waiter {
+            self.handle.inner.lock().unwrap().waiters.retain(|(waiter, _)| *waiter != id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn test_pause_resume_cancel() {
+        let handle = TransferHandle::new();
+        assert_eq!(handle.checkpoint().now_or_never(), Some(Ok(())));
+
+        handle.pause();
+        let mut checkpoint = handle.checkpoint();
+        assert!((&mut checkpoint).now_or_never().is_none());
+        handle.resume();
+        assert_eq!(checkpoint.now_or_never(), Some(Ok(())));
+
+        let cancelled = handle.cancelled();
+        handle.pause();
+        handle.cancel();
+        assert!(cancelled.now_or_never().unwrap().is_err());
+        handle.resume();
+        assert_eq!(handle.state(), TransferState::Cancelled);
+
+        handle.reset();
+        assert_eq!(handle.checkpoint().now_or_never(), Some(Ok(())));
+    }
+
+    #[test]
+    fn test_checkpoint_keeps_one_waker() {
+        let handle = TransferHandle::new();
+        let mut cancelled = handle.cancelled();
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        for _ in 0..3 {
+            assert!(Pin::new(&mut cancelled).poll(&mut cx).is_pending());
+        }
+        assert_eq!(handle.inner.lock().unwrap().waiters.len(), 1);
+        drop(cancelled);
+        assert!(handle.inner.lock().unwrap().waiters.is_empty());
+    }
+}
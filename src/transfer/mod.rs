@@ -0,0 +1,46 @@
+use crate::pipeline::buffer_pool::{BufferPool, MemoryBudget, DEFAULT_TRANSFER_MEMORY_BUDGET};
+use crate::transfer::events::TransferEvents;
+use crate::transfer::handle::TransferHandle;
+
+pub mod events;
+pub mod handle;
+pub mod scheduler;
+
+/// Requests running at the same time unless configured otherwise.
+pub const DEFAULT_MAX_IN_FLIGHT_REQUESTS: usize = 8;
+/// Files up to this size are scheduled before large files.
+pub const DEFAULT_SMALL_FILE_THRESHOLD: u64 = 8 * 1024 * 1024;
+
+/// Shared by every upload and download started with it, so the limits apply to all of them together.
+#[derive(Clone)]
+pub struct TransferContext {
+    pub max_in_flight_requests: usize,
+    pub small_file_threshold: u64,
+    pub buffers: BufferPool,
+    /// Progress of every transfer using this context, subscribe with [`TransferEvents::observe`].
+    pub events: TransferEvents,
+    /// Pauses, resumes or cancels every transfer using this context. Call [`TransferHandle::reset`] before reusing a cancelled context.
+    pub handle: TransferHandle,
+}
+
+impl TransferContext {
+    pub fn new(max_in_flight_requests: usize, memory_budget: u64) -> Self {
+        Self {
+            max_in_flight_requests: max_in_flight_requests.max(1),
+            small_file_threshold: DEFAULT_SMALL_FILE_THRESHOLD,
+            buffers: BufferPool::new(MemoryBudget::new(memory_budget)),
+            events: TransferEvents::new(),
+            handle: TransferHandle::new(),
+        }
+    }
+
+    pub fn memory_budget(&self) -> &MemoryBudget {
+        self.buffers.budget()
+    }
+}
+
+impl Default for TransferContext {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_IN_FLIGHT_REQUESTS, DEFAULT_TRANSFER_MEMORY_BUDGET)
+    }
+}
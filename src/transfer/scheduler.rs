@@ -0,0 +1,273 @@
+use crate::transfer::handle::{TransferCancelled, TransferState};
+use crate::transfer::TransferContext;
+use futures::future::{select, Either};
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use std::collections::VecDeque;
+use std::future::Future;
+
+/// A single HTTP request of a transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferJob {
+    pub file: usize,
+    /// Position of the request within its file, requests of a file run in this order.
+    pub sequence: usize,
+    /// Bytes sent or received by the request, used to tell small files from large ones.
+    pub size: u64,
+}
+
+struct QueuedFile {
+    file: usize,
+    size: u64,
+    jobs: VecDeque<TransferJob>,
+    in_flight: bool,
+}
+
+/// Decides which request runs next.
+/// Small files go first, smallest first, as they finish quickly and free their slot. Large files take turns one
+/// request at a time so every one of them makes progress. A file never has more than one request in flight,
+/// handlers read and write files sequentially.
+pub struct TransferQueue {
+    small_file_threshold: u64,
+    small: VecDeque<QueuedFile>,
+    large: VecDeque<QueuedFile>,
+}
+
+impl TransferQueue {
+    pub fn new(small_file_threshold: u64) -> Self {
+        Self {
+            small_file_threshold,
+            small: VecDeque::new(),
+            large: VecDeque::new(),
+        }
+    }
+
+    /// Adds a file made of requests of the given sizes.
+    pub fn push_file(&mut self, file: usize, request_sizes: impl IntoIterator<Item = u64>) {
+        let jobs: VecDeque<_> = request_sizes
+            .into_iter()
+            .enumerate()
+            .map(|(sequence, size)| TransferJob { file, sequence, size })
+            .collect();
+        if jobs.is_empty() {
+            return;
+        }
+        let size = jobs.iter().map(|job| job.size).sum();
+        let queued = QueuedFile {
+            file,
+            size,
+            jobs,
+            in_flight: false,
+        };
+        if size <= self.small_file_threshold {
+            let position = self.small.iter().position(|other| other.size > size).unwrap_or(self.small.len());
+            self.small.insert(position, queued);
+        } else {
+            self.large.push_back(queued);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.small.is_empty() && self.large.is_empty()
+    }
+
+    /// The next request that may start, none if every remaining file already has a request in flight.
+    pub fn next(&mut self) -> Option<TransferJob> {
+        if let Some(job) = self.small.iter_mut().find(|file| !file.in_flight).and_then(|file| {
+            file.in_flight = true;
+            file.jobs.pop_front()
+        }) {
+            return Some(job);
+        }
+        let position = self.large.iter().position(|file| !file.in_flight)?;
+        let mut file = self.large.remove(position).unwrap();
+        file.in_flight = true;
+        let job = file.jobs.pop_front();
+        // Back of the line, the other large files get their turn first.
+        self.large.push_back(file);
+        job
+    }
+
+    /// Marks the request as done, letting the next request of its file start.
+    pub fn complete(&mut self, job: &TransferJob) {
+        for files in [&mut self.small, &mut self.large] {
+            if let Some(position) = files.iter().position(|file| file.file == job.file) {
+                files[position].in_flight = false;
+                if files[position].jobs.is_empty() {
+                    files.remove(position);
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Runs the requests of a queue within the limits of a [`TransferContext`].
+pub struct TransferScheduler {
+    context: TransferContext,
+}
+
+impl TransferScheduler {
+    pub fn new(context: TransferContext) -> Self {
+        Self { context }
+    }
+
+    /// Runs `start` for every request of the queue, stops at the first error.
+    /// Only the number of requests is limited here. The memory they buffer is reserved by whoever allocates it, the
+    /// handlers through the context's buffer pool, so it is never accounted twice.
+    /// While the context's handle is paused no new request starts, once cancelled the requests in flight are dropped.
+    pub async fn run<F, Fut, E>(&self, mut queue: TransferQueue, mut start: F) -> Result<(), E>
+    where
+        F: FnMut(TransferJob) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: From<TransferCancelled>,
+    {
+        let handle = &self.context.handle;
+        let mut in_flight = FuturesUnordered::new();
+        loop {
+            if queue.is_empty() && in_flight.is_empty() {
+                return Ok(());
+            }
+            match handle.state() {
+                TransferState::Cancelled => return Err(TransferCancelled.into()),
+                TransferState::Paused if in_flight.is_empty() => {
+                    handle.checkpoint().await?;
+                    continue;
+                }
+                TransferState::Paused => {}
+                TransferState::Running => {
+                    while in_flight.len() < self.context.max_in_flight_requests {
+                        let Some(job) = queue.next() else {
+                            break;
+                        };
+                        let future = start(job);
+                        in_flight.push(async move { (job, future.await) });
+                    }
+                }
+            }
+            match select(in_flight.next(), handle.cancelled()).await {
+                Either::Left((Some((job, result)), _)) => {
+                    result?;
+                    queue.complete(&job);
+                }
+                Either::Left((None, _)) if queue.is_empty() => return Ok(()),
+                Either::Left((None, _)) => {}
+                Either::Right(_) => return Err(TransferCancelled.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn drain(queue: &mut TransferQueue) -> Vec<(usize, usize)> {
+        let mut order = Vec::new();
+        while let Some(job) = queue.next() {
+            order.push((job.file, job.sequence));
+            queue.complete(&job);
+        }
+        order
+    }
+
+    #[test]
+    fn test_small_files_first_then_round_robin() {
+        let mut queue = TransferQueue::new(100);
+        queue.push_file(0, [1000, 1000, 1000]);
+        queue.push_file(1, [50]);
+        queue.push_file(2, [1000, 1000]);
+        queue.push_file(3, [10]);
+        assert_eq!(drain(&mut queue), vec![(3, 0), (1, 0), (0, 0), (2, 0), (0, 1), (2, 1), (0, 2)]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_one_request_in_flight_per_file() {
+        let mut queue = TransferQueue::new(0);
+        queue.push_file(0, [10, 10]);
+        let first = queue.next().unwrap();
+        assert_eq!(queue.next(), None);
+        queue.complete(&first);
+        assert_eq!(queue.next().map(|job| job.sequence), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_limits_in_flight_requests() {
+        let scheduler = TransferScheduler::new(TransferContext::new(2, 1024));
+        let mut queue = TransferQueue::new(0);
+        for file in 0..6 {
+            queue.push_file(file, [100, 100]);
+        }
+        let running = RefCell::new(0usize);
+        let peak = RefCell::new(0usize);
+        let finished = RefCell::new(Vec::new());
+        scheduler
+            .run(queue, |job| {
+                let (running, peak, finished) = (&running, &peak, &finished);
+                async move {
+                    *running.borrow_mut() += 1;
+                    let current = *running.borrow();
+                    peak.replace_with(|peak| (*peak).max(current));
+                    tokio::task::yield_now().await;
+                    *running.borrow_mut() -= 1;
+                    finished.borrow_mut().push((job.file, job.sequence));
+                    Ok::<_, TransferCancelled>(())
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(*peak.borrow(), 2);
+        let finished = finished.into_inner();
+        assert_eq!(finished.len(), 12);
+        for file in 0..6 {
+            let first = finished.iter().position(|job| *job == (file, 0)).unwrap();
+            let second = finished.iter().position(|job| *job == (file, 1)).unwrap();
+            assert!(first < second);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_the_queue() {
+        let context = TransferContext::new(1, 1024);
+        let handle = context.handle.clone();
+        let scheduler = TransferScheduler::new(context);
+        let mut queue = TransferQueue::new(0);
+        queue.push_file(0, [10, 10, 10]);
+        let started = RefCell::new(0);
+        let result = scheduler
+            .run(queue, |_| {
+                *started.borrow_mut() += 1;
+                handle.cancel();
+                async { Ok::<_, TransferCancelled>(()) }
+            })
+            .await;
+        assert_eq!(result, Err(TransferCancelled));
+        assert_eq!(*started.borrow(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_requests_do_not_reserve_the_budget() {
+        let scheduler = TransferScheduler::new(TransferContext::new(8, 250));
+        let mut queue = TransferQueue::new(0);
+        for file in 0..4 {
+            queue.push_file(file, [1000]);
+        }
+        let reserved = RefCell::new(0u64);
+        let budget = scheduler.context.memory_budget().clone();
+        scheduler
+            .run(queue, |_| {
+                let (reserved, budget) = (&reserved, &budget);
+                async move {
+                    reserved.replace_with(|reserved| (*reserved).max(250 - budget.available()));
+                    tokio::task::yield_now().await;
+                    Ok::<_, TransferCancelled>(())
+                }
+            })
+            .await
+            .unwrap();
+        // The handlers reserve what they buffer, the scheduler adds nothing on top.
+        assert_eq!(*reserved.borrow(), 0);
+    }
+}
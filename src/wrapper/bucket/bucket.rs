@@ -1,16 +1,16 @@
 use crate::client::grpc::native::client::query_client::QueryClient;
-use crate::client::http::{HttpDownloadClientExt, HttpUploadClientExt};
+use crate::client::http::{HttpDownloadClientExt, HttpStatusCode, HttpUploadClientExt, ObjectHeaders};
 use bucket_api::backend_api::{DeleteFilesInBucketRequest, DeleteFilesInBucketResponse, GetBucketDetailsFromUrlRequest, GetBucketDetailsFromUrlResponse, GetBucketDetailsRequest, GetBucketDetailsResponse, GetBucketFilestructureRequest, GetBucketFilestructureResponse, MoveFilesInBucketRequest, MoveFilesInBucketResponse};
 use bucket_api::backend_api::{
     DownloadBucketRequest, DownloadFilesRequest, UploadFilesToBucketRequest,
 };
 use bucket_common_types::exclusive_share_link::ExclusiveShareLink;
 use bucket_common_types::share_link::ShareLink;
-use bucket_common_types::{BucketGuid, DownloadFormat, Encoding};
-use byte_unit::Byte;
+use bucket_common_types::{DownloadFormat, Encoding};
 use futures::io::BufReader;
 use futures::AsyncReadExt;
 use futures::StreamExt;
+use futures::TryStreamExt;
 use mime::Mime;
 use std::error::Error;
 use std::fmt::Debug;
@@ -35,72 +35,760 @@ use crate::wrapper::bucket::errors::{
 use crate::wrapper::bucket::ClientUploadExt;
 
 use crate::client::grpc::request_ext::RequestAuthorizationMetadataExt;
-use crate::client::http::native::http::HttpClient;
 use crate::encryption::EncryptionChooserHandler;
 use crate::io::file::VirtualFileDetails;
+use crate::io::{FileError, FileWrapper};
 use crate::wrapper::bucket::download::download_handler::BucketDownloadHandlerErrors;
-use crate::wrapper::bucket::upload::FileUploadHandler;
+use crate::wrapper::bucket::upload::{multipart_upload_id, FileUploadHandler};
+use crate::wrapper::bucket::upload::part_planner::{plan_parts, PartLimits};
+use crate::transfer::scheduler::{TransferQueue, TransferScheduler};
+use crate::transfer::events::TransferDirection;
+use crate::transfer::TransferContext;
+use crate::transfer::handle::TransferCancelled;
+use crate::pipeline::Pipeline;
+use crate::wrapper::bucket::upload::stream::SpooledStream;
+use crate::chunking::store::{ChunkStore, DeduplicationStats, Deduplicator};
+use crate::chunking::{ChunkHasher, ChunkingConfig, ChunkingError};
+use crate::bundle::format::{BundleIndex, BundleWriter};
+use crate::pipeline::PipelineDirection;
+use crate::delta::patch::{DeltaEncoder, PatchManifest, PATCH_OBJECT_SUFFIX};
+use crate::delta::signature::Signature;
+use crate::wrapper::bucket::upload::upload_handler::{BucketDownloadHandlerFileErrors, BucketFileReader};
+use bucket_api::backend_api::upload_files_to_bucket_request;
+#[cfg(not(target_family = "wasm"))]
+use crate::io::NativeFile;
+#[cfg(not(target_family = "wasm"))]
+use crate::wrapper::bucket::upload::directory::{walk_directory, DirectoryEntry, DirectoryUploadOptions, DirectoryUploadReport, EntryKind, FileOutcome, MetadataPreservation, SYMLINK_CONTENT_TYPE};
+use crate::io::metadata::PreservedMetadata;
+use std::collections::HashMap;
+use crate::integrity::{ContentHash, IntegrityError};
+use crate::io::bucket_path::{BucketPath, BucketPathError, LocalPathResolver};
+use crate::wrapper::bucket::download::conflict::{resolve_conflict, ConflictError, ConflictPolicy, ConflictResolution, DownloadOutcome, DownloadReport, RemoteFileInfo};
+use crate::client::http::ByteRange;
+use crate::wrapper::bucket::download::filter::DownloadFilter;
+use crate::io::metadata::FILE_METADATA_HEADER;
+use crate::wrapper::bucket::download::ranged::{download_ranged, RangedDownload, RangedDownloadError};
+#[cfg(not(target_family = "wasm"))]
+use crate::wrapper::bucket::download::partial::{download_resumable, PartialDownloadError};
+use std::cell::RefCell;
+#[cfg(not(target_family = "wasm"))]
+use crate::wrapper::bucket::upload::journal::{JournalFile, UploadJournal};
 
 
+/// Takes a file's upload handler out of its slot for one request and puts it back when dropped,
+/// so a request cancelled mid-flight still leaves the handler for `on_upload_cancel`.
+struct BorrowedHandler<'a, UH> {
+    slot: &'a RefCell<Option<UH>>,
+    handler: Option<UH>,
+}
+
+impl<'a, UH> BorrowedHandler<'a, UH> {
+    /// Fails when another request of the file holds the handler.
+    fn take(slot: &'a RefCell<Option<UH>>) -> Result<Self, UploadError> {
+        match slot.take() {
+            Some(handler) => Ok(Self { slot, handler: Some(handler) }),
+            None => Err(UploadError::HandlerInUse),
+        }
+    }
+
+    fn get(&mut self) -> Result<&mut UH, UploadError> {
+        self.handler.as_mut().ok_or(UploadError::HandlerInUse)
+    }
+
+    /// The handler is consumed, nothing goes back into the slot.
+    fn into_inner(mut self) -> Result<UH, UploadError> {
+        self.handler.take().ok_or(UploadError::HandlerInUse)
+    }
+}
+
+impl<UH> Drop for BorrowedHandler<'_, UH> {
+    fn drop(&mut self) {
+        if let Some(handler) = self.handler.take() {
+            self.slot.replace(Some(handler));
+        }
+    }
+}
+
+/// Aborts the server-side multipart uploads of a cancelled transfer. The API has no request for that, so the
+/// cancel fails with the IDs of the uploads left open instead of leaving them unnoticed.
+fn abort_multipart_uploads(upload_ids: Vec<String>, cancelled: TransferCancelled) -> UploadError {
+    match upload_ids.is_empty() {
+        true => cancelled.into(),
+        false => UploadError::MultipartAbortUnsupported(upload_ids),
+    }
+}
+
+/// A file of `upload_files` with the requests planned for it.
+struct ScheduledFile<UH> {
+    file_path: String,
+    /// Resolved by the caller and sent with every request of the file.
+    content_type: Mime,
+    urls: Vec<Url>,
+    /// (url index, request size) in upload order.
+    requests: Vec<(usize, u64)>,
+    handler: RefCell<Option<UH>>,
+    /// Set by the last request of the file, or by the one that failed.
+    outcome: RefCell<Option<Result<(), UploadError>>>,
+}
+
+impl<UH> ScheduledFile<UH> {
+    /// With `fail_fast` the error stops the transfer, otherwise only this file.
+    fn fail(&self, error: UploadError, fail_fast: bool) -> Result<(), UploadError> {
+        if fail_fast {
+            return Err(error);
+        }
+        self.outcome.replace(Some(Err(error)));
+        Ok(())
+    }
+}
+
+/// Uploads the files of `req` and returns the result of every file the server returned.
+/// With `fail_fast` the first failing file stops the transfer and is returned as the error, otherwise the
+/// other files continue and only a cancel or a failed request to the server stop it.
+async fn upload_files<R: Read, W: Write, UH: FileUploadHandler<R, W>, HTTP: HttpUploadClientExt>(
+    client: &mut QueryClient,
+    req: tonic::Request<UploadFilesToBucketRequest>,
+    open_file: impl Fn(&UploadFileDescriptionState) -> Result<UH, UH::Error>,
+    api_token: &ApiToken,
+    http_client: HTTP,
+    transfer_context: &TransferContext,
+    fail_fast: bool,
+) -> Result<Vec<(String, Result<(), UploadError>)>, UploadError> {
+    let total_upload_size: u64 = req
+        .get_ref()
+        .source_files
+        .iter()
+        .map(|file| file.size_in_bytes)
+        .sum();
+    let mut content_types: HashMap<String, Mime> = req
+        .get_ref()
+        .source_files
+        .iter()
+        .map(|file| {
+            let content_type = file.content_type.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM);
+            (file.file_path.clone(), content_type)
+        })
+        .collect();
+
+    let body = client.upload_files_to_bucket(req).await?.into_inner();
+
+    // Will probably be caught by the backend with an error response before client can even check.
+    if total_upload_size > body.size_in_bytes_limit {
+        return Err(UploadError::StorageNotAvailable);
+    }
+    /*
+     * Uploading uses multipart presigned urls meaning each file is uploaded in a max of 5 GiB chunks to each URL.
+     * Files over the limit of 5 GiB will be divided up into multiple uploads a.k.a multiple URL's.
+     * Each file is divided up into URL's. Then each URL upload is divided up into chunks depending on the memory limitations.
+     * Each chunk is uploaded to the presigned url in sequence until the full load has been uploaded.
+     * Uploading more will lead to overwriting previous uploads, just don't.
+     * The scheduler bounds the requests in flight and the bytes buffered across all files.
+     */
+    let part_limits = PartLimits::with_memory_limit(transfer_context.memory_budget().limit());
+    let events = &transfer_context.events;
+    let mut queue = TransferQueue::new(transfer_context.small_file_threshold);
+    let mut files = Vec::with_capacity(body.filepaths.len());
+    let mut not_started = Vec::new();
+    for filepath in body.filepaths {
+        events.started(&filepath.file_path, TransferDirection::Upload, filepath.total_file_size_in_bytes).await;
+        let planned = plan_parts(filepath.total_file_size_in_bytes, filepath.upload_urls.len(), &part_limits)
+            .map_err(UploadError::from)
+            .and_then(|plan| {
+                let urls = filepath
+                    .upload_urls
+                    .iter()
+                    .map(|url| Url::parse(url.as_str()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((plan, urls))
+            })
+            .and_then(|(plan, urls)| {
+                let upload_handler = open_file(&UploadFileDescriptionState {
+                    file_path: filepath.file_path.clone(),
+                    size_in_bytes: filepath.total_file_size_in_bytes,
+                    urls: filepath.upload_urls.clone(),
+                })
+                .map_err(|e| UploadToUrlError::UploadHandlerError(Box::new(e)))?;
+                Ok((plan, urls, upload_handler))
+            });
+        let (plan, urls, upload_handler) = match planned {
+            Ok(planned) => planned,
+            Err(e) => {
+                events.failed(&filepath.file_path, &e).await;
+                if fail_fast {
+                    return Err(e);
+                }
+                not_started.push((filepath.file_path, Err(e)));
+                continue;
+            }
+        };
+        let requests: Vec<(usize, u64)> = plan
+            .parts
+            .iter()
+            .flat_map(|part| part.requests.iter().map(|request| (part.url_index, request.end - request.start)))
+            .collect();
+        queue.push_file(files.len(), requests.iter().map(|(_, size)| *size));
+        files.push(ScheduledFile {
+            content_type: content_types.remove(&filepath.file_path).unwrap_or(mime::APPLICATION_OCTET_STREAM),
+            file_path: filepath.file_path,
+            urls,
+            requests,
+            handler: RefCell::new(Some(upload_handler)),
+            outcome: RefCell::new(None),
+        });
+    }
+
+    let result = TransferScheduler::new(transfer_context.clone())
+        .run(queue, |job| {
+            let file = &files[job.file];
+            let (url_index, chunk_size) = file.requests[job.sequence];
+            let http_client = &http_client;
+            async move {
+                // The remaining requests of a failed file are skipped.
+                if file.outcome.borrow().is_some() {
+                    return Ok(());
+                }
+                let file_path = &file.file_path;
+                // The queue never runs two requests of the same file at once, the handler should always be there.
+                // If the request is dropped on cancel the guard hands the handler back for `on_upload_cancel`.
+                let mut handler = match BorrowedHandler::take(&file.handler) {
+                    Ok(handler) => handler,
+                    Err(e) => {
+                        events.failed(file_path, &e).await;
+                        return file.fail(e, fail_fast);
+                    }
+                };
+                let upload_handler = match handler.get() {
+                    Ok(upload_handler) => upload_handler,
+                    Err(e) => {
+                        events.failed(file_path, &e).await;
+                        return file.fail(e, fail_fast);
+                    }
+                };
+                let result = <QueryClient as ClientUploadExt>::upload_to_url_raw(
+                    &file.urls[url_index],
+                    chunk_size,
+                    upload_handler,
+                    file.content_type.clone(),
+                    api_token,
+                    None,
+                    http_client,
+                )
+                .await;
+                if let Err(e) = result {
+                    drop(handler);
+                    events.failed(file_path, &e).await;
+                    return file.fail(e.into(), fail_fast);
+                }
+                events.bytes_transferred(file_path, chunk_size).await;
+                let next_url_index = file.requests.get(job.sequence + 1).map(|(url_index, _)| *url_index);
+                if next_url_index != Some(url_index) {
+                    let part_size = file.requests.iter().filter(|(index, _)| *index == url_index).map(|(_, size)| size).sum();
+                    events.part_uploaded(file_path, url_index, part_size).await;
+                }
+                if next_url_index.is_some() {
+                    return Ok(());
+                }
+                let handler = match handler.into_inner() {
+                    Ok(handler) => handler,
+                    Err(e) => {
+                        events.failed(file_path, &e).await;
+                        return file.fail(e, fail_fast);
+                    }
+                };
+                let hash_recorded = handler.content_hash().is_some();
+                if let Err(e) = handler.on_upload_finish().await {
+                    let e = UploadToUrlError::UploadHandlerError(Box::new(e));
+                    events.failed(file_path, &e).await;
+                    return file.fail(e.into(), fail_fast);
+                }
+                // The hash of the content went out with the final request, downloads are checked against it.
+                if hash_recorded {
+                    events.verified(file_path).await;
+                }
+                events.finished(file_path).await;
+                file.outcome.replace(Some(Ok(())));
+                Ok(())
+            }
+        })
+        .await;
+
+    if let Err(UploadError::Cancelled(cancelled)) = result {
+        // Every file that did not finish still owns its handler, in-flight ones included.
+        // Finished files stay, the multipart uploads of the others are still open on the server.
+        let mut upload_ids = Vec::new();
+        for file in files.iter().filter(|file| file.outcome.borrow().is_none()) {
+            if let Some(upload_handler) = file.handler.take() {
+                let _ = upload_handler.on_upload_cancel().await;
+                events.failed(&file.file_path, cancelled).await;
+            }
+            upload_ids.extend(file.urls.first().and_then(multipart_upload_id));
+        }
+        return Err(abort_multipart_uploads(upload_ids, cancelled));
+    }
+    result?;
+    Ok(not_started
+        .into_iter()
+        .chain(files.into_iter().map(|file| {
+            let outcome = file.outcome.into_inner().expect("every file ends with its last or a failed request");
+            (file.file_path, outcome)
+        }))
+        .collect())
+}
+
 impl ClientUploadExt for QueryClient {
     async fn upload_files_to_bucket_raw<R: std::io::Read, W: std::io::Write, UH: FileUploadHandler<R, W>, HTTP: HttpUploadClientExt>(
         &mut self,
         req: tonic::Request<UploadFilesToBucketRequest>,
-        mut upload_handler: UH,
+        open_file: impl Fn(&UploadFileDescriptionState) -> Result<UH, UH::Error>,
         api_token: &ApiToken,
         http_client: HTTP,
+        transfer_context: &TransferContext,
     ) -> Result<(), UploadError> {
-        let total_upload_size: u64 = req
-            .get_ref()
-            .source_files
-            .iter()
-            .map(|file| file.size_in_bytes)
-            .sum();
-        let temp_source_files_len = req.get_ref().source_files.len();
+        upload_files(self, req, open_file, api_token, http_client, transfer_context, true).await?;
+        Ok(())
+    }
+
+    async fn upload_stream_to_bucket_raw<S: futures::AsyncRead + Unpin, HTTP: HttpUploadClientExt>(
+        &mut self,
+        mut req: UploadFilesToBucketRequest,
+        target_file_path: String,
+        content_type: Mime,
+        stream: S,
+        pipeline: Pipeline,
+        api_token: &ApiToken,
+        http_client: HTTP,
+        transfer_context: &TransferContext,
+    ) -> Result<u64, UploadError> {
+        let spooled =
+            SpooledStream::spool(stream, target_file_path.clone(), content_type.clone(), transfer_context.memory_budget())
+                .await?;
+        let size_in_bytes = spooled.get_size();
+        req.source_files = vec![upload_files_to_bucket_request::File {
+            file_path: target_file_path,
+            size_in_bytes,
+            content_type: content_type.to_string(),
+        }];
+        let mut req = Request::new(req);
+        req.set_authorization_metadata(api_token);
+        // A single file is uploaded, the handler is only opened once.
+        let source = RefCell::new(Some((spooled, pipeline)));
+        self.upload_files_to_bucket_raw::<SpooledStream, SpooledStream, _, _>(
+            req,
+            |_| {
+                let (spooled, pipeline) = source.take().expect("a streamed upload has a single file");
+                <BucketFileReader<SpooledStream> as FileUploadHandler<SpooledStream, SpooledStream>>::new(spooled, pipeline)
+                    .map(|reader| reader.with_buffer_pool(transfer_context.buffers.clone()))
+            },
+            api_token,
+            http_client,
+            transfer_context,
+        )
+        .await?;
+        Ok(size_in_bytes)
+    }
+
+    async fn upload_deduplicated_to_bucket_raw<F: FileWrapper, CS: ChunkStore, HTTP: HttpUploadClientExt>(
+        &mut self,
+        req: UploadFilesToBucketRequest,
+        target_file_path: String,
+        source: &F,
+        chunk_store: &CS,
+        chunking: ChunkingConfig,
+        hasher: &ChunkHasher,
+        pipeline: Pipeline,
+        api_token: &ApiToken,
+        http_client: HTTP,
+        transfer_context: &TransferContext,
+    ) -> Result<DeduplicationStats, UploadError> {
+        let mut deduplicator = Deduplicator::new(chunking, hasher.clone(), chunk_store);
+        let mut offset = 0u64;
+        loop {
+            transfer_context.handle.checkpoint().await?;
+            let block = source
+                .read_at(offset, chunking.max_size as usize)
+                .await
+                .map_err(|e| ChunkingError::SourceError(e.into()))?;
+            if block.is_empty() {
+                break;
+            }
+            offset += block.len() as u64;
+            deduplicator.write(&block).await?;
+        }
+        let (manifest, stats) = deduplicator.finish().await?;
+        let manifest = manifest.to_json()?;
+        self.upload_stream_to_bucket_raw(
+            req,
+            target_file_path,
+            mime::APPLICATION_JSON,
+            futures::io::Cursor::new(manifest),
+            pipeline,
+            api_token,
+            http_client,
+            transfer_context,
+        )
+        .await?;
+        Ok(stats)
+    }
+
+    async fn upload_delta_to_bucket_raw<F: FileWrapper, HTTP: HttpUploadClientExt + Clone>(
+        &mut self,
+        req: UploadFilesToBucketRequest,
+        target_file_path: String,
+        source: &F,
+        signature: &Signature,
+        key: highway::Key,
+        make_pipeline: impl Fn() -> Pipeline,
+        api_token: &ApiToken,
+        http_client: HTTP,
+        transfer_context: &TransferContext,
+    ) -> Result<PatchManifest, UploadError> {
+        let block_size = signature.block_size as usize;
+        let manifest = RefCell::new(None);
+        // The source is read a few blocks at a time, only the literals are spooled for the upload.
+        let literals = futures::stream::unfold(
+            (Some(DeltaEncoder::new(signature, key)), 0u64),
+            |(encoder, offset)| {
+                let manifest = &manifest;
+                async move {
+                    let mut encoder = encoder?;
+                    if let Err(cancelled) = transfer_context.handle.checkpoint().await {
+                        return Some((Err(std::io::Error::other(cancelled)), (None, offset)));
+                    }
+                    match source.read_at(offset, 4 * block_size).await {
+                        Ok(block) if block.is_empty() => {
+                            let (patch, tail) = encoder.finish();
+                            manifest.replace(Some(patch));
+                            Some((Ok(tail), (None, offset)))
+                        }
+                        Ok(block) => {
+                            let literals = encoder.write(&block);
+                            Some((Ok(literals), (Some(encoder), offset + block.len() as u64)))
+                        }
+                        Err(e) => Some((Err(std::io::Error::other(Into::<FileError>::into(e))), (None, offset))),
+                    }
+                }
+            },
+        );
+        self.upload_stream_to_bucket_raw(
+            req.clone(),
+            target_file_path.clone(),
+            mime::APPLICATION_OCTET_STREAM,
+            Box::pin(literals).into_async_read(),
+            make_pipeline(),
+            api_token,
+            http_client.clone(),
+            transfer_context,
+        )
+        .await?;
 
-        let resp = self.upload_files_to_bucket(req).await.unwrap();
-        let body = resp.into_inner();
+        let manifest = manifest.take().expect("the literals are read to their end before the upload finishes");
+        self.upload_stream_to_bucket_raw(
+            req,
+            format!("{target_file_path}{PATCH_OBJECT_SUFFIX}"),
+            mime::APPLICATION_JSON,
+            futures::io::Cursor::new(manifest.to_json()?),
+            make_pipeline(),
+            api_token,
+            http_client,
+            transfer_context,
+        )
+        .await?;
+        Ok(manifest)
+    }
 
-        /// Will probably be caught by the backend with an error response before client can even check.
-        if total_upload_size > body.size_in_bytes_limit {
-            return Err(UploadError::StorageNotAvailable);
+    async fn upload_bundle_to_bucket_raw<F: FileWrapper, HTTP: HttpUploadClientExt>(
+        &mut self,
+        req: UploadFilesToBucketRequest,
+        bundle_path: String,
+        files: Vec<(String, F)>,
+        key: Option<&aes_gcm::Aes256Gcm>,
+        api_token: &ApiToken,
+        http_client: HTTP,
+        transfer_context: &TransferContext,
+    ) -> Result<BundleIndex, UploadError> {
+        let mut writer = BundleWriter::new(Vec::new());
+        if let Some(key) = key {
+            writer = writer.with_key(key.clone());
         }
-        /*
-         * Uploading uses multipart presigned urls meaning each file is uploaded in a max of 5 GiB chunks to each URL.
-         * Files over the limit of 5 GiB will be divided up into multiple uploads a.k.a multiple URL's.
-         * Each file is divided up into URL's. Then each URL upload is divided up into chunks depending on the memory limitations.
-         * Each chunk is uploaded to the presigned url in sequence until the full load has been uploaded.
-         * Uploading more will lead to overwriting previous uploads, just don't.
-         * Each URL is 5 GiB
-         */
-        let mut upload_task_set = tokio::task::JoinSet::new();
-        for filepath in body.filepaths.into_iter() {
-            let upload_urls = filepath.upload_urls.clone();
-
-            let file_upload_handler = UH::new();
-            for upload_url in upload_urls.clone() {
-                let url = url::Url::parse(upload_url.as_str())?;
-                let chunk_size = (Byte::GIBIBYTE.as_u64() * 5) - filepath.total_file_size_in_bytes;
-                //let chunk_size = GiB::from(1).to_bytes() as usize;
-                upload_task_set.spawn(async {
-                    Self::upload_to_url_raw(
-                        &url,
-                        chunk_size,
-                        &mut upload_handler,
-                        mime::APPLICATION_OCTET_STREAM,
-                        api_token,
-                        None,
-                        http_client
-                    )
-                    .await
-                    .unwrap()
-                });
+        let index = RefCell::new(None);
+        // Files are added one at a time, only the bytes of the current file are handed to the spool.
+        let bundle = futures::stream::unfold((Some(writer), files.into_iter()), |(writer, mut files)| {
+            let index = &index;
+            async move {
+                let mut writer = writer?;
+                if let Err(cancelled) = transfer_context.handle.checkpoint().await {
+                    return Some((Err(std::io::Error::other(cancelled)), (None, files)));
+                }
+                let Some((path, file)) = files.next() else {
+                    return Some(match writer.finish() {
+                        Ok((rest, bundle_index, _)) => {
+                            index.replace(Some(bundle_index));
+                            (Ok(rest), (None, files))
+                        }
+                        Err(e) => (Err(std::io::Error::other(e)), (None, files)),
+                    });
+                };
+                match add_bundled_file(&mut writer, path, &file).await {
+                    Ok(()) => {
+                        let written = std::mem::take(writer.get_mut());
+                        Some((Ok(written), (Some(writer), files)))
+                    }
+                    Err(e) => Some((Err(e), (None, files))),
+                }
+            }
+        });
+        // The files are sealed by the bundle itself, the object stays uncompressed so single files can be ranged.
+        let pipeline = Pipeline::builder(PipelineDirection::Upload).build()?;
+        self.upload_stream_to_bucket_raw(
+            req,
+            bundle_path,
+            mime::APPLICATION_OCTET_STREAM,
+            Box::pin(bundle).into_async_read(),
+            pipeline,
+            api_token,
+            http_client,
+            transfer_context,
+        )
+        .await?;
+        Ok(index.take().expect("the bundle is read to its end before the upload finishes"))
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    async fn upload_directory_raw<HTTP: HttpUploadClientExt + Clone>(
+        &mut self,
+        req: UploadFilesToBucketRequest,
+        root: &std::path::Path,
+        options: &DirectoryUploadOptions,
+        make_pipeline: impl Fn(&DirectoryEntry) -> Pipeline,
+        metadata_key: Option<&aes_gcm::Aes256Gcm>,
+        api_token: &ApiToken,
+        http_client: HTTP,
+        transfer_context: &TransferContext,
+    ) -> Result<DirectoryUploadReport, UploadError> {
+        let (entries, mut report) = walk_directory(root, options)?;
+        let mut files = HashMap::new();
+        let mut links = Vec::new();
+        for entry in entries {
+            match &entry.kind {
+                EntryKind::File => {
+                    let opened = std::fs::File::open(&entry.source).map_err(|e| e.to_string()).and_then(|file| {
+                        let file_metadata = match options.metadata {
+                            MetadataPreservation::None => None,
+                            preservation => Some(
+                                PreservedMetadata::read_from_path(&entry.source, preservation == MetadataPreservation::WithXattrs)
+                                    .and_then(|metadata| metadata.encode(metadata_key))
+                                    .map_err(|e| e.to_string())?,
+                            ),
+                        };
+                        Ok((file, file_metadata))
+                    });
+                    match opened {
+                        Ok((file, file_metadata)) => {
+                            let file = NativeFile::from_file_handle(file, entry.target_path.clone(), &mime::APPLICATION_OCTET_STREAM);
+                            let content_type = options.mime_resolver.resolve_file(&entry.target_path, &file).await;
+                            files.insert(entry.target_path.clone(), (entry, content_type, RefCell::new(Some((file, file_metadata)))));
+                        }
+                        Err(e) => report.record(entry.source, FileOutcome::Failed(e)),
+                    }
+                }
+                EntryKind::Symlink { .. } => links.push(entry),
+            }
+        }
+
+        if !files.is_empty() {
+            let mut files_req = req.clone();
+            files_req.source_files = files
+                .values()
+                .map(|(entry, content_type, _)| upload_files_to_bucket_request::File {
+                    file_path: entry.target_path.clone(),
+                    size_in_bytes: entry.size_in_bytes,
+                    content_type: content_type.to_string(),
+                })
+                .collect();
+            let mut files_req = Request::new(files_req);
+            files_req.set_authorization_metadata(api_token);
+            // A failing file does not stop the others, each one is reported with its own result.
+            let results = upload_files::<NativeFile, NativeFile, _, _>(
+                self,
+                files_req,
+                |state| {
+                    let (entry, _, file) = files
+                        .get(&state.file_path)
+                        .ok_or_else(|| BucketDownloadHandlerFileErrors::UnknownFile(state.file_path.clone()))?;
+                    let (file, file_metadata) = file
+                        .take()
+                        .ok_or_else(|| BucketDownloadHandlerFileErrors::UnknownFile(state.file_path.clone()))?;
+                    let reader = <BucketFileReader<NativeFile> as FileUploadHandler<NativeFile, NativeFile>>::new(file, make_pipeline(entry))?
+                        .with_buffer_pool(transfer_context.buffers.clone());
+                    Ok(match file_metadata {
+                        Some(file_metadata) => reader.with_file_metadata(file_metadata),
+                        None => reader,
+                    })
+                },
+                api_token,
+                http_client.clone(),
+                transfer_context,
+                false,
+            )
+            .await?;
+            let mut outcomes: HashMap<String, FileOutcome> = results
+                .into_iter()
+                .map(|(target_path, result)| match result {
+                    Ok(()) => (target_path, FileOutcome::Uploaded),
+                    Err(e) => (target_path, FileOutcome::Failed(e.to_string())),
+                })
+                .collect();
+            for (target_path, (entry, _, _)) in files {
+                let outcome = outcomes
+                    .remove(&target_path)
+                    .unwrap_or_else(|| FileOutcome::Failed("not returned by the server".to_string()));
+                report.record(entry.source, outcome);
+            }
+        }
+
+        for entry in links {
+            let EntryKind::Symlink { target } = &entry.kind else { continue };
+            let link = target.as_os_str().as_encoded_bytes().to_vec();
+            let result = self
+                .upload_stream_to_bucket_raw(
+                    req.clone(),
+                    entry.target_path.clone(),
+                    SYMLINK_CONTENT_TYPE.parse().expect("valid mime"),
+                    &link[..],
+                    make_pipeline(&entry),
+                    api_token,
+                    http_client.clone(),
+                    transfer_context,
+                )
+                .await;
+            match result {
+                Ok(_) => report.record(entry.source, FileOutcome::Uploaded),
+                Err(e @ (UploadError::Cancelled(_) | UploadError::MultipartAbortUnsupported(_))) => return Err(e),
+                Err(e) => report.record(entry.source, FileOutcome::Failed(e.to_string())),
             }
         }
-        while let Some(res) = upload_task_set.join_next().await {
-            let out = res?;
-            // ...
+        Ok(report)
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    async fn upload_files_to_bucket_resumable_raw<R: Read, W: Write, UH: FileUploadHandler<R, W>, HTTP: HttpUploadClientExt>(
+        &mut self,
+        req: tonic::Request<UploadFilesToBucketRequest>,
+        journal_path: &std::path::Path,
+        source_paths: &std::collections::HashMap<String, std::path::PathBuf>,
+        open_part: impl Fn(&JournalFile, usize) -> UH,
+        api_token: &ApiToken,
+        http_client: &HTTP,
+        transfer_context: &TransferContext,
+    ) -> Result<(), UploadError> {
+        let events = &transfer_context.events;
+        // Not stored in the journal, a resumed upload takes it from `req` again.
+        let hashed_password = req.get_ref().hashed_password.clone();
+        let mut journal = match UploadJournal::load(journal_path)? {
+            Some(journal) => journal,
+            None => {
+                let part_limits = PartLimits::with_memory_limit(transfer_context.memory_budget().limit());
+                let mut journal = UploadJournal::new(req.get_ref(), part_limits);
+                let content_types: std::collections::HashMap<_, _> = req
+                    .get_ref()
+                    .source_files
+                    .iter()
+                    .map(|file| (file.file_path.clone(), file.content_type.clone()))
+                    .collect();
+                let body = self.upload_files_to_bucket(req).await?.into_inner();
+                for filepath in body.filepaths {
+                    let source_path = source_paths.get(&filepath.file_path).cloned().unwrap_or_default();
+                    let content_type = content_types.get(&filepath.file_path).cloned().unwrap_or_default();
+                    journal.add_file(
+                        UploadFileDescriptionState {
+                            file_path: filepath.file_path,
+                            size_in_bytes: filepath.total_file_size_in_bytes,
+                            urls: filepath.upload_urls,
+                        },
+                        source_path,
+                        content_type,
+                    );
+                }
+                journal.save(journal_path)?;
+                journal
+            }
+        };
+
+        for file_index in 0..journal.files.len() {
+            let file_path = journal.files[file_index].state.file_path.clone();
+            if journal.files[file_index].is_complete() {
+                continue;
+            }
+            events.started(&file_path, TransferDirection::Upload, journal.files[file_index].state.size_in_bytes).await;
+            let mut refreshed = false;
+            let mut hash_recorded = false;
+            // Starts over when the URLs were refreshed, they belong to a new multipart upload.
+            'parts: loop {
+                let file = &journal.files[file_index];
+                let plan = plan_parts(file.state.size_in_bytes, file.state.urls.len(), &journal.part_limits)?;
+                for part in journal.files[file_index].remaining_parts() {
+                    if let Err(cancelled) = transfer_context.handle.checkpoint().await {
+                        events.failed(&file_path, cancelled).await;
+                        // The journal stays, it records the uploads that are still open.
+                        return Err(abort_multipart_uploads(journal.open_upload_ids(), cancelled));
+                    }
+                    let file = &journal.files[file_index];
+                    let url = url::Url::parse(file.state.urls[part].as_str())?;
+                    let content_type: Mime = file.content_type.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM);
+                    let is_last_part = part + 1 == file.state.urls.len();
+                    let mut upload_handler = open_part(file, part);
+                    let mut result = Ok(0);
+                    let mut part_size = 0;
+                    for request in plan.parts.get(part).map(|part| part.requests.clone()).unwrap_or_default() {
+                        result = Self::upload_to_url_raw(&url, request.end - request.start, &mut upload_handler, content_type.clone(), api_token, None, http_client).await;
+                        if result.is_err() {
+                            break;
+                        }
+                        part_size += request.end - request.start;
+                        events.bytes_transferred(&file_path, request.end - request.start).await;
+                    }
+                    // Only the handler of the last part reads to the end of the file.
+                    if result.is_ok() && is_last_part {
+                        hash_recorded = upload_handler.content_hash().is_some();
+                        result = upload_handler
+                            .on_upload_finish()
+                            .await
+                            .map(|_| 200)
+                            .map_err(|e| UploadToUrlError::UploadHandlerError(Box::new(e)));
+                    }
+                    match result {
+                        Ok(_) => {
+                            journal.mark_part_complete(&file_path, part)?;
+                            journal.save(journal_path)?;
+                            events.part_uploaded(&file_path, part, part_size).await;
+                        }
+                        // The presigned URLs expired, probably while the process was not running.
+                        Err(e @ UploadToUrlError::HttpResponseStatusError(403)) if !refreshed => {
+                            events.retried(&file_path, 1, &e).await;
+                            let mut req = Request::new(journal.remaining_request(hashed_password.clone()));
+                            req.set_authorization_metadata(api_token);
+                            let body = self.upload_files_to_bucket(req).await?.into_inner();
+                            for filepath in body.filepaths {
+                                journal.replace_urls(&filepath.file_path, filepath.upload_urls)?;
+                            }
+                            journal.save(journal_path)?;
+                            refreshed = true;
+                            continue 'parts;
+                        }
+                        Err(e) => {
+                            events.failed(&file_path, &e).await;
+                            return Err(e.into());
+                        }
+                    }
+                }
+                break;
+            }
+            if hash_recorded {
+                events.verified(&file_path).await;
+            }
+            events.finished(&file_path).await;
         }
+        UploadJournal::remove(journal_path)?;
         Ok(())
     }
 
@@ -111,7 +799,12 @@ impl ClientUploadExt for QueryClient {
         hashed_password: Option<String>,
         format: Option<DownloadFormat>,
         create_download_handler: FDHB,
-    )    -> Result<(), DownloadError> {
+        http_client: HTTP,
+        local: Option<&LocalPathResolver>,
+        conflict_policy: ConflictPolicy,
+        filter: Option<&DownloadFilter>,
+        transfer_context: &TransferContext,
+    ) -> Result<DownloadReport, DownloadError> {
         let bucket_details = match url {
             //(user_id, bucket_id)
             ExclusiveShareLink::ShareLink(share) => {
@@ -119,7 +812,7 @@ impl ClientUploadExt for QueryClient {
                     .get_bucket_details_from_url_raw(share)
                     .await
                     .map_err(|e| DownloadError::GetBucketDetailsFromUrlRequestFailed(e))?;
-                vec![detail.buckets.unwrap()]
+                detail.buckets.into_iter().collect::<Vec<_>>()
             }
             ExclusiveShareLink::SecretShareLink(secret) => {
                 let req = GetBucketDetailsRequest {
@@ -136,10 +829,6 @@ impl ClientUploadExt for QueryClient {
                 detail.buckets
             }
         };
-        if !bucket_details.is_empty() {
-            return Err(DownloadError::BucketNotFound);
-        }
-
         let detail = bucket_details.first();
         let detail = match detail {
             None => {
@@ -157,14 +846,26 @@ impl ClientUploadExt for QueryClient {
             bucket_id: bucket_id.to_string(),
             bucket_owner_id: user_id.to_string(),
             hashed_password,
-            format: format.map(|x| x.to_string()),
+            // Handlers that archive on the client need the plain files.
+            format: format
+                .filter(|_| create_download_handler.archive_format().is_none())
+                .map(|x| x.to_string()),
         };
         let mut req = Request::new(bucket_download_req);
         req.set_authorization_metadata(api_token);
 
-        self.download_bucket_raw(req, true, create_download_handler)
-            .await?;
-        Ok(())
+        self.download_bucket_raw(
+            req,
+            true,
+            create_download_handler,
+            api_token,
+            http_client,
+            local,
+            conflict_policy,
+            filter,
+            transfer_context,
+        )
+        .await
     }
 
     async fn download_files_from_bucket_raw<R: Read,W: Write, N: ArrayLength,HTTP: HttpDownloadClientExt,CCH: CompressionChooserHandling<R, W>, ECH: EncryptionChooserHandler<R, W, N>,FDHB: FileDownloadHandlerBuilder<R, W, N,HTTP, CCH, ECH>>(
@@ -172,49 +873,52 @@ impl ClientUploadExt for QueryClient {
         req: tonic::Request<DownloadFilesRequest>,
         file_download_handler_builder: FDHB,
         api_token: &ApiToken,
+        http_client: &HTTP,
         keep_file_structure: bool,
-    ) -> Result<(), DownloadFilesFromBucketError> {
-        let mut resp_stream = self.download_files(req).await.unwrap().into_inner();
-
-        while let Some(item) = resp_stream.next().await {
-            match item {
-                Ok(item) => {
-                    for file in item.filepaths {
-                        let virtual_detail = VirtualFileDetails {
-                            path: file.file_path,
-                            date: None,
-                            size_in_bytes: file.file_size_in_bytes,
-                            //file_format: mime::Mime::from_str(file.file_format.as_str())?,
-                        };
-
-                        let mut download_handler = file_download_handler_builder.handle(
-                            virtual_detail,
-                            keep_file_structure,
-                        );
-                        let url = url::Url::parse(file.download_url.as_str()).unwrap();
-                        let mut size_left_in_bytes = file.file_size_in_bytes;
-                        while size_left_in_bytes > 0 {
-                            download_from_url(
-                                &url,
-                                &mut size_left_in_bytes,
-                                &mut download_handler,
-                                api_token,
-                            )
-                            .await?;
-                        }
-                        download_handler.on_download_finish().map_err(
-                            |err| -> DownloadFilesFromBucketError {
-                                DownloadFilesFromBucketError::DownloadFinishError(Box::new(err))
-                            },
-                        )?;
-                    }
-                }
-                Err(_e) => {
-                    todo!()
-                }
+        local: Option<&LocalPathResolver>,
+        conflict_policy: ConflictPolicy,
+        filter: Option<&DownloadFilter>,
+        transfer_context: &TransferContext,
+    ) -> Result<DownloadReport, DownloadFilesFromBucketError> {
+        let timed_filter = match filter {
+            Some(filter) if filter.has_time_window() => {
+                let (bucket_id, bucket_owner_id) = (&req.get_ref().bucket_id, &req.get_ref().bucket_owner_id);
+                Some(with_listed_modified_times(self, filter, bucket_id, bucket_owner_id, api_token).await?)
             }
-        }
-        Ok(())
+            _ => None,
+        };
+        let filter = timed_filter.as_ref().or(filter);
+        let resp_stream = self
+            .download_files(req)
+            .await
+            .map_err(DownloadError::DownloadBucketRequestFailed)?
+            .into_inner();
+        let listing = resp_stream.map_ok(|item| {
+            item.filepaths
+                .into_iter()
+                .map(|file| ListedFile {
+                    file_path: file.file_path,
+                    download_url: file.download_url,
+                    size_in_bytes: file.file_size_in_bytes,
+                })
+                .collect::<Vec<_>>()
+        });
+        let download = ListingDownload {
+            api_token,
+            http_client,
+            keep_file_structure,
+            local,
+            conflict_policy,
+            filter,
+            transfer_context,
+        };
+        download_listing(listing, &file_download_handler_builder, &download)
+            .await
+            .map_err(|e| match e {
+                DownloadError::DownloadFromUrlError(DownloadFromUrlError::Cancelled(cancelled)) => cancelled.into(),
+                DownloadError::DownloadHandlerError(e) => DownloadFilesFromBucketError::DownloadFinishError(e),
+                e => e.into(),
+            })
     }
 
     async fn download_bucket_raw<R: Read, W: Write, N: ArrayLength, HTTP: HttpDownloadClientExt, CCH: CompressionChooserHandling<R, W>, ECH: EncryptionChooserHandler<R, W, N>, FDHB: FileDownloadHandlerBuilder<R, W, N, HTTP, CCH, ECH>>(
@@ -223,37 +927,46 @@ impl ClientUploadExt for QueryClient {
         keep_file_structure: bool,
         download_handler_builder: FDHB,
         api_token: &ApiToken,   http_client: HTTP,
-    ) -> Result<Vec<String>, DownloadError> {
-
-        let bucket_owner_id = uuid::Uuid::try_parse(req.get_ref().bucket_owner_id.as_str());
-        let bucket_id = uuid::Uuid::try_parse(req.get_ref().bucket_id.as_str());
-        let bucket_guid = BucketGuid::new(bucket_owner_id.unwrap(), bucket_id.unwrap());
-
-        let resp = self.download_bucket(req).await.unwrap();
-        let target_path ;
-        let mut res = resp.into_inner();
-        let msg = res.message().await.unwrap().unwrap();
-        for file in &msg.file.unwrap().filepaths {
-            let url = url::Url::parse(file.download_url.as_str()).unwrap();
-            let mut builder = FileDownloadHandlerBuilder::new(&bucket_guid,api_token,&target_path,&url, &http_client);
-
-            let content_encoding= req.get_ref().format.unwrap();
-            let http_resp = http_client.get(url, api_token,content_encoding).await.unwrap();
-            let virtual_file = VirtualFileDetails {
-                path: file.file_path.clone(),
-                date: None,
-                size_in_bytes: file.file_size_in_bytes,
-                //file_format: mime::Mime::from_str(file.file_format.as_str())?,
-            };
-
-            let mut download_handler = create_download_handler.handle(
-                virtual_file,
-                keep_file_structure,
-            );
-            download_handler.on_download_chunk(&resp_bin).await.unwrap();
-            //.map_err(|err| -> DownloadError {DownloadError::from(err)})?;
-        }
-        Ok(Vec::new())
+        local: Option<&LocalPathResolver>,
+        conflict_policy: ConflictPolicy,
+        filter: Option<&DownloadFilter>,
+        transfer_context: &TransferContext,
+    ) -> Result<DownloadReport, DownloadError> {
+        let timed_filter = match filter {
+            Some(filter) if filter.has_time_window() => {
+                let (bucket_id, bucket_owner_id) = (&req.get_ref().bucket_id, &req.get_ref().bucket_owner_id);
+                Some(with_listed_modified_times(self, filter, bucket_id, bucket_owner_id, api_token).await?)
+            }
+            _ => None,
+        };
+        let filter = timed_filter.as_ref().or(filter);
+        let resp_stream = self
+            .download_bucket(req)
+            .await
+            .map_err(DownloadError::DownloadBucketRequestFailed)?
+            .into_inner();
+        // Messages without a listing carry nothing to download.
+        let listing = resp_stream.map_ok(|msg| {
+            msg.file
+                .into_iter()
+                .flat_map(|listing| listing.filepaths)
+                .map(|file| ListedFile {
+                    file_path: file.file_path,
+                    download_url: file.download_url,
+                    size_in_bytes: file.file_size_in_bytes,
+                })
+                .collect::<Vec<_>>()
+        });
+        let download = ListingDownload {
+            api_token,
+            http_client: &http_client,
+            keep_file_structure,
+            local,
+            conflict_policy,
+            filter,
+            transfer_context,
+        };
+        download_listing(listing, &download_handler_builder, &download).await
     }
 
     async fn upload_to_url_raw<R: std::io::Read,W: std::io::Write,UH: FileUploadHandler<R,W>, HTTP: HttpUploadClientExt>(
@@ -263,12 +976,24 @@ impl ClientUploadExt for QueryClient {
         content_type: Mime,
         api_token: &ApiToken,
         content_encoding: Option<Encoding>,
-        http_client: HTTP,
+        http_client: &HTTP,
     ) -> Result<u16, UploadToUrlError>
     {
-        let file_chunk = upload_handler.on_upload_chunk(chunk_size).await.unwrap();
-        let resp = http_client.put(url.clone(), file_chunk.as_slice(), api_token,content_type, content_encoding).await.unwrap();
-        Ok()
+        let file_chunk = upload_handler
+            .on_upload_chunk(chunk_size)
+            .await
+            .map_err(|e| UploadToUrlError::UploadHandlerError(Box::new(e)))?;
+        let headers = ObjectHeaders {
+            content_hash: upload_handler.content_hash(),
+            file_metadata: upload_handler.file_metadata(),
+        };
+        http_client
+            .put_with_object_headers(url.clone(), &file_chunk, api_token, content_type, content_encoding, &headers)
+            .await
+            .map_err(|e| match e.status_code() {
+                Some(status) => UploadToUrlError::HttpResponseStatusError(status),
+                None => UploadToUrlError::HttpError(format!("{:?}", e)),
+            })
     }
 
     async fn get_bucket_details_raw(
@@ -326,6 +1051,7 @@ impl ClientUploadExt for QueryClient {
     }
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct UploadFileDescriptionState {
     pub file_path: String,
     pub size_in_bytes: u64,
@@ -340,6 +1066,18 @@ pub enum DownloadFilesFromBucketError {
     DownloadFromUrlError(#[from] DownloadFromUrlError),
     #[error(transparent)]
     FromStrError(#[from] mime::FromStrError),
+    #[error(transparent)]
+    Cancelled(#[from] crate::transfer::handle::TransferCancelled),
+    #[error(transparent)]
+    RangedDownloadError(#[from] RangedDownloadError),
+    #[error(transparent)]
+    InvalidPath(#[from] BucketPathError),
+    #[error(transparent)]
+    ConflictError(#[from] ConflictError),
+    #[error(transparent)]
+    ParamsParsingError(#[from] crate::dto::bucket::DownloadFilesParamsParsingError),
+    #[error(transparent)]
+    DownloadError(#[from] DownloadError),
 }
 
 impl From<BucketDownloadHandlerErrors> for DownloadFilesFromBucketError {
@@ -353,33 +1091,426 @@ pub enum DownloadFromUrlError {
     HttpResponseStatusError(u16),
     #[error("Empty body")]
     EmptyBody,
+    #[error(transparent)]
+    IntegrityError(#[from] IntegrityError),
+    #[error("Http request failed: {0}")]
+    HttpError(String),
+    #[error("Download handler failed")]
+    HandlerError(#[source] Box<dyn Error + Send + Sync>),
+    #[error(transparent)]
+    RangedDownloadError(#[from] RangedDownloadError),
+    #[cfg(not(target_family = "wasm"))]
+    #[error(transparent)]
+    PartialDownloadError(#[from] PartialDownloadError),
+    #[error(transparent)]
+    Cancelled(#[from] crate::transfer::handle::TransferCancelled),
+}
+
+/// Reads `file` up to its size and adds it to the bundle.
+async fn add_bundled_file<F: FileWrapper>(
+    writer: &mut BundleWriter<Vec<u8>>,
+    path: String,
+    file: &F,
+) -> Result<(), std::io::Error> {
+    let size = file.get_size() as usize;
+    let mut data = Vec::with_capacity(size);
+    while data.len() < size {
+        let read = file
+            .read_at(data.len() as u64, size - data.len())
+            .await
+            .map_err(|e| std::io::Error::other(Into::<FileError>::into(e)))?;
+        if read.is_empty() {
+            break;
+        }
+        data.extend_from_slice(&read);
+    }
+    writer.add_file(path, data.as_slice()).map_err(std::io::Error::other)?;
+    Ok(())
+}
+
+/// The download listings have no modification times, a filter with a time window gets them from the
+/// filestructure listing of the bucket, fetched once for the whole download.
+async fn with_listed_modified_times(
+    client: &mut QueryClient,
+    filter: &DownloadFilter,
+    bucket_id: &str,
+    bucket_owner_id: &str,
+    api_token: &ApiToken,
+) -> Result<DownloadFilter, DownloadError> {
+    let mut modified_times = Vec::new();
+    let mut continuation_token = None;
+    loop {
+        let mut req = Request::new(GetBucketFilestructureRequest {
+            bucket_id: bucket_id.to_string(),
+            bucket_owner_id: Some(bucket_owner_id.to_string()),
+            start_directory: None,
+            continuation_token,
+            page: false,
+        });
+        req.set_authorization_metadata(api_token);
+        let resp = client
+            .get_bucket_filestructure(req)
+            .await
+            .map_err(DownloadError::GetBucketFilestructureRequestFailed)?
+            .into_inner();
+        for file in resp.filesystem.into_iter().flat_map(|filesystem| filesystem.files) {
+            // Files without a date never match the window, as in `DownloadFilter::matches_modified`.
+            let modified = file
+                .date
+                .and_then(|date| time::OffsetDateTime::from_unix_timestamp_nanos(date.seconds as i128 * 1_000_000_000 + date.nanos as i128).ok());
+            if let Some(modified) = modified {
+                modified_times.push((file.path, modified));
+            }
+        }
+        continuation_token = resp.continuation_token;
+        if continuation_token.is_none() {
+            break;
+        }
+    }
+    Ok(filter.clone().with_modified_times(modified_times))
+}
+
+/// A file of the listing streamed by `download_bucket` or `download_files`.
+pub(crate) struct ListedFile {
+    pub file_path: String,
+    pub download_url: String,
+    pub size_in_bytes: u64,
+}
+
+/// What every file of one listing is downloaded with.
+pub(crate) struct ListingDownload<'a, HTTP> {
+    pub api_token: &'a ApiToken,
+    pub http_client: &'a HTTP,
+    pub keep_file_structure: bool,
+    pub local: Option<&'a LocalPathResolver>,
+    pub conflict_policy: ConflictPolicy,
+    pub filter: Option<&'a DownloadFilter>,
+    pub transfer_context: &'a TransferContext,
+}
+
+/// Downloads the files of `listing` with handlers of `builder`. Only a failed listing, a cancel or a handler
+/// failing to clean up stops the download, every other failure is recorded for its file and the rest continue.
+pub(crate) async fn download_listing<R, W, N, HTTP, CCH, ECH, FDHB>(
+    listing: impl futures::Stream<Item = Result<Vec<ListedFile>, Status>>,
+    builder: &FDHB,
+    download: &ListingDownload<'_, HTTP>,
+) -> Result<DownloadReport, DownloadError>
+where
+    R: Read,
+    W: Write,
+    N: ArrayLength,
+    HTTP: HttpDownloadClientExt,
+    CCH: CompressionChooserHandling<R, W>,
+    ECH: EncryptionChooserHandler<R, W, N>,
+    FDHB: FileDownloadHandlerBuilder<R, W, N, HTTP, CCH, ECH>,
+{
+    let events = &download.transfer_context.events;
+    let filter = download.filter;
+    let mut report = DownloadReport::default();
+    let mut listing = std::pin::pin!(listing);
+    while let Some(files) = listing.next().await {
+        for file in files.map_err(DownloadError::DownloadBucketRequestFailed)? {
+            // Every path is checked, a path leaving the download directory fails only that file and
+            // is rejected before a handler exists, so nothing is created for it.
+            let bucket_path = match BucketPath::parse(&file.file_path) {
+                Ok(bucket_path) => bucket_path,
+                Err(e) => {
+                    events.failed(&file.file_path, &e).await;
+                    report.record(&file.file_path, DownloadOutcome::Failed(e.to_string()));
+                    continue;
+                }
+            };
+            let url = match url::Url::parse(file.download_url.as_str()) {
+                Ok(url) => url,
+                Err(e) => {
+                    events.failed(&file.file_path, &e).await;
+                    report.record(&file.file_path, DownloadOutcome::Failed(e.to_string()));
+                    continue;
+                }
+            };
+            if filter.is_some_and(|filter| !filter.matches_listed(&file.file_path, file.size_in_bytes)) {
+                report.record(&file.file_path, DownloadOutcome::Filtered);
+                continue;
+            }
+            events.started(&file.file_path, TransferDirection::Download, file.size_in_bytes).await;
+            let (local_target, outcome) = match download.local {
+                None => (None, None),
+                Some(resolver) => {
+                    let resolution = resolve_download_target(
+                        download.http_client,
+                        resolver,
+                        &bucket_path,
+                        file.size_in_bytes,
+                        &url,
+                        download.api_token,
+                        download.conflict_policy,
+                    )
+                    .await;
+                    match resolution {
+                        Ok(ConflictResolution::Write { target, outcome }) => (Some(target), Some(outcome)),
+                        Ok(ConflictResolution::Skip(reason)) => {
+                            report.record(&file.file_path, DownloadOutcome::Skipped(reason));
+                            events.finished(&file.file_path).await;
+                            continue;
+                        }
+                        Err(e) => {
+                            events.failed(&file.file_path, &e).await;
+                            report.record(&file.file_path, DownloadOutcome::Failed(e.to_string()));
+                            continue;
+                        }
+                    }
+                }
+            };
+            let virtual_file = VirtualFileDetails {
+                path: bucket_path.to_string(),
+                date: filter.and_then(|filter| filter.modified_time(&file.file_path)),
+                size_in_bytes: file.size_in_bytes,
+                //file_format: mime::Mime::from_str(file.file_format.as_str())?,
+                local_target,
+            };
+
+            let mut download_handler = match builder.handle(virtual_file, download.keep_file_structure) {
+                Ok(download_handler) => download_handler,
+                Err(e) => {
+                    events.failed(&file.file_path, &e).await;
+                    report.record(&file.file_path, DownloadOutcome::Failed(e.to_string()));
+                    continue;
+                }
+            };
+            if let Err(cancelled) = download.transfer_context.handle.checkpoint().await {
+                download_handler
+                    .on_download_cancel()
+                    .await
+                    .map_err(|e| DownloadError::DownloadHandlerError(Box::new(e)))?;
+                events.failed(&file.file_path, cancelled).await;
+                return Err(DownloadFromUrlError::Cancelled(cancelled).into());
+            }
+            let mut size_left_in_bytes = file.size_in_bytes;
+            let result = download_from_url(
+                download.http_client,
+                &url,
+                &file.file_path,
+                &mut size_left_in_bytes,
+                &mut download_handler,
+                download.api_token,
+                download.transfer_context,
+            )
+            .await;
+            let fetched = match result {
+                Ok(download) => download,
+                Err(DownloadFromUrlError::Cancelled(cancelled)) => {
+                    download_handler
+                        .on_download_cancel()
+                        .await
+                        .map_err(|e| DownloadError::DownloadHandlerError(Box::new(e)))?;
+                    events.failed(&file.file_path, cancelled).await;
+                    return Err(DownloadFromUrlError::Cancelled(cancelled).into());
+                }
+                // Dropping the handler removes what it wrote, the other files continue.
+                Err(e) => {
+                    events.failed(&file.file_path, &e).await;
+                    report.record(&file.file_path, DownloadOutcome::Failed(e.to_string()));
+                    continue;
+                }
+            };
+            // Flushes the pipeline, a truncated stream or a bad signature only shows up here.
+            if let Err(e) = download_handler.on_download_finish().await {
+                events.failed(&file.file_path, &e).await;
+                report.record(&file.file_path, DownloadOutcome::Failed(e.to_string()));
+                continue;
+            }
+            // The handler compared the plaintext against the stored hash before it finished.
+            if fetched.verified {
+                events.verified(&file.file_path).await;
+            }
+            events.finished(&file.file_path).await;
+            report.record(&file.file_path, outcome.unwrap_or(DownloadOutcome::Downloaded(bucket_path.to_relative_path())));
+        }
+    }
+    Ok(report)
 }
 
-/// Uses HTTP client.
-pub async fn download_from_url<R, W, N, HTTP: HttpDownloadClientExt, CCH: CompressionChooserHandling<R, W>, ECH: EncryptionChooserHandler<R, W, N>, FDHB: FileDownloadHandlerBuilder<R, W, N, HTTP, CCH, ECH>>(
+/// Applies the conflict policy to the local target of `bucket_path`. The hash and modification time of the
+/// object are fetched with a one byte range request, only when the target exists and the policy needs them.
+async fn resolve_download_target<HTTP: HttpDownloadClientExt>(
+    http_client: &HTTP,
+    resolver: &LocalPathResolver,
+    bucket_path: &BucketPath,
+    size_in_bytes: u64,
+    url: &Url,
+    api_token: &ApiToken,
+    policy: ConflictPolicy,
+) -> Result<ConflictResolution, ConflictError> {
+    let target = resolver.resolve(bucket_path)?;
+    let remote = if policy.needs_remote_details() && target.exists() {
+        fetch_remote_details(http_client, url, api_token, size_in_bytes).await
+    } else {
+        RemoteFileInfo {
+            size_in_bytes,
+            ..Default::default()
+        }
+    };
+    resolve_conflict(&target, &remote, policy)
+}
+
+/// Reads the content hash and modification time stored with the object, with a one byte range request.
+/// What can't be fetched is left unset, the conflict policy then acts as it does without it.
+async fn fetch_remote_details<HTTP: HttpDownloadClientExt>(http_client: &HTTP, url: &Url, api_token: &ApiToken, size_in_bytes: u64) -> RemoteFileInfo {
+    let mut remote = RemoteFileInfo {
+        size_in_bytes,
+        ..Default::default()
+    };
+    if let Ok(resp) = http_client.get_range(url.clone(), api_token, ByteRange::Bounded { start: 0, end: 1 }).await {
+        remote.content_hash = resp.content_hash;
+        // Metadata of encrypted buckets can't be read here, their remote age stays unknown.
+        remote.modified = resp
+            .file_metadata
+            .and_then(|file_metadata| PreservedMetadata::decode(&file_metadata, None).ok())
+            .and_then(|metadata| metadata.modified());
+    }
+    remote
+}
+
+/// Size of the chunks handed to the download handler.
+const DOWNLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Downloads the object at `url`, whose size is `size_left_in_bytes`, into `download_handler`.
+/// Objects the handler's `ranged_download` applies to are fetched with parallel range requests, continuing an
+/// interrupted attempt when the handler keeps a resume state. Others are fetched with one GET and handed to the
+/// handler in chunks. `size_left_in_bytes` is reduced by what was downloaded, progress goes to the context's events.
+pub async fn download_from_url<R, W, HTTP: HttpDownloadClientExt, DH: FileDownloadHandler<R, W>>(
+    http_client: &HTTP,
     url: &url::Url,
+    file_path: &str,
     size_left_in_bytes: &mut u64,
-    download_handler: &mut FDHB,
+    download_handler: &mut DH,
     api_token: &ApiToken,
-) -> Result<u16, DownloadFromUrlError> {
+    transfer_context: &TransferContext,
+) -> Result<UrlDownload, DownloadFromUrlError> {
+    let object_size = *size_left_in_bytes;
+    if download_handler.ranged_download().is_some_and(|ranged| ranged.applies_to(object_size)) {
+        // The ranges are written without the handler, the headers of the object are read with a one byte range.
+        let resp = http_client
+            .get_range(url.clone(), api_token, ByteRange::Bounded { start: 0, end: 1 })
+            .await
+            .map_err(|e| DownloadFromUrlError::HttpError(format!("{:?}", e)))?;
+        let verified = on_object_headers(download_handler, resp.content_hash, resp.file_metadata).await?;
+        if let Some(ranged) = download_handler.ranged_download() {
+            fetch_ranged(http_client, url, api_token, file_path, object_size, ranged, transfer_context).await?;
+        }
+        *size_left_in_bytes = 0;
+        return Ok(UrlDownload {
+            status: 206,
+            verified,
+        });
+    }
     //https://docs.aws.amazon.com/AmazonS3/latest/API/API_CreateMultipartUpload.html
+    let resp = http_client
+        .get_object(url.clone(), api_token, None)
+        .await
+        .map_err(|e| DownloadFromUrlError::HttpError(format!("{:?}", e)))?;
+    let body = resp.bytes;
+    let verified = on_object_headers(download_handler, resp.content_hash, resp.file_metadata).await?;
+    // Slicing shares the response body, chunks are handed to the download handler without copying.
+    let mut offset = 0;
+    while offset < body.len() {
+        let end = usize::min(offset + DOWNLOAD_CHUNK_SIZE, body.len());
+        let chunk = body.slice(offset..end);
+        *size_left_in_bytes = size_left_in_bytes.saturating_sub(chunk.len() as u64);
+        download_handler
+            .on_download_chunk(chunk)
+            .await
+            .map_err(|e| DownloadFromUrlError::HandlerError(Box::new(e)))?;
+        offset = end;
+    }
+    transfer_context.events.bytes_transferred(file_path, body.len() as u64).await;
+    Ok(UrlDownload {
+        status: resp.status,
+        verified,
+    })
+}
 
-
-    if !resp.ok() {
-        return Err(DownloadFromUrlError::HttpResponseStatusError(resp.status()));
+/// Hands the content hash and the preserved metadata stored with the object to the handler.
+/// The hash is of the plaintext, only the handler sees that after decryption and decompression.
+/// Returns whether the handler checks the hash when it finishes.
+async fn on_object_headers<R, W, DH: FileDownloadHandler<R, W>>(
+    download_handler: &mut DH,
+    content_hash: Option<ContentHash>,
+    file_metadata: Option<String>,
+) -> Result<bool, DownloadFromUrlError> {
+    let verified = match content_hash {
+        Some(content_hash) => download_handler
+            .on_content_hash(content_hash)
+            .await
+            .map_err(|e| DownloadFromUrlError::HandlerError(Box::new(e)))?,
+        None => false,
+    };
+    if let Some(file_metadata) = file_metadata {
+        download_handler
+            .on_file_metadata(&file_metadata)
+            .await
+            .map_err(|e| DownloadFromUrlError::HandlerError(Box::new(e)))?;
     }
-    let body = resp.binary().await.unwrap();
-    let mut chunk = Vec::<u8>::new();
-    let mut stream = BufReader::new(body.as_ref());
-    while let Ok(size) = stream.read(&mut chunk).await {
-        if size == 0 {
-            break;
+    Ok(verified)
+}
+
+/// Writes the ranges of `ranged` to its target, a cancel is reported as `DownloadFromUrlError::Cancelled`.
+async fn fetch_ranged<HTTP: HttpDownloadClientExt>(
+    http_client: &HTTP,
+    url: &Url,
+    api_token: &ApiToken,
+    file_path: &str,
+    object_size: u64,
+    ranged: RangedDownload<'_>,
+    transfer_context: &TransferContext,
+) -> Result<u64, DownloadFromUrlError> {
+    let cancelled = |e: RangedDownloadError| match e {
+        RangedDownloadError::Cancelled(cancelled) => DownloadFromUrlError::Cancelled(cancelled),
+        e => e.into(),
+    };
+    #[cfg(not(target_family = "wasm"))]
+    {
+        if let Some(state_path) = ranged.resume_state {
+            return download_resumable(
+                http_client,
+                url,
+                api_token,
+                file_path,
+                object_size,
+                ranged.target,
+                state_path,
+                &ranged.options,
+                ranged.make_pipeline,
+                transfer_context,
+            )
+            .await
+            .map_err(|e| match e {
+                PartialDownloadError::RangedDownloadError(e) => cancelled(e),
+                e => e.into(),
+            });
         }
-        download_handler.on_download_chunk(&chunk).await.unwrap();
-        *size_left_in_bytes -= size as u64;
-        chunk.clear();
     }
-    Ok(resp.status())
+    download_ranged(
+        http_client,
+        url,
+        api_token,
+        file_path,
+        object_size,
+        ranged.target,
+        &ranged.options,
+        ranged.make_pipeline,
+        transfer_context,
+    )
+    .await
+    .map_err(cancelled)
+}
+
+/// Outcome of [`download_from_url`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UrlDownload {
+    pub status: u16,
+    /// The handler checks the plaintext against the content hash stored with the object when it finishes.
+    pub verified: bool,
 }
 
 
@@ -388,3 +1519,130 @@ impl From<Vec<String>> for DeleteFileInBucketError {
         DeleteFileInBucketError::FailedToDeleteFilepath(failed_file_paths)
     }
 }
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use super::*;
+    use crate::client::http::{ObjectResponse, RangeResponse};
+    use crate::compression::default_compression_chooser_handler::DefaultCompressionChooserHandler;
+    use crate::encryption::encryption_chooser_handler::DefaultEncryptionChooserHandler;
+    use crate::wrapper::bucket::download::atomic::resume_state_path_for;
+    use crate::wrapper::bucket::download::file_download_handler_builder::DefaultFileDownloadHandlerBuilder;
+    use crate::wrapper::bucket::download::ranged::RangedDownloadOptions;
+    use crate::wrapper::bucket::download::DownloadHandlerOptions;
+    use bucket_common_types::BucketGuid;
+    use bytes::Bytes;
+    use generic_array::typenum::U12;
+    use std::fs::File;
+
+    /// An object that is only served in ranges, with the hash and metadata it was stored with.
+    struct RangedObject {
+        content: Bytes,
+        file_metadata: String,
+    }
+
+    impl HttpDownloadClientExt for RangedObject {
+        type Error = ();
+
+        async fn get(&self, _url: Url, _api_token: &ApiToken, _content_encoding: Option<Encoding>) -> Result<Bytes, ()> {
+            Err(())
+        }
+
+        async fn get_object(&self, _url: Url, _api_token: &ApiToken, _content_encoding: Option<Encoding>) -> Result<ObjectResponse, ()> {
+            Err(())
+        }
+
+        async fn get_range(&self, _url: Url, _api_token: &ApiToken, range: ByteRange) -> Result<RangeResponse, ()> {
+            let ByteRange::Bounded { start, end } = range else {
+                return Err(());
+            };
+            Ok(RangeResponse {
+                bytes: self.content.slice(start as usize..end as usize),
+                etag: Some("etag".to_string()),
+                total_size: Some(self.content.len() as u64),
+                content_hash: Some(ContentHash::sha3_256(&self.content)),
+                file_metadata: Some(self.file_metadata.clone()),
+            })
+        }
+    }
+
+    #[test]
+    fn test_borrowed_handler_is_returned_to_its_slot() {
+        let slot = RefCell::new(Some(1));
+        let mut handler = BorrowedHandler::take(&slot).unwrap();
+        assert!(matches!(BorrowedHandler::take(&slot), Err(UploadError::HandlerInUse)));
+        *handler.get().unwrap() += 1;
+        drop(handler);
+        assert_eq!(BorrowedHandler::take(&slot).unwrap().into_inner().unwrap(), 2);
+        assert!(slot.borrow().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_listing_download_uses_the_download_options() {
+        let root = std::env::temp_dir().join(format!("bucket-listing-{}", uuid::Uuid::new_v4()));
+        let content = Bytes::from((0..10_000u32).map(|i| (i % 251) as u8).collect::<Vec<u8>>());
+        let modified = time::OffsetDateTime::from_unix_timestamp(1_600_000_000).unwrap();
+        let mut metadata = PreservedMetadata::default();
+        metadata.set_modified(Some(modified));
+        let http_client = RangedObject {
+            content: content.clone(),
+            file_metadata: metadata.encode(None).unwrap(),
+        };
+        let api_token = ApiToken::try_from("token").unwrap();
+        let mut builder = <DefaultFileDownloadHandlerBuilder as FileDownloadHandlerBuilder<
+            File,
+            File,
+            U12,
+            RangedObject,
+            DefaultCompressionChooserHandler,
+            DefaultEncryptionChooserHandler,
+        >>::new(
+            &BucketGuid::new(uuid::Uuid::new_v4(), uuid::Uuid::new_v4()),
+            &api_token,
+            &root.to_string_lossy().to_string(),
+            &Url::parse("https://storage.test").unwrap(),
+            &http_client,
+        );
+        FileDownloadHandlerBuilder::<File, File, U12, RangedObject, DefaultCompressionChooserHandler, DefaultEncryptionChooserHandler>::set_download_options(
+            &mut builder,
+            DownloadHandlerOptions {
+                ranged: Some(RangedDownloadOptions {
+                    range_size: 1024,
+                    min_file_size: 0,
+                    layout: None,
+                }),
+                resume: true,
+                restore_metadata: Some(None),
+            },
+        );
+        let listing = futures::stream::iter([Ok::<_, Status>(vec![ListedFile {
+            file_path: "/photos/img.bin".to_string(),
+            download_url: "https://storage.test/img.bin".to_string(),
+            size_in_bytes: content.len() as u64,
+        }])]);
+        let transfer_context = TransferContext::default();
+        let download = ListingDownload {
+            api_token: &api_token,
+            http_client: &http_client,
+            keep_file_structure: true,
+            local: None,
+            conflict_policy: ConflictPolicy::default(),
+            filter: None,
+            transfer_context: &transfer_context,
+        };
+
+        // `get` fails, the file can only have been written from ranges.
+        let report = download_listing::<File, File, U12, _, DefaultCompressionChooserHandler, DefaultEncryptionChooserHandler, _>(listing, &builder, &download)
+            .await
+            .unwrap();
+        let target = root.join("photos/img.bin");
+        assert_eq!(report.downloaded().collect::<Vec<_>>(), vec!["/photos/img.bin"]);
+        assert_eq!(std::fs::read(&target).unwrap(), content);
+        assert!(!resume_state_path_for(&target).unwrap().exists());
+        assert_eq!(
+            time::OffsetDateTime::from(std::fs::metadata(&target).unwrap().modified().unwrap()),
+            modified
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+use crate::wrapper::bucket::download::ranged::PositionalWrite;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Suffix of every file a download writes before the target appears, including resumable ones.
+pub const TEMP_FILE_SUFFIX: &str = ".part";
+/// Suffix of the sidecar recording which ranges of a resumable temp file are written.
+pub const RESUME_STATE_SUFFIX: &str = ".state";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AtomicWriteError {
+    #[error("Downloaded {actual} bytes to {path}, expected {expected} bytes")]
+    SizeMismatch { path: PathBuf, expected: u64, actual: u64 },
+    #[error("Target path {0} has no file name")]
+    InvalidTarget(PathBuf),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// Writes a download to a temp file next to the target, the target only appears once the file is complete.
+/// The temp file is removed when the writer is dropped without `commit`, so a failed download leaves nothing behind.
+/// A writer opened with `resume` keeps it instead, the next attempt continues where this one stopped.
+pub struct AtomicFileWriter {
+    file: Option<File>,
+    temp_path: PathBuf,
+    target: PathBuf,
+    committed: bool,
+    /// Set for resumable writers, the sidecar next to the temp file.
+    resume_state_path: Option<PathBuf>,
+    /// A resumed temp file is truncated before the first sequential write, only ranges continue it.
+    truncate_on_write: bool,
+}
+
+fn hidden_name(target: &Path, infix: &str) -> Result<PathBuf, AtomicWriteError> {
+    let name = target
+        .file_name()
+        .ok_or_else(|| AtomicWriteError::InvalidTarget(target.to_path_buf()))?
+        .to_string_lossy();
+    Ok(target.with_file_name(format!(".{}{}{}", name, infix, TEMP_FILE_SUFFIX)))
+}
+
+/// `.<name>.<random>.part` in the directory of `target`, a rename within one filesystem is atomic.
+pub fn temp_path_for(target: &Path) -> Result<PathBuf, AtomicWriteError> {
+    hidden_name(target, &format!(".{}", uuid::Uuid::new_v4().simple()))
+}
+
+/// `.<name>.part`, the same for every attempt so an interrupted download can be found again.
+pub fn resumable_temp_path_for(target: &Path) -> Result<PathBuf, AtomicWriteError> {
+    hidden_name(target, "")
+}
+
+/// `.<name>.part.state`, the sidecar of `resumable_temp_path_for`.
+pub fn resume_state_path_for(target: &Path) -> Result<PathBuf, AtomicWriteError> {
+    let mut path = resumable_temp_path_for(target)?.into_os_string();
+    path.push(RESUME_STATE_SUFFIX);
+    Ok(PathBuf::from(path))
+}
+
+fn create_parent(target: &Path) -> std::io::Result<()> {
+    match target.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        Some(parent) => std::fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+impl AtomicFileWriter {
+    pub fn create(target: impl Into<PathBuf>) -> Result<Self, AtomicWriteError> {
+        let target = target.into();
+        let temp_path = temp_path_for(&target)?;
+        create_parent(&target)?;
+        let file = File::options().write(true).create_new(true).open(&temp_path)?;
+        Ok(Self {
+            file: Some(file),
+            temp_path,
+            target,
+            committed: false,
+            resume_state_path: None,
+            truncate_on_write: false,
+        })
+    }
+
+    /// Opens the temp file of an earlier attempt, or creates it. It is kept when the writer is dropped,
+    /// `abort` removes it together with its resume state.
+    pub fn resume(target: impl Into<PathBuf>) -> Result<Self, AtomicWriteError> {
+        let target = target.into();
+        let temp_path = resumable_temp_path_for(&target)?;
+        let resume_state_path = resume_state_path_for(&target)?;
+        create_parent(&target)?;
+        let file = File::options().write(true).create(true).truncate(false).open(&temp_path)?;
+        Ok(Self {
+            file: Some(file),
+            temp_path,
+            target,
+            committed: false,
+            resume_state_path: Some(resume_state_path),
+            truncate_on_write: true,
+        })
+    }
+
+    pub fn temp_path(&self) -> &Path {
+        &self.temp_path
+    }
+
+    /// Where the written ranges of a resumable writer are recorded.
+    pub fn resume_state_path(&self) -> Option<&Path> {
+        self.resume_state_path.as_deref()
+    }
+
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    fn file(&self) -> &File {
+        self.file.as_ref().expect("file is only taken by commit")
+    }
+
+    /// Syncs the temp file, checks its size and renames it to the target. The directory is synced as well
+    /// so the rename survives a crash.
+    pub fn commit(mut self, expected_size: Option<u64>) -> Result<PathBuf, AtomicWriteError> {
+        let file = self.file.take().expect("file is only taken by commit");
+        file.sync_all()?;
+        let actual = file.metadata()?.len();
+        drop(file);
+        if let Some(expected) = expected_size.filter(|expected| *expected != actual) {
+            return Err(AtomicWriteError::SizeMismatch {
+                path: self.target.clone(),
+                expected,
+                actual,
+            });
+        }
+        std::fs::rename(&self.temp_path, &self.target)?;
+        if let Some(state_path) = self.resume_state_path.as_deref() {
+            remove_if_exists(state_path)?;
+        }
+        if let Some(parent) = self.target.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            File::open(parent)?.sync_all()?;
+        }
+        self.committed = true;
+        Ok(self.target.clone())
+    }
+
+    /// Removes the temp file and the resume state, which dropping only does for writers that can't resume.
+    pub fn abort(mut self) -> Result<(), AtomicWriteError> {
+        self.file.take();
+        self.committed = true;
+        if let Some(state_path) = self.resume_state_path.as_deref() {
+            remove_if_exists(state_path)?;
+        }
+        Ok(remove_if_exists(&self.temp_path)?)
+    }
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+impl Write for AtomicFileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let file = self.file.as_mut().expect("file is only taken by commit");
+        if std::mem::take(&mut self.truncate_on_write) {
+            file.set_len(0)?;
+        }
+        file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.as_mut().expect("file is only taken by commit").flush()
+    }
+}
+
+impl PositionalWrite for AtomicFileWriter {
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+        crate::io::positional::write_all_at(self.file(), buf, offset)
+    }
+
+    fn sync_data(&self) -> std::io::Result<()> {
+        self.file().sync_data()
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        self.file().set_len(len)
+    }
+}
+
+impl Drop for AtomicFileWriter {
+    fn drop(&mut self) {
+        if !self.committed && self.resume_state_path.is_none() {
+            self.file.take();
+            let _ = remove_if_exists(&self.temp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_and_cleanup() {
+        let dir = std::env::temp_dir().join(format!("bucket-atomic-{}", uuid::Uuid::new_v4()));
+        let target = dir.join("file.bin");
+
+        let mut writer = AtomicFileWriter::create(&target).unwrap();
+        writer.write_all(b"partial").unwrap();
+        let temp_path = writer.temp_path().to_path_buf();
+        assert!(temp_path.exists() && !target.exists());
+        drop(writer);
+        assert!(!temp_path.exists() && !target.exists());
+
+        let mut writer = AtomicFileWriter::create(&target).unwrap();
+        writer.write_all(b"complete").unwrap();
+        assert!(matches!(writer.commit(Some(3)), Err(AtomicWriteError::SizeMismatch { actual: 8, .. })));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        let mut writer = AtomicFileWriter::create(&target).unwrap();
+        writer.write_all(b"complete").unwrap();
+        assert_eq!(writer.commit(Some(8)).unwrap(), target);
+        assert_eq!(std::fs::read(&target).unwrap(), b"complete");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resume_keeps_the_temp_file() {
+        let dir = std::env::temp_dir().join(format!("bucket-atomic-{}", uuid::Uuid::new_v4()));
+        let target = dir.join("file.bin");
+
+        let writer = AtomicFileWriter::resume(&target).unwrap();
+        assert!(writer.temp_path().ends_with(".file.bin.part"));
+        assert!(writer.resume_state_path().unwrap().ends_with(".file.bin.part.state"));
+        writer.write_all_at(b"complete", 0).unwrap();
+        drop(writer);
+
+        let writer = AtomicFileWriter::resume(&target).unwrap();
+        writer.write_all_at(b"COMP", 0).unwrap();
+        assert_eq!(writer.commit(Some(8)).unwrap(), target);
+        assert_eq!(std::fs::read(&target).unwrap(), b"COMPlete");
+
+        let writer = AtomicFileWriter::resume(&target).unwrap();
+        writer.write_all_at(b"stale content", 0).unwrap();
+        drop(writer);
+        let mut writer = AtomicFileWriter::resume(&target).unwrap();
+        writer.write_all(b"new").unwrap();
+        assert_eq!(std::fs::read(writer.temp_path()).unwrap(), b"new");
+        writer.abort().unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
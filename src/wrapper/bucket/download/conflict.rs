@@ -0,0 +1,231 @@
+use crate::integrity::{ContentHash, ContentHashAlgorithm, ContentHasher};
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConflictError {
+    #[error(transparent)]
+    InvalidPath(#[from] crate::io::bucket_path::BucketPathError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// What a download does when its local target already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    #[default]
+    Overwrite,
+    Skip,
+    /// Skips when the local file has the plaintext hash stored with the object, which also means the same size.
+    /// The listed size is of the stored object, which differs for compressed or encrypted buckets, so only the hash
+    /// decides. Objects stored without a SHA3 hash are never considered identical.
+    SkipIfIdentical,
+    /// Downloads next to the existing file as `name (1).ext`, `name (2).ext`, ...
+    KeepBoth,
+    /// Overwrites when the remote modification time is later than the local one. Without a known remote time
+    /// the local file is kept.
+    OverwriteIfNewer,
+}
+
+impl ConflictPolicy {
+    /// Whether the hash or modification time of the object has to be fetched before deciding.
+    pub fn needs_remote_details(&self) -> bool {
+        matches!(self, ConflictPolicy::SkipIfIdentical | ConflictPolicy::OverwriteIfNewer)
+    }
+}
+
+/// What is known about the object when the conflict is resolved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteFileInfo {
+    pub size_in_bytes: u64,
+    pub content_hash: Option<ContentHash>,
+    pub modified: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictSkipReason {
+    Exists,
+    Identical,
+    /// The local file is as new as the remote one or newer.
+    NotNewer,
+    RemoteAgeUnknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadOutcome {
+    Downloaded(PathBuf),
+    Overwritten(PathBuf),
+    /// Written under a new name, the existing file was kept.
+    KeptBoth(PathBuf),
+    Skipped(ConflictSkipReason),
+    /// Left out by the `DownloadFilter`.
+    Filtered,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// Download to `target`, `outcome` is recorded once the download succeeded.
+    Write { target: PathBuf, outcome: DownloadOutcome },
+    Skip(ConflictSkipReason),
+}
+
+/// Per file outcome of a download, keyed by bucket path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DownloadReport {
+    pub files: Vec<(String, DownloadOutcome)>,
+}
+
+impl DownloadReport {
+    pub fn record(&mut self, file: impl Into<String>, outcome: DownloadOutcome) {
+        self.files.push((file.into(), outcome));
+    }
+
+    pub fn downloaded(&self) -> impl Iterator<Item = &str> {
+        self.with_outcome(|outcome| {
+            matches!(
+                outcome,
+                DownloadOutcome::Downloaded(_) | DownloadOutcome::Overwritten(_) | DownloadOutcome::KeptBoth(_)
+            )
+        })
+    }
+
+    pub fn skipped(&self) -> impl Iterator<Item = &str> {
+        self.with_outcome(|outcome| matches!(outcome, DownloadOutcome::Skipped(_)))
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &str> {
+        self.with_outcome(|outcome| matches!(outcome, DownloadOutcome::Failed(_)))
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed().next().is_none()
+    }
+
+    fn with_outcome(&self, filter: impl Fn(&DownloadOutcome) -> bool) -> impl Iterator<Item = &str> {
+        self.files
+            .iter()
+            .filter(move |(_, outcome)| filter(outcome))
+            .map(|(file, _)| file.as_str())
+    }
+}
+
+/// Decides where `remote` is written when it is downloaded to `target`.
+#[cfg(not(target_family = "wasm"))]
+pub fn resolve_conflict(
+    target: &Path,
+    remote: &RemoteFileInfo,
+    policy: ConflictPolicy,
+) -> Result<ConflictResolution, ConflictError> {
+    let local = match std::fs::metadata(target) {
+        Ok(local) => local,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(ConflictResolution::Write {
+                target: target.to_path_buf(),
+                outcome: DownloadOutcome::Downloaded(target.to_path_buf()),
+            })
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let overwrite = ConflictResolution::Write {
+        target: target.to_path_buf(),
+        outcome: DownloadOutcome::Overwritten(target.to_path_buf()),
+    };
+    Ok(match policy {
+        ConflictPolicy::Overwrite => overwrite,
+        ConflictPolicy::Skip => ConflictResolution::Skip(ConflictSkipReason::Exists),
+        ConflictPolicy::SkipIfIdentical => {
+            let identical = match remote.content_hash {
+                Some(expected) if expected.algorithm == ContentHashAlgorithm::Sha3_256 => hash_file(target)? == expected,
+                _ => false,
+            };
+            if identical {
+                ConflictResolution::Skip(ConflictSkipReason::Identical)
+            } else {
+                overwrite
+            }
+        }
+        ConflictPolicy::KeepBoth => {
+            let target = numbered_path(target);
+            ConflictResolution::Write {
+                outcome: DownloadOutcome::KeptBoth(target.clone()),
+                target,
+            }
+        }
+        ConflictPolicy::OverwriteIfNewer => match remote.modified {
+            None => ConflictResolution::Skip(ConflictSkipReason::RemoteAgeUnknown),
+            Some(remote_modified) if OffsetDateTime::from(local.modified()?) < remote_modified => overwrite,
+            Some(_) => ConflictResolution::Skip(ConflictSkipReason::NotNewer),
+        },
+    })
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn hash_file(path: &Path) -> Result<ContentHash, std::io::Error> {
+    let mut reader = crate::integrity::HashingReader::new(std::fs::File::open(path)?, ContentHasher::sha3_256());
+    std::io::copy(&mut reader, &mut std::io::sink())?;
+    Ok(reader.finalize().1)
+}
+
+/// The first `name (n).ext` next to `target` that does not exist yet.
+#[cfg(not(target_family = "wasm"))]
+pub fn numbered_path(target: &Path) -> PathBuf {
+    let stem = target.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = target.extension().map(|extension| extension.to_string_lossy().into_owned());
+    (1u64..)
+        .map(|n| {
+            let name = match &extension {
+                Some(extension) => format!("{} ({}).{}", stem, n, extension),
+                None => format!("{} ({})", stem, n),
+            };
+            target.with_file_name(name)
+        })
+        .find(|candidate| std::fs::symlink_metadata(candidate).is_err())
+        .expect("some numbered name is free")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_conflicts() {
+        let dir = std::env::temp_dir().join(format!("bucket-conflict-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("report.pdf");
+        let remote = RemoteFileInfo {
+            size_in_bytes: 7,
+            content_hash: Some(ContentHash::sha3_256(b"content")),
+            modified: None,
+        };
+
+        let missing = resolve_conflict(&target, &remote, ConflictPolicy::Skip).unwrap();
+        assert_eq!(missing, ConflictResolution::Write { target: target.clone(), outcome: DownloadOutcome::Downloaded(target.clone()) });
+
+        std::fs::write(&target, b"content").unwrap();
+        std::fs::write(dir.join("report (1).pdf"), b"older copy").unwrap();
+        assert_eq!(
+            resolve_conflict(&target, &remote, ConflictPolicy::SkipIfIdentical).unwrap(),
+            ConflictResolution::Skip(ConflictSkipReason::Identical)
+        );
+        let changed = RemoteFileInfo { content_hash: Some(ContentHash::sha3_256(b"changed")), ..remote.clone() };
+        assert!(matches!(
+            resolve_conflict(&target, &changed, ConflictPolicy::SkipIfIdentical).unwrap(),
+            ConflictResolution::Write { outcome: DownloadOutcome::Overwritten(_), .. }
+        ));
+        assert_eq!(
+            resolve_conflict(&target, &remote, ConflictPolicy::KeepBoth).unwrap(),
+            ConflictResolution::Write { target: dir.join("report (2).pdf"), outcome: DownloadOutcome::KeptBoth(dir.join("report (2).pdf")) }
+        );
+        assert_eq!(
+            resolve_conflict(&target, &remote, ConflictPolicy::OverwriteIfNewer).unwrap(),
+            ConflictResolution::Skip(ConflictSkipReason::RemoteAgeUnknown)
+        );
+        let newer = RemoteFileInfo { modified: Some(OffsetDateTime::now_utc() + time::Duration::hours(1)), ..remote };
+        assert!(matches!(
+            resolve_conflict(&target, &newer, ConflictPolicy::OverwriteIfNewer).unwrap(),
+            ConflictResolution::Write { outcome: DownloadOutcome::Overwritten(_), .. }
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
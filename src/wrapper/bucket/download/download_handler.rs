@@ -1,45 +1,89 @@
-use crate::compression::{CompressorModule, DecompressModule};
 use async_trait::async_trait;
-use bucket_common_types::{BucketCompression, BucketEncryption, Encryption};
-use futures::future::Either;
-use generic_array::ArrayLength;
+use bucket_common_types::{BucketCompression, Encryption};
+use bytes::Bytes;
 use mime::FromStrError;
-use std::marker::PhantomData;
+use std::io::Write;
 use uuid::Uuid;
-use zero_knowledge_encryption::encryption::aead::decryption_module::DecryptionError;
-use zero_knowledge_encryption::encryption::aead::DecryptionModule;
+use crate::integrity::{ContentHash, ContentHashAlgorithm, ContentHasher};
+use crate::io::metadata::PreservedMetadata;
 use crate::io::FileWrapper;
+use crate::pipeline::{Pipeline, PipelineError};
+#[cfg(not(target_family = "wasm"))]
+use crate::wrapper::bucket::download::ranged::{DownloadRange, PositionalWrite, RangedDownload, RangedDownloadOptions};
 use crate::wrapper::bucket::download::FileDownloadHandler;
+#[cfg(not(target_family = "wasm"))]
+use std::cell::Cell;
 
 #[derive(Debug, thiserror::Error)]
 pub enum BucketDownloadHandlerErrors {
     #[error(transparent)]
     FromStrError(#[from] FromStrError),
     #[error(transparent)]
-    DecryptionError(#[from] DecryptionError),
+    PipelineError(#[from] PipelineError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[error("Writing the target file failed: {0}")]
+    FileError(#[from] crate::io::FileError),
+    #[error(transparent)]
+    IntegrityError(#[from] crate::integrity::IntegrityError),
+    #[error(transparent)]
+    InvalidPath(#[from] crate::io::bucket_path::BucketPathError),
+    #[error(transparent)]
+    FileMetadataError(#[from] crate::io::metadata::FileMetadataError),
+    #[error(transparent)]
+    ArchiveError(#[from] crate::archive::ArchiveError),
+    #[cfg(not(target_family = "wasm"))]
+    #[error(transparent)]
+    AtomicWriteError(#[from] crate::wrapper::bucket::download::atomic::AtomicWriteError),
 }
 
 
 
 //#[derive(Clone)]
-pub struct WebBucketFileWriter<
-    R: std::io::Read,
-    W: std::io::Write,
-    N: ArrayLength,
-    DE: DecryptionModule<R, W, N>,
-    DC: DecompressModule<R, W>,
-    BF: FileWrapper<Error=(), FileHandle=()>
-> {
-    phantom_data_r: PhantomData<R>,
-    phantom_data_w: PhantomData<W>,
-    phantom_data: PhantomData<N>,
+pub struct WebBucketFileWriter<BF: FileWrapper> {
     //write_target_file: gloo::file::File,
     write_target_file: BF,
     pub offset: u64,
-    pub decryption_module: Option<DE>,
-    pub decompression_module: Option<DC>,
-    // Will be none if no encryption was used. Everything encryption related is handled by the module.
+    /// Signature verification, decryption and decompression are all handled by the pipeline.
+    pub pipeline: Pipeline,
     pub is_checking_signature: bool, // TODO: Add support for this feature. The file will be checked against another file with special signature to ensure the signature matches against the supplied signature. This ensures the content can't be tampered with.
+    /// Names the file in integrity errors.
+    pub file_path: String,
+    /// Hash of the plaintext recorded on upload, checked when the download finishes.
+    pub expected_hash: Option<ContentHash>,
+    hasher: Option<ContentHasher>,
+}
+
+impl<BF: FileWrapper> WebBucketFileWriter<BF> {
+    pub fn new(write_target_file: BF, pipeline: Pipeline) -> Self {
+        Self {
+            write_target_file,
+            offset: 0,
+            pipeline,
+            is_checking_signature: false,
+            file_path: String::new(),
+            expected_hash: None,
+            hasher: None,
+        }
+    }
+
+    /// Checks the keyed hash stored with the object, SHA3-256 hashes are checked without a hasher.
+    pub fn with_content_hasher(mut self, hasher: ContentHasher) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    async fn write_plaintext(&mut self, plaintext: &[u8]) -> Result<(), BucketDownloadHandlerErrors> {
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(plaintext);
+        }
+        self.write_target_file
+            .write_at(self.offset, plaintext)
+            .await
+            .map_err(|e| BucketDownloadHandlerErrors::FileError(e.into()))?;
+        self.offset += plaintext.len() as u64;
+        Ok(())
+    }
 }
 
 pub struct DownloadStartParams {
@@ -57,79 +101,383 @@ pub struct DownloadStartParams {
 }
 
 #[async_trait(? Send)]
-impl<R: std::io::Read, W: std::io::Write, N: generic_array::ArrayLength, DE: DecryptionModule<R, W, N>, DC: DecompressModule<R, W>, BF: FileWrapper<Error = (), FileHandle = ()>>
-    FileDownloadHandler<R, W> for WebBucketFileWriter<R, W, N, DE, DC, BF>
+impl<R, W, BF: FileWrapper> FileDownloadHandler<R, W> for WebBucketFileWriter<BF>
 {
     type Error = BucketDownloadHandlerErrors;
 
-    // Called when a chunk is downloaded. It's up to the user to decrypt the chunk if the bucket is encrypted, or to save the chunk to a file.
-    async fn on_download_chunk(&mut self, chunk: &mut [u8]) -> Result<(), Self::Error> {
-        let decompressed_buffer;
-        match &mut self.decryption_module {
-            None => {}
-            Some(de) => {
-                let size = de.decrypt_block(chunk).unwrap();
-            }
-        };
-        match &mut self.decompression_module {
-            None => {}
-            Some(dm) => {
-                dm.decompress_chunk(chunk).unwrap();
-            }
-        };
+    // Called when a chunk is downloaded. The chunk is passed through the pipeline and the plaintext is written to the file.
+    async fn on_download_chunk(&mut self, chunk: Bytes) -> Result<(), Self::Error> {
+        let plaintext = self.pipeline.process(chunk).await?;
+        self.write_plaintext(&plaintext).await?;
+        Ok(())
+    }
+    // Called when the last chunk has been downloaded.
+    async fn on_download_finish(mut self) -> Result<(), Self::Error> {
+        // Fails if the stream was truncated or the signature does not match.
+        let tail = self.pipeline.finish().await?;
+        self.write_plaintext(&tail).await?;
+        if let (Some(expected), Some(hasher)) = (self.expected_hash, self.hasher.take()) {
+            expected.verify(&self.file_path, &hasher.finalize())?;
+        }
+        Ok(())
+    }
 
+    async fn on_content_hash(&mut self, content_hash: ContentHash) -> Result<bool, Self::Error> {
+        Ok(accept_content_hash(&mut self.expected_hash, &mut self.hasher, content_hash))
+    }
+}
+
+/// Takes the hash stored with the object as the expected one, unless the caller gave one already.
+/// Returns false when it can't be checked, a keyed hash needs a hasher with its key.
+fn accept_content_hash(expected_hash: &mut Option<ContentHash>, hasher: &mut Option<ContentHasher>, content_hash: ContentHash) -> bool {
+    if expected_hash.is_some() {
+        return true;
+    }
+    match hasher.as_ref() {
+        Some(hasher) if hasher.algorithm() == content_hash.algorithm => {}
+        None if content_hash.algorithm == ContentHashAlgorithm::Sha3_256 => *hasher = Some(ContentHasher::sha3_256()),
+        _ => return false,
+    }
+    *expected_hash = Some(content_hash);
+    true
+}
+
+/// Native download handler that writes through a temp file, the target only appears after the pipeline
+/// has verified the signature and the size matches.
+#[cfg(not(target_family = "wasm"))]
+pub struct AtomicBucketFileWriter {
+    writer: crate::wrapper::bucket::download::atomic::AtomicFileWriter,
+    pipeline: Pipeline,
+    /// Size of the plaintext, checked before the temp file is renamed.
+    pub expected_size: Option<u64>,
+    /// Hash of the plaintext recorded on upload, checked before the temp file is renamed.
+    pub expected_hash: Option<ContentHash>,
+    hasher: Option<ContentHasher>,
+    file_path: String,
+    /// Set by `with_metadata_restore`, the key decrypts the metadata of encrypted buckets.
+    metadata_key: Option<Option<aes_gcm::Aes256Gcm>>,
+    metadata: Option<PreservedMetadata>,
+    ranged: Option<(RangedDownloadOptions, Box<dyn Fn(&DownloadRange) -> Pipeline>)>,
+    /// Set once the file was written through `PositionalWrite`, the pipeline of the handler is then unused.
+    written_in_ranges: Cell<bool>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl AtomicBucketFileWriter {
+    pub fn new(target: impl Into<std::path::PathBuf>, pipeline: Pipeline) -> Result<Self, BucketDownloadHandlerErrors> {
+        let target = target.into();
+        let writer = crate::wrapper::bucket::download::atomic::AtomicFileWriter::create(&target)?;
+        Ok(Self::with_writer(target, writer, pipeline))
+    }
+
+    /// Like `new`, but continues the temp file of an interrupted attempt when the object is fetched in ranges,
+    /// see `with_ranged_download`. The temp file is kept when the download fails and removed on cancel.
+    pub fn resume(target: impl Into<std::path::PathBuf>, pipeline: Pipeline) -> Result<Self, BucketDownloadHandlerErrors> {
+        let target = target.into();
+        let writer = crate::wrapper::bucket::download::atomic::AtomicFileWriter::resume(&target)?;
+        Ok(Self::with_writer(target, writer, pipeline))
+    }
+
+    fn with_writer(
+        target: std::path::PathBuf,
+        writer: crate::wrapper::bucket::download::atomic::AtomicFileWriter,
+        pipeline: Pipeline,
+    ) -> Self {
+        Self {
+            file_path: target.to_string_lossy().into_owned(),
+            writer,
+            pipeline,
+            expected_size: None,
+            expected_hash: None,
+            hasher: None,
+            metadata_key: None,
+            metadata: None,
+            ranged: None,
+            written_in_ranges: Cell::new(false),
+        }
+    }
+
+    /// Lets large objects be fetched with parallel range requests, `make_pipeline` returns the pipeline of a range.
+    /// Objects whose pipeline can't process ranges, see [`RangedDownloadOptions::check_pipeline`], use a single request.
+    pub fn with_ranged_download(
+        mut self,
+        options: RangedDownloadOptions,
+        make_pipeline: impl Fn(&DownloadRange) -> Pipeline + 'static,
+    ) -> Self {
+        self.ranged = Some((options, Box::new(make_pipeline)));
+        self
+    }
 
-        let decrypt_buffer;
+    /// Restores the modification time, mode and extended attributes recorded on upload once the file is committed.
+    /// `key` is the bucket key for encrypted buckets.
+    pub fn with_metadata_restore(mut self, key: Option<aes_gcm::Aes256Gcm>) -> Self {
+        self.metadata_key = Some(key);
+        self
+    }
+
+    /// Writes the bucket file `path` below the root of `resolver`.
+    pub fn for_bucket_path(
+        resolver: &crate::io::bucket_path::LocalPathResolver,
+        path: &crate::io::bucket_path::BucketPath,
+        pipeline: Pipeline,
+    ) -> Result<Self, BucketDownloadHandlerErrors> {
+        Self::new(resolver.resolve(path)?, pipeline)
+    }
+
+    /// `hasher` must use the algorithm and key the content was hashed with on upload.
+    pub fn with_expected_hash(mut self, expected_hash: ContentHash, hasher: ContentHasher) -> Self {
+        self.expected_hash = Some(expected_hash);
+        self.hasher = Some(hasher);
+        self
+    }
+
+    /// Checks the keyed hash stored with the object, SHA3-256 hashes are checked without a hasher.
+    pub fn with_content_hasher(mut self, hasher: ContentHasher) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    fn write_plaintext(&mut self, plaintext: &[u8]) -> Result<(), BucketDownloadHandlerErrors> {
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(plaintext);
+        }
+        self.writer.write_all(plaintext)?;
+        Ok(())
+    }
 
-        let decrypted_buffer: futures::future::Either<Vec<u8>, &Vec<u8>> =
-            match &mut self.decryption_module {
-                Some(x) => {
-                    let mut decrypted_buffer: Vec<u8> = chunk.clone();
-                    decrypted_buffer = x.update(chunk).unwrap();
-                    Either::Left(decrypted_buffer)
+    /// Feeds what the ranges wrote to the temp file to the hasher, read on the blocking pool like `NativeFile`.
+    /// Ranges finish out of order, so they can't be hashed as they are written.
+    async fn hash_temp_file(&self, mut hasher: ContentHasher) -> Result<ContentHasher, BucketDownloadHandlerErrors> {
+        let temp_path = self.writer.temp_path().to_path_buf();
+        let hashed = tokio::task::spawn_blocking(move || -> std::io::Result<ContentHasher> {
+            let mut file = std::fs::File::open(temp_path)?;
+            let mut buf = vec![0; 1024 * 1024];
+            loop {
+                let read = std::io::Read::read(&mut file, &mut buf)?;
+                if read == 0 {
+                    return Ok(hasher);
                 }
-                None => Either::Right(chunk),
-            };
-        match decrypted_buffer {
-            Either::Left(decrypted_buffer) => {
-                self.write_target_file.write(&decrypted_buffer.as_slice()[(self.offset as usize)..(decrypted_buffer.len() - (self.offset as usize))])
-                    .unwrap();
-                self.offset += decrypted_buffer.len() as u64;
-            }
-            Either::Right(decrypted_buffer) => {
-                self.write_target_file
-                    .write_chunk(&decrypted_buffer, self.offset)
-                    .unwrap();
-                self.offset += decrypted_buffer.len() as u64;
+                hasher.update(&buf[..read]);
             }
-        }
+        });
+        Ok(hashed.await.map_err(std::io::Error::other)??)
+    }
+}
 
-        /*
-         * Create custom object for URL to download the file.
-         * The file will be stored as a "xxxx.temp" file. After the download the file is renamed to the correct filename.
-         */
+#[cfg(not(target_family = "wasm"))]
+impl PositionalWrite for AtomicBucketFileWriter {
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+        self.written_in_ranges.set(true);
+        self.writer.write_all_at(buf, offset)
+    }
+
+    // A resumed download with every range already written only syncs.
+    fn sync_data(&self) -> std::io::Result<()> {
+        self.written_in_ranges.set(true);
+        self.writer.sync_data()
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        self.writer.set_len(len)
+    }
+}
 
-        //let reader = read_as_bytes(&self.write_target_file.slice(start, end)).await?;
-        // let reader = read_as_array_buffer(blob, |buffer, |{
-        //     Self.to_owned().on_download_chunk(r_bytes);
-        //     let bytes = js_sys::Uint8Array::new(&buffer).to_vec();
-        //     let r_bytes = byte;
-        // });
+#[cfg(not(target_family = "wasm"))]
+#[async_trait(? Send)]
+impl<R, W> FileDownloadHandler<R, W> for AtomicBucketFileWriter {
+    type Error = BucketDownloadHandlerErrors;
 
-        //let buffer = reader.as_slice();
+    async fn on_download_chunk(&mut self, chunk: Bytes) -> Result<(), Self::Error> {
+        let plaintext = self.pipeline.process(chunk).await?;
+        self.write_plaintext(&plaintext)?;
+        Ok(())
+    }
 
+    async fn on_file_metadata(&mut self, file_metadata: &str) -> Result<(), Self::Error> {
+        if let Some(key) = self.metadata_key.as_ref() {
+            self.metadata = Some(PreservedMetadata::decode(file_metadata, key.as_ref())?);
+        }
         Ok(())
     }
-    // Called when the last chunk has been downloaded.
-    fn on_download_finish(self) -> Result<(), Self::Error> {
-        //TODO: Check if file match checksums.
-        match self.decryption_module {
-            None => {}
-            Some(module) => {
-                module.finalize(); //TODO: Invalid Signature
+
+    async fn on_content_hash(&mut self, content_hash: ContentHash) -> Result<bool, Self::Error> {
+        Ok(accept_content_hash(&mut self.expected_hash, &mut self.hasher, content_hash))
+    }
+
+    // A failed signature, hash or size check drops the writer, which removes the temp file.
+    // Metadata is restored after the rename, a failure there leaves the complete file in place.
+    async fn on_download_finish(mut self) -> Result<(), Self::Error> {
+        if self.written_in_ranges.get() {
+            // Every range flushed its own pipeline, the hash is computed from the written file.
+            if let Some(hasher) = self.hasher.take() {
+                self.hasher = Some(self.hash_temp_file(hasher).await?);
             }
+        } else {
+            let tail = self.pipeline.finish().await?;
+            self.write_plaintext(&tail)?;
+        }
+        if let (Some(expected), Some(hasher)) = (self.expected_hash, self.hasher.take()) {
+            expected.verify(&self.file_path, &hasher.finalize())?;
         }
+        let target = self.writer.commit(self.expected_size)?;
+        if let Some(metadata) = self.metadata.take() {
+            metadata.restore(&target)?;
+        }
+        Ok(())
+    }
 
+    fn ranged_download(&self) -> Option<RangedDownload<'_>> {
+        let (options, make_pipeline) = self.ranged.as_ref()?;
+        Some(RangedDownload {
+            target: self,
+            resume_state: self.writer.resume_state_path(),
+            options: *options,
+            make_pipeline: Box::new(move |range| make_pipeline(range)),
+        })
+    }
+
+    async fn on_download_cancel(self) -> Result<(), Self::Error> {
+        self.writer.abort()?;
         Ok(())
     }
 }
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use super::*;
+    use crate::client::http::{ByteRange, HttpDownloadClientExt, ObjectResponse, RangeResponse};
+    use crate::pipeline::PipelineDirection;
+    use crate::token::ApiToken;
+    use crate::transfer::TransferContext;
+    use crate::wrapper::bucket::download::ranged::download_ranged;
+    use bucket_common_types::Encoding;
+    use url::Url;
+
+    /// An object with the content hash it was stored with.
+    struct MemoryObject(Bytes, Option<ContentHash>);
+
+    impl HttpDownloadClientExt for MemoryObject {
+        type Error = ();
+
+        async fn get(&self, _url: Url, _api_token: &ApiToken, _content_encoding: Option<Encoding>) -> Result<Bytes, ()> {
+            Ok(self.0.clone())
+        }
+
+        async fn get_object(&self, _url: Url, _api_token: &ApiToken, _content_encoding: Option<Encoding>) -> Result<ObjectResponse, ()> {
+            Err(())
+        }
+
+        async fn get_range(&self, _url: Url, _api_token: &ApiToken, range: ByteRange) -> Result<RangeResponse, ()> {
+            let ByteRange::Bounded { start, end } = range else {
+                return Err(());
+            };
+            Ok(RangeResponse {
+                bytes: self.0.slice(start as usize..end as usize),
+                etag: None,
+                total_size: Some(self.0.len() as u64),
+                content_hash: self.1,
+                file_metadata: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ranged_download_commits_and_verifies() {
+        let dir = std::env::temp_dir().join(format!("bucket-ranged-{}", uuid::Uuid::new_v4()));
+        let target = dir.join("file.bin");
+        let content = Bytes::from((0..10_000u32).map(|i| (i % 251) as u8).collect::<Vec<u8>>());
+        let options = RangedDownloadOptions {
+            range_size: 1024,
+            min_file_size: 0,
+            layout: None,
+        };
+        let mut hasher = ContentHasher::sha3_256();
+        hasher.update(&content);
+        let mut writer = AtomicBucketFileWriter::new(&target, Pipeline::passthrough(PipelineDirection::Download))
+            .unwrap()
+            .with_ranged_download(options, |_| Pipeline::passthrough(PipelineDirection::Download))
+            .with_expected_hash(hasher.finalize(), ContentHasher::sha3_256());
+        writer.expected_size = Some(content.len() as u64);
+
+        let ranged = FileDownloadHandler::<std::fs::File, std::fs::File>::ranged_download(&writer).unwrap();
+        assert!(ranged.applies_to(content.len() as u64));
+        let written = download_ranged(
+            &MemoryObject(content.clone(), None),
+            &Url::parse("https://storage.test/file.bin").unwrap(),
+            &ApiToken::try_from("token").unwrap(),
+            "file.bin",
+            content.len() as u64,
+            ranged.target,
+            &ranged.options,
+            ranged.make_pipeline,
+            &TransferContext::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, content.len() as u64);
+        assert!(!target.exists());
+        FileDownloadHandler::<std::fs::File, std::fs::File>::on_download_finish(writer).await.unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), content);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ranged_download_from_url_checks_the_stored_hash() {
+        let dir = std::env::temp_dir().join(format!("bucket-ranged-url-{}", uuid::Uuid::new_v4()));
+        let content = Bytes::from((0..10_000u32).map(|i| (i % 241) as u8).collect::<Vec<u8>>());
+        let options = RangedDownloadOptions {
+            range_size: 1024,
+            min_file_size: 0,
+            layout: None,
+        };
+        let mut hasher = ContentHasher::sha3_256();
+        hasher.update(&content);
+        let stored_hash = hasher.finalize();
+        let mut other = ContentHasher::sha3_256();
+        other.update(b"other content");
+        let url = Url::parse("https://storage.test/file.bin").unwrap();
+        let token = ApiToken::try_from("token").unwrap();
+
+        for (name, object_hash, succeeds) in [("good.bin", stored_hash, true), ("bad.bin", other.finalize(), false)] {
+            let target = dir.join(name);
+            let mut writer = AtomicBucketFileWriter::new(&target, Pipeline::passthrough(PipelineDirection::Download))
+                .unwrap()
+                .with_ranged_download(options, |_| Pipeline::passthrough(PipelineDirection::Download));
+            let mut size_left = content.len() as u64;
+            let download = crate::wrapper::bucket::bucket::download_from_url::<std::fs::File, std::fs::File, _, _>(
+                &MemoryObject(content.clone(), Some(object_hash)),
+                &url,
+                name,
+                &mut size_left,
+                &mut writer,
+                &token,
+                &TransferContext::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(download.status, 206);
+            assert!(download.verified);
+            let finished = FileDownloadHandler::<std::fs::File, std::fs::File>::on_download_finish(writer).await;
+            assert_eq!(finished.is_ok(), succeeds);
+            assert_eq!(target.exists(), succeeds);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_web_writer_checks_the_stored_hash() {
+        for (stored, succeeds) in [(&b"hello world"[..], true), (&b"tampered"[..], false)] {
+            let file = crate::io::memory::MemoryFile::new("hello.txt", Vec::new(), &mime::TEXT_PLAIN);
+            let mut writer = WebBucketFileWriter::new(file, Pipeline::passthrough(PipelineDirection::Download));
+            let verified = FileDownloadHandler::<std::fs::File, std::fs::File>::on_content_hash(&mut writer, ContentHash::sha3_256(stored))
+                .await
+                .unwrap();
+            assert!(verified);
+            FileDownloadHandler::<std::fs::File, std::fs::File>::on_download_chunk(&mut writer, Bytes::from_static(b"hello world"))
+                .await
+                .unwrap();
+            let finished = FileDownloadHandler::<std::fs::File, std::fs::File>::on_download_finish(writer).await;
+            assert_eq!(finished.is_ok(), succeeds);
+        }
+    }
+}
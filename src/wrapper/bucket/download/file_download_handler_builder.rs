@@ -1,60 +1,147 @@
 use std::io::{Read, Write};
+use std::path::{Component, PathBuf};
 use bucket_common_types::BucketGuid;
 use generic_array::ArrayLength;
 use url::Url;
-use zero_knowledge_encryption::encryption::aead::DecryptionModule;
-use crate::client::http::HttpUploadClientExt;
-use crate::compression::DecompressModule;
-use crate::wrapper::bucket::download::{FileDownloadHandler, FileDownloadHandlerBuilder};
+use crate::client::http::HttpDownloadClientExt;
+use crate::compression::CompressionChooserHandling;
+use crate::encryption::EncryptionChooserHandler;
+use crate::io::bucket_path::{BucketPath, BucketPathError, LocalPathResolver};
+use crate::io::file::VirtualFileDetails;
+use crate::pipeline::{Pipeline, PipelineDirection};
+use crate::token::ApiToken;
+use crate::wrapper::bucket::download::download_handler::{AtomicBucketFileWriter, BucketDownloadHandlerErrors};
+use crate::wrapper::bucket::download::{DownloadHandlerOptions, FileDownloadHandlerBuilder};
 
-pub struct DefaultFileDownloadHandlerBuilder<HTTP: HttpUploadClientExt, R: Read, W: Write, N: ArrayLength, DECOMPRESS: DecompressModule<R, W>, DECRYPT: DecryptionModule<R, W, N>> {
+/// Writes every file of a download below `target_path` through an [`AtomicBucketFileWriter`].
+/// The files are fetched by the download functions with the caller's client, the builder only creates the handlers.
+pub struct DefaultFileDownloadHandlerBuilder {
     pub target_bucket: BucketGuid,
-    pub target_path: String,
-    pub upload_url: Url,
-    pub client: HTTP,
+    /// The download directory, no handler writes outside of it.
+    pub target_path: PathBuf,
     pub download_size: Option<u64>,
-    pub decompression_module: Option<DECOMPRESS>,
-    pub decryption_module: Option<DECRYPT>,
+    /// Files are placed directly in `target_path` when either the builder or the download doesn't keep the structure.
     pub keep_file_structure: bool,
-    
+    /// Ranges are only used for files without pipeline stages, a range can't be decrypted on its own here.
+    pub options: DownloadHandlerOptions,
+    make_pipeline: Box<dyn Fn(&VirtualFileDetails) -> Pipeline>,
 }
 
-impl <HTTP: HttpUploadClientExt, CCH, ECH, N, W, R, DECOMPRESS: DecompressModule<R, W>, DECRYPT: DecryptionModule<R, W, N>> FileDownloadHandlerBuilder<R,W,N,HTTP, CCH, ECH> for DefaultFileDownloadHandlerBuilder<HTTP, R, W, N, DECOMPRESS, DECRYPT> {
-    type OutputType = FileDownloadHandler<R, W, Error=()>;
+impl DefaultFileDownloadHandlerBuilder {
+    /// The handler of `file`. A target chosen by the conflict policy, such as the numbered name of `KeepBoth`,
+    /// is used as long as it is below `target_path`, otherwise the bucket path is resolved below it.
+    pub fn handler_for(
+        &self,
+        file: VirtualFileDetails,
+        keep_file_structure: bool,
+    ) -> Result<AtomicBucketFileWriter, BucketDownloadHandlerErrors> {
+        let target = match file.local_target.clone() {
+            Some(target)
+                if target.starts_with(&self.target_path)
+                    && !target.components().any(|component| component == Component::ParentDir) =>
+            {
+                target
+            }
+            Some(target) => return Err(BucketPathError::OutsideRoot(target).into()),
+            None => LocalPathResolver::new(&self.target_path, keep_file_structure && self.keep_file_structure)
+                .resolve(&BucketPath::parse(&file.path)?)?,
+        };
+        let pipeline = (self.make_pipeline)(&file);
+        // Only without stages is the stored size the size of the plaintext.
+        let plaintext = pipeline.stage_kinds().is_empty();
+        let ranged = self.options.ranged.filter(|_| plaintext);
+        let mut handler = match ranged {
+            Some(_) if self.options.resume => AtomicBucketFileWriter::resume(target, pipeline)?,
+            _ => AtomicBucketFileWriter::new(target, pipeline)?,
+        };
+        if let Some(ranged) = ranged {
+            handler = handler.with_ranged_download(ranged, |_| Pipeline::passthrough(PipelineDirection::Download));
+        }
+        if let Some(key) = self.options.restore_metadata.clone() {
+            handler = handler.with_metadata_restore(key);
+        }
+        handler.expected_size = plaintext.then_some(file.size_in_bytes);
+        Ok(handler)
+    }
+}
 
-    fn new(target_bucket: &BucketGuid, target_path: &String, upload_url: &Url, client: &HTTP) -> Self {
+impl<R, W, N, HTTP, CCH, ECH> FileDownloadHandlerBuilder<R, W, N, HTTP, CCH, ECH> for DefaultFileDownloadHandlerBuilder
+where
+    R: Read,
+    W: Write,
+    N: ArrayLength,
+    HTTP: HttpDownloadClientExt,
+    CCH: CompressionChooserHandling<R, W>,
+    ECH: EncryptionChooserHandler<R, W, N>,
+{
+    type OutputType = Self;
+    type Handler = AtomicBucketFileWriter;
+
+    fn new(target_bucket: &BucketGuid, _api_token: &ApiToken, target_path: &String, _upload_url: &Url, _client: &HTTP) -> Self {
         Self {
-            target_bucket: target_bucket,
-            target_path: target_path,
-            upload_url: upload_url,
-            client: client,
+            target_bucket: BucketGuid::new(target_bucket.user_id, target_bucket.bucket_id),
+            target_path: PathBuf::from(target_path),
             download_size: None,
-            decompression_module: None,
-            decryption_module: None,
             keep_file_structure: true,
+            options: DownloadHandlerOptions::default(),
+            make_pipeline: Box::new(|_| Pipeline::passthrough(PipelineDirection::Download)),
         }
     }
 
-
     fn set_total_download_size(&mut self, total_download_size: u64) {
         self.download_size = Some(total_download_size);
     }
 
     fn set_keep_structure(&mut self, keep_file_structure: bool) {
-        todo!()
+        self.keep_file_structure = keep_file_structure;
     }
 
-    fn set_decompressed_module<DC: DecompressModule<R, W>>(&mut self, decompressor: DC) {
-        self.decompression_module = Some(decompressor);
+    fn set_pipeline(&mut self, make_pipeline: Box<dyn Fn(&VirtualFileDetails) -> Pipeline>) {
+        self.make_pipeline = make_pipeline;
     }
 
-    fn set_decrypt_module<DM: DecryptionModule<R, W, N>>(&mut self, decrypt_module: DM) {
-        self.decryption_module = Some(decrypt_module);
+    fn set_download_options(&mut self, options: DownloadHandlerOptions) {
+        self.options = options;
     }
 
     fn build(self) -> Self::OutputType {
-        FileDownloadHandler {
+        self
+    }
 
-        }
+    fn handle(&self, file: VirtualFileDetails, keep_file_structure: bool) -> Result<Self::Handler, BucketDownloadHandlerErrors> {
+        self.handler_for(file, keep_file_structure)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handler_targets_stay_in_the_download_directory() {
+        let root = std::env::temp_dir().join(format!("bucket-builder-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let builder = DefaultFileDownloadHandlerBuilder {
+            target_bucket: BucketGuid::new(uuid::Uuid::new_v4(), uuid::Uuid::new_v4()),
+            target_path: root.clone(),
+            download_size: None,
+            keep_file_structure: true,
+            options: DownloadHandlerOptions::default(),
+            make_pipeline: Box::new(|_| Pipeline::passthrough(PipelineDirection::Download)),
+        };
+        let file = |local_target: Option<PathBuf>| VirtualFileDetails {
+            path: "/photos/img.jpg".to_string(),
+            date: None,
+            size_in_bytes: 3,
+            local_target,
+        };
+
+        assert!(builder.handler_for(file(None), true).is_ok());
+        assert!(builder.handler_for(file(Some(root.join("photos/img (1).jpg"))), true).is_ok());
+        assert!(matches!(
+            builder.handler_for(file(Some(std::env::temp_dir().join("img.jpg"))), true),
+            Err(BucketDownloadHandlerErrors::InvalidPath(BucketPathError::OutsideRoot(_)))
+        ));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use glob::{MatchOptions, Pattern};
+use std::collections::HashMap;
+use time::OffsetDateTime;
+
+/// `*` stops at `/`, `**` matches across directories, as in `.gitignore`.
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadFilterError {
+    #[error(transparent)]
+    InvalidPattern(#[from] glob::PatternError),
+}
+
+/// Selects the files of a download from the listing. Patterns are matched against the bucket path without
+/// its leading `/`, e.g. `logs/2026-10/*.json`. A file is downloaded when it matches an include pattern,
+/// or there are none, and no exclude pattern, and its size and modification time are within the limits.
+/// Modification times are not part of the download listing, the downloads fetch the filestructure listing once
+/// for a filter with a time window and pass it to `with_modified_times`, no request is made per file.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    pub min_size_in_bytes: Option<u64>,
+    pub max_size_in_bytes: Option<u64>,
+    /// Inclusive. Files without a known modification time never match a time window.
+    pub modified_after: Option<OffsetDateTime>,
+    /// Exclusive.
+    pub modified_before: Option<OffsetDateTime>,
+    /// Keyed by bucket path without its leading `/`.
+    modified_times: HashMap<String, OffsetDateTime>,
+}
+
+impl DownloadFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn include(mut self, pattern: &str) -> Result<Self, DownloadFilterError> {
+        self.include.push(Pattern::new(pattern.trim_start_matches('/'))?);
+        Ok(self)
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Result<Self, DownloadFilterError> {
+        self.exclude.push(Pattern::new(pattern.trim_start_matches('/'))?);
+        Ok(self)
+    }
+
+    pub fn with_size_range(mut self, min_size_in_bytes: Option<u64>, max_size_in_bytes: Option<u64>) -> Self {
+        self.min_size_in_bytes = min_size_in_bytes;
+        self.max_size_in_bytes = max_size_in_bytes;
+        self
+    }
+
+    pub fn modified_between(mut self, after: Option<OffsetDateTime>, before: Option<OffsetDateTime>) -> Self {
+        self.modified_after = after;
+        self.modified_before = before;
+        self
+    }
+
+    /// Modification times of the bucket's files, e.g. from `get_bucket_filestructure_fully`.
+    pub fn with_modified_times(mut self, modified_times: impl IntoIterator<Item = (String, OffsetDateTime)>) -> Self {
+        self.modified_times.extend(
+            modified_times
+                .into_iter()
+                .map(|(path, modified)| (path.trim_start_matches('/').to_string(), modified)),
+        );
+        self
+    }
+
+    pub fn modified_time(&self, path: &str) -> Option<OffsetDateTime> {
+        self.modified_times.get(path.trim_start_matches('/')).copied()
+    }
+
+    /// Whether the modification time of the files is needed, it is not part of every listing.
+    pub fn has_time_window(&self) -> bool {
+        self.modified_after.is_some() || self.modified_before.is_some()
+    }
+
+    /// Checks path and size only, files failing here don't need their modification time looked up.
+    pub fn matches_path_and_size(&self, path: &str, size_in_bytes: u64) -> bool {
+        let path = path.trim_start_matches('/');
+        let included = self.include.is_empty()
+            || self.include.iter().any(|pattern| pattern.matches_with(path, MATCH_OPTIONS));
+        let excluded = self.exclude.iter().any(|pattern| pattern.matches_with(path, MATCH_OPTIONS));
+        included
+            && !excluded
+            && self.min_size_in_bytes.map_or(true, |min| size_in_bytes >= min)
+            && self.max_size_in_bytes.map_or(true, |max| size_in_bytes <= max)
+    }
+
+    pub fn matches_modified(&self, modified: Option<OffsetDateTime>) -> bool {
+        if !self.has_time_window() {
+            return true;
+        }
+        let Some(modified) = modified else { return false };
+        self.modified_after.map_or(true, |after| modified >= after)
+            && self.modified_before.map_or(true, |before| modified < before)
+    }
+
+    /// Applies the filter to a file of the download listing, its modification time is looked up in the given times.
+    pub fn matches_listed(&self, path: &str, size_in_bytes: u64) -> bool {
+        self.matches_path_and_size(path, size_in_bytes) && self.matches_modified(self.modified_time(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let filter = DownloadFilter::new()
+            .include("/logs/2026-10/*.json")
+            .unwrap()
+            .include("reports/**/*.pdf")
+            .unwrap()
+            .exclude("**/draft-*")
+            .unwrap()
+            .with_size_range(None, Some(1024));
+        assert!(filter.matches_path_and_size("/logs/2026-10/app.json", 10));
+        assert!(!filter.matches_path_and_size("/logs/2026-10/nested/app.json", 10));
+        assert!(!filter.matches_path_and_size("/logs/2026-10/app.json", 2048));
+        assert!(filter.matches_path_and_size("/reports/q3/final.pdf", 10));
+        assert!(!filter.matches_path_and_size("/reports/q3/draft-1.pdf", 10));
+
+        let october = OffsetDateTime::from_unix_timestamp(1_790_812_800).unwrap();
+        let filter = DownloadFilter::new().modified_between(Some(october), None);
+        assert!(filter.matches_modified(Some(october)));
+        assert!(!filter.matches_modified(Some(october - time::Duration::seconds(1))));
+        assert!(!filter.matches_modified(None));
+
+        let filter = filter.with_modified_times([("/logs/app.json".to_string(), october)]);
+        assert!(filter.matches_listed("/logs/app.json", 10));
+        assert!(!filter.matches_listed("/logs/other.json", 10));
+    }
+}
@@ -1,17 +1,29 @@
 use std::io::{Read, Write};
 use async_trait::async_trait;
-use brotli::Decompressor;
 use bucket_common_types::{BucketCompression, BucketEncryption, BucketGuid};
+use bytes::Bytes;
 use generic_array::ArrayLength;
 use url::Url;
-use zero_knowledge_encryption::encryption::aead::{DecryptionModule, EncryptionModule};
+use crate::archive::ArchiveFormat;
 use crate::client::http::HttpDownloadClientExt;
-use crate::compression::{CompressionChooserHandling, CompressorModule, DecompressModule};
+use crate::compression::CompressionChooserHandling;
 use crate::encryption::EncryptionChooserHandler;
+use crate::integrity::ContentHash;
+use crate::io::file::VirtualFileDetails;
+use crate::pipeline::Pipeline;
 use crate::token::ApiToken;
+use crate::wrapper::bucket::download::ranged::{RangedDownload, RangedDownloadOptions};
 
 pub mod download_handler;
+#[cfg(not(target_family = "wasm"))]
 pub mod file_download_handler_builder;
+pub mod ranged;
+pub mod conflict;
+pub mod filter;
+#[cfg(not(target_family = "wasm"))]
+pub mod partial;
+#[cfg(not(target_family = "wasm"))]
+pub mod atomic;
 // A handler is create for each file download.
 
 pub trait FileDownloadHandlerBuilder<R,W,N,HTTP,CCH,ECH>
@@ -23,6 +35,8 @@ HTTP: HttpDownloadClientExt,
 CCH: CompressionChooserHandling<R, W>,
 ECH: EncryptionChooserHandler<R, W, N>{
     type OutputType;
+    /// Handler of a single file, created for every file of a download.
+    type Handler: FileDownloadHandler<R, W>;
     fn new(target_bucket: &BucketGuid,
            api_token: &ApiToken,
            target_path: &String,
@@ -30,9 +44,32 @@ ECH: EncryptionChooserHandler<R, W, N>{
            client: &HTTP) -> Self;
     fn set_total_download_size(&mut self, total_download_size: u64);
     fn set_keep_structure(&mut self, keep_file_structure: bool);
-    fn set_decompressed_module<DC>(&mut self, decompressor: DC) where DC: DecompressModule<R, W>;
-    fn set_decrypt_module<DM>(&mut self, decrypt_module: DM) where DM: DecryptionModule<R, W, N>;
+    /// The pipeline holds the signature verification, decryption and decompression stages used for the download.
+    /// `make_pipeline` is called for every file, a pipeline only processes one stream.
+    fn set_pipeline(&mut self, make_pipeline: Box<dyn Fn(&VirtualFileDetails) -> Pipeline>);
     fn build(self) -> Self::OutputType;
+    /// The archive the handlers build on the client, `None` when they write plain files.
+    /// A download sends no `DownloadFormat` to the server when the handlers archive themselves.
+    fn archive_format(&self) -> Option<ArchiveFormat> {
+        None
+    }
+    /// How the handlers fetch and finish their files, builders without these features ignore them.
+    fn set_download_options(&mut self, options: DownloadHandlerOptions) {
+        let _ = options;
+    }
+    /// Creates the handler that writes `file`.
+    fn handle(&self, file: VirtualFileDetails, keep_file_structure: bool) -> Result<Self::Handler, <Self::Handler as FileDownloadHandler<R, W>>::Error>;
+}
+
+/// Download features of the handlers created by a [`FileDownloadHandlerBuilder`].
+#[derive(Clone, Default)]
+pub struct DownloadHandlerOptions {
+    /// Fetches large files with parallel range requests, `None` downloads every file with a single request.
+    pub ranged: Option<RangedDownloadOptions>,
+    /// Continues the ranged download an earlier attempt left behind instead of starting over.
+    pub resume: bool,
+    /// Restores the metadata stored with each object, `Some(None)` for metadata that was stored unencrypted.
+    pub restore_metadata: Option<Option<aes_gcm::Aes256Gcm>>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -41,10 +78,33 @@ pub enum BucketUploadHandlerErrors {}
 #[async_trait(? Send)]
 pub trait FileDownloadHandler<R, W> {
     type Error: std::error::Error + Send + Sync + 'static;
-    // Called when a chunk is downloaded. It's up to the user to decrypt the chunk if the bucket is encrypted, or to save the chunk to a file.
-    async fn on_download_chunk(&mut self, chunk: &mut [u8]) -> Result<(), Self::Error>;
-    // Called when the last chunk has been downloaded.
-    fn on_download_finish(self) -> Result<(), Self::Error>;
+    // Called when a chunk is downloaded. The chunk is passed through the pipeline before it is saved to a file.
+    async fn on_download_chunk(&mut self, chunk: Bytes) -> Result<(), Self::Error>;
+    // Called when the last chunk has been downloaded, flushes the pipeline.
+    async fn on_download_finish(self) -> Result<(), Self::Error>;
+    // Called with the encoded `PreservedMetadata` when the object was stored with one, before the first chunk.
+    async fn on_file_metadata(&mut self, file_metadata: &str) -> Result<(), Self::Error> {
+        let _ = file_metadata;
+        Ok(())
+    }
+    // Called with the hash of the plaintext stored with the object, before the first chunk.
+    // Returns whether `on_download_finish` checks the written plaintext against it.
+    async fn on_content_hash(&mut self, content_hash: ContentHash) -> Result<bool, Self::Error> {
+        let _ = content_hash;
+        Ok(false)
+    }
+    // Returns the target for parallel range requests, the file is then not passed through `on_download_chunk`.
+    // Used when `RangedDownload::applies_to` the object, `on_download_finish` is called once every range is written.
+    fn ranged_download(&self) -> Option<RangedDownload<'_>> {
+        None
+    }
+    // Called instead of `on_download_finish` when the transfer is cancelled, should remove what was written so far.
+    async fn on_download_cancel(self) -> Result<(), Self::Error>
+    where
+        Self: Sized,
+    {
+        Ok(())
+    }
 }
 
 
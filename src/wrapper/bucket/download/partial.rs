@@ -0,0 +1,216 @@
+use crate::client::http::HttpDownloadClientExt;
+use crate::pipeline::Pipeline;
+use crate::token::ApiToken;
+use crate::transfer::TransferContext;
+use crate::wrapper::bucket::download::atomic::{temp_path_for, AtomicWriteError};
+use crate::wrapper::bucket::download::ranged::{
+    fetch_ranges, plan_ranges, DownloadRange, PositionalWrite, RangedDownloadError, RangedDownloadOptions,
+};
+use std::fs::File;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
+use url::Url;
+
+pub const PARTIAL_DOWNLOAD_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PartialDownloadError {
+    #[error("Unsupported partial download version {0}")]
+    UnsupportedVersion(u32),
+    #[error(transparent)]
+    RangedDownloadError(#[from] RangedDownloadError),
+    #[error(transparent)]
+    SerializationError(#[from] serde_json::Error),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[error(transparent)]
+    AtomicWriteError(#[from] AtomicWriteError),
+}
+
+/// Which byte ranges of the remote object are already in the `.part` file.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PartialDownloadState {
+    pub version: u32,
+    /// ETag of the object the ranges were downloaded from, a different ETag means the ranges are stale.
+    pub etag: Option<String>,
+    pub object_size: u64,
+    /// Sorted, non-overlapping ranges of the stored object.
+    pub completed: Vec<Range<u64>>,
+}
+
+impl PartialDownloadState {
+    pub fn new(object_size: u64) -> Self {
+        Self {
+            version: PARTIAL_DOWNLOAD_VERSION,
+            etag: None,
+            object_size,
+            completed: Vec::new(),
+        }
+    }
+
+    pub fn mark_completed(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+        self.completed.push(range);
+        self.completed.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(self.completed.len());
+        for range in self.completed.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        self.completed = merged;
+    }
+
+    pub fn is_completed(&self, range: &Range<u64>) -> bool {
+        range.is_empty() || self.completed.iter().any(|done| done.start <= range.start && range.end <= done.end)
+    }
+
+    pub fn completed_bytes(&self) -> u64 {
+        self.completed.iter().map(|range| range.end - range.start).sum()
+    }
+
+    /// Ranges of the plan that still have to be downloaded.
+    pub fn pending_ranges(&self, options: &RangedDownloadOptions) -> Vec<DownloadRange> {
+        plan_ranges(self.object_size, options)
+            .into_iter()
+            .filter(|range| !self.is_completed(&range.source))
+            .collect()
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>, PartialDownloadError> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let state: Self = serde_json::from_slice(&data)?;
+        if state.version != PARTIAL_DOWNLOAD_VERSION {
+            return Err(PartialDownloadError::UnsupportedVersion(state.version));
+        }
+        Ok(Some(state))
+    }
+
+    /// Written to a temporary file that replaces the sidecar, a crash mid-write leaves the previous state intact.
+    pub fn save(&self, path: &Path) -> Result<(), PartialDownloadError> {
+        let temp_path = temp_path_for(path)?;
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&serde_json::to_vec(self)?)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Downloads `url` into `target`, usually the `.part` file of `AtomicFileWriter::resume`, continuing from the
+/// ranges recorded at `state_path` by an earlier attempt. The download restarts from scratch once if the
+/// remote ETag changed. The state is removed once every range is written, committing `target` is left to
+/// its owner. Returns the number of bytes written by this call.
+pub async fn download_resumable<HTTP, T, F>(
+    http_client: &HTTP,
+    url: &Url,
+    api_token: &ApiToken,
+    file_path: &str,
+    object_size: u64,
+    target: &T,
+    state_path: &Path,
+    options: &RangedDownloadOptions,
+    make_pipeline: F,
+    transfer_context: &TransferContext,
+) -> Result<u64, PartialDownloadError>
+where
+    HTTP: HttpDownloadClientExt,
+    T: PositionalWrite + ?Sized,
+    F: Fn(&DownloadRange) -> Pipeline,
+{
+    options.check_pipeline(&make_pipeline(&plan_ranges(object_size, options)[0]))?;
+    let mut restarted = false;
+    loop {
+        let mut state = match PartialDownloadState::load(state_path)? {
+            Some(state) if state.object_size == object_size => state,
+            _ => {
+                target.set_len(0)?;
+                PartialDownloadState::new(object_size)
+            }
+        };
+        let result = fetch_ranges(
+            http_client,
+            url,
+            api_token,
+            file_path,
+            state.pending_ranges(options),
+            target,
+            &make_pipeline,
+            transfer_context,
+            |range, etag| -> Result<(), PartialDownloadError> {
+                match (state.etag.as_deref(), etag) {
+                    (Some(expected), Some(actual)) if expected != actual => {
+                        return Err(RangedDownloadError::EtagMismatch {
+                            expected: expected.to_string(),
+                            actual: actual.to_string(),
+                        }
+                        .into());
+                    }
+                    (None, Some(actual)) => state.etag = Some(actual.to_string()),
+                    _ => {}
+                }
+                state.mark_completed(range.source.clone());
+                target.sync_data()?;
+                state.save(state_path)
+            },
+        )
+        .await;
+        match result {
+            Ok(written) => {
+                target.sync_data()?;
+                remove_if_exists(state_path)?;
+                return Ok(written);
+            }
+            Err(PartialDownloadError::RangedDownloadError(RangedDownloadError::EtagMismatch { .. })) if !restarted => {
+                restarted = true;
+                remove_if_exists(state_path)?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_tracks_pending_ranges() {
+        let options = RangedDownloadOptions {
+            range_size: 10,
+            min_file_size: 0,
+            layout: None,
+        };
+        let mut state = PartialDownloadState::new(35);
+        state.mark_completed(10..20);
+        state.mark_completed(0..10);
+        state.mark_completed(30..35);
+        assert_eq!(state.completed, vec![0..20, 30..35]);
+        let pending: Vec<_> = state.pending_ranges(&options).into_iter().map(|range| range.source).collect();
+        assert_eq!(pending, vec![20..30]);
+
+        let dir = std::env::temp_dir().join(format!("bucket-partial-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = crate::wrapper::bucket::download::atomic::resume_state_path_for(&dir.join("big.img")).unwrap();
+        assert!(path.ends_with(".big.img.part.state"));
+        state.etag = Some("\"abc\"".to_string());
+        state.save(&path).unwrap();
+        assert_eq!(PartialDownloadState::load(&path).unwrap(), Some(state));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,373 @@
+use crate::client::http::{ByteRange, HttpDownloadClientExt};
+use crate::pipeline::buffer_pool::MemoryBudgetError;
+use crate::pipeline::framing::RECORD_HEADER_SIZE;
+use crate::pipeline::stages::encryption::TAG_SIZE;
+use crate::pipeline::{Pipeline, PipelineError, StageKind};
+use crate::token::ApiToken;
+use crate::transfer::handle::TransferCancelled;
+use crate::transfer::TransferContext;
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use std::ops::Range;
+use url::Url;
+
+/// Files smaller than this are downloaded with a single request.
+pub const DEFAULT_MIN_RANGED_FILE_SIZE: u64 = 64 * 1024 * 1024;
+pub const DEFAULT_RANGE_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RangedDownloadError {
+    #[error("Http request failed: {0}")]
+    HttpError(String),
+    #[error("Range request returned {actual} bytes, expected {expected} bytes")]
+    ShortRead { expected: u64, actual: u64 },
+    #[error(transparent)]
+    PipelineError(#[from] PipelineError),
+    #[error(transparent)]
+    MemoryBudgetError(#[from] MemoryBudgetError),
+    #[error(transparent)]
+    Cancelled(#[from] TransferCancelled),
+    #[error("The object changed during the download, ETag {actual:?} does not match {expected:?}")]
+    EtagMismatch { expected: String, actual: String },
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[error("Ranges can't be processed by a {0:?} stage")]
+    UnsupportedStage(StageKind),
+}
+
+/// Writes at an offset, so ranges that finish out of order can be written as they arrive.
+pub trait PositionalWrite {
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> std::io::Result<()>;
+    /// Makes what was written durable, called before a resumable download records a range as written.
+    fn sync_data(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+    /// Called with zero when a resumable download starts over.
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        let _ = len;
+        Ok(())
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl PositionalWrite for std::fs::File {
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+        crate::io::positional::write_all_at(self, buf, offset)
+    }
+
+    fn sync_data(&self) -> std::io::Result<()> {
+        std::fs::File::sync_data(self)
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        std::fs::File::set_len(self, len)
+    }
+}
+
+/// Layout of an object written by the chunked AES-GCM stage with a fixed plaintext chunk size and no compression.
+/// Every record then has the same size, so a record boundary can be computed from a byte offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordLayout {
+    pub plaintext_record_size: u64,
+}
+
+impl RecordLayout {
+    pub fn record_size(&self) -> u64 {
+        RECORD_HEADER_SIZE as u64 + self.plaintext_record_size + TAG_SIZE as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangedDownloadOptions {
+    pub range_size: u64,
+    pub min_file_size: u64,
+    /// Set for encrypted objects, ranges are then aligned to whole records.
+    pub layout: Option<RecordLayout>,
+}
+
+impl Default for RangedDownloadOptions {
+    fn default() -> Self {
+        Self {
+            range_size: DEFAULT_RANGE_SIZE,
+            min_file_size: DEFAULT_MIN_RANGED_FILE_SIZE,
+            layout: None,
+        }
+    }
+}
+
+impl RangedDownloadOptions {
+    pub fn should_use_ranges(&self, object_size: u64) -> bool {
+        object_size >= self.min_file_size && object_size > self.range_size
+    }
+
+    /// Every range runs through a pipeline of its own, which rules out compression and signatures.
+    /// Decryption only works when the records are aligned, that is when `layout` is set.
+    pub fn check_pipeline(&self, pipeline: &Pipeline) -> Result<(), RangedDownloadError> {
+        match pipeline.stage_kinds().into_iter().find(|kind| *kind != StageKind::Encryption || self.layout.is_none()) {
+            Some(kind) => Err(RangedDownloadError::UnsupportedStage(kind)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returned by a download handler that can write a file with parallel range requests.
+pub struct RangedDownload<'a> {
+    pub target: &'a dyn PositionalWrite,
+    /// Sidecar recording the written ranges, set by targets that continue an interrupted download.
+    pub resume_state: Option<&'a std::path::Path>,
+    pub options: RangedDownloadOptions,
+    pub make_pipeline: Box<dyn Fn(&DownloadRange) -> Pipeline + 'a>,
+}
+
+impl RangedDownload<'_> {
+    /// Whether an object of `object_size` bytes is large enough for ranges and its pipeline can process them.
+    /// Otherwise the file is downloaded with a single request.
+    pub fn applies_to(&self, object_size: u64) -> bool {
+        self.options.should_use_ranges(object_size)
+            && plan_ranges(object_size, &self.options)
+                .first()
+                .is_some_and(|range| self.options.check_pipeline(&(self.make_pipeline)(range)).is_ok())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadRange {
+    /// Bytes of the stored object.
+    pub source: Range<u64>,
+    /// Index of the first record in the range, zero for objects without records.
+    pub first_record: u64,
+    /// Where the output of the range is written in the target file.
+    pub target_offset: u64,
+    pub is_last: bool,
+}
+
+pub fn plan_ranges(object_size: u64, options: &RangedDownloadOptions) -> Vec<DownloadRange> {
+    let (step, record_size) = match options.layout {
+        Some(layout) => {
+            let record_size = layout.record_size();
+            ((options.range_size / record_size).max(1) * record_size, Some(record_size))
+        }
+        None => (options.range_size.max(1), None),
+    };
+    (0..object_size.max(1))
+        .step_by(step as usize)
+        .map(|start| {
+            let end = u64::min(start + step, object_size);
+            let (first_record, target_offset) = match (record_size, options.layout) {
+                (Some(record_size), Some(layout)) => (start / record_size, start / record_size * layout.plaintext_record_size),
+                _ => (0, start),
+            };
+            DownloadRange {
+                source: start..end,
+                first_record,
+                target_offset,
+                is_last: end == object_size,
+            }
+        })
+        .collect()
+}
+
+/// Runs a downloaded range through its pipeline, only the last range flushes it.
+pub async fn process_range(range: &DownloadRange, pipeline: &mut Pipeline, body: Bytes) -> Result<Bytes, PipelineError> {
+    let output = pipeline.process(body).await?;
+    if !range.is_last {
+        return Ok(output);
+    }
+    let tail = pipeline.finish().await?;
+    if tail.is_empty() {
+        return Ok(output);
+    }
+    let mut combined = BytesMut::with_capacity(output.len() + tail.len());
+    combined.extend_from_slice(&output);
+    combined.extend_from_slice(&tail);
+    Ok(combined.freeze())
+}
+
+/// Downloads `url` with parallel range requests, writing every range at its offset in `target`.
+/// `make_pipeline` returns the pipeline for a range, for encrypted objects its decrypt stage starts at
+/// `DownloadRange::first_record`. Returns the number of bytes written.
+pub async fn download_ranged<HTTP, T, F>(
+    http_client: &HTTP,
+    url: &Url,
+    api_token: &ApiToken,
+    file_path: &str,
+    object_size: u64,
+    target: &T,
+    options: &RangedDownloadOptions,
+    make_pipeline: F,
+    transfer_context: &TransferContext,
+) -> Result<u64, RangedDownloadError>
+where
+    HTTP: HttpDownloadClientExt,
+    T: PositionalWrite + ?Sized,
+    F: Fn(&DownloadRange) -> Pipeline,
+{
+    let ranges = plan_ranges(object_size, options);
+    options.check_pipeline(&make_pipeline(&ranges[0]))?;
+    fetch_ranges(http_client, url, api_token, file_path, ranges, target, make_pipeline, transfer_context, |_, _| {
+        Ok::<_, RangedDownloadError>(())
+    })
+    .await
+}
+
+/// Fetches `ranges` in parallel, `on_complete` is called with the ETag of the response once a range is written.
+pub(crate) async fn fetch_ranges<HTTP, T, F, C, E>(
+    http_client: &HTTP,
+    url: &Url,
+    api_token: &ApiToken,
+    file_path: &str,
+    ranges: Vec<DownloadRange>,
+    target: &T,
+    make_pipeline: F,
+    transfer_context: &TransferContext,
+    mut on_complete: C,
+) -> Result<u64, E>
+where
+    HTTP: HttpDownloadClientExt,
+    T: PositionalWrite + ?Sized,
+    F: Fn(&DownloadRange) -> Pipeline,
+    C: FnMut(&DownloadRange, Option<&str>) -> Result<(), E>,
+    E: From<RangedDownloadError>,
+{
+    let budget = transfer_context.memory_budget();
+    let events = &transfer_context.events;
+    let make_pipeline = &make_pipeline;
+    let mut ranges = futures::stream::iter(ranges)
+        .map(|range| async move {
+            transfer_context.handle.checkpoint().await?;
+            let expected = range.source.end - range.source.start;
+            let _reservation = budget.reserve(expected.min(budget.limit()))?.await;
+            let (body, etag) = if expected == 0 {
+                (Bytes::new(), None)
+            } else {
+                let byte_range = ByteRange::Bounded {
+                    start: range.source.start,
+                    end: range.source.end,
+                };
+                let response = http_client
+                    .get_range(url.clone(), api_token, byte_range)
+                    .await
+                    .map_err(|e| RangedDownloadError::HttpError(format!("{:?}", e)))?;
+                (response.bytes, response.etag)
+            };
+            if body.len() as u64 != expected {
+                return Err(RangedDownloadError::ShortRead {
+                    expected,
+                    actual: body.len() as u64,
+                });
+            }
+            let output = process_range(&range, &mut make_pipeline(&range), body).await?;
+            target.write_all_at(&output, range.target_offset)?;
+            events.bytes_transferred(file_path, expected).await;
+            Ok((range, etag, output.len() as u64))
+        })
+        .buffer_unordered(transfer_context.max_in_flight_requests);
+
+    let mut written = 0;
+    while let Some(result) = ranges.next().await {
+        let (range, etag, length) = result?;
+        on_complete(&range, etag.as_deref())?;
+        written += length;
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::stages::encryption::{Aes256GcmDecryptStage, Aes256GcmEncryptStage};
+    use crate::pipeline::PipelineDirection;
+    use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+    use std::cell::RefCell;
+
+    impl PositionalWrite for RefCell<Vec<u8>> {
+        fn write_all_at(&self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+            let mut target = self.borrow_mut();
+            let end = offset as usize + buf.len();
+            if target.len() < end {
+                target.resize(end, 0);
+            }
+            target[offset as usize..end].copy_from_slice(buf);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_plan_without_records() {
+        let options = RangedDownloadOptions {
+            range_size: 10,
+            min_file_size: 0,
+            layout: None,
+        };
+        let ranges = plan_ranges(25, &options);
+        assert_eq!(ranges.iter().map(|range| range.source.clone()).collect::<Vec<_>>(), vec![0..10, 10..20, 20..25]);
+        assert!(ranges[2].is_last && !ranges[1].is_last);
+        assert_eq!(plan_ranges(0, &options).len(), 1);
+    }
+
+    #[test]
+    fn test_check_pipeline() {
+        let cipher = Aes256Gcm::new_from_slice(&[3u8; 32]).unwrap();
+        let decrypt = || {
+            Pipeline::builder(PipelineDirection::Download)
+                .stage(Aes256GcmDecryptStage::from_cipher(cipher.clone(), *Nonce::from_slice(&[9u8; 12])))
+                .build()
+                .unwrap()
+        };
+        let mut options = RangedDownloadOptions::default();
+        assert!(options.check_pipeline(&Pipeline::passthrough(PipelineDirection::Download)).is_ok());
+        assert!(matches!(
+            options.check_pipeline(&decrypt()),
+            Err(RangedDownloadError::UnsupportedStage(StageKind::Encryption))
+        ));
+        options.layout = Some(RecordLayout {
+            plaintext_record_size: 100,
+        });
+        assert!(options.check_pipeline(&decrypt()).is_ok());
+        let compressed = Pipeline::builder(PipelineDirection::Download)
+            .stage(crate::pipeline::stages::compression::Lz4DecompressStage::new())
+            .build()
+            .unwrap();
+        assert!(matches!(
+            options.check_pipeline(&compressed),
+            Err(RangedDownloadError::UnsupportedStage(StageKind::Compression))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_ranges_out_of_order() {
+        let cipher = Aes256Gcm::new_from_slice(&[3u8; 32]).unwrap();
+        let nonce = *Nonce::from_slice(&[9u8; 12]);
+        let chunk_size = 100;
+        let plaintext: Vec<u8> = (0..1050u32).map(|i| (i % 251) as u8).collect();
+
+        let mut encrypt = Pipeline::builder(PipelineDirection::Upload)
+            .stage(Aes256GcmEncryptStage::from_cipher(cipher.clone(), nonce))
+            .build()
+            .unwrap();
+        let mut object = Vec::new();
+        for chunk in plaintext.chunks(chunk_size) {
+            object.extend_from_slice(&encrypt.process(Bytes::copy_from_slice(chunk)).await.unwrap());
+        }
+        object.extend_from_slice(&encrypt.finish().await.unwrap());
+
+        let options = RangedDownloadOptions {
+            range_size: 300,
+            min_file_size: 0,
+            layout: Some(RecordLayout {
+                plaintext_record_size: chunk_size as u64,
+            }),
+        };
+        let target = RefCell::new(Vec::new());
+        for range in plan_ranges(object.len() as u64, &options).into_iter().rev() {
+            let mut pipeline = Pipeline::builder(PipelineDirection::Download)
+                .stage(Aes256GcmDecryptStage::from_cipher(cipher.clone(), nonce).with_first_record_index(range.first_record))
+                .build()
+                .unwrap();
+            let body = Bytes::copy_from_slice(&object[range.source.start as usize..range.source.end as usize]);
+            let output = process_range(&range, &mut pipeline, body).await.unwrap();
+            target.write_all_at(&output, range.target_offset).unwrap();
+        }
+        assert_eq!(target.into_inner(), plaintext);
+    }
+}
@@ -21,8 +21,20 @@ pub enum DownloadError {
     GetBucketDetailsRequestFailed(#[source] tonic::Status),
     #[error("GetBucketDetailsFromUrlRequestFailed")]
     GetBucketDetailsFromUrlRequestFailed(#[source] tonic::Status),
+    #[error("DownloadBucketRequestFailed")]
+    DownloadBucketRequestFailed(#[source] tonic::Status),
+    #[error("GetBucketFilestructureRequestFailed")]
+    GetBucketFilestructureRequestFailed(#[source] tonic::Status),
     #[error(transparent)]
     FromStrError(#[from] FromStrError),
+    #[error(transparent)]
+    InvalidPath(#[from] crate::io::bucket_path::BucketPathError),
+    #[error(transparent)]
+    ConflictError(#[from] crate::wrapper::bucket::download::conflict::ConflictError),
+    #[error(transparent)]
+    DownloadFromUrlError(#[from] crate::wrapper::bucket::bucket::DownloadFromUrlError),
+    #[error("Download handler failed")]
+    DownloadHandlerError(#[source] Box<dyn std::error::Error + Send + Sync>),
 }
 #[derive(Debug, thiserror::Error)]
 pub enum UploadToUrlError {
@@ -34,6 +46,10 @@ pub enum UploadToUrlError {
     Utf8Error(#[from] std::str::Utf8Error),
     #[error(transparent)]
     BucketDownloadHandlerFileError(#[from] BucketDownloadHandlerFileErrors),
+    #[error("Http request failed: {0}")]
+    HttpError(String),
+    #[error("Upload handler failed")]
+    UploadHandlerError(#[source] Box<dyn std::error::Error>),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -46,6 +62,33 @@ pub enum UploadError {
     PoisonError(#[from] Box<dyn std::error::Error>),
     #[error("StorageNotAvailable")]
     StorageNotAvailable,
+    #[error("Upload request failed")]
+    RequestError(#[from] tonic::Status),
+    #[error(transparent)]
+    PartPlanError(#[from] crate::wrapper::bucket::upload::part_planner::PartPlanError),
+    #[error(transparent)]
+    Cancelled(#[from] crate::transfer::handle::TransferCancelled),
+    /// The API has no request to abort a multipart upload, the server side of a cancelled upload stays open.
+    #[error("Transfer was cancelled but the multipart uploads {0:?} could not be aborted, the API has no abort request")]
+    MultipartAbortUnsupported(Vec<String>),
+    #[error("The upload handler of the file is held by another request")]
+    HandlerInUse,
+    #[error(transparent)]
+    StreamUploadError(#[from] crate::wrapper::bucket::upload::stream::StreamUploadError),
+    #[error(transparent)]
+    ChunkingError(#[from] crate::chunking::ChunkingError),
+    #[error(transparent)]
+    DeltaError(#[from] crate::delta::DeltaError),
+    #[error(transparent)]
+    BundleError(#[from] crate::bundle::BundleError),
+    #[error(transparent)]
+    PipelineError(#[from] crate::pipeline::PipelineError),
+    #[cfg(not(target_family = "wasm"))]
+    #[error(transparent)]
+    DirectoryUploadError(#[from] crate::wrapper::bucket::upload::directory::DirectoryUploadError),
+    #[cfg(not(target_family = "wasm"))]
+    #[error(transparent)]
+    UploadJournalError(#[from] crate::wrapper::bucket::upload::journal::UploadJournalError),
 }
 
 impl<T: 'static> From<PoisonError<T>> for UploadError {
@@ -9,14 +9,27 @@ use mime::Mime;
 use tonic::Request;
 use url::Url;
 use zero_knowledge_encryption::encryption::aead::EncryptionModule;
+use crate::bundle::format::BundleIndex;
+use crate::chunking::store::{ChunkStore, DeduplicationStats};
+use crate::chunking::{ChunkHasher, ChunkingConfig};
+use crate::delta::patch::PatchManifest;
+use crate::delta::signature::Signature;
 use crate::client::http::{HttpDownloadClientExt, HttpUploadClientExt};
 use crate::compression::{CompressionChooserHandling, CompressorModule};
-use crate::dto::bucket::DownloadFilesParams;
+use crate::io::bucket_path::LocalPathResolver;
+use crate::wrapper::bucket::download::conflict::{ConflictPolicy, DownloadReport};
+use crate::wrapper::bucket::download::filter::DownloadFilter;
 use crate::encryption::EncryptionChooserHandler;
 use crate::io::FileWrapper;
-use crate::wrapper::bucket::bucket::{DownloadFilesFromBucketError};
+use crate::wrapper::bucket::bucket::{DownloadFilesFromBucketError, UploadFileDescriptionState};
+use crate::transfer::TransferContext;
 use crate::wrapper::bucket::errors::{DeleteFileInBucketError, DownloadError, GetFilesystemDetailsError, MoveFilesInBucketError, UploadError, UploadToUrlError};
 use crate::wrapper::bucket::upload::FileUploadHandler;
+#[cfg(not(target_family = "wasm"))]
+use crate::wrapper::bucket::upload::journal::JournalFile;
+#[cfg(not(target_family = "wasm"))]
+use crate::wrapper::bucket::upload::directory::{DirectoryEntry, DirectoryUploadOptions, DirectoryUploadReport};
+use crate::pipeline::Pipeline;
 use crate::token::ApiToken;
 use crate::wrapper::bucket::download::FileDownloadHandlerBuilder;
 
@@ -27,12 +40,102 @@ pub mod upload;
 
 pub trait ClientUploadExt {
     /// Note: THe api token need to be set for the request in order for it to work.
+    /// `open_file` creates the handler reading a file, it is called once per file in the response. An error fails
+    /// the upload before any request is sent.
     async fn upload_files_to_bucket_raw<R: Read, W: Write, UH: FileUploadHandler<R, W>, HTTP: HttpUploadClientExt>(
         &mut self,
         req: tonic::Request<UploadFilesToBucketRequest>,
-        upload_handler: UH,
+        open_file: impl Fn(&UploadFileDescriptionState) -> Result<UH, UH::Error>,
         api_token: &ApiToken,
         http_client: HTTP,
+        transfer_context: &TransferContext,
+    ) -> Result<(), UploadError>;
+    /// Uploads a stream of unknown length, e.g. stdin, to `target_file_path`. The stream is spooled first so the
+    /// size is known when the URLs are requested, `req` gives the bucket and its `source_files` are replaced.
+    async fn upload_stream_to_bucket_raw<S: futures::AsyncRead + Unpin, HTTP: HttpUploadClientExt>(
+        &mut self,
+        req: UploadFilesToBucketRequest,
+        target_file_path: String,
+        content_type: Mime,
+        stream: S,
+        pipeline: Pipeline,
+        api_token: &ApiToken,
+        http_client: HTTP,
+        transfer_context: &TransferContext,
+    ) -> Result<u64, UploadError>;
+    /// Chunks `source` as it is read and puts the chunks `chunk_store` is missing, then uploads the file's
+    /// `ChunkManifest` to `target_file_path` in place of its content, through `pipeline`.
+    async fn upload_deduplicated_to_bucket_raw<F: FileWrapper, CS: ChunkStore, HTTP: HttpUploadClientExt>(
+        &mut self,
+        req: UploadFilesToBucketRequest,
+        target_file_path: String,
+        source: &F,
+        chunk_store: &CS,
+        chunking: ChunkingConfig,
+        hasher: &ChunkHasher,
+        pipeline: Pipeline,
+        api_token: &ApiToken,
+        http_client: HTTP,
+        transfer_context: &TransferContext,
+    ) -> Result<DeduplicationStats, UploadError>;
+    /// Uploads only what changed in `source` since the version described by `signature`. The source is hashed
+    /// as it is read, its literal bytes are uploaded to `target_file_path` and the `PatchManifest` next to them,
+    /// under `PATCH_OBJECT_SUFFIX`. `make_pipeline` is called for each of the two objects.
+    async fn upload_delta_to_bucket_raw<F: FileWrapper, HTTP: HttpUploadClientExt + Clone>(
+        &mut self,
+        req: UploadFilesToBucketRequest,
+        target_file_path: String,
+        source: &F,
+        signature: &Signature,
+        key: highway::Key,
+        make_pipeline: impl Fn() -> Pipeline,
+        api_token: &ApiToken,
+        http_client: HTTP,
+        transfer_context: &TransferContext,
+    ) -> Result<PatchManifest, UploadError>;
+    /// Packs `files`, pairs of target path and source, into one bundle object at `bundle_path`, see
+    /// `BundleWriter`. Files and index are encrypted with `key`, which must be set for encrypted buckets.
+    async fn upload_bundle_to_bucket_raw<F: FileWrapper, HTTP: HttpUploadClientExt>(
+        &mut self,
+        req: UploadFilesToBucketRequest,
+        bundle_path: String,
+        files: Vec<(String, F)>,
+        key: Option<&aes_gcm::Aes256Gcm>,
+        api_token: &ApiToken,
+        http_client: HTTP,
+        transfer_context: &TransferContext,
+    ) -> Result<BundleIndex, UploadError>;
+    /// Walks `root` and uploads it under `options.target_directory`, see `walk_directory` for the rules.
+    /// `make_pipeline` returns the pipeline of each file. Failing files are reported instead of aborting the others.
+    /// File metadata is encrypted with `metadata_key`, which must be set for encrypted buckets.
+    #[cfg(not(target_family = "wasm"))]
+    async fn upload_directory_raw<HTTP: HttpUploadClientExt + Clone>(
+        &mut self,
+        req: UploadFilesToBucketRequest,
+        root: &std::path::Path,
+        options: &DirectoryUploadOptions,
+        make_pipeline: impl Fn(&DirectoryEntry) -> Pipeline,
+        metadata_key: Option<&aes_gcm::Aes256Gcm>,
+        api_token: &ApiToken,
+        http_client: HTTP,
+        transfer_context: &TransferContext,
+    ) -> Result<DirectoryUploadReport, UploadError>;
+    /// Like `upload_files_to_bucket_raw`, but every finished part is recorded in the journal at `journal_path`.
+    /// If a journal from an interrupted upload exists, only its missing parts are sent and `req` is not issued.
+    /// The bucket password is not journaled, `req` has to carry it again when resuming.
+    /// Progress is reported on the context's events, a refresh of expired URLs as a retry.
+    /// `open_part` returns a handler reading the given part of a file, starting at the part's offset.
+    /// Such a handler can't hash the whole file, only single-part files get their content hash recorded.
+    #[cfg(not(target_family = "wasm"))]
+    async fn upload_files_to_bucket_resumable_raw<R: Read, W: Write, UH: FileUploadHandler<R, W>, HTTP: HttpUploadClientExt>(
+        &mut self,
+        req: tonic::Request<UploadFilesToBucketRequest>,
+        journal_path: &std::path::Path,
+        source_paths: &std::collections::HashMap<String, std::path::PathBuf>,
+        open_part: impl Fn(&JournalFile, usize) -> UH,
+        api_token: &ApiToken,
+        http_client: &HTTP,
+        transfer_context: &TransferContext,
     ) -> Result<(), UploadError>;
     async fn download_from_url_raw<R: Read, W: Write, N: ArrayLength, HTTP: HttpDownloadClientExt, CCH: CompressionChooserHandling<R, W>, ECH:EncryptionChooserHandler<R, W, N>,FDHB: FileDownloadHandlerBuilder<R, W, N, HTTP, CCH, ECH>>(
         &mut self,
@@ -41,13 +144,25 @@ pub trait ClientUploadExt {
         hashed_password: Option<String>,
         format: Option<DownloadFormat>,
         create_download_handler: FDHB,
-    )    -> Result<(), DownloadError>;
+        http_client: HTTP,
+        local: Option<&LocalPathResolver>,
+        conflict_policy: ConflictPolicy,
+        filter: Option<&DownloadFilter>,
+        transfer_context: &TransferContext,
+    ) -> Result<DownloadReport, DownloadError>;
 
     async fn download_files_from_bucket_raw<R: Read,W: Write, N: ArrayLength,HTTP: HttpDownloadClientExt,CCH: CompressionChooserHandling<R, W>, ECH: EncryptionChooserHandler<R, W, N>,FDHB: FileDownloadHandlerBuilder<R, W, N,HTTP, CCH, ECH>>(
         &mut self,
-        param: DownloadFilesParams,
-        file_download_handler_builder: FDHB
-    )  -> Result<(), DownloadFilesFromBucketError>;
+        req: tonic::Request<DownloadFilesRequest>,
+        file_download_handler_builder: FDHB,
+        api_token: &ApiToken,
+        http_client: &HTTP,
+        keep_file_structure: bool,
+        local: Option<&LocalPathResolver>,
+        conflict_policy: ConflictPolicy,
+        filter: Option<&DownloadFilter>,
+        transfer_context: &TransferContext,
+    )  -> Result<DownloadReport, DownloadFilesFromBucketError>;
 
     async fn download_bucket_raw<R: Read, W: Write, N: ArrayLength, HTTP: HttpDownloadClientExt, CCH: CompressionChooserHandling<R, W>, ECH: EncryptionChooserHandler<R, W, N>, FDHB: FileDownloadHandlerBuilder<R, W, N, HTTP, CCH, ECH>>(
         &mut self,
@@ -55,7 +170,11 @@ pub trait ClientUploadExt {
         keep_file_structure: bool,
         download_handler_builder: FDHB,
         api_token: &ApiToken,   http_client: HTTP,
-    ) -> Result<Vec<String>, DownloadError>;
+        local: Option<&LocalPathResolver>,
+        conflict_policy: ConflictPolicy,
+        filter: Option<&DownloadFilter>,
+        transfer_context: &TransferContext,
+    ) -> Result<DownloadReport, DownloadError>;
     /*
      * Upload to pre-signed url using PUT.
      */
@@ -73,7 +192,7 @@ pub trait ClientUploadExt {
         upload_handler: &mut BF,
         content_type: Mime,
         content_encoding: Option<Encoding>,
-        http_client: HTTP,
+        http_client: &HTTP,
     ) -> Result<u16, UploadToUrlError>;
 
     async fn get_bucket_details_raw(
@@ -0,0 +1,259 @@
+use crate::io::mime_resolver::MimeResolver;
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use std::path::{Path, PathBuf};
+
+/// Ignore file read in every directory of the upload, it uses gitignore syntax.
+pub const BUCKET_IGNORE_FILE: &str = ".bucketignore";
+/// Content type of the objects created for `SymlinkPolicy::StoreAsLink`, the object holds the link target.
+pub const SYMLINK_CONTENT_TYPE: &str = "inode/symlink";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DirectoryUploadError {
+    #[error("{0} is not a directory")]
+    NotADirectory(PathBuf),
+    #[error(transparent)]
+    InvalidGlob(#[from] ignore::Error),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Uploads what the link points to.
+    Follow,
+    #[default]
+    Skip,
+    /// Uploads a small object holding the link target, with `SYMLINK_CONTENT_TYPE`.
+    StoreAsLink,
+}
+
+/// Which file attributes are recorded with each object, see `PreservedMetadata`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetadataPreservation {
+    None,
+    /// Modification time and mode bits.
+    #[default]
+    Basic,
+    /// Also the extended attributes, they count against the small metadata limit of the object.
+    WithXattrs,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectoryUploadOptions {
+    /// Bucket directory the root of the local tree is mapped to.
+    pub target_directory: String,
+    /// Only files matching one of these globs are uploaded, everything is when empty.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub symlinks: SymlinkPolicy,
+    pub include_hidden: bool,
+    pub metadata: MetadataPreservation,
+    pub mime_resolver: MimeResolver,
+}
+
+impl Default for DirectoryUploadOptions {
+    fn default() -> Self {
+        Self {
+            target_directory: "/".to_string(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            symlinks: SymlinkPolicy::default(),
+            include_hidden: false,
+            metadata: MetadataPreservation::default(),
+            mime_resolver: MimeResolver::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Symlink { target: PathBuf },
+}
+
+/// A local file and where it ends up in the bucket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub source: PathBuf,
+    pub target_path: String,
+    pub size_in_bytes: u64,
+    pub kind: EntryKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    Symlink,
+    /// Sockets, fifos and devices are never uploaded.
+    SpecialFile,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileOutcome {
+    Uploaded,
+    Skipped(SkipReason),
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirectoryUploadReport {
+    pub files: Vec<(PathBuf, FileOutcome)>,
+}
+
+impl DirectoryUploadReport {
+    pub fn record(&mut self, source: PathBuf, outcome: FileOutcome) {
+        self.files.push((source, outcome));
+    }
+
+    pub fn uploaded(&self) -> impl Iterator<Item = &Path> {
+        self.with_outcome(|outcome| matches!(outcome, FileOutcome::Uploaded))
+    }
+
+    pub fn skipped(&self) -> impl Iterator<Item = &Path> {
+        self.with_outcome(|outcome| matches!(outcome, FileOutcome::Skipped(_)))
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &Path> {
+        self.with_outcome(|outcome| matches!(outcome, FileOutcome::Failed(_)))
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed().next().is_none()
+    }
+
+    fn with_outcome(&self, filter: impl Fn(&FileOutcome) -> bool) -> impl Iterator<Item = &Path> {
+        self.files
+            .iter()
+            .filter(move |(_, outcome)| filter(outcome))
+            .map(|(source, _)| source.as_path())
+    }
+}
+
+/// Maps `relative` under `target_directory` with `/` separators.
+pub fn target_path(target_directory: &str, relative: &Path) -> String {
+    let mut target = target_directory.trim_end_matches('/').to_string();
+    for component in relative.components() {
+        target.push('/');
+        target.push_str(&component.as_os_str().to_string_lossy());
+    }
+    target
+}
+
+/// Walks `root`, honoring `.bucketignore` files and the globs of `options`. Returns the files to upload and
+/// a report holding the entries that were skipped because of the symlink policy or their file type.
+pub fn walk_directory(
+    root: &Path,
+    options: &DirectoryUploadOptions,
+) -> Result<(Vec<DirectoryEntry>, DirectoryUploadReport), DirectoryUploadError> {
+    if !root.is_dir() {
+        return Err(DirectoryUploadError::NotADirectory(root.to_path_buf()));
+    }
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in &options.include {
+        overrides.add(glob)?;
+    }
+    for glob in &options.exclude {
+        overrides.add(&format!("!{}", glob))?;
+    }
+    let walker = WalkBuilder::new(root)
+        .overrides(overrides.build()?)
+        .hidden(!options.include_hidden)
+        .follow_links(options.symlinks == SymlinkPolicy::Follow)
+        .git_ignore(false)
+        .git_global(false)
+        .git_exclude(false)
+        .parents(false)
+        .add_custom_ignore_filename(BUCKET_IGNORE_FILE)
+        .build();
+
+    let mut entries = Vec::new();
+    let mut report = DirectoryUploadReport::default();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = match &e {
+                    ignore::Error::WithPath { path, .. } => path.clone(),
+                    _ => root.to_path_buf(),
+                };
+                report.record(path, FileOutcome::Failed(e.to_string()));
+                continue;
+            }
+        };
+        let Some(file_type) = entry.file_type() else { continue };
+        if file_type.is_dir() || entry.file_name() == BUCKET_IGNORE_FILE {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        let target_path = target_path(&options.target_directory, relative);
+        if file_type.is_symlink() {
+            match options.symlinks {
+                SymlinkPolicy::StoreAsLink => {
+                    let target = std::fs::read_link(entry.path())?;
+                    entries.push(DirectoryEntry {
+                        source: entry.path().to_path_buf(),
+                        target_path,
+                        size_in_bytes: target.as_os_str().len() as u64,
+                        kind: EntryKind::Symlink { target },
+                    });
+                }
+                // Followed links are resolved by the walker, a link reported here is broken.
+                SymlinkPolicy::Follow | SymlinkPolicy::Skip => {
+                    report.record(entry.path().to_path_buf(), FileOutcome::Skipped(SkipReason::Symlink))
+                }
+            }
+            continue;
+        }
+        if !file_type.is_file() {
+            report.record(entry.path().to_path_buf(), FileOutcome::Skipped(SkipReason::SpecialFile));
+            continue;
+        }
+        entries.push(DirectoryEntry {
+            source: entry.path().to_path_buf(),
+            target_path,
+            size_in_bytes: entry.metadata().map_err(|e| std::io::Error::other(e.to_string()))?.len(),
+            kind: EntryKind::File,
+        });
+    }
+    entries.sort_by(|a, b| a.target_path.cmp(&b.target_path));
+    Ok((entries, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_honors_ignore_rules_and_symlinks() {
+        let root = std::env::temp_dir().join(format!("bucket-walk-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("src/target")).unwrap();
+        std::fs::write(root.join(BUCKET_IGNORE_FILE), "target/\n*.log\n").unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("src/debug.log"), "ignored").unwrap();
+        std::fs::write(root.join("src/target/out.bin"), "ignored").unwrap();
+        std::fs::write(root.join("notes.txt"), "excluded").unwrap();
+        std::os::unix::fs::symlink("src/main.rs", root.join("link.rs")).unwrap();
+
+        let options = DirectoryUploadOptions {
+            target_directory: "/backup/".to_string(),
+            exclude: vec!["*.txt".to_string()],
+            symlinks: SymlinkPolicy::StoreAsLink,
+            ..Default::default()
+        };
+        let (entries, report) = walk_directory(&root, &options).unwrap();
+        let targets: Vec<_> = entries.iter().map(|entry| entry.target_path.as_str()).collect();
+        assert_eq!(targets, vec!["/backup/link.rs", "/backup/src/main.rs"]);
+        assert_eq!(entries[0].kind, EntryKind::Symlink { target: PathBuf::from("src/main.rs") });
+        assert!(report.is_success());
+
+        let options = DirectoryUploadOptions {
+            symlinks: SymlinkPolicy::Skip,
+            ..options
+        };
+        let (entries, report) = walk_directory(&root, &options).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(report.skipped().collect::<Vec<_>>(), vec![root.join("link.rs").as_path()]);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
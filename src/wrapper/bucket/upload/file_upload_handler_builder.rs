@@ -1,18 +1,17 @@
 use std::io::{Read, Write};
 use bucket_common_types::{BucketCompression, BucketEncryption, BucketGuid};
-use generic_array::ArrayLength;
-use zero_knowledge_encryption::encryption::aead::EncryptionModule;
-use crate::compression::CompressorModule;
+use crate::pipeline::Pipeline;
+use crate::token::ApiToken;
 use crate::wrapper::bucket::upload::{FileUploadHandler, FileUploadHandlerBuilder};
 
 pub struct DefaultFileUploadHandlerBuilder {
-
+    pub pipeline: Option<Pipeline>,
 }
 
-impl<R: Read,W: Write, N: ArrayLength> FileUploadHandlerBuilder<R, W, N> for DefaultFileUploadHandlerBuilder {
+impl<R: Read,W: Write> FileUploadHandlerBuilder<R, W> for DefaultFileUploadHandlerBuilder {
     type OutputType = FileUploadHandler<R, W, Error=()>;
 
-    fn new(target: BucketGuid, bucket_compression: Option<BucketCompression>, bucket_encryption: Option<BucketEncryption>, use_client_compression: bool) -> Self {
+    fn new(target: BucketGuid, api_token: &ApiToken, bucket_compression: Option<BucketCompression>, bucket_encryption: Option<BucketEncryption>, use_client_compression: bool) -> Self {
         todo!()
     }
 
@@ -20,15 +19,11 @@ impl<R: Read,W: Write, N: ArrayLength> FileUploadHandlerBuilder<R, W, N> for Def
         todo!()
     }
 
-    fn set_encryption_module<EC: EncryptionModule<R, W, N>>(&mut self, encryption_module: EC) {
-        todo!()
-    }
-
-    fn set_compression_module<CM: CompressorModule<R, W>>(&mut self, compression_module: CM) {
-        todo!()
+    fn set_pipeline(&mut self, pipeline: Pipeline) {
+        self.pipeline = Some(pipeline);
     }
 
     fn build(&self) -> Self::OutputType {
         todo!()
     }
-}
\ No newline at end of file
+}
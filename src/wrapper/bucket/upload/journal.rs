@@ -0,0 +1,222 @@
+use crate::wrapper::bucket::bucket::UploadFileDescriptionState;
+use crate::wrapper::bucket::upload::multipart_upload_id;
+use crate::wrapper::bucket::upload::part_planner::PartLimits;
+use bucket_api::backend_api::{upload_files_to_bucket_request, UploadFilesToBucketRequest};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const UPLOAD_JOURNAL_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadJournalError {
+    #[error("Unsupported upload journal version {0}")]
+    UnsupportedVersion(u32),
+    #[error("File {0} is not part of the upload")]
+    UnknownFile(String),
+    #[error(transparent)]
+    SerializationError(#[from] serde_json::Error),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// Progress of a single file, a part is uploaded through the presigned URL with the same index.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct JournalFile {
+    pub state: UploadFileDescriptionState,
+    /// Where the file is read from on this machine.
+    pub source_path: PathBuf,
+    pub content_type: String,
+    pub completed_parts: BTreeSet<usize>,
+}
+
+impl JournalFile {
+    pub fn remaining_parts(&self) -> Vec<usize> {
+        (0..self.state.urls.len()).filter(|part| !self.completed_parts.contains(part)).collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completed_parts.len() >= self.state.urls.len()
+    }
+
+    /// The multipart upload the parts are sent to, taken from the presigned URLs.
+    pub fn upload_id(&self) -> Option<String> {
+        self.state
+            .urls
+            .first()
+            .and_then(|url| url::Url::parse(url).ok())
+            .and_then(|url| multipart_upload_id(&url))
+    }
+}
+
+/// Record of a multipart upload kept on disk, so an interrupted upload only sends the parts that are missing.
+/// The bucket password is not stored, it has to be given again when the upload is resumed.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct UploadJournal {
+    pub version: u32,
+    pub target_bucket_id: String,
+    pub target_bucket_owner_id: String,
+    pub target_directory: String,
+    /// The parts were planned with these limits, a resumed upload has to split the files the same way.
+    /// Journals written before the limits were recorded used the defaults.
+    #[serde(default)]
+    pub part_limits: PartLimits,
+    pub files: Vec<JournalFile>,
+}
+
+impl UploadJournal {
+    pub fn new(req: &UploadFilesToBucketRequest, part_limits: PartLimits) -> Self {
+        Self {
+            version: UPLOAD_JOURNAL_VERSION,
+            target_bucket_id: req.target_bucket_id.clone(),
+            target_bucket_owner_id: req.target_bucket_owner_id.clone(),
+            target_directory: req.target_directory.clone(),
+            part_limits,
+            files: Vec::new(),
+        }
+    }
+
+    pub fn add_file(&mut self, state: UploadFileDescriptionState, source_path: PathBuf, content_type: String) {
+        self.files.push(JournalFile {
+            state,
+            source_path,
+            content_type,
+            completed_parts: BTreeSet::new(),
+        });
+    }
+
+    pub fn file(&self, file_path: &str) -> Result<&JournalFile, UploadJournalError> {
+        self.files
+            .iter()
+            .find(|file| file.state.file_path == file_path)
+            .ok_or_else(|| UploadJournalError::UnknownFile(file_path.to_string()))
+    }
+
+    fn file_mut(&mut self, file_path: &str) -> Result<&mut JournalFile, UploadJournalError> {
+        self.files
+            .iter_mut()
+            .find(|file| file.state.file_path == file_path)
+            .ok_or_else(|| UploadJournalError::UnknownFile(file_path.to_string()))
+    }
+
+    pub fn mark_part_complete(&mut self, file_path: &str, part: usize) -> Result<(), UploadJournalError> {
+        self.file_mut(file_path)?.completed_parts.insert(part);
+        Ok(())
+    }
+
+    /// Presigned URLs expire, the URLs of a re-issued request replace the stored ones.
+    /// They belong to a new multipart upload, the parts sent to the previous one are sent again.
+    pub fn replace_urls(&mut self, file_path: &str, urls: Vec<String>) -> Result<(), UploadJournalError> {
+        let file = self.file_mut(file_path)?;
+        file.state.urls = urls;
+        file.completed_parts.clear();
+        Ok(())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.files.iter().all(JournalFile::is_complete)
+    }
+
+    /// Multipart uploads of the files that still have parts left, they stay open on the server until aborted.
+    pub fn open_upload_ids(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .filter(|file| !file.is_complete())
+            .filter_map(JournalFile::upload_id)
+            .collect()
+    }
+
+    /// Request for new presigned URLs, covering the files that still have parts left.
+    pub fn remaining_request(&self, hashed_password: Option<String>) -> UploadFilesToBucketRequest {
+        UploadFilesToBucketRequest {
+            target_bucket_id: self.target_bucket_id.clone(),
+            target_bucket_owner_id: self.target_bucket_owner_id.clone(),
+            target_directory: self.target_directory.clone(),
+            source_files: self
+                .files
+                .iter()
+                .filter(|file| !file.is_complete())
+                .map(|file| upload_files_to_bucket_request::File {
+                    file_path: file.state.file_path.clone(),
+                    size_in_bytes: file.state.size_in_bytes,
+                    content_type: file.content_type.clone(),
+                })
+                .collect(),
+            hashed_password,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>, UploadJournalError> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let journal: Self = serde_json::from_slice(&data)?;
+        if journal.version != UPLOAD_JOURNAL_VERSION {
+            return Err(UploadJournalError::UnsupportedVersion(journal.version));
+        }
+        Ok(Some(journal))
+    }
+
+    /// Written to a temporary file that replaces the journal, a crash mid-write leaves the previous journal intact.
+    pub fn save(&self, path: &Path) -> Result<(), UploadJournalError> {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&serde_json::to_vec(self)?)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Called once the upload has finished.
+    pub fn remove(path: &Path) -> Result<(), UploadJournalError> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_persists_progress() {
+        let dir = std::env::temp_dir().join(format!("bucket-journal-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("upload.journal");
+
+        let mut journal = UploadJournal::new(&UploadFilesToBucketRequest::default(), PartLimits::with_memory_limit(4));
+        journal.add_file(
+            UploadFileDescriptionState {
+                file_path: "/big.img".to_string(),
+                size_in_bytes: 12,
+                urls: (1..=3)
+                    .map(|part| format!("https://s3.example/big.img?partNumber={}&uploadId=upload-1", part))
+                    .collect(),
+            },
+            dir.join("big.img"),
+            "application/octet-stream".to_string(),
+        );
+        journal.mark_part_complete("/big.img", 1).unwrap();
+        journal.save(&path).unwrap();
+
+        let journal = UploadJournal::load(&path).unwrap().unwrap();
+        assert_eq!(journal.file("/big.img").unwrap().remaining_parts(), vec![0, 2]);
+        assert_eq!(journal.part_limits, PartLimits::with_memory_limit(4));
+        assert_eq!(journal.remaining_request(None).source_files.len(), 1);
+        assert_eq!(journal.open_upload_ids(), vec!["upload-1".to_string()]);
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("hashed_password"));
+        let mut journal = journal;
+        journal.replace_urls("/big.img", vec!["https://d".to_string()]).unwrap();
+        assert_eq!(journal.file("/big.img").unwrap().remaining_parts(), vec![0]);
+        UploadJournal::remove(&path).unwrap();
+        assert!(UploadJournal::load(&path).unwrap().is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,17 +1,28 @@
-use std::fmt::Debug;
 use std::io::{Read, Write};
 use async_trait::async_trait;
 use bucket_common_types::{BucketCompression, BucketEncryption, BucketGuid};
-use generic_array::ArrayLength;
-use zero_knowledge_encryption::encryption::aead::EncryptionModule;
-use crate::compression::CompressorModule;
+use bytes::Bytes;
+use crate::integrity::ContentHash;
 use crate::io::FileWrapper;
+use crate::pipeline::Pipeline;
 use crate::token::ApiToken;
+use url::Url;
 
 pub mod upload_handler;
 pub mod file_upload_handler_builder;
+pub mod part_planner;
+pub mod stream;
+#[cfg(not(target_family = "wasm"))]
+pub mod directory;
+#[cfg(not(target_family = "wasm"))]
+pub mod journal;
 
-pub trait FileUploadHandlerBuilder<R: Read,W: Write,N: ArrayLength> {
+/// The multipart upload a presigned part URL belongs to, S3 carries its ID in the `uploadId` parameter.
+pub fn multipart_upload_id(url: &Url) -> Option<String> {
+    url.query_pairs().find(|(key, _)| key == "uploadId").map(|(_, value)| value.into_owned())
+}
+
+pub trait FileUploadHandlerBuilder<R: Read,W: Write> {
     type OutputType;
     fn new(target: BucketGuid,
            api_token: &ApiToken,
@@ -19,8 +30,8 @@ pub trait FileUploadHandlerBuilder<R: Read,W: Write,N: ArrayLength> {
            bucket_encryption: Option<BucketEncryption>,
            use_client_compression: bool) -> Self;
     fn set_total_upload_size(&mut self, total_upload_size: u64);
-    fn set_encryption_module<EC: EncryptionModule<R, W, N>>(&mut self, encryption_module: EC);
-    fn set_compression_module<CM: CompressorModule<R, W>>(&mut self, compression_module: CM);
+    /// The pipeline holds the compression, encryption and signing stages used for the upload.
+    fn set_pipeline(&mut self, pipeline: Pipeline);
     fn build(&self) -> Self::OutputType;
 }
 
@@ -28,9 +39,10 @@ pub trait FileUploadHandlerBuilder<R: Read,W: Write,N: ArrayLength> {
 #[async_trait(?Send)]
 pub trait FileUploadHandler<R: std::io::Read, W: std::io::Write>: Sized {
     // : Send + Sync
-    type Error: Debug;
+    type Error: std::error::Error + 'static;
+    type File: FileWrapper;
 
-    fn new<N: ArrayLength,BF: FileWrapper, EM: EncryptionModule<R, W, N>, CM: CompressorModule<R, W>>(read_target_file: BF, encryption_module: Option<EM>, compression_module: Option<CM>, bucket_compression: BucketCompression, use_client_compression: bool) -> Result<Self,Self::Error>;
+    fn new(read_target_file: Self::File, pipeline: Pipeline) -> Result<Self,Self::Error>;
     // Called when the upload starts.
     fn on_upload_start(
         &self,
@@ -43,10 +55,22 @@ pub trait FileUploadHandler<R: std::io::Read, W: std::io::Write>: Sized {
         use_client_side_compression: bool,
         upload_size_in_bytes: u64,
     ) -> Result<u64, Self::Error>;
-    // Called when a chunk is uploaded. returns the chunk to be uploaded. It's up to the implementation to encrypt the chunk and do compression if needed.
-    async fn on_upload_chunk(&mut self, chunk_size: u64) -> Result<Vec<u8>, Self::Error>;
-    // Called when the last chunk has been uploaded.
-    // In this method, the user is still able to upload data, if so it will return a Vec.
-    fn on_upload_finish(self) -> Result<(), Self::Error>;
+    // Called when a chunk is uploaded. returns the chunk to be uploaded, after it has been passed through the pipeline.
+    async fn on_upload_chunk(&mut self, chunk_size: u64) -> Result<Bytes, Self::Error>;
+    // Called when the last chunk has been uploaded. The last chunk already holds the tail of the pipeline,
+    // anything still left in it could not be uploaded and fails the file.
+    async fn on_upload_finish(self) -> Result<(), Self::Error>;
+    // Encoded `PreservedMetadata` stored with the object, see `FILE_METADATA_HEADER`.
+    fn file_metadata(&self) -> Option<&str> {
+        None
+    }
+    // Hash of the whole plaintext, set once the last chunk was returned so it goes out with the final request.
+    fn content_hash(&self) -> Option<&ContentHash> {
+        None
+    }
+    // Called instead of `on_upload_finish` when the transfer is cancelled.
+    async fn on_upload_cancel(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
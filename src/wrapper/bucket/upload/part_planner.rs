@@ -0,0 +1,246 @@
+use std::ops::Range;
+
+pub const KIB: u64 = 1024;
+pub const MIB: u64 = 1024 * KIB;
+pub const GIB: u64 = 1024 * MIB;
+
+/// S3 multipart limits, every part but the last must be at least 5 MiB and no part may exceed 5 GiB.
+pub const S3_MIN_PART_SIZE: u64 = 5 * MIB;
+pub const S3_MAX_PART_SIZE: u64 = 5 * GIB;
+pub const S3_MAX_PARTS: usize = 10_000;
+/// Bytes buffered for a single HTTP request unless the memory budget says otherwise.
+pub const DEFAULT_MAX_REQUEST_SIZE: u64 = 64 * MIB;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum PartPlanError {
+    #[error("No upload URLs")]
+    NoUrls,
+    #[error("More than {0} upload URLs")]
+    TooManyUrls(usize),
+    #[error("{file_size} bytes need at least {required} parts, but only {available} URLs were given")]
+    TooFewUrls { file_size: u64, required: u64, available: usize },
+    #[error("Invalid part limits")]
+    InvalidLimits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PartLimits {
+    pub min_part_size: u64,
+    pub max_part_size: u64,
+    /// Upper bound for the body of one HTTP request, it is held in memory while being sent.
+    pub max_request_size: u64,
+}
+
+impl PartLimits {
+    /// The request size is bounded by the memory each transfer may use.
+    pub fn with_memory_limit(memory_limit: u64) -> Self {
+        Self {
+            max_request_size: memory_limit.clamp(1, DEFAULT_MAX_REQUEST_SIZE),
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for PartLimits {
+    fn default() -> Self {
+        Self {
+            min_part_size: S3_MIN_PART_SIZE,
+            max_part_size: S3_MAX_PART_SIZE,
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+        }
+    }
+}
+
+/// Bytes sent to a single presigned URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedPart {
+    /// Index into `upload_urls`.
+    pub url_index: usize,
+    pub range: Range<u64>,
+    /// The HTTP requests the part is sent in, in order.
+    pub requests: Vec<Range<u64>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartPlan {
+    pub file_size: u64,
+    pub part_size: u64,
+    pub parts: Vec<PlannedPart>,
+}
+
+impl PartPlan {
+    /// URLs that are not needed, the backend may hand out more than the file requires.
+    pub fn unused_urls(&self, url_count: usize) -> Range<usize> {
+        self.parts.len()..url_count
+    }
+}
+
+fn split_requests(range: &Range<u64>, max_request_size: u64) -> Vec<Range<u64>> {
+    if range.is_empty() {
+        // An empty file is still uploaded, as a single empty request.
+        return vec![range.clone()];
+    }
+    (range.start..range.end)
+        .step_by(max_request_size as usize)
+        .map(|start| start..u64::min(start + max_request_size, range.end))
+        .collect()
+}
+
+/// Divides a file into byte ranges, one part per URL and one or more HTTP requests per part.
+/// Parts are spread evenly over the URLs, but never below the minimum part size, so small files use fewer URLs.
+pub fn plan_parts(file_size: u64, url_count: usize, limits: &PartLimits) -> Result<PartPlan, PartPlanError> {
+    if limits.min_part_size == 0 || limits.min_part_size > limits.max_part_size || limits.max_request_size == 0 {
+        return Err(PartPlanError::InvalidLimits);
+    }
+    if url_count == 0 {
+        return Err(PartPlanError::NoUrls);
+    }
+    if url_count > S3_MAX_PARTS {
+        return Err(PartPlanError::TooManyUrls(S3_MAX_PARTS));
+    }
+    let required = file_size.div_ceil(limits.max_part_size).max(1);
+    if required > url_count as u64 {
+        return Err(PartPlanError::TooFewUrls {
+            file_size,
+            required,
+            available: url_count,
+        });
+    }
+    let part_size = file_size
+        .div_ceil(url_count as u64)
+        .clamp(limits.min_part_size, limits.max_part_size);
+    let part_count = file_size.div_ceil(part_size).max(1) as usize;
+
+    let parts = (0..part_count)
+        .map(|url_index| {
+            let start = url_index as u64 * part_size;
+            let range = start..u64::min(start + part_size, file_size);
+            PlannedPart {
+                url_index,
+                requests: split_requests(&range, limits.max_request_size),
+                range,
+            }
+        })
+        .collect();
+    Ok(PartPlan {
+        file_size,
+        part_size,
+        parts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// URLs as handed out by the backend, one per started 5 GiB.
+    fn backend_urls(file_size: u64) -> usize {
+        file_size.div_ceil(S3_MAX_PART_SIZE).max(1) as usize
+    }
+
+    fn assert_valid(plan: &PartPlan, limits: &PartLimits) {
+        let mut expected_start = 0;
+        for (i, part) in plan.parts.iter().enumerate() {
+            assert_eq!(part.url_index, i);
+            assert_eq!(part.range.start, expected_start);
+            let len = part.range.end - part.range.start;
+            assert!(len <= limits.max_part_size);
+            if i + 1 < plan.parts.len() {
+                assert!(len >= limits.min_part_size, "part {i} is {len} bytes");
+            }
+            let mut request_start = part.range.start;
+            for request in &part.requests {
+                assert_eq!(request.start, request_start);
+                assert!(request.end - request.start <= limits.max_request_size);
+                request_start = request.end;
+            }
+            assert_eq!(request_start, part.range.end);
+            expected_start = part.range.end;
+        }
+        assert_eq!(expected_start, plan.file_size);
+    }
+
+    fn plan(file_size: u64, url_count: usize, limits: &PartLimits) -> PartPlan {
+        let plan = plan_parts(file_size, url_count, limits).unwrap();
+        assert_valid(&plan, limits);
+        plan
+    }
+
+    #[test]
+    fn test_empty_file() {
+        let plan = plan(0, 1, &PartLimits::default());
+        assert_eq!(plan.parts.len(), 1);
+        assert_eq!(plan.parts[0].range, 0..0);
+        assert_eq!(plan.parts[0].requests, vec![0..0]);
+    }
+
+    #[test]
+    fn test_exactly_max_part_size() {
+        let limits = PartLimits::default();
+        let plan = plan(5 * GIB, backend_urls(5 * GIB), &limits);
+        assert_eq!(plan.parts.len(), 1);
+        assert_eq!(plan.parts[0].range, 0..5 * GIB);
+        assert_eq!(plan.parts[0].requests.len(), (5 * GIB / DEFAULT_MAX_REQUEST_SIZE) as usize);
+    }
+
+    #[test]
+    fn test_one_byte_over_max_part_size() {
+        let limits = PartLimits::default();
+        let size = 5 * GIB + 1;
+        assert_eq!(
+            plan_parts(size, 1, &limits),
+            Err(PartPlanError::TooFewUrls { file_size: size, required: 2, available: 1 })
+        );
+        let plan = plan(size, backend_urls(size), &limits);
+        assert_eq!(plan.parts.len(), 2);
+        assert_eq!(plan.parts[0].range, 0..size / 2 + 1);
+        // The first part is 1 byte over a multiple of the request size, its last request carries that byte.
+        let request = plan.parts[0].requests.last().unwrap();
+        assert_eq!(request.end - request.start, 1);
+    }
+
+    #[test]
+    fn test_one_byte_over_part_boundary() {
+        let limits = PartLimits::default();
+        let plan = plan(2 * S3_MIN_PART_SIZE + 1, 3, &limits);
+        assert_eq!(plan.part_size, S3_MIN_PART_SIZE);
+        assert_eq!(plan.parts.len(), 3);
+        assert_eq!(plan.parts[2].range, 2 * S3_MIN_PART_SIZE..2 * S3_MIN_PART_SIZE + 1);
+        assert_eq!(plan.unused_urls(3), 3..3);
+    }
+
+    #[test]
+    fn test_small_file_uses_fewer_urls() {
+        let plan = plan(100, 4, &PartLimits::default());
+        assert_eq!(plan.parts.len(), 1);
+        assert_eq!(plan.unused_urls(4), 1..4);
+    }
+
+    #[test]
+    fn test_edge_sizes_with_small_limits() {
+        let limits = PartLimits {
+            min_part_size: 4,
+            max_part_size: 10,
+            max_request_size: 3,
+        };
+        for file_size in 0..=40u64 {
+            let required = file_size.div_ceil(limits.max_part_size).max(1) as usize;
+            for url_count in required..required + 4 {
+                plan(file_size, url_count, &limits);
+            }
+            if required > 1 {
+                assert!(plan_parts(file_size, required - 1, &limits).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert_eq!(plan_parts(1, 0, &PartLimits::default()), Err(PartPlanError::NoUrls));
+        let limits = PartLimits {
+            max_request_size: 0,
+            ..PartLimits::default()
+        };
+        assert_eq!(plan_parts(1, 1, &limits), Err(PartPlanError::InvalidLimits));
+    }
+}
@@ -0,0 +1,264 @@
+use crate::io::{FileMetadata, FileWrapper};
+use crate::pipeline::buffer_pool::{MemoryBudget, MemoryReservation};
+use bytes::{Bytes, BytesMut};
+use futures::{AsyncRead, AsyncReadExt};
+use mime::Mime;
+use std::io::Write;
+#[cfg(not(target_family = "wasm"))]
+use std::sync::Arc;
+
+/// Size of the reads from the stream, memory is reserved from the budget one chunk at a time.
+pub const STREAM_SPOOL_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StreamUploadError {
+    #[error("Stream is larger than the memory budget of {0} bytes and can't be spilled to disk")]
+    StreamTooLarge(u64),
+    #[error("A streamed upload can't be created or written")]
+    Unsupported,
+    #[error("UnknownInferredFileType")]
+    UnknownInferredFileType,
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// A stream of unknown length such as stdin or a pipe, read to its end so the size is known before
+/// the presigned URLs are requested. Chunks stay in memory while the transfer budget has room for them,
+/// the rest is spilled to an unlinked temp file on native targets.
+/// The budget is only held while spooling, the upload needs it for its own buffers afterwards.
+pub struct SpooledStream {
+    filename: String,
+    content_type: Mime,
+    size: u64,
+    /// Every chunk but the last one is `STREAM_SPOOL_CHUNK_SIZE` long, the spill file continues after them.
+    chunks: Vec<Bytes>,
+    #[cfg(not(target_family = "wasm"))]
+    spill: Option<Arc<std::fs::File>>,
+}
+
+impl SpooledStream {
+    pub async fn spool<S: AsyncRead + Unpin>(
+        mut stream: S,
+        filename: impl Into<String>,
+        content_type: Mime,
+        budget: &MemoryBudget,
+    ) -> Result<Self, StreamUploadError> {
+        let mut spooled = Self::empty(filename, content_type);
+        // Released when the stream is read, a stream as large as the budget would otherwise starve its upload.
+        let mut reservations = Vec::new();
+        loop {
+            let mut chunk = BytesMut::zeroed(STREAM_SPOOL_CHUNK_SIZE);
+            let mut filled = 0;
+            while filled < chunk.len() {
+                match stream.read(&mut chunk[filled..]).await? {
+                    0 => break,
+                    n => filled += n,
+                }
+            }
+            if filled == 0 {
+                break;
+            }
+            chunk.truncate(filled);
+            spooled.push(chunk.freeze(), budget, &mut reservations).await?;
+            if filled < STREAM_SPOOL_CHUNK_SIZE {
+                break;
+            }
+        }
+        Ok(spooled)
+    }
+
+    /// A spool that is filled chunk by chunk with `push`.
+    pub(crate) fn empty(filename: impl Into<String>, content_type: Mime) -> Self {
+        Self {
+            filename: filename.into(),
+            content_type,
+            size: 0,
+            chunks: Vec::new(),
+            #[cfg(not(target_family = "wasm"))]
+            spill: None,
+        }
+    }
+
+    /// Every chunk but the last one has to be `STREAM_SPOOL_CHUNK_SIZE` long.
+    pub(crate) async fn push(
+        &mut self,
+        chunk: Bytes,
+        budget: &MemoryBudget,
+        reservations: &mut Vec<MemoryReservation>,
+    ) -> Result<(), StreamUploadError> {
+        self.size += chunk.len() as u64;
+        #[cfg(not(target_family = "wasm"))]
+        if let Some(spill) = self.spill.clone() {
+            let write = tokio::task::spawn_blocking(move || (&*spill).write_all(&chunk));
+            write.await.map_err(std::io::Error::other)??;
+            return Ok(());
+        }
+        match budget.try_reserve(chunk.len() as u64) {
+            Some(reservation) => {
+                self.chunks.push(chunk);
+                reservations.push(reservation);
+                Ok(())
+            }
+            #[cfg(not(target_family = "wasm"))]
+            None => {
+                // Like the reads in `read_at`, the spill file is created and written on the blocking pool.
+                let spill = tokio::task::spawn_blocking(move || -> std::io::Result<std::fs::File> {
+                    let mut spill = spill_file()?;
+                    spill.write_all(&chunk)?;
+                    Ok(spill)
+                });
+                self.spill = Some(Arc::new(spill.await.map_err(std::io::Error::other)??));
+                Ok(())
+            }
+            #[cfg(target_family = "wasm")]
+            None => Err(StreamUploadError::StreamTooLarge(budget.limit())),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    fn memory_size(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.len() as u64).sum()
+    }
+}
+
+/// The file is removed right after it is created, the data lives until the handle is closed.
+#[cfg(not(target_family = "wasm"))]
+fn spill_file() -> std::io::Result<std::fs::File> {
+    let path = std::env::temp_dir().join(format!("bucket-stream-{}.spool", uuid::Uuid::new_v4()));
+    let file = std::fs::File::options().read(true).write(true).create_new(true).open(&path)?;
+    std::fs::remove_file(&path)?;
+    Ok(file)
+}
+
+#[tonic::async_trait(?Send)]
+impl FileWrapper for SpooledStream {
+    type Error = StreamUploadError;
+    type FileHandle = SpooledStream;
+
+    fn create_file(_filename: &str, _mime: &Mime) -> Result<Self, Self::Error> {
+        Err(StreamUploadError::Unsupported)
+    }
+
+    fn from_file_handle(mut file_handle: Self::FileHandle, filename: String, mime: &Mime) -> Self {
+        file_handle.filename = filename;
+        file_handle.content_type = mime.clone();
+        file_handle
+    }
+
+    fn get_extension(&self) -> Option<String> {
+        self.filename.rsplit_once('.').map(|(_, extension)| extension.to_string())
+    }
+
+    fn get_mime_type(&self) -> Result<Mime, Self::Error> {
+        Ok(self.content_type.clone())
+    }
+
+    async fn infer_mime_type(&self) -> Result<infer::Type, Self::Error> {
+        let head = self.read_at(0, 16).await?;
+        infer::get(&head).ok_or(StreamUploadError::UnknownInferredFileType)
+    }
+
+    fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    async fn read_at(&self, offset: u64, len: usize) -> Result<Bytes, Self::Error> {
+        let end = u64::min(offset.saturating_add(len as u64), self.size);
+        let mut output = BytesMut::with_capacity(end.saturating_sub(offset) as usize);
+        let memory_size = self.memory_size();
+        let mut position = offset;
+        while position < end.min(memory_size) {
+            let chunk = &self.chunks[(position / STREAM_SPOOL_CHUNK_SIZE as u64) as usize];
+            let start = (position % STREAM_SPOOL_CHUNK_SIZE as u64) as usize;
+            let take = usize::min(chunk.len() - start, (end - position) as usize);
+            output.extend_from_slice(&chunk[start..start + take]);
+            position += take as u64;
+        }
+        #[cfg(not(target_family = "wasm"))]
+        if let Some(spill) = self.spill.clone().filter(|_| position < end) {
+            // Like `NativeFile`, the disk is read on the blocking pool.
+            let (length, spill_offset) = ((end - position) as usize, position - memory_size);
+            let read = tokio::task::spawn_blocking(move || -> std::io::Result<BytesMut> {
+                let mut buf = BytesMut::zeroed(length);
+                crate::io::positional::read_exact_at(&spill, &mut buf, spill_offset)?;
+                Ok(buf)
+            });
+            output.unsplit(read.await.map_err(std::io::Error::other)??);
+        }
+        Ok(output.freeze())
+    }
+
+    async fn write_at(&self, _offset: u64, _data: &[u8]) -> Result<(), Self::Error> {
+        Err(StreamUploadError::Unsupported)
+    }
+
+    async fn metadata(&self) -> Result<FileMetadata, Self::Error> {
+        Ok(FileMetadata {
+            size_in_bytes: self.size,
+            modified: None,
+            mode: None,
+        })
+    }
+
+    async fn truncate(&self, _size: u64) -> Result<(), Self::Error> {
+        Err(StreamUploadError::Unsupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::buffer_pool::BufferPool;
+    use crate::pipeline::{Pipeline, PipelineDirection};
+    use crate::wrapper::bucket::upload::upload_handler::BucketFileReader;
+    use crate::wrapper::bucket::upload::FileUploadHandler;
+
+    #[tokio::test]
+    async fn test_spool_spills_past_budget() {
+        let data: Vec<u8> = (0..(3 * STREAM_SPOOL_CHUNK_SIZE + 17) as u32).map(|i| (i % 239) as u8).collect();
+        let budget = MemoryBudget::new(STREAM_SPOOL_CHUNK_SIZE as u64);
+        let spooled = SpooledStream::spool(&data[..], "backup.sql", mime::TEXT_PLAIN, &budget).await.unwrap();
+        assert_eq!(spooled.get_size(), data.len() as u64);
+        assert_eq!(spooled.chunks.len(), 1);
+        assert_eq!(budget.available(), budget.limit());
+
+        let read_back = spooled.read_at(0, data.len() + 10).await.unwrap();
+        assert_eq!(read_back, data);
+        let across = STREAM_SPOOL_CHUNK_SIZE - 5;
+        assert_eq!(spooled.read_at(across as u64, 10).await.unwrap(), &data[across..across + 10]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_larger_than_budget_uploads() {
+        let data: Vec<u8> = (0..(2 * STREAM_SPOOL_CHUNK_SIZE + 3) as u32).map(|i| (i % 241) as u8).collect();
+        let buffers = BufferPool::new(MemoryBudget::new(STREAM_SPOOL_CHUNK_SIZE as u64));
+        let spooled = SpooledStream::spool(&data[..], "dump.bin", mime::APPLICATION_OCTET_STREAM, buffers.budget()).await.unwrap();
+        let mut reader = <BucketFileReader<SpooledStream> as FileUploadHandler<SpooledStream, SpooledStream>>::new(
+            spooled,
+            Pipeline::passthrough(PipelineDirection::Upload),
+        )
+        .unwrap()
+        .with_buffer_pool(buffers);
+
+        let mut uploaded = Vec::new();
+        while uploaded.len() < data.len() {
+            // Every request needs the whole budget, which the spooled stream must not hold.
+            let chunk = tokio::time::timeout(
+                std::time::Duration::from_secs(10),
+                FileUploadHandler::<SpooledStream, SpooledStream>::on_upload_chunk(&mut reader, STREAM_SPOOL_CHUNK_SIZE as u64),
+            )
+            .await
+            .expect("the buffer pool has room for the request")
+            .unwrap();
+            uploaded.extend_from_slice(&chunk);
+        }
+        assert_eq!(uploaded, data);
+    }
+}
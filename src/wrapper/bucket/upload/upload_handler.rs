@@ -1,55 +1,88 @@
-use crate::compression::{CompressorModule, DecompressModule};
+use crate::integrity::{ContentHash, ContentHasher};
 use crate::io::FileWrapper;
+use crate::pipeline::buffer_pool::{BufferPool, MemoryBudgetError};
+use crate::pipeline::{Chunk, Pipeline, PipelineError, Room};
 use crate::wrapper::bucket::upload::FileUploadHandler;
 use async_trait::async_trait;
 use bucket_common_types::{BucketCompression, BucketEncryption};
-use generic_array::ArrayLength;
-use std::fmt::Debug;
+use bytes::{Bytes, BytesMut};
 use std::io::{Read, Write};
-use std::marker::PhantomData;
 use uuid::Uuid;
-use zero_knowledge_encryption::encryption::aead::encryption_module::EncryptionError;
-use zero_knowledge_encryption::encryption::aead::EncryptionModule;
 
 #[derive(Debug, thiserror::Error)]
 pub enum BucketDownloadHandlerFileErrors {
     #[error("Encryption module not initialized when bucket is encrypted.")]
     EncryptionModuleNotInitialized,
     #[error(transparent)]
-    EncryptionError(#[from] EncryptionError),
+    PipelineError(#[from] PipelineError),
+    #[error(transparent)]
+    MemoryBudgetError(#[from] MemoryBudgetError),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[error("Reading the source file failed: {0}")]
+    FileError(#[from] crate::io::FileError),
+    #[error("The source was not read to its end, {0} bytes of the pipeline were not uploaded")]
+    UnflushedOutput(usize),
+    #[error("The server returned {0}, which is not one of the files to upload")]
+    UnknownFile(String),
 }
 
-#[derive(Clone)]
-pub struct BucketFileReader<
-    R: Read,
-    W: Write,
-    N: ArrayLength,
-    EC: EncryptionModule<R, W, N>,
-    CM: CompressorModule<R, W>,
-    BF: FileWrapper
-> {
-    phantom_data_r: PhantomData<R>,
-    phantom_data_w: PhantomData<W>,
-    phantom_data_n: PhantomData<N>,
+pub struct BucketFileReader<BF: FileWrapper> {
     pub read_target_file: BF,
-    pub encryption_module: Option<EC>,
-    pub compression_module: Option<CM>,
+    /// Compression, encryption and signing are all handled by the pipeline.
+    pub pipeline: Pipeline,
     pub offset: u64,
-    pub bucket_compression: BucketCompression,
-    pub use_client_compression: bool,
+    /// Chunks are read into pooled buffers, bounded by the memory budget of the transfer.
+    pub buffers: BufferPool,
+    finished: bool,
+    file_metadata: Option<String>,
+    /// Hashes the plaintext, dropped when the reader did not start at the beginning of the file.
+    hasher: Option<ContentHasher>,
+    hashed_bytes: u64,
+    content_hash: Option<ContentHash>,
 }
 
+impl<BF: FileWrapper> BucketFileReader<BF> {
+    /// Shares the pool, and with it the memory budget, with the other files of the same transfer.
+    /// The stages of the pipeline are moved to the same pool.
+    pub fn with_buffer_pool(mut self, buffers: BufferPool) -> Self {
+        self.pipeline.set_buffer_pool(&buffers);
+        self.buffers = buffers;
+        self
+    }
 
+    /// `file_metadata` is encoded with `PreservedMetadata::encode`, with the bucket key for encrypted buckets.
+    pub fn with_file_metadata(mut self, file_metadata: String) -> Self {
+        self.file_metadata = Some(file_metadata);
+        self
+    }
+
+    /// Replaces the SHA3-256 content hash, e.g. with a keyed HighwayHash.
+    pub fn with_content_hasher(mut self, hasher: ContentHasher) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+}
 
 #[async_trait(?Send)]
-impl<R: std::io::Read, W: std::io::Write, N: ArrayLength, EM: EncryptionModule<R, W, N>, CM: CompressorModule<R, W>, BF: FileWrapper>
-    FileUploadHandler<R, W> for BucketFileReader<R, W, N, EM, CM, BF>
+impl<R: Read, W: Write, BF: FileWrapper> FileUploadHandler<R, W> for BucketFileReader<BF>
 {
     //BucketDownloadHandlerFile
     type Error = BucketDownloadHandlerFileErrors;
+    type File = BF;
 
-    fn new(read_target_file: BF, encryption_module: Option<EM>, compression_module: Option<CM>, bucket_compression: BucketCompression, use_client_compression: bool) -> Result<Self, Self::Error> {
-        todo!()
+    fn new(read_target_file: BF, pipeline: Pipeline) -> Result<Self, Self::Error> {
+        Ok(Self {
+            read_target_file,
+            pipeline,
+            offset: 0,
+            buffers: BufferPool::default(),
+            finished: false,
+            file_metadata: None,
+            hasher: Some(ContentHasher::sha3_256()),
+            hashed_bytes: 0,
+            content_hash: None,
+        })
     }
 
 
@@ -57,31 +90,116 @@ impl<R: std::io::Read, W: std::io::Write, N: ArrayLength, EM: EncryptionModule<R
         todo!()
     }
 
-    async fn on_upload_chunk(&mut self, chunk_size: u64) -> Result<Vec<u8>, Self::Error> {
-        let mut buffer = Vec::with_capacity(chunk_size as usize);
-        self.read_target_file.read(&mut buffer.as_slice()).await.unwrap();
-
-        match &mut self.compression_module {
-            None => {}
-            Some(compression_module) => {
-                compression_module.compress_chunk(&mut buffer.as_slice()).unwrap();
+    async fn on_upload_chunk(&mut self, chunk_size: u64) -> Result<Bytes, Self::Error> {
+        if self.finished {
+            return Ok(Bytes::new());
+        }
+        // The chunk is read behind the room the first stage asked for, so it can be transformed in place.
+        let Room { front, back } = self.pipeline.input_room();
+        let mut buffer = self.buffers.acquire(front + chunk_size as usize + back).await?;
+        buffer.resize(front, 0);
+        // Sources may return short reads, only an empty read is the end of the file.
+        while ((buffer.len() - front) as u64) < chunk_size {
+            let read = self
+                .read_target_file
+                .read_at(self.offset, chunk_size as usize - (buffer.len() - front))
+                .await
+                .map_err(|e| BucketDownloadHandlerFileErrors::FileError(e.into()))?;
+            if read.is_empty() {
+                break;
             }
+            buffer.extend_from_slice(&read);
+            self.offset += read.len() as u64;
+        }
+        let filled = buffer.len() - front;
+        // Only a reader that saw every byte from the start can hash the whole file.
+        if self.offset - filled as u64 == self.hashed_bytes {
+            if let Some(hasher) = self.hasher.as_mut() {
+                hasher.update(&buffer[front..]);
+                self.hashed_bytes = self.offset;
+            }
+        } else {
+            self.hasher = None;
         }
 
-        match &mut self.encryption_module {
-            Some(x) => {
-                let encrypted_bytes = x.encrypt_stream(bytes).unwrap();
-                Ok(encrypted_bytes)
+        let body = match filled {
+            0 => Bytes::new(),
+            _ => self.pipeline.process_chunk(Chunk::Owned { buffer, front }).await?,
+        };
+        // At the end of the file whatever the stages still buffer belongs to the last chunk, also when the
+        // size is an exact multiple of the chunk size and the last read was a full one.
+        if (filled as u64) < chunk_size || self.offset >= self.read_target_file.get_size() {
+            let tail = self.pipeline.finish().await?;
+            self.finished = true;
+            self.content_hash = self.hasher.take().map(ContentHasher::finalize);
+            if !tail.is_empty() {
+                // Only the last chunk is copied, to append the tail.
+                let mut output = BytesMut::with_capacity(body.len() + tail.len());
+                output.extend_from_slice(&body);
+                output.extend_from_slice(&tail);
+                return Ok(output.freeze());
             }
-            None => Ok(bytes),
         }
+        Ok(body)
     }
 
-    fn on_upload_finish(self) -> Result<(), Self::Error> {
-        let signed_hash = match self.encryption_module {
-            Some(x) => Some(x.finalize()),
-            None => return Err(Self::Error::EncryptionModuleNotInitialized),
-        };
-        Ok(())
+    async fn on_upload_finish(mut self) -> Result<(), Self::Error> {
+        if self.finished {
+            return Ok(());
+        }
+        // The source was not read to its end, e.g. it grew after its size was sent. The stages are still
+        // flushed, but their tail can't be uploaded anymore.
+        let tail = self.pipeline.finish().await?;
+        Err(BucketDownloadHandlerFileErrors::UnflushedOutput(tail.len()))
+    }
+
+    fn file_metadata(&self) -> Option<&str> {
+        self.file_metadata.as_deref()
+    }
+
+    fn content_hash(&self) -> Option<&ContentHash> {
+        self.content_hash.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::memory::MemoryFile;
+    use crate::pipeline::stages::encryption::{Aes256GcmDecryptStage, Aes256GcmEncryptStage};
+    use crate::pipeline::PipelineDirection;
+    use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+
+    #[tokio::test]
+    async fn test_exact_multiple_of_chunk_size_keeps_final_record() {
+        let cipher = Aes256Gcm::new_from_slice(&[9u8; 32]).unwrap();
+        let nonce = *Nonce::from_slice(&[4u8; 12]);
+        let plaintext = vec![42u8; 2048];
+        let pipeline = Pipeline::builder(PipelineDirection::Upload)
+            .stage(Aes256GcmEncryptStage::from_cipher(cipher.clone(), nonce))
+            .build()
+            .unwrap();
+        let file = MemoryFile::new("a.bin", plaintext.clone(), &mime::APPLICATION_OCTET_STREAM);
+        let mut reader = <BucketFileReader<MemoryFile> as FileUploadHandler<MemoryFile, MemoryFile>>::new(file, pipeline).unwrap();
+
+        let mut body = BytesMut::new();
+        for _ in 0..2 {
+            assert!(FileUploadHandler::<MemoryFile, MemoryFile>::content_hash(&reader).is_none());
+            body.extend_from_slice(&FileUploadHandler::<MemoryFile, MemoryFile>::on_upload_chunk(&mut reader, 1024).await.unwrap());
+        }
+        // The hash is of the plaintext, not of the uploaded records.
+        assert_eq!(
+            FileUploadHandler::<MemoryFile, MemoryFile>::content_hash(&reader),
+            Some(&crate::integrity::ContentHash::sha3_256(&plaintext))
+        );
+        FileUploadHandler::<MemoryFile, MemoryFile>::on_upload_finish(reader).await.unwrap();
+
+        let mut download = Pipeline::builder(PipelineDirection::Download)
+            .stage(Aes256GcmDecryptStage::from_cipher(cipher, nonce))
+            .build()
+            .unwrap();
+        let mut output = BytesMut::from(download.process(body.freeze()).await.unwrap().as_ref());
+        output.extend_from_slice(&download.finish().await.unwrap());
+        assert_eq!(output.as_ref(), plaintext.as_slice());
     }
 }